@@ -0,0 +1,57 @@
+//! how much faster the block cache runs straight-line code than stepping,
+//! run with `cargo bench --bench block_cache`
+//!
+//! the program is most of memory filled with register and stack
+//! instructions, rerun from a snapshot so cached blocks outlive each run the
+//! way they do in a long running program
+
+use std::time::Duration;
+
+use cpu_emu::{op_codes::*, stats, Cpu, CpuBuilder};
+
+/// how long each configuration runs for
+const DURATION: Duration = Duration::from_secs(2);
+
+/// where the program is loaded and started from
+const ORIGIN: usize = 0x0600;
+
+/// end of the straight-line code, the NOP halting it goes here
+const END: usize = 0xF000;
+
+fn main() {
+    let body = [
+        LDA_IM, 0x0F, LSR_ACC, TAX, ORA_IM, 0x80, PHA, TAY, ANDA_IM, 0x3C, PLA, TXA, LDX_ZP, 0x10,
+        LDY_ABS, 0x00, 0x02,
+    ];
+    let mut program: Vec<u8> = body.iter().copied().cycle().take(END - ORIGIN).collect();
+    program.truncate(program.len() - program.len() % body.len());
+    program.push(NOP);
+
+    let build = |builder: CpuBuilder| {
+        builder
+            .pc(ORIGIN as u16)
+            .sp(0x01FF)
+            .memory(ORIGIN, program.clone())
+            .build()
+            .expect("the program fits in memory")
+    };
+    let stepped = run("stepped", &build(Cpu::builder()));
+    let cached = run("block cache", &build(Cpu::builder().block_cache(true)));
+    let fast = run(
+        "block cache, fast",
+        &build(Cpu::builder().block_cache(true).fast(true)),
+    );
+
+    println!(
+        "block cache {:.1}x, with fast {:.1}x the speed of stepping",
+        cached / stepped,
+        fast / stepped
+    );
+}
+
+/// bench a cpu, printing and returning its instructions a second
+fn run(name: &str, cpu: &Cpu) -> f64 {
+    let speed = stats::bench_restore(cpu, DURATION).expect("the program halts");
+    println!("{name:18} {speed}");
+    speed.instructions_per_sec()
+}
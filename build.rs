@@ -0,0 +1,118 @@
+fn main() {
+    op_codes();
+    #[cfg(feature = "perfect6502")]
+    perfect6502();
+}
+
+/// generate the `Opcode` enum, its byte constants and the instruction table
+/// from src/op_codes.csv, included by src/op_codes.rs
+fn op_codes() {
+    use std::{env, fmt::Write, fs, path::PathBuf};
+
+    println!("cargo:rerun-if-changed=src/op_codes.csv");
+    let csv = fs::read_to_string("src/op_codes.csv").expect("src/op_codes.csv should be readable");
+
+    let mut variants = String::new();
+    let mut consts = String::new();
+    let mut table = String::new();
+    for (line_number, line) in csv.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            variants.push('\n');
+            consts.push('\n');
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(8, ',').collect();
+        let [opcode, name, mnemonic, mode, bytes, cycles, variant, description] = fields[..] else {
+            panic!("src/op_codes.csv:{}: expected 8 fields", line_number + 1);
+        };
+        let availability = match variant {
+            "all" => "All",
+            "nmos" => "Nmos",
+            "cmos" => "Cmos",
+            _ => panic!(
+                "src/op_codes.csv:{}: unknown variant {variant}",
+                line_number + 1
+            ),
+        };
+        let (mode, operand_len) = match mode {
+            "IMP" => ("Implied", 0),
+            "ACC" => ("Accumulator", 0),
+            "IMM" => ("Immediate", 1),
+            "ZP" => ("ZeroPage", 1),
+            "ZPX" => ("ZeroPageX", 1),
+            "ZPY" => ("ZeroPageY", 1),
+            "ABS" => ("Absolute", 2),
+            "ABSX" => ("AbsoluteX", 2),
+            "ABSY" => ("AbsoluteY", 2),
+            "IND" => ("Indirect", 2),
+            "INDX" => ("ZeroPageXIndirect", 1),
+            "INDY" => ("ZeroPageIndirectY", 1),
+            "REL" => ("Relative", 1),
+            _ => panic!("src/op_codes.csv:{}: unknown mode {mode}", line_number + 1),
+        };
+        assert_eq!(
+            bytes.parse::<usize>().ok(),
+            Some(1 + operand_len),
+            "src/op_codes.csv:{}: {bytes} bytes doesn't match the addressing mode",
+            line_number + 1
+        );
+
+        let name_in_enum: String = name
+            .split('_')
+            .map(|word| word[..1].to_string() + &word[1..].to_lowercase())
+            .collect();
+        writeln!(
+            variants,
+            "    /// {description}\n    {name_in_enum} = {opcode},"
+        )
+        .unwrap();
+        writeln!(
+            consts,
+            "pub const {name}: u8 = Opcode::{name_in_enum} as u8;"
+        )
+        .unwrap();
+        writeln!(
+            table,
+            "    Instruction::new(Opcode::{name_in_enum}, \"{mnemonic}\", AddressingMode::{mode}, {cycles}, Availability::{availability}, \"{description}\"),"
+        )
+        .unwrap();
+    }
+
+    let generated = format!(
+        "\
+/// an implemented instruction, with the byte that encodes it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Opcode {{
+{variants}}}
+
+// the opcodes as plain bytes, for writing programs out as byte vectors
+{consts}
+/// every implemented instruction
+pub const INSTRUCTIONS: &[Instruction] = &[
+{table}];
+"
+    );
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("op_codes.rs");
+    fs::write(out, generated).expect("generated opcode table should be writable");
+}
+
+/// compile perfect6502's simulation into the crate, from the checkout at PERFECT6502_DIR
+#[cfg(feature = "perfect6502")]
+fn perfect6502() {
+    use std::{env, path::PathBuf};
+
+    println!("cargo:rerun-if-env-changed=PERFECT6502_DIR");
+    let dir = PathBuf::from(
+        env::var("PERFECT6502_DIR")
+            .expect("PERFECT6502_DIR should point at a perfect6502 checkout"),
+    );
+    for source in ["perfect6502.c", "netlist_sim.c"] {
+        println!("cargo:rerun-if-changed={}", dir.join(source).display());
+    }
+    cc::Build::new()
+        .files(["perfect6502.c", "netlist_sim.c"].map(|source| dir.join(source)))
+        .include(&dir)
+        .warnings(false)
+        .compile("perfect6502");
+}
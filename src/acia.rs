@@ -0,0 +1,274 @@
+//! a 6551 ACIA, the serial chip programs talk to a terminal through
+//!
+//! | offset | read            | write                         |
+//! |--------|-----------------|-------------------------------|
+//! | 0      | received byte   | byte to transmit              |
+//! | 1      | status          | programmed reset              |
+//! | 2      | command         | command                       |
+//! | 3      | control         | control                       |
+//!
+//! the line is whatever [`SerialPort`] the chip is attached to, bytes are sent
+//! and received instantly whatever the baud rate, and interrupts aren't raised
+
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use tracing::{info, warn};
+
+use crate::device::Device;
+
+/// address the ACIA is mapped at unless told otherwise
+pub const DEFAULT_BASE: u16 = 0x8800;
+
+/// addresses the ACIA's registers take up
+pub const LEN: usize = 4;
+
+/// status bit set while a received byte is waiting to be read
+const RECEIVE_FULL: u8 = 0x08;
+/// status bit set while a byte can be written, always since sending is instant
+const TRANSMIT_EMPTY: u8 = 0x10;
+
+/// command register bits a programmed reset clears
+const RESET_COMMAND: u8 = 0x1F;
+
+/// the other end of the serial line
+pub trait SerialPort {
+    /// the next byte that arrived, none if nothing is waiting
+    fn receive(&mut self) -> Option<u8>;
+
+    /// send a byte down the line
+    fn transmit(&mut self, byte: u8);
+}
+
+/// a port receiving from a buffer and keeping what's transmitted, for tests
+/// and capturing output
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BufferPort {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl SerialPort for BufferPort {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+/// a serial console on a tcp port for `telnet` or `nc`, one client at a time
+///
+/// clients are accepted whenever the program touches the ACIA, bytes sent
+/// with nobody connected are dropped
+#[derive(Debug)]
+pub struct TcpPort {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+}
+
+impl TcpPort {
+    /// listen on `address` without waiting for a client
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+        })
+    }
+
+    /// the address being listened on
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// the connected client, accepting a waiting one if there's nobody yet
+    fn client(&mut self) -> Option<&mut TcpStream> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    if let Err(err) = stream.set_nonblocking(true) {
+                        warn!("serial client {peer} dropped: {err}");
+                        return None;
+                    }
+                    let _ = stream.set_nodelay(true);
+                    info!("serial client {peer} connected");
+                    self.client = Some(stream);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => warn!("failed to accept a serial client: {err}"),
+            }
+        }
+        self.client.as_mut()
+    }
+
+    fn disconnect(&mut self) {
+        if self.client.take().is_some() {
+            info!("serial client disconnected");
+        }
+    }
+}
+
+impl SerialPort for TcpPort {
+    fn receive(&mut self) -> Option<u8> {
+        let client = self.client()?;
+        let mut byte = [0];
+        match client.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => None,
+            _ => {
+                self.disconnect();
+                None
+            }
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        let Some(client) = self.client() else {
+            return;
+        };
+        // the socket is nonblocking, wait for room rather than lose output
+        let sent = loop {
+            match client.write(&[byte]) {
+                Ok(0) => break false,
+                Ok(_) => break true,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break false,
+            }
+        };
+        if !sent {
+            self.disconnect();
+        }
+    }
+}
+
+/// the ACIA's registers and the port its line is attached to
+#[derive(Debug)]
+pub struct Acia<P> {
+    port: P,
+    /// the byte in the receive register, filled from the port when the program
+    /// checks for one
+    received: Option<u8>,
+    command: u8,
+    control: u8,
+}
+
+impl<P: SerialPort> Acia<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            received: None,
+            command: 0,
+            control: 0,
+        }
+    }
+
+    /// the port the line is attached to
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    pub fn port_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    fn poll(&mut self) {
+        if self.received.is_none() {
+            self.received = self.port.receive();
+        }
+    }
+}
+
+impl<P: SerialPort> Device for Acia<P> {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => {
+                self.poll();
+                self.received.take().unwrap_or(0)
+            }
+            1 => {
+                self.poll();
+                let full = if self.received.is_some() {
+                    RECEIVE_FULL
+                } else {
+                    0
+                };
+                TRANSMIT_EMPTY | full
+            }
+            2 => self.command,
+            _ => self.control,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset {
+            0 => self.port.transmit(value),
+            1 => self.command &= !RESET_COMMAND,
+            2 => self.command = value,
+            _ => self.control = value,
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("ACIA")
+    }
+
+    fn register_name(&self, offset: u16) -> Option<&'static str> {
+        Some(["DATA", "STATUS", "COMMAND", "CONTROL"][offset as usize & 0x03])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn registers_pass_bytes_through_the_port() {
+        let mut acia = Acia::new(BufferPort::default());
+        assert_eq!(acia.read(1), TRANSMIT_EMPTY);
+
+        acia.port_mut().input.extend(b"hi");
+        assert_eq!(acia.read(1), TRANSMIT_EMPTY | RECEIVE_FULL);
+        assert_eq!(acia.read(0), b'h');
+        assert_eq!(acia.read(0), b'i');
+        assert_eq!(acia.read(1), TRANSMIT_EMPTY);
+
+        acia.write(0, b'!');
+        assert_eq!(acia.port().output, b"!");
+
+        acia.write(2, 0xFF);
+        acia.write(1, 0);
+        assert_eq!(acia.read(2), 0xE0);
+    }
+
+    #[test]
+    fn tcp_clients_talk_to_the_acia() {
+        let port = TcpPort::bind("127.0.0.1:0").unwrap();
+        let address = port.local_addr().unwrap();
+        let mut acia = Acia::new(port);
+
+        // nobody's connected yet, this is dropped
+        acia.write(0, b'x');
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"a").unwrap();
+        let mut waited = 0;
+        while acia.read(1) & RECEIVE_FULL == 0 && waited < 100 {
+            std::thread::sleep(Duration::from_millis(10));
+            waited += 1;
+        }
+        assert_eq!(acia.read(0), b'a');
+
+        acia.write(0, b'b');
+        let mut byte = [0];
+        client.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, *b"b");
+    }
+}
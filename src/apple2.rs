@@ -0,0 +1,457 @@
+//! enough of an Apple II to run programs that print through the monitor ROM
+//! and read the keyboard, without emulating the rest of the hardware
+//!
+//! the monitor's character routines are traps with an RTS at their entry
+//! points, so a program's `JSR COUT` draws on the text page at $0400 and
+//! echoes to the host, the cursor is kept in CH ($24) and CV ($25) like the
+//! ROM keeps it
+//!
+//! | address | routine                                              |
+//! |---------|------------------------------------------------------|
+//! | $FC58   | HOME, clear the screen and home the cursor           |
+//! | $FD0C   | RDKEY, wait for a key and return it in A             |
+//! | $FD8E   | CROUT, start a new line                              |
+//! | $FDDA   | PRBYTE, print A as two hex digits                    |
+//! | $FDED   | COUT, print the character in A                       |
+//!
+//! the keyboard softswitches are a device at $C000, reading $C000 gives the
+//! last key with bit 7 set until any access to $C010-$C01F clears the strobe,
+//! keys come from any reader, uppercased like the II+ keyboard and with line
+//! ends turned into returns
+//!
+//! the text page's rows are interleaved, row `r` starts at
+//! `$0400 + (r % 8) * $80 + (r / 8) * $28`, see [`row_address`]
+
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    rc::Rc,
+};
+
+use tracing::warn;
+
+use crate::{
+    cpu::{Cpu, CpuBuilder},
+    device::Device,
+    memory::Memory,
+    op_codes::RTS,
+};
+
+/// the first text page
+pub const TEXT_PAGE: u16 = 0x0400;
+/// characters across the text screen
+pub const COLUMNS: usize = 40;
+/// rows down the text screen
+pub const ROWS: usize = 24;
+
+/// the keyboard data and strobe softswitches
+pub const KEYBOARD: u16 = 0xC000;
+/// addresses the keyboard softswitches take up
+pub const KEYBOARD_LEN: usize = 0x20;
+
+pub const HOME: u16 = 0xFC58;
+pub const RDKEY: u16 = 0xFD0C;
+pub const CROUT: u16 = 0xFD8E;
+pub const PRBYTE: u16 = 0xFDDA;
+pub const COUT: u16 = 0xFDED;
+
+/// zero page cursor column and row
+pub const CH: u16 = 0x24;
+pub const CV: u16 = 0x25;
+
+/// a space in normal video
+const BLANK: u8 = 0xA0;
+/// a return with its high bit set, as the ROM passes characters
+const RETURN: u8 = 0x8D;
+const BACKSPACE: u8 = 0x88;
+
+/// the address of the first character of a text row
+pub fn row_address(row: usize) -> u16 {
+    TEXT_PAGE + (row % 8 * 0x80 + row / 8 * COLUMNS) as u16
+}
+
+/// the character a screen code shows, inverse and flashing characters shown
+/// as their normal selves
+pub fn screen_char(code: u8) -> char {
+    // inverse and flashing characters hold only uppercase and symbols
+    let ascii = match code {
+        0x00..=0x7F => code & 0x3F,
+        _ => code & 0x7F,
+    };
+    let ascii = match ascii {
+        0x00..=0x1F => ascii + 0x40,
+        _ => ascii,
+    };
+    ascii as char
+}
+
+/// the text page as 24 lines of 40 characters
+pub fn render(memory: &Memory) -> String {
+    (0..ROWS)
+        .map(|row| {
+            let start = row_address(row) as usize;
+            (start..start + COLUMNS)
+                .map(|address| screen_char(memory.read_byte(address)))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// keys from a reader behind the keyboard softswitches
+pub struct Keyboard {
+    input: Box<dyn Read>,
+    /// the last key, bit 7 set while it hasn't been cleared by the strobe
+    latch: u8,
+    ended: bool,
+}
+
+impl Keyboard {
+    pub fn new(input: Box<dyn Read>) -> Self {
+        Self {
+            input,
+            latch: 0,
+            ended: false,
+        }
+    }
+
+    /// latch the next key unless one is waiting or input has ended
+    fn fill(&mut self) {
+        if self.latch & 0x80 != 0 || self.ended {
+            return;
+        }
+        let mut byte = [0];
+        loop {
+            match self.input.read(&mut byte) {
+                Ok(0) => self.ended = true,
+                Ok(_) => {
+                    let key = match byte[0] {
+                        b'\n' => b'\r',
+                        key => key.to_ascii_uppercase(),
+                    };
+                    self.latch = key | 0x80;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => {
+                    warn!("keyboard input failed: {err}");
+                    self.ended = true;
+                }
+            }
+            break;
+        }
+    }
+
+    /// wait for the next key and clear the strobe, a return once input ends
+    /// so programs waiting on a line finish it
+    pub fn key(&mut self) -> u8 {
+        self.fill();
+        if self.latch & 0x80 == 0 {
+            return RETURN;
+        }
+        let key = self.latch;
+        self.latch &= 0x7F;
+        key
+    }
+}
+
+impl fmt::Debug for Keyboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keyboard")
+            .field("latch", &self.latch)
+            .field("ended", &self.ended)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0x00..=0x0F => {
+                self.fill();
+                self.latch
+            }
+            _ => {
+                self.latch &= 0x7F;
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u16, _value: u8) {
+        if offset >= 0x10 {
+            self.latch &= 0x7F;
+        }
+    }
+}
+
+/// a monitor routine carried out by the host
+type Routine = fn(&mut Apple2, &mut Cpu);
+
+/// the monitor routines and keyboard of an Apple II
+pub struct Apple2 {
+    keyboard: Rc<RefCell<Keyboard>>,
+    output: Box<dyn Write>,
+}
+
+impl Apple2 {
+    /// keys from `input`, what's printed echoed to `output`
+    pub fn new(input: Box<dyn Read>, output: Box<dyn Write>) -> Self {
+        Self {
+            keyboard: Rc::new(RefCell::new(Keyboard::new(input))),
+            output,
+        }
+    }
+
+    /// the host's stdin and stdout
+    pub fn stdio() -> Self {
+        Self::new(Box::new(io::stdin()), Box::new(io::stdout()))
+    }
+
+    /// map the keyboard and install the traps and their RTSs on a cpu being
+    /// built
+    pub fn attach(self, builder: CpuBuilder) -> CpuBuilder {
+        let keyboard = self.keyboard.clone();
+        let apple = Rc::new(RefCell::new(self));
+        let routines: [(u16, Routine); 5] = [
+            (HOME, Apple2::home),
+            (RDKEY, Apple2::rdkey),
+            (CROUT, Apple2::crout),
+            (PRBYTE, Apple2::prbyte),
+            (COUT, Apple2::cout),
+        ];
+        let mut builder = builder.device(KEYBOARD as usize, KEYBOARD_LEN, keyboard);
+        for (address, routine) in routines {
+            let apple = apple.clone();
+            builder = builder.memory(address as usize, vec![RTS]).trap(
+                address,
+                Rc::new(RefCell::new(move |cpu: &mut Cpu| {
+                    routine(&mut apple.borrow_mut(), cpu)
+                })),
+            );
+        }
+        builder
+    }
+
+    /// draw a character at the cursor and echo it, like COUT
+    pub fn print(&mut self, cpu: &mut Cpu, character: u8) {
+        let character = character | 0x80;
+        let (mut column, mut row) = cursor(cpu);
+        match character {
+            RETURN => (column, row) = (0, row + 1),
+            BACKSPACE => column = column.saturating_sub(1),
+            // the bell and other controls draw nothing
+            _ if character < BLANK => {}
+            _ => {
+                let address = row_address(row) + column as u16;
+                cpu.store(address, character);
+                column += 1;
+                if column == COLUMNS {
+                    (column, row) = (0, row + 1);
+                }
+            }
+        }
+        if row == ROWS {
+            scroll(cpu);
+            row = ROWS - 1;
+        }
+        cpu.store(CH, column as u8);
+        cpu.store(CV, row as u8);
+
+        let echo = match character {
+            RETURN => Some(b'\n'),
+            BACKSPACE => Some(b'\x08'),
+            _ if character < BLANK => None,
+            _ => Some(character & 0x7F),
+        };
+        // flush at line ends so output shows up as the program prints it
+        let written = echo.map_or(Ok(()), |byte| {
+            self.output.write_all(&[byte]).and_then(|_| match byte {
+                b'\n' => self.output.flush(),
+                _ => Ok(()),
+            })
+        });
+        if let Err(err) = written {
+            warn!("apple II output failed: {err}");
+        }
+    }
+
+    fn cout(&mut self, cpu: &mut Cpu) {
+        let character = cpu.a();
+        self.print(cpu, character);
+    }
+
+    fn crout(&mut self, cpu: &mut Cpu) {
+        self.print(cpu, RETURN);
+    }
+
+    fn home(&mut self, cpu: &mut Cpu) {
+        for row in 0..ROWS {
+            let start = row_address(row);
+            for address in start..start + COLUMNS as u16 {
+                cpu.store(address, BLANK);
+            }
+        }
+        cpu.store(CH, 0);
+        cpu.store(CV, 0);
+    }
+
+    fn rdkey(&mut self, cpu: &mut Cpu) {
+        // a prompt waiting on a key shows before it blocks
+        let _ = self.output.flush();
+        let key = self.keyboard.borrow_mut().key();
+        cpu.set_a(key);
+    }
+
+    fn prbyte(&mut self, cpu: &mut Cpu) {
+        let digits = format!("{:02X}", cpu.a());
+        for digit in digits.bytes() {
+            self.print(cpu, digit);
+        }
+    }
+}
+
+impl fmt::Debug for Apple2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Apple2")
+            .field("keyboard", &self.keyboard)
+            .finish_non_exhaustive()
+    }
+}
+
+/// the cursor from CH and CV, kept on the screen
+fn cursor(cpu: &Cpu) -> (usize, usize) {
+    let column = cpu.memory.read_byte(CH as usize) as usize;
+    let row = cpu.memory.read_byte(CV as usize) as usize;
+    (column.min(COLUMNS - 1), row.min(ROWS - 1))
+}
+
+/// move every row up one and blank the bottom row
+fn scroll(cpu: &mut Cpu) {
+    for row in 0..ROWS {
+        let to = row_address(row);
+        for column in 0..COLUMNS as u16 {
+            let byte = match row + 1 < ROWS {
+                true => cpu
+                    .memory
+                    .read_byte((row_address(row + 1) + column) as usize),
+                false => BLANK,
+            };
+            cpu.store(to + column, byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    /// a writer the test keeps a handle on while the profile owns it
+    #[derive(Debug, Default, Clone)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn screen(cpu: &Cpu) -> Vec<String> {
+        render(&cpu.memory)
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_interleaved() {
+        assert_eq!(row_address(0), 0x0400);
+        assert_eq!(row_address(1), 0x0480);
+        assert_eq!(row_address(8), 0x0428);
+        assert_eq!(row_address(23), 0x07D0);
+    }
+
+    #[test]
+    fn cout_draws_at_the_cursor_and_echoes() {
+        let output = Shared::default();
+        let mut apple = Apple2::new(Box::new(io::empty()), Box::new(output.clone()));
+        let mut cpu = Cpu::new();
+        apple.home(&mut cpu);
+        for character in b"HI\rA" {
+            apple.print(&mut cpu, *character);
+        }
+        cpu.set_a(0x3F);
+        apple.prbyte(&mut cpu);
+
+        let screen = screen(&cpu);
+        assert_eq!(screen[..3], ["HI", "A3F", ""]);
+        assert_eq!(cpu.memory.read_byte(0x0480), b'A' | 0x80);
+        assert_eq!(
+            (
+                cpu.memory.read_byte(CH as usize),
+                cpu.memory.read_byte(CV as usize)
+            ),
+            (3, 1)
+        );
+        assert_eq!(*output.0.borrow(), b"HI\nA3F");
+    }
+
+    #[test]
+    fn the_bottom_row_scrolls() {
+        let mut apple = Apple2::new(Box::new(io::empty()), Box::new(io::sink()));
+        let mut cpu = Cpu::new();
+        apple.home(&mut cpu);
+        for row in 0..ROWS + 1 {
+            apple.print(&mut cpu, b'A' + row as u8);
+            apple.print(&mut cpu, RETURN);
+        }
+        let screen = screen(&cpu);
+        assert_eq!(screen[0], "C");
+        assert_eq!(screen[22], "Y");
+        assert_eq!(screen[23], "");
+    }
+
+    #[test]
+    fn keys_are_latched_until_the_strobe_clears() {
+        let mut keyboard = Keyboard::new(Box::new(&b"a\n"[..]));
+        assert_eq!(keyboard.read(0), b'A' | 0x80);
+        assert_eq!(keyboard.read(0), b'A' | 0x80);
+        keyboard.read(0x10);
+        assert_eq!(keyboard.read(0), b'\r' | 0x80);
+        keyboard.write(0x10, 0);
+        assert_eq!(keyboard.read(0), b'\r');
+        // a return once input runs out
+        assert_eq!(keyboard.key(), RETURN);
+    }
+
+    #[test]
+    fn programs_reach_the_traps() {
+        let output = Shared::default();
+        let apple = Apple2::new(Box::new(&b"q"[..]), Box::new(output.clone()));
+        let [rdkey_low, rdkey_high] = RDKEY.to_le_bytes();
+        let [cout_low, cout_high] = COUT.to_le_bytes();
+        let [home_low, home_high] = HOME.to_le_bytes();
+        // clear the screen, read a key and print it, the way an Apple II
+        // program calls the ROM
+        let builder = Cpu::builder().pc(0x0800).sp(0x01FF).memory(
+            0x0800,
+            vec![
+                JSR, home_low, home_high, JSR, rdkey_low, rdkey_high, JSR, cout_low, cout_high,
+                LDX_IM, 0x01, NOP,
+            ],
+        );
+        let mut cpu = apple.attach(builder).build().unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a(), b'Q' | 0x80);
+        assert_eq!((cpu.pc(), cpu.x(), cpu.sp()), (0x080C, 0x01, 0x01FF));
+        assert_eq!(screen(&cpu)[0], "Q");
+        assert_eq!(*output.0.borrow(), b"Q");
+        assert_eq!(cpu.memory.read_byte(KEYBOARD as usize), b'Q');
+    }
+}
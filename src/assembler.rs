@@ -0,0 +1,693 @@
+//! a line at a time 6502 assembler
+//!
+//! a line can start with a `name:` label, which operands can use in place of
+//! an address, or `<name` and `>name` for its low and high byte, labels
+//! always assemble as absolute addresses, even in the zero page
+//!
+//! besides instructions, sources can pull in other files and define macros:
+//!
+//! ```text
+//! .include "delays.s"     ; path relative to the including file
+//! .macro load value, index
+//!     LDA #\value
+//!     LDX \index
+//! .endmacro
+//! load $42, $10
+//! ```
+//!
+//! a macro is used like an instruction with its arguments separated by
+//! commas outside parentheses, so `$10,X` can't be passed as one argument,
+//! each `\parameter` in its body is replaced with the argument given and
+//! macros can use other macros defined before them
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    disassembler::Line,
+    op_codes::{self, AddressingMode},
+    session::Session,
+};
+
+/// how deep includes and macros can nest, catching files including themselves
+const MAX_DEPTH: usize = 16;
+
+/// errors produced while assembling a line of source
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AssemblerError {
+    /// the mnemonic isn't an implemented instruction
+    #[error("unknown mnemonic {0}")]
+    UnknownMnemonic(String),
+    /// the operand couldn't be parsed
+    #[error("invalid operand {0}")]
+    InvalidOperand(String),
+    /// the instruction exists but not in the requested addressing mode
+    #[error("{0} does not support {1:?} addressing")]
+    UnsupportedMode(String, AddressingMode),
+    /// a branch target is more than 128 bytes away
+    #[error("branch target ${0:04X} is out of range")]
+    BranchOutOfRange(u16),
+    /// an included file couldn't be read
+    #[error("failed to include {0}: {1}")]
+    Include(PathBuf, String),
+    /// a directive the assembler doesn't know
+    #[error("unknown directive {0}")]
+    UnknownDirective(String),
+    /// a `.macro` without its `.endmacro`
+    #[error("macro {0} is missing its .endmacro")]
+    UnterminatedMacro(String),
+    /// a macro used with the wrong number of arguments
+    #[error("macro {0} takes {1} arguments, given {2}")]
+    MacroArguments(String, usize, usize),
+    /// includes or macros nested more than `MAX_DEPTH` deep
+    #[error("includes and macros nest too deeply at {0}")]
+    TooDeep(String),
+    /// a label was used but never defined
+    #[error("undefined label {0}")]
+    UndefinedLabel(String),
+    /// a label was defined twice
+    #[error("label {0} is defined more than once")]
+    DuplicateLabel(String),
+}
+
+/// a whole source assembled, with where everything ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+    /// every source line after expanding includes and macros, with the
+    /// address and bytes it assembled to
+    pub listing: Vec<Line>,
+}
+
+impl Assembly {
+    /// assemble source placed from `origin` on, includes are found relative
+    /// to the working directory
+    pub fn from_source(source: &str, origin: u16) -> Result<Self, AssemblerError> {
+        assemble_lines(&expand(source, Path::new(""))?, origin)
+    }
+
+    /// assemble a source file, includes are found relative to the file
+    pub fn from_file(path: impl AsRef<Path>, origin: u16) -> Result<Self, AssemblerError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|err| AssemblerError::Include(path.to_path_buf(), err.to_string()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        assemble_lines(&expand(&source, dir)?, origin)
+    }
+
+    /// the listing as text, one source line per line
+    pub fn listing_text(&self) -> String {
+        self.listing
+            .iter()
+            .map(|line| format!("{}\n", line.to_string().trim_end()))
+            .collect()
+    }
+
+    /// the labels as VICE `al` commands, which the monitor can load
+    pub fn vice_labels(&self) -> String {
+        Session {
+            labels: self.labels.clone(),
+            ..Default::default()
+        }
+        .to_commands()
+    }
+}
+
+/// assemble a single line of source (e.g. `LDA #$42`) into machine code
+/// comments starting with `;` are ignored, a blank line assembles to nothing
+/// branch targets are resolved as if the line were assembled at $0000
+pub fn assemble_line(line: &str) -> Result<Vec<u8>, AssemblerError> {
+    assemble_line_at(line, 0)
+}
+
+/// assemble a single line of source that will be placed at `address`
+pub fn assemble_line_at(line: &str, address: u16) -> Result<Vec<u8>, AssemblerError> {
+    let line = line.split(';').next().unwrap_or_default().trim();
+    if line.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let (mnemonic, operand) = match line.split_once(char::is_whitespace) {
+        Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+        None => (line, ""),
+    };
+    let mnemonic = mnemonic.to_uppercase();
+
+    if !op_codes::INSTRUCTIONS
+        .iter()
+        .any(|info| info.mnemonic == mnemonic)
+    {
+        return Err(AssemblerError::UnknownMnemonic(mnemonic));
+    }
+
+    let (mode, value) = parse_operand(operand)?;
+
+    // branches take an absolute target and encode the offset from the next instruction
+    if let Some(opcode) = op_codes::find_opcode(&mnemonic, AddressingMode::Relative) {
+        if !matches!(mode, AddressingMode::ZeroPage | AddressingMode::Absolute) {
+            return Err(AssemblerError::UnsupportedMode(mnemonic, mode));
+        }
+        let offset = value.wrapping_sub(address.wrapping_add(2)) as i16;
+        let offset = i8::try_from(offset).map_err(|_| AssemblerError::BranchOutOfRange(value))?;
+        return Ok(vec![opcode, offset as u8]);
+    }
+
+    // zero page operands can be widened when the instruction only has the absolute form
+    let (opcode, mode) = match op_codes::find_opcode(&mnemonic, mode) {
+        Some(opcode) => (opcode, mode),
+        None => {
+            let widened =
+                widen(mode).ok_or(AssemblerError::UnsupportedMode(mnemonic.clone(), mode))?;
+            let opcode = op_codes::find_opcode(&mnemonic, widened)
+                .ok_or(AssemblerError::UnsupportedMode(mnemonic, mode))?;
+            (opcode, widened)
+        }
+    };
+
+    let mut bytes = vec![opcode];
+    bytes.extend_from_slice(&value.to_le_bytes()[..mode.operand_len()]);
+    Ok(bytes)
+}
+
+/// assemble source with one instruction per line, placed from `origin` on
+/// includes are found relative to the working directory
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AssemblerError> {
+    Assembly::from_source(source, origin).map(|assembly| assembly.bytes)
+}
+
+/// assemble a source file, includes are found relative to the file
+pub fn assemble_file(path: impl AsRef<Path>, origin: u16) -> Result<Vec<u8>, AssemblerError> {
+    Assembly::from_file(path, origin).map(|assembly| assembly.bytes)
+}
+
+/// the lines left once includes and macros are expanded
+fn expand(source: &str, dir: &Path) -> Result<Vec<String>, AssemblerError> {
+    let mut preprocessor = Preprocessor::default();
+    preprocessor.expand(source.lines().map(str::to_string), dir, 0)?;
+    Ok(preprocessor.lines)
+}
+
+/// assemble expanded lines in two passes, the first places labels, with
+/// labels not defined yet standing in as the line's own address, and the
+/// second assembles with every label known
+/// labels always assemble as 4 digit addresses so both passes agree on sizes
+fn assemble_lines(lines: &[String], origin: u16) -> Result<Assembly, AssemblerError> {
+    let mut labels = BTreeMap::new();
+    let mut address = origin;
+    for line in lines {
+        let (label, code) = split_label(strip_comment(line));
+        if let Some(label) = label {
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(AssemblerError::DuplicateLabel(label.to_string()));
+            }
+        }
+        let code = resolve_labels(code, &labels, Some(address))?;
+        address = address.wrapping_add(assemble_line_at(&code, address)?.len() as u16);
+    }
+
+    let mut bytes = Vec::new();
+    let mut listing = Vec::new();
+    for line in lines {
+        let address = origin.wrapping_add(bytes.len() as u16);
+        let (_, code) = split_label(strip_comment(line));
+        let assembled = assemble_line_at(&resolve_labels(code, &labels, None)?, address)?;
+        bytes.extend_from_slice(&assembled);
+        listing.push(Line {
+            address,
+            bytes: assembled,
+            text: line.trim_end().to_string(),
+        });
+    }
+    Ok(Assembly {
+        origin,
+        bytes,
+        labels,
+        listing,
+    })
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split(';').next().unwrap_or_default().trim()
+}
+
+/// split a leading `name:` off a line
+fn split_label(code: &str) -> (Option<&str>, &str) {
+    match code.split_once(':') {
+        Some((label, rest)) if is_identifier(label) => (Some(label), rest.trim()),
+        _ => (None, code),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// replace labels in an instruction's operand with their addresses, `<label`
+/// and `>label` take the low and high byte
+/// undefined labels are an error unless `placeholder` is given to use instead
+fn resolve_labels(
+    code: &str,
+    labels: &BTreeMap<String, u16>,
+    placeholder: Option<u16>,
+) -> Result<String, AssemblerError> {
+    let Some((mnemonic, operand)) = code.split_once(char::is_whitespace) else {
+        return Ok(code.to_string());
+    };
+
+    let mut resolved = format!("{mnemonic} ");
+    let mut chars = operand.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '_' && !c.is_ascii_alphabetic() {
+            resolved.push(c);
+            // hex digits after a `$` aren't labels
+            if c == '$' {
+                while let Some(digit) = chars.next_if(char::is_ascii_hexdigit) {
+                    resolved.push(digit);
+                }
+            }
+            continue;
+        }
+
+        let mut name = c.to_string();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+        if matches!(name.to_uppercase().as_str(), "A" | "X" | "Y") {
+            resolved.push_str(&name);
+            continue;
+        }
+
+        let address = labels
+            .get(&name)
+            .copied()
+            .or(placeholder)
+            .ok_or(AssemblerError::UndefinedLabel(name))?;
+        match resolved.pop() {
+            Some('<') => resolved.push_str(&format!("${:02X}", address & 0xFF)),
+            Some('>') => resolved.push_str(&format!("${:02X}", address >> 8)),
+            other => {
+                resolved.extend(other);
+                resolved.push_str(&format!("${address:04X}"));
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// expands includes and macros into plain instruction lines
+#[derive(Debug, Default)]
+struct Preprocessor {
+    /// macros by lowercase name
+    macros: HashMap<String, Macro>,
+    lines: Vec<String>,
+}
+
+impl Preprocessor {
+    fn expand(
+        &mut self,
+        lines: impl IntoIterator<Item = String>,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AssemblerError> {
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            let (label, code) = split_label(strip_comment(&line));
+            let (word, rest) = match code.split_once(char::is_whitespace) {
+                Some((word, rest)) => (word, rest.trim()),
+                None => (code, ""),
+            };
+
+            match word.to_lowercase().as_str() {
+                ".include" => {
+                    if depth == MAX_DEPTH {
+                        return Err(AssemblerError::TooDeep(rest.to_string()));
+                    }
+                    let path = dir.join(rest.trim_matches('"'));
+                    let source = fs::read_to_string(&path)
+                        .map_err(|err| AssemblerError::Include(path.clone(), err.to_string()))?;
+                    let lines: Vec<String> = source.lines().map(str::to_string).collect();
+                    self.expand(lines, path.parent().unwrap_or(dir), depth + 1)?;
+                }
+                ".macro" => {
+                    let (name, params) = match rest.split_once(char::is_whitespace) {
+                        Some((name, params)) => (name, split_arguments(params)),
+                        None => (rest, Vec::new()),
+                    };
+                    let mut body = Vec::new();
+                    loop {
+                        let Some(line) = lines.next() else {
+                            return Err(AssemblerError::UnterminatedMacro(name.to_string()));
+                        };
+                        if line.trim().eq_ignore_ascii_case(".endmacro") {
+                            break;
+                        }
+                        body.push(line);
+                    }
+                    self.macros
+                        .insert(name.to_lowercase(), Macro { params, body });
+                }
+                directive if directive.starts_with('.') => {
+                    return Err(AssemblerError::UnknownDirective(word.to_string()));
+                }
+                name => match self.macros.get(name).cloned() {
+                    Some(definition) => {
+                        if let Some(label) = label {
+                            self.lines.push(format!("{label}:"));
+                        }
+                        if depth == MAX_DEPTH {
+                            return Err(AssemblerError::TooDeep(word.to_string()));
+                        }
+                        let args = split_arguments(rest);
+                        if args.len() != definition.params.len() {
+                            return Err(AssemblerError::MacroArguments(
+                                word.to_string(),
+                                definition.params.len(),
+                                args.len(),
+                            ));
+                        }
+                        // longest first so `\a` doesn't eat the start of `\ab`
+                        let mut substitutions: Vec<_> =
+                            definition.params.iter().zip(&args).collect();
+                        substitutions.sort_by_key(|(param, _)| std::cmp::Reverse(param.len()));
+                        let body = definition.body.iter().map(|line| {
+                            substitutions
+                                .iter()
+                                .fold(line.clone(), |line, (param, arg)| {
+                                    line.replace(&format!("\\{param}"), arg)
+                                })
+                        });
+                        self.expand(body.collect::<Vec<_>>(), dir, depth + 1)?;
+                    }
+                    None => self.lines.push(line),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// split comma separated arguments, leaving commas inside parentheses alone
+fn split_arguments(args: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in args.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !split.is_empty() {
+        split.push(current.trim().to_string());
+    }
+    split
+}
+
+/// the absolute equivalent of a zero page addressing mode
+fn widen(mode: AddressingMode) -> Option<AddressingMode> {
+    match mode {
+        AddressingMode::ZeroPage => Some(AddressingMode::Absolute),
+        AddressingMode::ZeroPageX => Some(AddressingMode::AbsoluteX),
+        AddressingMode::ZeroPageY => Some(AddressingMode::AbsoluteY),
+        AddressingMode::Implied => Some(AddressingMode::Accumulator),
+        _ => None,
+    }
+}
+
+/// work out the addressing mode and value from the operand syntax
+fn parse_operand(operand: &str) -> Result<(AddressingMode, u16), AssemblerError> {
+    let invalid = || AssemblerError::InvalidOperand(operand.to_string());
+    let upper = operand.to_uppercase().replace(' ', "");
+
+    if upper.is_empty() {
+        return Ok((AddressingMode::Implied, 0));
+    }
+    if upper == "A" {
+        return Ok((AddressingMode::Accumulator, 0));
+    }
+    if let Some(value) = upper.strip_prefix('#') {
+        let (value, _) = parse_number(value).ok_or_else(invalid)?;
+        let value = u8::try_from(value).map_err(|_| invalid())?;
+        return Ok((AddressingMode::Immediate, value as u16));
+    }
+    if let Some(inner) = upper.strip_suffix(",X)").and_then(|s| s.strip_prefix('(')) {
+        let (value, _) = parse_number(inner).ok_or_else(invalid)?;
+        let value = u8::try_from(value).map_err(|_| invalid())?;
+        return Ok((AddressingMode::ZeroPageXIndirect, value as u16));
+    }
+    if let Some(inner) = upper.strip_suffix("),Y").and_then(|s| s.strip_prefix('(')) {
+        let (value, _) = parse_number(inner).ok_or_else(invalid)?;
+        let value = u8::try_from(value).map_err(|_| invalid())?;
+        return Ok((AddressingMode::ZeroPageIndirectY, value as u16));
+    }
+    if let Some(inner) = upper.strip_suffix(')').and_then(|s| s.strip_prefix('(')) {
+        let (value, _) = parse_number(inner).ok_or_else(invalid)?;
+        return Ok((AddressingMode::Indirect, value));
+    }
+
+    let (base, index) = match upper.split_once(',') {
+        Some((base, index)) => (base, Some(index)),
+        None => (upper.as_str(), None),
+    };
+    let (value, zero_page) = parse_number(base).ok_or_else(invalid)?;
+
+    let mode = match (index, zero_page) {
+        (None, true) => AddressingMode::ZeroPage,
+        (None, false) => AddressingMode::Absolute,
+        (Some("X"), true) => AddressingMode::ZeroPageX,
+        (Some("X"), false) => AddressingMode::AbsoluteX,
+        (Some("Y"), true) => AddressingMode::ZeroPageY,
+        (Some("Y"), false) => AddressingMode::AbsoluteY,
+        _ => return Err(invalid()),
+    };
+    Ok((mode, value))
+}
+
+/// parse `$nn`/`$nnnn` hex or decimal numbers
+/// also returns whether the number was written as a zero page (one byte) value
+fn parse_number(value: &str) -> Option<(u16, bool)> {
+    if let Some(hex) = value.strip_prefix('$') {
+        let number = u16::from_str_radix(hex, 16).ok()?;
+        Some((number, hex.len() <= 2))
+    } else {
+        let number: u16 = value.parse().ok()?;
+        Some((number, number <= 0xFF))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn assemble_implied() {
+        assert_eq!(assemble_line("nop"), Ok(vec![NOP]));
+    }
+
+    #[test]
+    fn assemble_immediate() {
+        assert_eq!(assemble_line("LDA #$42"), Ok(vec![LDA_IM, 0x42]));
+    }
+
+    #[test]
+    fn assemble_zero_page_and_absolute() {
+        assert_eq!(assemble_line("LDA $42"), Ok(vec![LDA_ZP, 0x42]));
+        assert_eq!(assemble_line("LDA $4480"), Ok(vec![LDA_ABS, 0x80, 0x44]));
+    }
+
+    #[test]
+    fn assemble_indexed() {
+        assert_eq!(assemble_line("LDY $42,X"), Ok(vec![LDY_ZP_X, 0x42]));
+        assert_eq!(
+            assemble_line("LDA $4480,y"),
+            Ok(vec![LDA_ABS_Y, 0x80, 0x44])
+        );
+        assert_eq!(assemble_line("ORA ($11,X)"), Ok(vec![ORA_ZP_XI, 0x11]));
+        assert_eq!(assemble_line("AND ($11),Y"), Ok(vec![ANDA_ZP_IY, 0x11]));
+    }
+
+    #[test]
+    fn assemble_widens_zero_page_when_needed() {
+        assert_eq!(assemble_line("JMP $10"), Ok(vec![JMP_ABS, 0x10, 0x00]));
+        assert_eq!(assemble_line("LSR"), Ok(vec![LSR_ACC]));
+    }
+
+    #[test]
+    fn assemble_indirect() {
+        assert_eq!(
+            assemble_line("JMP ($BBBB)"),
+            Ok(vec![JMP_ABS_IND, 0xBB, 0xBB])
+        );
+    }
+
+    #[test]
+    fn assemble_branch_relative_to_address() {
+        assert_eq!(assemble_line_at("BNE $0602", 0x0606), Ok(vec![BNE, 0xFA]));
+        assert_eq!(assemble_line_at("BCC $0702", 0x06F0), Ok(vec![BCC, 0x10]));
+        assert_eq!(
+            assemble_line_at("BEQ $0700", 0x0600),
+            Err(AssemblerError::BranchOutOfRange(0x0700))
+        );
+    }
+
+    #[test]
+    fn assemble_lines_from_origin() {
+        let source = "LDA #$01 ; count\n\nloop:\nBNE $0600";
+        assert_eq!(assemble(source, 0x0600), Ok(vec![LDA_IM, 0x01, BNE, 0xFC]));
+        assert_eq!(
+            assemble("LDA #$01 ; count\n\nBNE $0600", 0x0600),
+            Ok(vec![LDA_IM, 0x01, BNE, 0xFC])
+        );
+    }
+
+    #[test]
+    fn assemble_resolves_labels() {
+        let source = "\
+start:  LDX #<table
+        LDY #>table
+loop:   LDA table,X ; labels can come after they're used
+        BNE loop
+        JMP (vector)
+vector: .macro_free
+table:";
+        assert_eq!(
+            assemble(source, 0x0600),
+            Err(AssemblerError::UnknownDirective(".macro_free".to_string()))
+        );
+
+        let assembly =
+            Assembly::from_source(&source.replace(".macro_free", "JSR start\nLSR A"), 0x0600)
+                .unwrap();
+        let addresses = "\
+LDX #$10\nLDY #$06\nLDA $0610,X\nBNE $0604\nJMP ($060C)\nJSR $0600\nLSR A";
+        assert_eq!(assembly.bytes, assemble(addresses, 0x0600).unwrap());
+        assert_eq!(assembly.labels["loop"], 0x0604);
+        assert!(assembly.vice_labels().contains("al C:0610 .table\n"));
+        assert_eq!(
+            assembly.listing[2].to_string(),
+            "$0604  BD 10 06  loop:   LDA table,X ; labels can come after they're used"
+        );
+
+        assert_eq!(
+            assemble("JMP nowhere", 0),
+            Err(AssemblerError::UndefinedLabel("nowhere".to_string()))
+        );
+        assert_eq!(
+            assemble("a1:\na1:", 0),
+            Err(AssemblerError::DuplicateLabel("a1".to_string()))
+        );
+    }
+
+    #[test]
+    fn assemble_expands_macros() {
+        let source = "\
+.macro load value, address ; comments are fine here
+    LDX #\\value
+    LDA \\address
+.endmacro
+.MACRO twice value
+    load \\value, ($10,X)
+    load \\value, $2000
+.endmacro
+twice $42";
+        assert_eq!(
+            assemble(source, 0x0600),
+            Ok(vec![
+                LDX_IM, 0x42, LDA_ZP_XI, 0x10, LDX_IM, 0x42, LDA_ABS, 0x00, 0x20
+            ])
+        );
+        assert_eq!(
+            assemble(".macro one a\nTAX\n.endmacro\none", 0),
+            Err(AssemblerError::MacroArguments("one".to_string(), 1, 0))
+        );
+        assert_eq!(
+            assemble(".macro open\nTAX", 0),
+            Err(AssemblerError::UnterminatedMacro("open".to_string()))
+        );
+        assert_eq!(
+            assemble(".macro loop\nloop\n.endmacro\nloop", 0),
+            Err(AssemblerError::TooDeep("loop".to_string()))
+        );
+        assert_eq!(
+            assemble(".org $0600", 0),
+            Err(AssemblerError::UnknownDirective(".org".to_string()))
+        );
+    }
+
+    #[test]
+    fn assemble_includes_files() {
+        let dir = std::env::temp_dir().join("cpu_emu_assembler_include");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("lib/macros.s"),
+            ".include \"delay.s\"\n.macro pause\n  delay\n  delay\n.endmacro",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/delay.s"), ".macro delay\nNOP\n.endmacro").unwrap();
+        fs::write(dir.join("main.s"), ".include \"lib/macros.s\"\nTAX\npause").unwrap();
+        fs::write(dir.join("self.s"), ".include \"self.s\"").unwrap();
+
+        assert_eq!(
+            assemble_file(dir.join("main.s"), 0x0600),
+            Ok(vec![TAX, NOP, NOP])
+        );
+        assert!(matches!(
+            assemble_file(dir.join("self.s"), 0x0600),
+            Err(AssemblerError::TooDeep(_))
+        ));
+        assert!(matches!(
+            assemble_file(dir.join("missing.s"), 0x0600),
+            Err(AssemblerError::Include(..))
+        ));
+    }
+
+    #[test]
+    fn assemble_ignores_comments() {
+        assert_eq!(assemble_line("  ; nothing here"), Ok(vec![]));
+        assert_eq!(assemble_line("TAX ; copy a"), Ok(vec![TAX]));
+    }
+
+    #[test]
+    fn assemble_unknown_mnemonic() {
+        assert_eq!(
+            assemble_line("XYZ #$01"),
+            Err(AssemblerError::UnknownMnemonic("XYZ".to_string()))
+        );
+    }
+
+    #[test]
+    fn assemble_unsupported_mode() {
+        assert_eq!(
+            assemble_line("TAX #$01"),
+            Err(AssemblerError::UnsupportedMode(
+                "TAX".to_string(),
+                AddressingMode::Immediate
+            ))
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! the cpu and RIOT half of an Atari 2600, to unit test the game logic and
+//! timing loops of 2600 code without the TIA drawing anything
+//!
+//! the 6507 only has 13 address lines, so the cpu is built with
+//! [`address_lines`](crate::CpuBuilder::address_lines) 13 and everything
+//! repeats every 8K, cartridges sit at $1000-$1FFF and are reached through
+//! $F000 as they expect
+//!
+//! | range         | what's there                                          |
+//! |---------------|-------------------------------------------------------|
+//! | $0000-$007F   | TIA registers, left as plain RAM here                 |
+//! | $0080-$00FF   | RIOT RAM, mirrored at $0180-$01FF for the stack       |
+//! | $0280-$029F   | RIOT ports and timer                                  |
+//! | $1000-$1FFF   | cartridge ROM, a 2K one twice                         |
+//!
+//! the RIOT's registers, from $0280
+//!
+//! | offset | read                               | write                       |
+//! |--------|------------------------------------|-----------------------------|
+//! | $00    | SWCHA, the joysticks               | ignored                     |
+//! | $02    | SWCHB, the console switches        | ignored                     |
+//! | $04    | INTIM, the timer, clears TIMINT    | ignored                     |
+//! | $05    | TIMINT, bit 7 once the timer wraps | ignored                     |
+//! | $14-17 | INTIM                              | TIM1T, TIM8T, TIM64T, T1024T|
+//!
+//! the timer counts down once an interval of 1, 8, 64 or 1024 cycles from the
+//! value written, and once it passes zero sets TIMINT and counts down every
+//! cycle from $FF, the 6507 has no IRQ line so the timer never interrupts
+
+use std::{cell::RefCell, rc::Rc};
+
+use thiserror::Error;
+
+use crate::{cpu::CpuBuilder, device::Device, machine::SharedRam, memory::RomImage};
+
+/// address lines a 6507 has
+pub const ADDRESS_LINES: u32 = 13;
+
+/// the RIOT's RAM and its mirror
+pub const RAM: u16 = 0x0080;
+pub const RAM_MIRROR: u16 = 0x0180;
+pub const RAM_LEN: usize = 128;
+
+/// the RIOT's ports and timer
+pub const RIOT: u16 = 0x0280;
+/// addresses the RIOT's registers take up
+pub const RIOT_LEN: usize = 0x20;
+
+/// where the cartridge is reached, with the address lines masked
+pub const CARTRIDGE: u16 = 0x1000;
+
+/// errors loading a cartridge
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CartridgeError {
+    #[error("cartridges are 2K or 4K without bank switching, not {0} bytes")]
+    Size(usize),
+}
+
+/// the RIOT's input ports and interval timer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Riot {
+    /// the joysticks, a bit clear for each direction pushed
+    pub port_a: u8,
+    /// the console switches, a bit clear for each switch pressed
+    pub port_b: u8,
+    /// the value last written to the timer and the cycle it was written on
+    start: u8,
+    written: u64,
+    /// cycles a count takes, until the timer wraps
+    interval: u64,
+    /// the cycle INTIM was last read on, clearing TIMINT
+    read: Option<u64>,
+    /// the cycle the cpu is at
+    now: u64,
+}
+
+impl Default for Riot {
+    /// nothing pushed, the timer counting down from $FF a count every 1024
+    /// cycles as it would on power up
+    fn default() -> Self {
+        Self {
+            port_a: 0xFF,
+            port_b: 0xFF,
+            start: 0xFF,
+            written: 0,
+            interval: 1024,
+            read: None,
+            now: 0,
+        }
+    }
+}
+
+impl Riot {
+    /// the cycle the timer wraps past zero on
+    fn wraps_at(&self) -> u64 {
+        self.written + (self.start as u64 + 1) * self.interval
+    }
+
+    /// the timer's count
+    pub fn timer(&self) -> u8 {
+        let elapsed = self.now - self.written;
+        match self.now.checked_sub(self.wraps_at()) {
+            None => self.start - (elapsed / self.interval) as u8,
+            Some(since) => 0xFF - since as u8,
+        }
+    }
+
+    /// whether the timer has wrapped since INTIM was last read
+    pub fn timer_flag(&self) -> bool {
+        let wrapped = self.wraps_at();
+        self.now >= wrapped && self.read.is_none_or(|read| read < wrapped)
+    }
+}
+
+impl Device for Riot {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset & 0x07 {
+            0x00 => self.port_a,
+            0x02 => self.port_b,
+            0x04 | 0x06 => {
+                let timer = self.timer();
+                self.read = Some(self.now);
+                timer
+            }
+            0x05 | 0x07 => (self.timer_flag() as u8) << 7,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if offset & 0x14 == 0x14 {
+            self.start = value;
+            self.written = self.now;
+            self.interval = [1, 8, 64, 1024][offset as usize & 0x03];
+            self.read = None;
+        }
+    }
+
+    fn scheduled(&self) -> bool {
+        // nothing to wake for, just kept told the cycle
+        true
+    }
+
+    fn sync(&mut self, cycle: u64) {
+        self.now = cycle;
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("RIOT")
+    }
+
+    fn register_name(&self, offset: u16) -> Option<&'static str> {
+        match offset {
+            0x14..=0x17 => Some(["TIM1T", "TIM8T", "TIM64T", "T1024T"][offset as usize & 0x03]),
+            _ => match offset & 0x07 {
+                0x00 => Some("SWCHA"),
+                0x02 => Some("SWCHB"),
+                0x04 | 0x06 => Some("INTIM"),
+                0x05 | 0x07 => Some("TIMINT"),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// a 2600's cartridge and RIOT
+#[derive(Debug, Clone)]
+pub struct Atari2600 {
+    cartridge: Vec<u8>,
+    riot: Rc<RefCell<Riot>>,
+}
+
+impl Atari2600 {
+    /// a console with a 2K or 4K cartridge in it
+    pub fn new(cartridge: Vec<u8>) -> Result<Self, CartridgeError> {
+        let cartridge = match cartridge.len() {
+            2048 => cartridge.repeat(2),
+            4096 => cartridge,
+            len => return Err(CartridgeError::Size(len)),
+        };
+        Ok(Self {
+            cartridge,
+            riot: Default::default(),
+        })
+    }
+
+    /// the RIOT, to push joystick directions and console switches
+    pub fn riot(&self) -> Rc<RefCell<Riot>> {
+        self.riot.clone()
+    }
+
+    /// where the cartridge's reset vector points
+    pub fn reset_vector(&self) -> u16 {
+        u16::from_le_bytes([self.cartridge[0xFFC], self.cartridge[0xFFD]])
+    }
+
+    /// a 6507 with the RIOT and the cartridge, starting from its reset
+    /// vector
+    pub fn attach(&self, builder: CpuBuilder) -> CpuBuilder {
+        let ram = Rc::new(RefCell::new(SharedRam::new(RAM_LEN)));
+        builder
+            .address_lines(ADDRESS_LINES)
+            .device(RAM as usize, RAM_LEN, ram.clone())
+            .device(RAM_MIRROR as usize, RAM_LEN, ram)
+            .device(RIOT as usize, RIOT_LEN, self.riot.clone())
+            .rom(
+                CARTRIDGE as usize,
+                RomImage::Shared(self.cartridge.clone().into()),
+            )
+            .pc(self.reset_vector())
+            .sp(RAM_MIRROR + RAM_LEN as u16 - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    /// a 4K cartridge starting at $F000 with `program`
+    fn cartridge(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![NOP; 4096];
+        rom[..program.len()].copy_from_slice(program);
+        rom[0xFFC..0xFFE].copy_from_slice(&[0x00, 0xF0]);
+        rom
+    }
+
+    #[test]
+    fn the_address_bus_is_13_bits() {
+        // LDA $E080 reaches RIOT RAM, LDX $1234 the cartridge at $F234
+        let mut rom = cartridge(&[LDA_ABS, 0x80, 0xE0, LDX_ABS, 0x34, 0x12, PHA]);
+        rom[0x234] = 0x5A;
+        let console = Atari2600::new(rom).unwrap();
+        let mut cpu = console.attach(Cpu::builder()).build().unwrap();
+        assert_eq!(cpu.pc(), 0xF000);
+        cpu.memory.write_byte(0x0080, 0x42);
+
+        cpu.execute().unwrap();
+        assert_eq!((cpu.a(), cpu.x()), (0x42, 0x5A));
+        // pushed through the mirror into RIOT RAM
+        assert_eq!(cpu.memory.read_byte(0x00FF), 0x42);
+        assert_eq!(cpu.memory.read_byte(0xE0FF), 0x42);
+        assert_eq!(cpu.memory.address_lines(), 13);
+    }
+
+    #[test]
+    fn the_timer_counts_down_then_wraps() {
+        let mut riot = Riot::default();
+        riot.sync(100);
+        // TIM64T
+        riot.write(0x16, 3);
+        riot.sync(100 + 64 * 3 + 10);
+        assert_eq!(riot.read(0x04), 0);
+        assert_eq!(riot.read(0x05), 0);
+        riot.sync(100 + 64 * 4 + 5);
+        assert_eq!(riot.read(0x05), 0x80);
+        assert_eq!(riot.read(0x04), 0xFA);
+        // reading INTIM cleared the flag
+        assert_eq!(riot.read(0x05), 0);
+    }
+
+    #[test]
+    fn programs_poll_the_timer() {
+        // wait: LDA INTIM, BNE wait, in a 2K cartridge
+        let mut rom = vec![NOP; 2048];
+        rom[..5].copy_from_slice(&[LDA_ABS, 0x84, 0x02, BNE, 0xFB]);
+        rom[0x7FC..0x7FE].copy_from_slice(&[0x00, 0xF8]);
+        let console = Atari2600::new(rom).unwrap();
+        assert_eq!(console.reset_vector(), 0xF800);
+        // TIM8T
+        console.riot().borrow_mut().write(0x15, 5);
+        let mut cpu = console.attach(Cpu::builder()).build().unwrap();
+        cpu.execute().unwrap();
+        assert!(cpu.cycles() >= 40);
+        assert_eq!(cpu.a(), 0);
+        assert!(Atari2600::new(vec![0; 100]).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! the path sound takes from devices to the host's speakers
+//!
+//! devices set the level of a channel of an [`AudioBridge`] at the cycle it
+//! changes, the bridge turns the levels into samples at the backend's rate by
+//! averaging each channel over the cycles a sample covers, mixes the channels
+//! and hands the samples to an [`AudioBackend`]
+//!
+//! a backend playing in real time keeps a bounded queue and makes the cpu
+//! wait while it's full, so a machine making sound runs at the speed of its
+//! clock instead of as fast as it can
+//!
+//! the [`Beeper`] is a one bit speaker on a single address, any access flips
+//! the cone
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::device::Device;
+
+/// samples handed to the backend at a time
+const BLOCK: usize = 512;
+
+/// address the beeper is mapped at unless told otherwise
+pub const BEEPER_BASE: u16 = 0xD400;
+
+/// how often a beeper brings the bridge up to date, in cycles, so sound keeps
+/// flowing while the speaker isn't touched
+const BEEPER_FLUSH: u64 = 10_000;
+
+/// where mixed samples go
+pub trait AudioBackend {
+    /// samples a second the backend plays
+    fn sample_rate(&self) -> u32;
+
+    /// queue mono samples between -1 and 1
+    fn play(&mut self, samples: &[f32]);
+}
+
+/// a backend keeping every sample, for tests and rendering to a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferBackend {
+    pub rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl BufferBackend {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            samples: Vec::new(),
+        }
+    }
+}
+
+impl AudioBackend for BufferBackend {
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn play(&mut self, samples: &[f32]) {
+        self.samples.extend_from_slice(samples);
+    }
+}
+
+/// a bridge shared between the devices making sound and their owner
+pub type SharedAudio<B> = Rc<RefCell<AudioBridge<B>>>;
+
+/// turns channel levels set at cpu cycles into samples for a backend
+#[derive(Debug)]
+pub struct AudioBridge<B> {
+    backend: B,
+    /// cpu cycles a sample covers
+    cycles_per_sample: f64,
+    /// each channel's current level
+    levels: Vec<f32>,
+    /// the cycle, fractional, the sample being built started at
+    start: f64,
+    /// the level summed over the sample being built, weighted by cycles
+    sum: f64,
+    /// the cycle everything up to has been mixed
+    now: u64,
+    block: Vec<f32>,
+}
+
+impl<B: AudioBackend> AudioBridge<B> {
+    /// a bridge for a cpu clocked at `clock` Hz
+    pub fn new(clock: u64, backend: B) -> Self {
+        Self {
+            cycles_per_sample: clock as f64 / backend.sample_rate().max(1) as f64,
+            backend,
+            levels: Vec::new(),
+            start: 0.0,
+            sum: 0.0,
+            now: 0,
+            block: Vec::with_capacity(BLOCK),
+        }
+    }
+
+    /// add a silent channel, returning its index
+    pub fn channel(&mut self) -> usize {
+        self.levels.push(0.0);
+        self.levels.len() - 1
+    }
+
+    /// change a channel's level from `cycle` on
+    pub fn set_level(&mut self, channel: usize, cycle: u64, level: f32) {
+        self.advance(cycle);
+        self.levels[channel] = level;
+    }
+
+    /// mix everything up to `cycle`, at most a sample short of it
+    pub fn advance(&mut self, cycle: u64) {
+        if cycle <= self.now {
+            return;
+        }
+        let level = self.levels.iter().sum::<f32>().clamp(-1.0, 1.0) as f64;
+        let mut from = self.now as f64;
+        let to = cycle as f64;
+        while self.start + self.cycles_per_sample <= to {
+            let end = self.start + self.cycles_per_sample;
+            self.sum += level * (end - from);
+            self.block.push((self.sum / self.cycles_per_sample) as f32);
+            if self.block.len() == BLOCK {
+                self.flush();
+            }
+            self.sum = 0.0;
+            from = end;
+            self.start = end;
+        }
+        self.sum += level * (to - from);
+        self.now = cycle;
+    }
+
+    /// hand the samples mixed so far to the backend
+    pub fn flush(&mut self) {
+        if !self.block.is_empty() {
+            self.backend.play(&self.block);
+            self.block.clear();
+        }
+    }
+
+    /// cpu cycles each sample covers
+    pub fn cycles_per_sample(&self) -> f64 {
+        self.cycles_per_sample
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+}
+
+/// a one bit speaker, each access flips the cone, like the Apple II's
+#[derive(Debug)]
+pub struct Beeper<B> {
+    audio: SharedAudio<B>,
+    channel: usize,
+    high: bool,
+    /// the cycle the cpu was at when the beeper was last accessed
+    now: u64,
+    next_flush: u64,
+}
+
+impl<B: AudioBackend> Beeper<B> {
+    pub fn new(audio: SharedAudio<B>) -> Self {
+        let channel = audio.borrow_mut().channel();
+        Self {
+            audio,
+            channel,
+            high: false,
+            now: 0,
+            next_flush: BEEPER_FLUSH,
+        }
+    }
+
+    fn toggle(&mut self) {
+        self.high = !self.high;
+        let level = if self.high { 0.5 } else { -0.5 };
+        self.audio
+            .borrow_mut()
+            .set_level(self.channel, self.now, level);
+    }
+}
+
+impl<B: AudioBackend> Device for Beeper<B> {
+    fn read(&mut self, _offset: u16) -> u8 {
+        self.toggle();
+        0
+    }
+
+    fn write(&mut self, _offset: u16, _value: u8) {
+        self.toggle();
+    }
+
+    fn scheduled(&self) -> bool {
+        true
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        Some(self.next_flush)
+    }
+
+    fn event(&mut self, cycle: u64) {
+        let mut audio = self.audio.borrow_mut();
+        audio.advance(cycle);
+        audio.flush();
+        self.next_flush = cycle + BEEPER_FLUSH;
+    }
+
+    fn sync(&mut self, cycle: u64) {
+        self.now = cycle;
+    }
+}
+
+/// the host's default output device, through cpal
+#[cfg(feature = "audio")]
+pub mod speaker {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use tracing::warn;
+
+    use super::AudioBackend;
+
+    /// most sound queued ahead of the speaker, playing waits while there's
+    /// more than this
+    const MAX_LATENCY: Duration = Duration::from_millis(100);
+
+    /// plays samples on the default output device
+    pub struct Speaker {
+        rate: u32,
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        max_queued: usize,
+        _stream: cpal::Stream,
+    }
+
+    impl Speaker {
+        /// open the default output device at its preferred rate
+        pub fn open() -> Result<Self, String> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or("no audio output device")?;
+            let config = device
+                .default_output_config()
+                .map_err(|err| err.to_string())?;
+            let rate = config.sample_rate().0;
+            let channels = config.channels() as usize;
+
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+            let playing = queue.clone();
+            let stream = device
+                .build_output_stream(
+                    &config.into(),
+                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                        let mut queue = playing.lock().unwrap();
+                        // every output channel plays the same sample, silence
+                        // when the cpu falls behind
+                        for frame in data.chunks_mut(channels) {
+                            frame.fill(queue.pop_front().unwrap_or(0.0));
+                        }
+                    },
+                    |err| warn!("audio output failed: {err}"),
+                    None,
+                )
+                .map_err(|err| err.to_string())?;
+            stream.play().map_err(|err| err.to_string())?;
+
+            Ok(Self {
+                rate,
+                queue,
+                max_queued: (rate as f64 * MAX_LATENCY.as_secs_f64()) as usize,
+                _stream: stream,
+            })
+        }
+    }
+
+    impl AudioBackend for Speaker {
+        fn sample_rate(&self) -> u32 {
+            self.rate
+        }
+
+        fn play(&mut self, samples: &[f32]) {
+            // hold the cpu back until the speaker catches up
+            while self.queue.lock().unwrap().len() > self.max_queued {
+                thread::sleep(MAX_LATENCY / 10);
+            }
+            self.queue.lock().unwrap().extend(samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    #[test]
+    fn levels_are_averaged_over_each_sample() {
+        // 10 cycles a sample
+        let mut bridge = AudioBridge::new(1000, BufferBackend::new(100));
+        let beeper = bridge.channel();
+        let hum = bridge.channel();
+        bridge.set_level(hum, 0, 0.25);
+        bridge.set_level(beeper, 15, 0.5);
+        bridge.set_level(beeper, 30, -0.5);
+        bridge.advance(45);
+        bridge.flush();
+        assert_eq!(bridge.backend().samples, [0.25, 0.5, 0.75, -0.25]);
+    }
+
+    #[test]
+    fn beepers_flip_on_every_access() {
+        let audio = Rc::new(RefCell::new(AudioBridge::new(
+            1_000_000,
+            BufferBackend::new(1000),
+        )));
+        // touch the speaker every 10 instructions of TAX, 2 cycles each
+        let mut program = vec![LDA_ABS, 0x00, 0xD4];
+        program.extend([TAX; 10]);
+        program.extend([JMP_ABS, 0x00, 0x06]);
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, program)
+            .device(
+                BEEPER_BASE as usize,
+                1,
+                Rc::new(RefCell::new(Beeper::new(audio.clone()))),
+            )
+            .instruction_limit(12 * 2000)
+            .build()
+            .unwrap();
+
+        let _ = cpu.execute();
+        let bridge = audio.borrow();
+        let samples = &bridge.backend().samples;
+        // 27 cycles a period, so a millisecond sample averages out near 0
+        assert!(samples.len() >= 40);
+        assert!(samples.iter().all(|sample| sample.abs() < 0.05));
+    }
+}
@@ -0,0 +1,171 @@
+//! run many independent cpus over the same rom across threads, for fuzzing,
+//! test sweeps and brute-force searches over inputs
+//!
+//! every worker thread builds one cpu and snapshots it, each input then starts
+//! from that snapshot, is set up by the caller and runs until it halts
+//!
+//! ```
+//! use cpu_emu::{batch::Batch, memory::RomImage, op_codes::*};
+//!
+//! // LDA $10, LSR A, NOP
+//! let rom = RomImage::Static(&[LDA_ZP, 0x10, LSR_ACC, NOP]);
+//! let outcomes = Batch::new(rom, 0x0600)?.run(
+//!     &[2, 4, 8],
+//!     |cpu, input| cpu.memory.write_byte(0x10, *input),
+//!     |_| (),
+//! );
+//! let halves: Vec<u8> = outcomes.iter().map(|outcome| outcome.registers.a).collect();
+//! assert_eq!(halves, [1, 2, 4]);
+//! # Ok::<(), cpu_emu::BusError>(())
+//! ```
+
+use rayon::prelude::*;
+
+use crate::{
+    cpu::{Cpu, CpuBuilder, CpuError},
+    memory::{BusError, RomImage, MAX_MEM},
+    registers::Registers,
+};
+
+/// how one input's run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome<T> {
+    pub result: Result<(), CpuError>,
+    pub registers: Registers,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// whatever the caller pulled out of the cpu once it stopped
+    pub output: T,
+}
+
+/// a rom and the cpu configuration shared by every run
+#[derive(Debug, Clone)]
+pub struct Batch {
+    rom: RomImage,
+    address: u16,
+    pc: u16,
+    configure: fn(CpuBuilder) -> CpuBuilder,
+}
+
+impl Batch {
+    /// runs of `rom` mapped at `address`, starting from its first byte
+    /// fails if the rom runs past the end of memory
+    pub fn new(rom: RomImage, address: u16) -> Result<Self, BusError> {
+        if address as usize + rom.len() > MAX_MEM {
+            return Err(BusError::OutOfRange {
+                address: address as usize,
+                len: rom.len(),
+            });
+        }
+        Ok(Self {
+            rom,
+            address,
+            pc: address,
+            configure: |builder| builder,
+        })
+    }
+
+    /// address each run starts from
+    pub fn pc(mut self, pc: u16) -> Self {
+        self.pc = pc;
+        self
+    }
+
+    /// adjust the cpu every worker builds, e.g. to pick a variant or set an
+    /// instruction limit so runs that never halt stop
+    pub fn configure(mut self, configure: fn(CpuBuilder) -> CpuBuilder) -> Self {
+        self.configure = configure;
+        self
+    }
+
+    /// run once per input, in parallel, returning the outcomes in input order
+    /// `setup` prepares the fresh cpu for an input and `output` collects what
+    /// the caller wants from it once it stops
+    pub fn run<I, T>(
+        &self,
+        inputs: &[I],
+        setup: impl Fn(&mut Cpu, &I) + Sync,
+        output: impl Fn(&Cpu) -> T + Sync,
+    ) -> Vec<Outcome<T>>
+    where
+        I: Sync,
+        T: Send,
+    {
+        inputs
+            .par_iter()
+            .map_init(
+                || {
+                    let mut cpu = self.build();
+                    let snapshot = cpu.snapshot();
+                    (cpu, snapshot)
+                },
+                |(cpu, snapshot), input| {
+                    cpu.restore(snapshot);
+                    setup(cpu, input);
+                    let result = cpu.execute();
+                    Outcome {
+                        result,
+                        registers: cpu.registers(),
+                        cycles: cpu.cycles(),
+                        instructions: cpu.instructions(),
+                        output: output(cpu),
+                    }
+                },
+            )
+            .collect()
+    }
+
+    fn build(&self) -> Cpu {
+        (self.configure)(Cpu::builder())
+            .pc(self.pc)
+            .rom(self.address as usize, self.rom.clone())
+            .build()
+            .expect("the rom was checked to fit when the batch was made")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn runs_come_back_in_input_order() {
+        // LDX $10, LDA $10,X, NOP over a table at $0700
+        let mut rom = vec![0; 0x200];
+        rom[..5].copy_from_slice(&[LDX_ZP, 0x10, LDA_ABS_X, 0x00, 0x07]);
+        rom[5] = NOP;
+        for (i, byte) in rom[0x100..].iter_mut().enumerate() {
+            *byte = !(i as u8);
+        }
+        let batch = Batch::new(RomImage::Shared(rom.into()), 0x0600).unwrap();
+
+        let inputs: Vec<u8> = (0..=255).collect();
+        let outcomes = batch.run(
+            &inputs,
+            |cpu, input| cpu.memory.write_byte(0x10, *input),
+            |cpu| cpu.memory.read_byte(0x10),
+        );
+        for (input, outcome) in inputs.iter().zip(&outcomes) {
+            assert_eq!(outcome.result, Ok(()));
+            assert_eq!(outcome.output, *input);
+            assert_eq!(outcome.registers.a, !input);
+            assert_eq!(outcome.instructions, 2);
+        }
+    }
+
+    #[test]
+    fn runs_that_never_halt_stop_at_the_limit() {
+        let rom = RomImage::Static(&[JMP_ABS, 0x00, 0x06]);
+        let batch = Batch::new(rom, 0x0600)
+            .unwrap()
+            .configure(|builder| builder.instruction_limit(50));
+        let outcomes = batch.run(&[(); 4], |_, _| {}, |_| ());
+        assert!(Batch::new(RomImage::Static(&[NOP; 2]), 0xFFFF).is_err());
+        assert!(outcomes.iter().all(|outcome| outcome.result
+            == Err(CpuError::InstructionLimit {
+                limit: 50,
+                pc: 0x0600
+            })));
+    }
+}
@@ -0,0 +1,968 @@
+//! the VICE binary monitor protocol, so debuggers and IDE integrations built
+//! for VICE's `-binarymonitor` can drive the emulator
+//!
+//! commands are `02 02 <body len u32> <request id u32> <type u8> <body>` and
+//! every response is `02 02 <body len u32> <type u8> <error u8> <request id
+//! u32> <body>`, little endian throughout, events the client didn't ask for
+//! carry the request id `ffffffff`
+//!
+//! supported: memory get and set, checkpoints (get, set, delete, list and
+//! toggle), registers get and set, advance instructions, execute until
+//! return, ping, registers available, info, exit, quit and reset, conditions
+//! and banks aren't, memory get only reads devices when asked for side effects
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    rc::Rc,
+};
+
+use tracing::warn;
+
+use crate::{
+    cpu::{Cpu, RESET_VECTOR},
+    op_codes::{JSR, RTI, RTS},
+    processor_status::ProcessorStatus,
+    session::{WatchKind, Watcher},
+};
+
+/// address `cpu_emu binmon` listens on unless told otherwise, VICE's default
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6502";
+
+/// first byte of every command and response
+const STX: u8 = 0x02;
+
+/// the protocol version responses are sent with
+const API_VERSION: u8 = 0x02;
+
+/// the longest command body read, a memory set of all 64K and its fields,
+/// longer lengths are refused before anything is allocated for them
+const MAX_BODY_LEN: usize = 8 + 0x10000;
+
+/// request id of responses nobody asked for
+const EVENT: u32 = 0xFFFF_FFFF;
+
+/// instructions run between checks for a command while the cpu is running
+const RUN_SLICE: usize = 1000;
+
+const MEMORY_GET: u8 = 0x01;
+const MEMORY_SET: u8 = 0x02;
+const CHECKPOINT_GET: u8 = 0x11;
+const CHECKPOINT_SET: u8 = 0x12;
+const CHECKPOINT_DELETE: u8 = 0x13;
+const CHECKPOINT_LIST: u8 = 0x14;
+const CHECKPOINT_TOGGLE: u8 = 0x15;
+const REGISTERS_GET: u8 = 0x31;
+const REGISTERS_SET: u8 = 0x32;
+const STOPPED: u8 = 0x62;
+const RESUMED: u8 = 0x63;
+const ADVANCE_INSTRUCTIONS: u8 = 0x71;
+const EXECUTE_UNTIL_RETURN: u8 = 0x73;
+const PING: u8 = 0x81;
+const REGISTERS_AVAILABLE: u8 = 0x83;
+const INFO: u8 = 0x85;
+const EXIT: u8 = 0xAA;
+const QUIT: u8 = 0xBB;
+const RESET: u8 = 0xCC;
+
+const OK: u8 = 0x00;
+const NOT_FOUND: u8 = 0x01;
+const INVALID_MEMSPACE: u8 = 0x02;
+const BAD_LENGTH: u8 = 0x80;
+const INVALID_PARAMETER: u8 = 0x81;
+const UNSUPPORTED_VERSION: u8 = 0x82;
+const UNKNOWN_COMMAND: u8 = 0x83;
+
+/// checkpoint operations, a checkpoint can have several
+const LOAD: u8 = 0x01;
+const STORE: u8 = 0x02;
+const EXEC: u8 = 0x04;
+
+/// the main computer's memspace, the only one there is
+const MAIN_MEMSPACE: u8 = 0x00;
+
+/// register ids, names and widths in bits as reported by registers available
+const REGISTERS: [(u8, &str, u8); 6] = [
+    (0x00, "A", 8),
+    (0x01, "X", 8),
+    (0x02, "Y", 8),
+    (0x03, "PC", 16),
+    (0x04, "SP", 8),
+    (0x05, "FL", 8),
+];
+
+/// a request from the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub version: u8,
+    pub id: u32,
+    pub kind: u8,
+    pub body: Vec<u8>,
+}
+
+impl Command {
+    pub fn new(id: u32, kind: u8, body: Vec<u8>) -> Self {
+        Self {
+            version: API_VERSION,
+            id,
+            kind,
+            body,
+        }
+    }
+
+    /// read the next command, failing on anything that isn't one
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0; 11];
+        reader.read_exact(&mut header)?;
+        if header[0] != STX {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("commands start with ${STX:02X}, not ${:02X}", header[0]),
+            ));
+        }
+        let len = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+        if len > MAX_BODY_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("a {len} byte command body is longer than the {MAX_BODY_LEN} allowed"),
+            ));
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        Ok(Self {
+            version: header[1],
+            id: u32::from_le_bytes(header[6..10].try_into().unwrap()),
+            kind: header[10],
+            body,
+        })
+    }
+
+    /// the command as sent on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![STX, self.version];
+        bytes.extend((self.body.len() as u32).to_le_bytes());
+        bytes.extend(self.id.to_le_bytes());
+        bytes.push(self.kind);
+        bytes.extend(&self.body);
+        bytes
+    }
+}
+
+/// an answer to a command, or an event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub kind: u8,
+    pub error: u8,
+    /// the command's request id, `ffffffff` for events
+    pub id: u32,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(kind: u8, id: u32, body: Vec<u8>) -> Self {
+        Self {
+            kind,
+            error: OK,
+            id,
+            body,
+        }
+    }
+
+    fn error(kind: u8, id: u32, error: u8) -> Self {
+        Self {
+            kind,
+            error,
+            id,
+            body: Vec::new(),
+        }
+    }
+
+    /// the response as sent on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![STX, API_VERSION];
+        bytes.extend((self.body.len() as u32).to_le_bytes());
+        bytes.push(self.kind);
+        bytes.push(self.error);
+        bytes.extend(self.id.to_le_bytes());
+        bytes.extend(&self.body);
+        bytes
+    }
+}
+
+/// reads the fields of a command body in order
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let (first, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(*first)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes([
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+        ]))
+    }
+
+    fn rest(&mut self) -> &[u8] {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// a breakpoint or watchpoint over a range of addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Checkpoint {
+    number: u32,
+    start: u16,
+    end: u16,
+    /// stop the cpu on a hit, otherwise hits are only counted
+    stop: bool,
+    enabled: bool,
+    operation: u8,
+    /// deleted after its first hit
+    temporary: bool,
+    hits: u32,
+}
+
+impl Checkpoint {
+    fn covers(&self, address: u16, operation: u8) -> bool {
+        self.enabled
+            && self.operation & operation != 0
+            && (self.start..=self.end).contains(&address)
+    }
+
+    fn info(&self, id: u32, hit: bool) -> Response {
+        let mut body = self.number.to_le_bytes().to_vec();
+        body.push(hit as u8);
+        body.extend(self.start.to_le_bytes());
+        body.extend(self.end.to_le_bytes());
+        body.extend([
+            self.stop as u8,
+            self.enabled as u8,
+            self.operation,
+            self.temporary as u8,
+        ]);
+        body.extend(self.hits.to_le_bytes());
+        // ignore count, has condition and memspace
+        body.extend(0u32.to_le_bytes());
+        body.extend([0, MAIN_MEMSPACE]);
+        Response::ok(CHECKPOINT_GET, id, body)
+    }
+}
+
+/// what the cpu is doing while the monitor isn't stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goal {
+    /// run until a checkpoint stops it or a command arrives
+    Resume,
+    /// run `remaining` instructions, finishing a subroutine called from the
+    /// current one first when stepping over
+    Advance {
+        remaining: u16,
+        step_over: bool,
+        returns_to: Option<u16>,
+    },
+    /// run until the current subroutine returns, `depth` counts subroutines
+    /// called from it that haven't returned yet
+    Return { depth: u32 },
+}
+
+/// a cpu and the commands that drive it, separate from the socket so it can be
+/// tested without one
+pub struct BinaryMonitor {
+    cpu: Cpu,
+    checkpoints: BTreeMap<u32, Checkpoint>,
+    next_checkpoint: u32,
+    watcher: Rc<RefCell<Watcher>>,
+    /// none while stopped
+    goal: Option<Goal>,
+    /// the pc execution resumed from, so a breakpoint there doesn't stop it
+    /// straight away
+    resumed_at: Option<u16>,
+    quit: bool,
+}
+
+impl BinaryMonitor {
+    /// a monitor with the cpu stopped
+    pub fn new(mut cpu: Cpu) -> Self {
+        let watcher = Rc::new(RefCell::new(Watcher::default()));
+        cpu.subscribe(watcher.clone());
+        Self {
+            cpu,
+            checkpoints: BTreeMap::new(),
+            next_checkpoint: 1,
+            watcher,
+            goal: None,
+            resumed_at: None,
+            quit: false,
+        }
+    }
+
+    /// the cpu being driven
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// whether the cpu is executing rather than waiting for commands
+    pub fn running(&self) -> bool {
+        self.goal.is_some()
+    }
+
+    /// whether the client asked the emulator to quit
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// stop the cpu, returning the stopped event when it was running
+    pub fn stop(&mut self) -> Vec<Response> {
+        match self.goal.take() {
+            Some(_) => vec![self.stopped()],
+            None => Vec::new(),
+        }
+    }
+
+    /// answer a command, some answers are followed by events
+    pub fn handle(&mut self, command: &Command) -> Vec<Response> {
+        let id = command.id;
+        if !(1..=API_VERSION).contains(&command.version) {
+            return vec![Response::error(command.kind, id, UNSUPPORTED_VERSION)];
+        }
+        let mut fields = Fields(&command.body);
+        let responses = match command.kind {
+            MEMORY_GET => self.memory_get(id, &mut fields),
+            MEMORY_SET => self.memory_set(id, &mut fields),
+            CHECKPOINT_GET => fields
+                .u32()
+                .map(|number| match self.checkpoints.get(&number) {
+                    Some(checkpoint) => vec![checkpoint.info(id, false)],
+                    None => vec![Response::error(CHECKPOINT_GET, id, NOT_FOUND)],
+                }),
+            CHECKPOINT_SET => self.checkpoint_set(id, &mut fields),
+            CHECKPOINT_DELETE => fields.u32().map(|number| {
+                let deleted = self.checkpoints.remove(&number).is_some();
+                self.watch_checkpoints();
+                match deleted {
+                    true => vec![Response::ok(CHECKPOINT_DELETE, id, Vec::new())],
+                    false => vec![Response::error(CHECKPOINT_DELETE, id, NOT_FOUND)],
+                }
+            }),
+            CHECKPOINT_LIST => {
+                let mut responses: Vec<Response> = self
+                    .checkpoints
+                    .values()
+                    .map(|checkpoint| checkpoint.info(id, false))
+                    .collect();
+                let count = (responses.len() as u32).to_le_bytes().to_vec();
+                responses.push(Response::ok(CHECKPOINT_LIST, id, count));
+                Some(responses)
+            }
+            CHECKPOINT_TOGGLE => (|| {
+                let number = fields.u32()?;
+                let enabled = fields.u8()? != 0;
+                let Some(checkpoint) = self.checkpoints.get_mut(&number) else {
+                    return Some(vec![Response::error(CHECKPOINT_TOGGLE, id, NOT_FOUND)]);
+                };
+                checkpoint.enabled = enabled;
+                self.watch_checkpoints();
+                Some(vec![Response::ok(CHECKPOINT_TOGGLE, id, Vec::new())])
+            })(),
+            REGISTERS_GET => fields.u8().map(|memspace| {
+                vec![self.with_memspace(REGISTERS_GET, id, memspace, Self::registers)]
+            }),
+            REGISTERS_SET => self.registers_set(id, &mut fields),
+            ADVANCE_INSTRUCTIONS => (|| {
+                let step_over = fields.u8()? != 0;
+                let remaining = fields.u16()?;
+                Some(self.resume(
+                    ADVANCE_INSTRUCTIONS,
+                    id,
+                    Goal::Advance {
+                        remaining,
+                        step_over,
+                        returns_to: None,
+                    },
+                ))
+            })(),
+            EXECUTE_UNTIL_RETURN => {
+                Some(self.resume(EXECUTE_UNTIL_RETURN, id, Goal::Return { depth: 0 }))
+            }
+            PING => Some(vec![Response::ok(PING, id, Vec::new())]),
+            REGISTERS_AVAILABLE => fields.u8().map(|memspace| {
+                vec![
+                    self.with_memspace(REGISTERS_AVAILABLE, id, memspace, |_, id| {
+                        registers_available(id)
+                    }),
+                ]
+            }),
+            INFO => {
+                // version 3.7.0.0 and no svn revision
+                let body = vec![4, 3, 7, 0, 0, 4, 0, 0, 0, 0];
+                Some(vec![Response::ok(INFO, id, body)])
+            }
+            EXIT => Some(self.resume(EXIT, id, Goal::Resume)),
+            QUIT => {
+                self.quit = true;
+                Some(vec![Response::ok(QUIT, id, Vec::new())])
+            }
+            RESET => {
+                let vector = self.cpu.memory.read_word(RESET_VECTOR as usize);
+                self.cpu.reset(Some(vector));
+                Some(vec![Response::ok(RESET, id, Vec::new())])
+            }
+            kind => Some(vec![Response::error(kind, id, UNKNOWN_COMMAND)]),
+        };
+        responses.unwrap_or_else(|| vec![Response::error(command.kind, id, BAD_LENGTH)])
+    }
+
+    /// answer with `answer` if the command is for the main memspace
+    fn with_memspace(
+        &self,
+        kind: u8,
+        id: u32,
+        memspace: u8,
+        answer: impl Fn(&Self, u32) -> Response,
+    ) -> Response {
+        match memspace {
+            MAIN_MEMSPACE => answer(self, id),
+            _ => Response::error(kind, id, INVALID_MEMSPACE),
+        }
+    }
+
+    fn memory_get(&self, id: u32, fields: &mut Fields) -> Option<Vec<Response>> {
+        let side_effects = fields.u8()? != 0;
+        let start = fields.u16()?;
+        let end = fields.u16()?;
+        let memspace = fields.u8()?;
+        let _bank = fields.u16()?;
+        if start > end {
+            return Some(vec![Response::error(MEMORY_GET, id, INVALID_PARAMETER)]);
+        }
+        Some(vec![self.with_memspace(
+            MEMORY_GET,
+            id,
+            memspace,
+            |monitor, id| {
+                // a length of 0 means all 64k
+                let mut body = ((end - start) as u32 + 1).to_le_bytes()[..2].to_vec();
+                let memory = &monitor.cpu.memory;
+                body.extend((start..=end).map(|address| match side_effects {
+                    true => memory.read_byte(address as usize),
+                    false => memory.peek_byte(address as usize),
+                }));
+                Response::ok(MEMORY_GET, id, body)
+            },
+        )])
+    }
+
+    fn memory_set(&mut self, id: u32, fields: &mut Fields) -> Option<Vec<Response>> {
+        let _side_effects = fields.u8()?;
+        let start = fields.u16()?;
+        let end = fields.u16()?;
+        let memspace = fields.u8()?;
+        let _bank = fields.u16()?;
+        let bytes = fields.rest();
+        if end < start || bytes.len() != (end - start) as usize + 1 {
+            return None;
+        }
+        if memspace != MAIN_MEMSPACE {
+            return Some(vec![Response::error(MEMORY_SET, id, INVALID_MEMSPACE)]);
+        }
+        for (address, byte) in (start..=end).zip(bytes) {
+            self.cpu.memory.write_byte(address as usize, *byte);
+        }
+        Some(vec![Response::ok(MEMORY_SET, id, Vec::new())])
+    }
+
+    fn checkpoint_set(&mut self, id: u32, fields: &mut Fields) -> Option<Vec<Response>> {
+        let start = fields.u16()?;
+        let end = fields.u16()?;
+        let stop = fields.u8()? != 0;
+        let enabled = fields.u8()? != 0;
+        let operation = fields.u8()?;
+        let temporary = fields.u8()? != 0;
+        let memspace = fields.u8().unwrap_or(MAIN_MEMSPACE);
+        if memspace != MAIN_MEMSPACE {
+            return Some(vec![Response::error(CHECKPOINT_SET, id, INVALID_MEMSPACE)]);
+        }
+        if start > end || operation & (LOAD | STORE | EXEC) == 0 {
+            return Some(vec![Response::error(CHECKPOINT_SET, id, INVALID_PARAMETER)]);
+        }
+
+        let checkpoint = Checkpoint {
+            number: self.next_checkpoint,
+            start,
+            end,
+            stop,
+            enabled,
+            operation,
+            temporary,
+            hits: 0,
+        };
+        self.next_checkpoint += 1;
+        let response = checkpoint.info(id, false);
+        self.checkpoints.insert(checkpoint.number, checkpoint);
+        self.watch_checkpoints();
+        Some(vec![response])
+    }
+
+    /// point the watcher at every address an enabled load or store checkpoint covers
+    fn watch_checkpoints(&mut self) {
+        let mut watcher = self.watcher.borrow_mut();
+        watcher.watchpoints.clear();
+        for checkpoint in self
+            .checkpoints
+            .values()
+            .filter(|checkpoint| checkpoint.enabled)
+        {
+            let kind = match (
+                checkpoint.operation & LOAD != 0,
+                checkpoint.operation & STORE != 0,
+            ) {
+                (true, true) => WatchKind::Any,
+                (true, false) => WatchKind::Load,
+                (false, true) => WatchKind::Store,
+                (false, false) => continue,
+            };
+            for address in checkpoint.start..=checkpoint.end {
+                watcher
+                    .watchpoints
+                    .entry(address)
+                    .and_modify(|watched| {
+                        if *watched != kind {
+                            *watched = WatchKind::Any
+                        }
+                    })
+                    .or_insert(kind);
+            }
+        }
+    }
+
+    fn registers(&self, id: u32) -> Response {
+        let cpu = &self.cpu;
+        let values = [
+            cpu.a() as u16,
+            cpu.x() as u16,
+            cpu.y() as u16,
+            cpu.pc(),
+            cpu.sp() & 0xFF,
+            cpu.status().bits() as u16,
+        ];
+        let mut body = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+        for ((register, _, _), value) in REGISTERS.iter().zip(values) {
+            body.extend([3, *register]);
+            body.extend(value.to_le_bytes());
+        }
+        Response::ok(REGISTERS_GET, id, body)
+    }
+
+    fn registers_set(&mut self, id: u32, fields: &mut Fields) -> Option<Vec<Response>> {
+        let memspace = fields.u8()?;
+        let count = fields.u16()?;
+        let mut values = Vec::new();
+        for _ in 0..count {
+            let size = fields.u8()?;
+            if size < 3 {
+                return None;
+            }
+            let register = fields.u8()?;
+            let value = fields.u16()?;
+            for _ in 3..size {
+                fields.u8()?;
+            }
+            values.push((register, value));
+        }
+        if memspace != MAIN_MEMSPACE {
+            return Some(vec![Response::error(REGISTERS_SET, id, INVALID_MEMSPACE)]);
+        }
+        if values
+            .iter()
+            .any(|(register, _)| *register as usize >= REGISTERS.len())
+        {
+            return Some(vec![Response::error(REGISTERS_SET, id, INVALID_PARAMETER)]);
+        }
+
+        for (register, value) in values {
+            let byte = value as u8;
+            match register {
+                0x00 => self.cpu.set_a(byte),
+                0x01 => self.cpu.set_x(byte),
+                0x02 => self.cpu.set_y(byte),
+                0x03 => self.cpu.set_pc(value),
+                0x04 => self.cpu.set_sp(0x0100 | byte as u16),
+                _ => self
+                    .cpu
+                    .set_status(ProcessorStatus::from_bits_truncate(byte)),
+            }
+        }
+        Some(vec![self.registers(id)])
+    }
+
+    /// answer a command that starts the cpu running
+    fn resume(&mut self, kind: u8, id: u32, goal: Goal) -> Vec<Response> {
+        self.goal = Some(goal);
+        self.resumed_at = Some(self.cpu.pc());
+        let resumed = Response::ok(RESUMED, EVENT, self.cpu.pc().to_le_bytes().to_vec());
+        vec![Response::ok(kind, id, Vec::new()), resumed]
+    }
+
+    fn stopped(&self) -> Response {
+        Response::ok(STOPPED, EVENT, self.cpu.pc().to_le_bytes().to_vec())
+    }
+
+    /// run up to `steps` instructions while the cpu is running, returning the
+    /// events to send when something stopped it
+    pub fn run(&mut self, steps: usize) -> Vec<Response> {
+        let mut events = Vec::new();
+        for _ in 0..steps {
+            let Some(goal) = self.goal else {
+                break;
+            };
+            let pc = self.cpu.pc();
+            if self.resumed_at.take() != Some(pc) && self.hit(pc, EXEC, &mut events) {
+                break;
+            }
+
+            let opcode = self.cpu.memory.read_byte(pc as usize);
+            let result = self.cpu.step();
+            self.goal = self.advance(goal, opcode, pc);
+
+            let watched = self.watcher.borrow_mut().hit.take();
+            if watched.is_some_and(|address| self.hit(address, LOAD | STORE, &mut events)) {
+                break;
+            }
+            match result {
+                Ok(true) => {}
+                Ok(false) => self.goal = None,
+                Err(err) => {
+                    warn!("stopped on {err}");
+                    self.goal = None;
+                }
+            }
+            if self.goal.is_none() {
+                events.push(self.stopped());
+                break;
+            }
+        }
+        events
+    }
+
+    /// the goal after executing `opcode` at `pc`, none once it's reached
+    fn advance(&self, goal: Goal, opcode: u8, pc: u16) -> Option<Goal> {
+        match goal {
+            Goal::Resume => Some(goal),
+            Goal::Advance {
+                remaining,
+                step_over,
+                returns_to,
+            } => {
+                let returns_to = match returns_to {
+                    None if step_over && opcode == JSR => Some(pc.wrapping_add(3)),
+                    Some(address) if address == self.cpu.pc() => None,
+                    returns_to => returns_to,
+                };
+                let remaining = match returns_to {
+                    Some(_) => remaining,
+                    None => remaining.saturating_sub(1),
+                };
+                (remaining > 0).then_some(Goal::Advance {
+                    remaining,
+                    step_over,
+                    returns_to,
+                })
+            }
+            Goal::Return { depth } => match opcode {
+                JSR => Some(Goal::Return { depth: depth + 1 }),
+                RTS | RTI if depth == 0 => None,
+                RTS | RTI => Some(Goal::Return { depth: depth - 1 }),
+                _ => Some(goal),
+            },
+        }
+    }
+
+    /// count hits on checkpoints covering the access, stopping the cpu and
+    /// queuing events for those that stop it, returns whether any did
+    fn hit(&mut self, address: u16, operation: u8, events: &mut Vec<Response>) -> bool {
+        let mut stopped = false;
+        let mut expired = Vec::new();
+        for checkpoint in self.checkpoints.values_mut() {
+            if !checkpoint.covers(address, operation) {
+                continue;
+            }
+            checkpoint.hits += 1;
+            if checkpoint.stop {
+                events.push(checkpoint.info(EVENT, true));
+                stopped = true;
+            }
+            if checkpoint.temporary {
+                expired.push(checkpoint.number);
+            }
+        }
+        for number in &expired {
+            self.checkpoints.remove(number);
+        }
+        if !expired.is_empty() {
+            self.watch_checkpoints();
+        }
+        if stopped {
+            self.goal = None;
+            events.push(self.stopped());
+        }
+        stopped
+    }
+}
+
+fn registers_available(id: u32) -> Response {
+    let mut body = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+    for (register, name, bits) in REGISTERS {
+        body.extend([3 + name.len() as u8, register, bits, name.len() as u8]);
+        body.extend(name.bytes());
+    }
+    Response::ok(REGISTERS_AVAILABLE, id, body)
+}
+
+/// accept clients on `listener` one at a time and answer their commands until
+/// one of them asks to quit
+pub fn serve(listener: TcpListener, cpu: Cpu) -> io::Result<()> {
+    let mut monitor = BinaryMonitor::new(cpu);
+    for stream in listener.incoming() {
+        match connect(&mut monitor, &mut stream?) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {}
+            Err(err) => warn!("client dropped: {err}"),
+        }
+        if monitor.quit() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn connect(monitor: &mut BinaryMonitor, stream: &mut TcpStream) -> io::Result<()> {
+    let send = |stream: &mut TcpStream, responses: Vec<Response>| {
+        responses
+            .iter()
+            .try_for_each(|response| stream.write_all(&response.to_bytes()))
+    };
+
+    while !monitor.quit() {
+        if monitor.running() {
+            // any command stops the cpu before it's answered
+            stream.set_nonblocking(true)?;
+            let waiting = stream.peek(&mut [0]);
+            stream.set_nonblocking(false)?;
+            match waiting {
+                Ok(0) => return Ok(()),
+                Ok(_) => send(stream, monitor.stop())?,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    send(stream, monitor.run(RUN_SLICE))?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let command = Command::read(stream)?;
+        send(stream, monitor.handle(&command))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{device::Device, op_codes::*, testing::DEFAULT_ORIGIN};
+
+    fn monitor() -> BinaryMonitor {
+        let cpu = Cpu::builder()
+            .pc(DEFAULT_ORIGIN)
+            .sp(0x01FF)
+            .memory(
+                DEFAULT_ORIGIN as usize,
+                vec![LDA_IM, 0x42, PHA, LDX_IM, 0x07, NOP],
+            )
+            .build()
+            .unwrap();
+        BinaryMonitor::new(cpu)
+    }
+
+    fn checkpoint(start: u16, end: u16, operation: u8) -> Command {
+        let mut body = start.to_le_bytes().to_vec();
+        body.extend(end.to_le_bytes());
+        body.extend([1, 1, operation, 0]);
+        Command::new(2, CHECKPOINT_SET, body)
+    }
+
+    #[test]
+    fn commands_and_responses_on_the_wire() {
+        let command = Command::new(7, PING, Vec::new());
+        let bytes = command.to_bytes();
+        assert_eq!(bytes, [2, 2, 0, 0, 0, 0, 7, 0, 0, 0, 0x81]);
+        assert_eq!(Command::read(&mut &bytes[..]).unwrap(), command);
+        assert!(Command::read(&mut &[0u8; 11][..]).is_err());
+        let huge = [2, 2, 0xFF, 0xFF, 0xFF, 0xFF, 7, 0, 0, 0, 0x81];
+        let err = Command::read(&mut &huge[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let responses = monitor().handle(&command);
+        assert_eq!(
+            responses[0].to_bytes(),
+            [2, 2, 0, 0, 0, 0, 0x81, 0, 7, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn memory_and_registers() {
+        let mut monitor = monitor();
+        let get = Command::new(1, MEMORY_GET, vec![0, 0x00, 0x06, 0x01, 0x06, 0, 0, 0]);
+        assert_eq!(monitor.handle(&get)[0].body, [2, 0, LDA_IM, 0x42]);
+
+        let set = Command::new(
+            1,
+            MEMORY_SET,
+            vec![0, 0x01, 0x06, 0x01, 0x06, 0, 0, 0, 0x99],
+        );
+        assert_eq!(monitor.handle(&set)[0].error, OK);
+        assert_eq!(monitor.cpu().memory.read_byte(0x0601), 0x99);
+        let short = Command::new(
+            1,
+            MEMORY_SET,
+            vec![0, 0x01, 0x06, 0x02, 0x06, 0, 0, 0, 0x99],
+        );
+        assert_eq!(monitor.handle(&short)[0].error, BAD_LENGTH);
+
+        // set x and the pc
+        let registers = Command::new(
+            3,
+            REGISTERS_SET,
+            vec![0, 2, 0, 3, 0x01, 0x12, 0x00, 3, 0x03, 0x02, 0x06],
+        );
+        let response = &monitor.handle(&registers)[0];
+        assert_eq!(response.kind, REGISTERS_GET);
+        assert_eq!(monitor.cpu().x(), 0x12);
+        assert_eq!(monitor.cpu().pc(), 0x0602);
+        assert_eq!(response.body[..6], [6, 0, 3, 0x00, 0, 0]);
+
+        assert_eq!(
+            monitor.handle(&Command::new(4, 0x42, Vec::new()))[0].error,
+            UNKNOWN_COMMAND
+        );
+        assert_eq!(
+            monitor.handle(&Command::new(4, REGISTERS_GET, vec![1]))[0].error,
+            INVALID_MEMSPACE
+        );
+    }
+
+    /// counts up on every read, like a register that clears when read
+    struct Counter(u8);
+
+    impl Device for Counter {
+        fn read(&mut self, _offset: u16) -> u8 {
+            self.0 += 1;
+            self.0
+        }
+
+        fn write(&mut self, _offset: u16, _value: u8) {}
+    }
+
+    #[test]
+    fn memory_get_reads_devices_only_for_side_effects() {
+        let counter = Rc::new(RefCell::new(Counter(0)));
+        let cpu = Cpu::builder()
+            .device(0xD000, 1, counter.clone())
+            .build()
+            .unwrap();
+        let mut monitor = BinaryMonitor::new(cpu);
+
+        let get = |side_effects| {
+            Command::new(
+                1,
+                MEMORY_GET,
+                vec![side_effects, 0x00, 0xD0, 0x00, 0xD0, 0, 0, 0],
+            )
+        };
+        assert_eq!(monitor.handle(&get(0))[0].body, [1, 0, 0]);
+        assert_eq!(counter.borrow().0, 0);
+        assert_eq!(monitor.handle(&get(1))[0].body, [1, 0, 1]);
+        assert_eq!(counter.borrow().0, 1);
+    }
+
+    #[test]
+    fn checkpoints_stop_execution() {
+        let mut monitor = monitor();
+        let set = monitor.handle(&checkpoint(0x0605, 0x0605, EXEC));
+        assert_eq!(set[0].body[..4], 1u32.to_le_bytes());
+        monitor.handle(&checkpoint(0x01FF, 0x01FF, STORE));
+
+        let exit = monitor.handle(&Command::new(5, EXIT, Vec::new()));
+        assert_eq!(exit[1].kind, RESUMED);
+
+        // the push trips the store checkpoint first
+        let events = monitor.run(100);
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].kind, events[0].id), (CHECKPOINT_GET, EVENT));
+        assert_eq!(events[0].body[..5], [2, 0, 0, 0, 1]);
+        assert_eq!(events[1].kind, STOPPED);
+        assert_eq!(events[1].body, 0x0603u16.to_le_bytes());
+
+        // resuming runs on to the breakpoint, then to the halt
+        monitor.handle(&Command::new(6, EXIT, Vec::new()));
+        let events = monitor.run(100);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].body, 0x0605u16.to_le_bytes());
+        assert_eq!(monitor.cpu().x(), 0x07);
+        monitor.handle(&Command::new(7, EXIT, Vec::new()));
+        assert_eq!(monitor.run(100).len(), 1);
+        assert!(!monitor.running());
+
+        let list = monitor.handle(&Command::new(8, CHECKPOINT_LIST, Vec::new()));
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[2].body, 2u32.to_le_bytes());
+        // the breakpoint was hit once, resuming from it doesn't count
+        assert_eq!(list[0].body[13..17], 1u32.to_le_bytes());
+    }
+
+    #[test]
+    fn advance_instructions() {
+        let mut monitor = monitor();
+        let responses = monitor.handle(&Command::new(1, ADVANCE_INSTRUCTIONS, vec![0, 2, 0]));
+        assert_eq!(responses[0].kind, ADVANCE_INSTRUCTIONS);
+        let events = monitor.run(100);
+        assert_eq!(events[0].kind, STOPPED);
+        assert_eq!(monitor.cpu().pc(), 0x0603);
+        assert!(!monitor.running());
+    }
+
+    #[test]
+    fn serves_a_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let cpu = Cpu::new().reset(Some(DEFAULT_ORIGIN));
+            serve(listener, cpu).is_ok()
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(&Command::new(9, PING, Vec::new()).to_bytes())
+            .unwrap();
+        let mut response = [0; 12];
+        client.read_exact(&mut response).unwrap();
+        assert_eq!(response[6..], [PING, OK, 9, 0, 0, 0]);
+
+        client
+            .write_all(&Command::new(10, QUIT, Vec::new()).to_bytes())
+            .unwrap();
+        client.read_exact(&mut response).unwrap();
+        assert_eq!(response[6], QUIT);
+        assert!(server.join().unwrap());
+    }
+}
@@ -0,0 +1,275 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    cpu::{handlers, Handler, Variant},
+    memory::Memory,
+    op_codes::{self, *},
+};
+
+/// longest run of instructions decoded into a single block
+const MAX_BLOCK_LEN: usize = 64;
+
+/// number of 256 byte pages in the address space
+const PAGES: usize = 256;
+
+/// an instruction decoded ahead of time
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decoded {
+    /// address of the opcode
+    pub pc: u16,
+    pub opcode: u8,
+    /// the operand bytes, low byte first, so the handler doesn't fetch them
+    pub operand: u16,
+    pub operand_len: u8,
+    pub handler: Handler,
+    pub cycles: u8,
+}
+
+/// a straight-line run of instructions
+/// blocks end after a jump, or before an instruction that has to go through `Cpu::step`
+#[derive(Debug)]
+pub(crate) struct Block {
+    pub start: u16,
+    /// address one past the last byte of the block
+    pub end: u32,
+    pub instructions: Vec<Decoded>,
+}
+
+/// cache of predecoded blocks keyed by start address
+/// writes to memory holding cached code, operands included, invalidate the
+/// blocks covering it
+#[derive(Debug, Clone)]
+pub struct BlockCache {
+    blocks: HashMap<u16, Rc<Block>>,
+    /// number of cached blocks overlapping each page, used to spot self-modifying code
+    code_pages: Vec<u16>,
+    /// bumped whenever blocks are invalidated so a running block knows to stop
+    generation: u64,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            code_pages: vec![0; PAGES],
+            generation: 0,
+        }
+    }
+}
+
+impl BlockCache {
+    /// number of blocks currently cached
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// drop every cached block
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.code_pages.iter_mut().for_each(|count| *count = 0);
+        self.generation += 1;
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// the block starting at `pc`, decoding it if it isn't cached
+    pub(crate) fn get(&mut self, pc: u16, memory: &Memory, variant: Variant) -> Rc<Block> {
+        if let Some(block) = self.blocks.get(&pc) {
+            return block.clone();
+        }
+
+        let block = Rc::new(decode(pc, memory, variant));
+        for page in pages(&block) {
+            self.code_pages[page] += 1;
+        }
+        self.blocks.insert(pc, block.clone());
+        block
+    }
+
+    /// drop the blocks overlapping a 256 byte page
+    pub(crate) fn invalidate_page(&mut self, page: usize) {
+        if self.code_pages[page] == 0 {
+            return;
+        }
+
+        let code_pages = &mut self.code_pages;
+        self.blocks.retain(|_, block| {
+            let stale = pages(block).contains(&page);
+            if stale {
+                for page in pages(block) {
+                    code_pages[page] -= 1;
+                }
+            }
+            !stale
+        });
+        self.generation += 1;
+    }
+
+    /// called on every memory write, drops blocks containing the written address
+    pub(crate) fn invalidate(&mut self, address: u16) {
+        if self.code_pages[address as usize >> 8] == 0 {
+            return;
+        }
+
+        let address = address as u32;
+        let cached = self.blocks.len();
+        let code_pages = &mut self.code_pages;
+        self.blocks.retain(|_, block| {
+            let stale = (block.start as u32..block.end).contains(&address);
+            if stale {
+                for page in pages(block) {
+                    code_pages[page] -= 1;
+                }
+            }
+            !stale
+        });
+
+        if self.blocks.len() != cached {
+            self.generation += 1;
+        }
+    }
+}
+
+/// pages a block overlaps
+fn pages(block: &Block) -> std::ops::RangeInclusive<usize> {
+    let last = block.end.max(block.start as u32 + 1) - 1;
+    (block.start as usize >> 8)..=(last as usize >> 8)
+}
+
+/// decode instructions starting at `pc` until the block has to end
+fn decode(pc: u16, memory: &Memory, variant: Variant) -> Block {
+    #[cfg(feature = "self-profile")]
+    crate::self_profile::record(|counters| counters.blocks_decoded += 1);
+    let mut instructions = Vec::new();
+    let mut address = pc as u32;
+
+    while instructions.len() < MAX_BLOCK_LEN {
+        // code a device answers for can change without a write to invalidate it
+        if memory.is_device(address as usize) {
+            break;
+        }
+        let opcode = memory.peek_byte(address as usize);
+        // BRK can be a software breakpoint, which only `Cpu::step` looks for
+        if opcode == BRK {
+            break;
+        }
+        let (Some(handler), Some(info)) = (
+            handlers(variant)[opcode as usize],
+            op_codes::instruction(opcode),
+        ) else {
+            break;
+        };
+
+        // leave instructions that wrap around the address space to `Cpu::step`
+        let next = address + info.size() as u32;
+        if next > u16::MAX as u32 || (address + 1..next).any(|at| memory.is_device(at as usize)) {
+            break;
+        }
+        let operand_len = info.mode.operand_len();
+        let operand = (1..=operand_len).rev().fold(0, |operand, offset| {
+            operand << 8 | memory.peek_byte(address as usize + offset) as u16
+        });
+
+        instructions.push(Decoded {
+            pc: address as u16,
+            opcode,
+            operand,
+            operand_len: operand_len as u8,
+            handler,
+            cycles: info.cycles,
+        });
+        address = next;
+
+        if info.opcode.is_branch()
+            || matches!(
+                info.opcode,
+                Opcode::JmpAbs | Opcode::JmpAbsInd | Opcode::Jsr | Opcode::Rts | Opcode::Rti
+            )
+        {
+            break;
+        }
+    }
+
+    Block {
+        start: pc,
+        end: address,
+        instructions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_with(address: usize, program: &[u8]) -> Memory {
+        let mut memory = Memory::default();
+        memory.write_bytes(address, program).unwrap();
+        memory
+    }
+
+    #[test]
+    fn decode_stops_after_jumps() {
+        let memory = memory_with(0x0600, &[LDA_IM, 0x01, TAX, JMP_ABS, 0x00, 0x06, TAY]);
+        let block = decode(0x0600, &memory, Variant::Nmos);
+
+        let pcs: Vec<u16> = block.instructions.iter().map(|d| d.pc).collect();
+        assert_eq!(pcs, vec![0x0600, 0x0602, 0x0603]);
+        assert_eq!(block.end, 0x0606);
+    }
+
+    #[test]
+    fn decode_reads_operands_and_leaves_device_code_alone() {
+        let mut memory = memory_with(0x0600, &[LDA_IM, 0x42, LDX_ABS, 0x34, 0x12, TAX]);
+        let ram = crate::machine::SharedRam::new(1);
+        memory
+            .map_device(0x0605, 1, std::rc::Rc::new(std::cell::RefCell::new(ram)))
+            .unwrap();
+        let block = decode(0x0600, &memory, Variant::Nmos);
+
+        let decoded: Vec<(u8, u16, u8)> = block
+            .instructions
+            .iter()
+            .map(|d| (d.opcode, d.operand, d.operand_len))
+            .collect();
+        assert_eq!(decoded, vec![(LDA_IM, 0x42, 1), (LDX_ABS, 0x1234, 2)]);
+        assert_eq!(block.end, 0x0605);
+    }
+
+    #[test]
+    fn decode_stops_before_halt_and_unknown_opcodes() {
+        let memory = memory_with(0x0600, &[TAX, NOP]);
+        assert_eq!(decode(0x0600, &memory, Variant::Nmos).instructions.len(), 1);
+
+        let memory = memory_with(0x0600, &[TAX, 0xFF]);
+        assert_eq!(decode(0x0600, &memory, Variant::Nmos).instructions.len(), 1);
+    }
+
+    #[test]
+    fn decode_limits_block_length() {
+        let memory = memory_with(0x0600, &[TAX; 100]);
+        assert_eq!(
+            decode(0x0600, &memory, Variant::Nmos).instructions.len(),
+            MAX_BLOCK_LEN
+        );
+    }
+
+    #[test]
+    fn invalidate_drops_blocks_containing_address() {
+        let memory = memory_with(0x0600, &[TAX, TAY, JMP_ABS, 0x00, 0x06]);
+        let mut cache = BlockCache::default();
+        cache.get(0x0600, &memory, Variant::Nmos);
+
+        cache.invalidate(0x0700);
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate(0x0604);
+        assert!(cache.is_empty());
+        assert_eq!(cache.code_pages[0x06], 0);
+    }
+}
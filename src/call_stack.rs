@@ -0,0 +1,175 @@
+use std::mem;
+
+use crate::{
+    events::{Event, Observer},
+    op_codes::{JSR, RTI, RTS},
+};
+
+/// how a frame was entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Subroutine,
+    /// BRK or a hardware interrupt
+    Interrupt,
+}
+
+/// a subroutine call or interrupt that hasn't returned yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// address execution continues from once the frame returns
+    pub return_address: u16,
+    /// stack addresses written when the frame was entered
+    pub pushed: Vec<u16>,
+}
+
+/// observer following JSR/RTS and interrupts to know which stack bytes are return addresses
+#[derive(Debug, Default, Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    /// bytes pushed by the instruction currently executing, with their values
+    pushes: Vec<(u16, u8)>,
+}
+
+impl CallStack {
+    /// frames that haven't returned, outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// the frame that pushed the byte at a stack address
+    pub fn frame_at(&self, address: u16) -> Option<&Frame> {
+        self.frames
+            .iter()
+            .rev()
+            .find(|frame| frame.pushed.contains(&address))
+    }
+
+    fn pop(&mut self, kind: FrameKind) {
+        if self.frames.last().is_some_and(|frame| frame.kind == kind) {
+            self.frames.pop();
+        }
+    }
+}
+
+impl Observer for CallStack {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::StackPush { address, value } => self.pushes.push((address, value)),
+            // pc high, pc low then status
+            Event::InterruptTaken { .. } => {
+                let pushes = mem::take(&mut self.pushes);
+                let return_address = match pushes[..] {
+                    [(_, high), (_, low), ..] => u16::from_le_bytes([low, high]),
+                    _ => return,
+                };
+                self.frames.push(Frame {
+                    kind: FrameKind::Interrupt,
+                    return_address,
+                    pushed: pushes.iter().map(|(address, _)| *address).collect(),
+                });
+            }
+            Event::InstructionRetired { pc, opcode, .. } => {
+                let pushes = mem::take(&mut self.pushes);
+                match opcode {
+                    JSR => self.frames.push(Frame {
+                        kind: FrameKind::Subroutine,
+                        return_address: pc.wrapping_add(3),
+                        pushed: pushes.iter().map(|(address, _)| *address).collect(),
+                    }),
+                    RTS => self.pop(FrameKind::Subroutine),
+                    RTI => self.pop(FrameKind::Interrupt),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    #[test]
+    fn tracks_calls_and_interrupts() {
+        let calls = Rc::new(RefCell::new(CallStack::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0010)
+            .memory(0x0010, vec![JSR, 0x30, 0x00, NOP])
+            .memory(0x0030, vec![BRK, 0x00, RTS])
+            .memory(0x0040, vec![NOP])
+            .memory(0xFFFE, vec![0x40, 0x00])
+            .observer(calls.clone())
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        let calls = calls.borrow();
+        let frames = calls.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].kind, FrameKind::Subroutine);
+        assert_eq!(frames[0].return_address, 0x0013);
+        assert_eq!(frames[1].kind, FrameKind::Interrupt);
+        assert_eq!(frames[1].return_address, 0x0032);
+        assert_eq!(frames[1].pushed.len(), 3);
+        assert_eq!(calls.frame_at(frames[0].pushed[0]), Some(&frames[0]));
+    }
+
+    #[test]
+    fn returns_pop_frames() {
+        let calls = Rc::new(RefCell::new(CallStack::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0010)
+            .memory(0x0010, vec![JSR, 0x30, 0x00, NOP])
+            .memory(0x0030, vec![RTS])
+            .observer(calls.clone())
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert!(calls.borrow().frames().is_empty());
+    }
+
+    #[test]
+    fn frames_unwind_as_calls_return_outside_page_zero() {
+        let calls = Rc::new(RefCell::new(CallStack::default()));
+        // $0600 calls $0700, which calls $0780, each return checked by where
+        // execution carries on
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![JSR, 0x00, 0x07, LDX_IM, 0x01, NOP])
+            .memory(0x0700, vec![JSR, 0x80, 0x07, LDY_IM, 0x02, RTS])
+            .memory(0x0780, vec![RTS])
+            .observer(calls.clone())
+            .build()
+            .unwrap();
+
+        let mut depths = Vec::new();
+        while cpu.step().unwrap() {
+            let calls = calls.borrow();
+            let returns: Vec<u16> = calls
+                .frames()
+                .iter()
+                .map(|frame| frame.return_address)
+                .collect();
+            depths.push((cpu.pc(), returns));
+        }
+        assert_eq!(
+            depths,
+            vec![
+                (0x0700, vec![0x0603]),
+                (0x0780, vec![0x0603, 0x0703]),
+                (0x0703, vec![0x0603]),
+                (0x0705, vec![0x0603]),
+                (0x0603, vec![]),
+                (0x0605, vec![]),
+            ]
+        );
+        assert_eq!((cpu.x(), cpu.y(), cpu.sp()), (0x01, 0x02, 0x01FF));
+    }
+}
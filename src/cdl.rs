@@ -0,0 +1,164 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    events::{Access, Event, Observer},
+    memory::MAX_MEM,
+    op_codes::{self, JMP_ABS_IND},
+};
+
+/// the byte was fetched as part of an instruction
+pub const CODE: u8 = 0x01;
+/// the byte was read as an operand
+pub const DATA: u8 = 0x02;
+/// the byte is an instruction reached through an indirect jump
+pub const INDIRECT_CODE: u8 = 0x10;
+/// the byte was read through a pointer
+pub const INDIRECT_DATA: u8 = 0x20;
+
+/// code/data log recording how every byte of the address space was used
+/// flags use the FCEUX CDL bit layout, one byte per address
+#[derive(Debug, Clone)]
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+    /// an indirect jump just retired, the next instruction was reached through it
+    indirect_jump: bool,
+}
+
+impl Default for CodeDataLog {
+    fn default() -> Self {
+        Self {
+            flags: vec![0; MAX_MEM],
+            indirect_jump: false,
+        }
+    }
+}
+
+impl CodeDataLog {
+    /// load a previously exported log, shorter logs are padded with unused bytes
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut log = Self::default();
+        let len = bytes.len().min(MAX_MEM);
+        log.flags[..len].copy_from_slice(&bytes[..len]);
+        log
+    }
+
+    /// read a log exported with `save`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_bytes(&fs::read(path)?))
+    }
+
+    /// flags recorded for an address
+    pub fn flags(&self, address: u16) -> u8 {
+        self.flags[address as usize]
+    }
+
+    pub fn is_code(&self, address: u16) -> bool {
+        self.flags(address) & CODE != 0
+    }
+
+    pub fn is_data(&self, address: u16) -> bool {
+        self.flags(address) & DATA != 0
+    }
+
+    /// one flag byte per address
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.flags
+    }
+
+    /// write the log in CDL format
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, &self.flags)
+    }
+
+    /// merge flags recorded by another run
+    pub fn merge(&mut self, other: &CodeDataLog) {
+        for (flags, other) in self.flags.iter_mut().zip(&other.flags) {
+            *flags |= other;
+        }
+    }
+
+    fn mark(&mut self, address: u16, flags: u8) {
+        self.flags[address as usize] |= flags;
+    }
+}
+
+impl Observer for CodeDataLog {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::InstructionRetired { pc, opcode, .. } => {
+                let size = op_codes::instruction(opcode).map_or(1, |info| info.size());
+                for offset in 0..size {
+                    self.mark(pc.wrapping_add(offset as u16), CODE);
+                }
+                if self.indirect_jump {
+                    self.mark(pc, INDIRECT_CODE);
+                }
+                self.indirect_jump = opcode == JMP_ABS_IND;
+            }
+            Event::MemoryRead {
+                address, access, ..
+            } => match access {
+                Access::Data | Access::Pointer => self.mark(address, DATA),
+                Access::Indirect => self.mark(address, DATA | INDIRECT_DATA),
+                Access::Dummy => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    #[test]
+    fn log_marks_code_data_and_pointers() {
+        let log = Rc::new(RefCell::new(CodeDataLog::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(
+                0x0600,
+                vec![
+                    LDA_ABS,
+                    0x00,
+                    0x02,
+                    ORA_ZP_IY,
+                    0x10,
+                    JMP_ABS_IND,
+                    0x12,
+                    0x00,
+                ],
+            )
+            .memory(0x0010, vec![0x01, 0x02, 0x00, 0x07])
+            .memory(0x0700, vec![TAX, NOP])
+            .observer(log.clone())
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+
+        let log = log.borrow();
+        assert!((0x0600..0x0608).all(|address| log.flags(address) == CODE));
+        assert_eq!(log.flags(0x0200), DATA);
+        assert_eq!(log.flags(0x0010), DATA);
+        assert_eq!(log.flags(0x0011), DATA);
+        assert_eq!(log.flags(0x0201), DATA | INDIRECT_DATA);
+        assert_eq!(log.flags(0x0012), DATA);
+        assert_eq!(log.flags(0x0700), CODE | INDIRECT_CODE);
+        // the halting NOP never retires
+        assert_eq!(log.flags(0x0701), 0);
+    }
+
+    #[test]
+    fn log_round_trips_through_bytes() {
+        let mut log = CodeDataLog::default();
+        log.mark(0x1234, CODE | INDIRECT_CODE);
+
+        let loaded = CodeDataLog::from_bytes(log.as_bytes());
+        assert!(loaded.is_code(0x1234));
+        assert!(!loaded.is_data(0x1234));
+        assert_eq!(loaded.flags(0x1234), CODE | INDIRECT_CODE);
+    }
+}
@@ -0,0 +1,191 @@
+//! a console made of any reader and writer, so files, pipes, sockets and
+//! in-memory buffers can stand in for a terminal without a device of their own
+//!
+//! | offset | read                                  | write          |
+//! |--------|---------------------------------------|----------------|
+//! | 0      | next input byte, 0 once input ends    | byte to output |
+//! | 1      | status                                | ignored        |
+//!
+//! checking the status reads a byte ahead, which waits for input from a
+//! blocking reader like a pipe, a nonblocking one that has nothing yet just
+//! reports nothing ready
+
+use std::{
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+};
+
+use tracing::warn;
+
+use crate::device::Device;
+
+/// address the device is mapped at unless told otherwise
+pub const DEFAULT_BASE: u16 = 0xD200;
+
+/// addresses the device's registers take up
+pub const LEN: usize = 2;
+
+/// status bit set while an input byte is waiting to be read
+pub const INPUT_READY: u8 = 0x01;
+/// status bit set once input has ended and every byte has been read
+pub const INPUT_ENDED: u8 = 0x02;
+
+/// a reader and writer mapped to data and status registers
+pub struct CharDevice {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    /// the byte read ahead when the status was checked
+    lookahead: Option<u8>,
+    ended: bool,
+}
+
+impl CharDevice {
+    pub fn new(input: Box<dyn Read>, output: Box<dyn Write>) -> Self {
+        Self {
+            input,
+            output,
+            lookahead: None,
+            ended: false,
+        }
+    }
+
+    /// the host's stdin and stdout
+    pub fn stdio() -> Self {
+        Self::new(Box::new(io::stdin()), Box::new(io::stdout()))
+    }
+
+    /// read a byte ahead unless one is waiting or input has ended
+    fn fill(&mut self) {
+        if self.lookahead.is_some() || self.ended {
+            return;
+        }
+        let mut byte = [0];
+        loop {
+            match self.input.read(&mut byte) {
+                Ok(0) => self.ended = true,
+                Ok(_) => self.lookahead = Some(byte[0]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => {
+                    warn!("console input failed: {err}");
+                    self.ended = true;
+                }
+            }
+            break;
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        self.fill();
+        match (self.lookahead, self.ended) {
+            (Some(_), _) => INPUT_READY,
+            (None, true) => INPUT_ENDED,
+            (None, false) => 0,
+        }
+    }
+}
+
+impl fmt::Debug for CharDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CharDevice")
+            .field("lookahead", &self.lookahead)
+            .field("ended", &self.ended)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Device for CharDevice {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => {
+                self.fill();
+                self.lookahead.take().unwrap_or(0)
+            }
+            _ => self.status(),
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if offset != 0 {
+            return;
+        }
+        // flush at line ends so output shows up as the program prints it
+        let written = self.output.write_all(&[value]).and_then(|_| match value {
+            b'\n' => self.output.flush(),
+            _ => Ok(()),
+        });
+        if let Err(err) = written {
+            warn!("console output failed: {err}");
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("CONSOLE")
+    }
+
+    fn register_name(&self, offset: u16) -> Option<&'static str> {
+        Some(if offset == 0 { "DATA" } else { "STATUS" })
+    }
+}
+
+impl Drop for CharDevice {
+    fn drop(&mut self) {
+        let _ = self.output.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    /// a writer the test keeps a handle on while the device owns it
+    #[derive(Debug, Default, Clone)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registers_read_and_write_the_streams() {
+        let output = Shared::default();
+        let mut device = CharDevice::new(Box::new(&b"hi"[..]), Box::new(output.clone()));
+
+        assert_eq!(device.read(1), INPUT_READY);
+        assert_eq!((device.read(0), device.read(0)), (b'h', b'i'));
+        assert_eq!((device.read(1), device.read(0)), (INPUT_ENDED, 0));
+        device.write(0, b'o');
+        device.write(1, b'!');
+        assert_eq!(*output.0.borrow(), b"o");
+    }
+
+    #[test]
+    fn programs_echo_until_input_ends() {
+        let output = Shared::default();
+        let device = CharDevice::new(Box::new(&b"6502"[..]), Box::new(output.clone()));
+        // LDA status, LSR A, BCC done, LSR data, JMP loop, done: NOP
+        // LSR puts each byte back shifted right, so echo shows up halved
+        let program = vec![
+            LDA_ABS, 0x01, 0xD2, LSR_ACC, BCC, 0x06, LSR_ABS, 0x00, 0xD2, JMP_ABS, 0x00, 0x06, NOP,
+        ];
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, program)
+            .device(DEFAULT_BASE as usize, LEN, Rc::new(RefCell::new(device)))
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        let halved: Vec<u8> = b"6502".iter().map(|byte| byte >> 1).collect();
+        assert_eq!(*output.0.borrow(), halved);
+    }
+}
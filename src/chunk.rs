@@ -0,0 +1,194 @@
+//! the chunked container save states and core files are written in, so
+//! files from older and newer builds of this crate can still be read
+//!
+//! a file is a header and then a chunk for each part of it, little endian
+//! throughout
+//!
+//! | bytes | what                                          |
+//! |-------|-----------------------------------------------|
+//! | 8     | the file's magic, like `6502SAVE`             |
+//! | 1     | the container's version                       |
+//!
+//! then each chunk
+//!
+//! | bytes | what                                          |
+//! |-------|-----------------------------------------------|
+//! | 4     | its tag, like `REGS`                          |
+//! | 1     | its version                                   |
+//! | 4     | the length of what follows                    |
+//! | n     | its fields                                    |
+//!
+//! a chunk only gains fields at its end, so loading skips fields past the
+//! ones it knows and chunks it doesn't know at all, anything else bumps the
+//! chunk's version, older versions are migrated as they're read and newer
+//! ones are refused rather than read as garbage
+
+use thiserror::Error;
+
+use crate::{processor_status::ProcessorStatus, registers::Registers};
+
+/// a chunk's tag
+pub type Tag = [u8; 4];
+
+/// PC, SP, A, X, Y and the status flags
+pub(crate) const REGISTERS: Tag = *b"REGS";
+/// cycles then instructions since reset
+pub(crate) const COUNTERS: Tag = *b"CNTR";
+
+/// the length of a chunk's tag, version and length
+const HEADER_LEN: usize = 9;
+
+/// errors reading the chunks of a file
+#[derive(Debug, Error)]
+pub enum ChunkError {
+    #[error(
+        "the {} chunk is version {version}, newer than this build reads",
+        name(*tag)
+    )]
+    Version { tag: Tag, version: u8 },
+    #[error("there's no {} chunk", name(*.0))]
+    Missing(Tag),
+    #[error("the {} chunk ends early", name(*.0))]
+    Truncated(Tag),
+    #[error("a chunk's header ends early")]
+    Header,
+}
+
+/// a tag as text for errors
+fn name(tag: Tag) -> String {
+    String::from_utf8_lossy(&tag).trim_end().to_string()
+}
+
+/// a file starting with `magic` at container `version`, for [`chunk`] to
+/// append to
+pub(crate) fn header(magic: &[u8; 8], version: u8) -> Vec<u8> {
+    let mut bytes = magic.to_vec();
+    bytes.push(version);
+    bytes
+}
+
+/// the container version of a file starting with `magic` and the chunks
+/// after it, `None` when it isn't one
+pub(crate) fn open<'a>(bytes: &'a [u8], magic: &[u8; 8]) -> Option<(u8, &'a [u8])> {
+    bytes
+        .strip_prefix(magic)?
+        .split_first()
+        .map(|(version, rest)| (*version, rest))
+}
+
+/// append a chunk of the current version
+pub(crate) fn chunk(bytes: &mut Vec<u8>, tag: Tag, fields: &[u8]) {
+    bytes.extend_from_slice(&tag);
+    bytes.push(1);
+    bytes.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(fields);
+}
+
+/// the chunk `bytes` starts with, moving `bytes` past it
+pub(crate) fn next<'a>(bytes: &mut &'a [u8]) -> Result<Chunk<'a>, ChunkError> {
+    let header = bytes.get(..HEADER_LEN).ok_or(ChunkError::Header)?;
+    let mut tag = [0; 4];
+    tag.copy_from_slice(&header[..4]);
+    let version = header[4];
+    let len = u32::from_le_bytes([header[5], header[6], header[7], header[8]]) as usize;
+    let fields = bytes
+        .get(HEADER_LEN..)
+        .and_then(|rest| rest.get(..len))
+        .ok_or(ChunkError::Truncated(tag))?;
+    *bytes = &bytes[HEADER_LEN + len..];
+    Ok(Chunk::new(tag, version, fields))
+}
+
+/// a chunk, read a field at a time in order
+pub(crate) struct Chunk<'a> {
+    pub tag: Tag,
+    pub version: u8,
+    bytes: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    pub fn new(tag: Tag, version: u8, bytes: &'a [u8]) -> Self {
+        Self {
+            tag,
+            version,
+            bytes,
+        }
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], ChunkError> {
+        if self.bytes.len() < len {
+            return Err(ChunkError::Truncated(self.tag));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// the fields left, for chunks that are one field to their end
+    pub fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.bytes)
+    }
+
+    pub fn byte(&mut self) -> Result<u8, ChunkError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn word(&mut self) -> Result<u16, ChunkError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn long(&mut self) -> Result<u64, ChunkError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// the error for a version newer than this build reads
+    pub fn newer(&self) -> ChunkError {
+        ChunkError::Version {
+            tag: self.tag,
+            version: self.version,
+        }
+    }
+}
+
+pub(crate) fn write_registers(bytes: &mut Vec<u8>, registers: &Registers) {
+    let mut fields = registers.pc.to_le_bytes().to_vec();
+    fields.extend_from_slice(&registers.sp.to_le_bytes());
+    fields.extend_from_slice(&[
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.status.bits(),
+    ]);
+    chunk(bytes, REGISTERS, &fields);
+}
+
+pub(crate) fn read_registers(chunk: &mut Chunk) -> Result<Registers, ChunkError> {
+    match chunk.version {
+        1 => Ok(Registers {
+            pc: chunk.word()?,
+            sp: chunk.word()?,
+            a: chunk.byte()?,
+            x: chunk.byte()?,
+            y: chunk.byte()?,
+            status: ProcessorStatus::from_bits_truncate(chunk.byte()?),
+        }),
+        _ => Err(chunk.newer()),
+    }
+}
+
+pub(crate) fn write_counters(bytes: &mut Vec<u8>, cycles: u64, instructions: u64) {
+    let mut fields = cycles.to_le_bytes().to_vec();
+    fields.extend_from_slice(&instructions.to_le_bytes());
+    chunk(bytes, COUNTERS, &fields);
+}
+
+/// the cycles and instructions since reset
+pub(crate) fn read_counters(chunk: &mut Chunk) -> Result<(u64, u64), ChunkError> {
+    match chunk.version {
+        1 => Ok((chunk.long()?, chunk.long()?)),
+        _ => Err(chunk.newer()),
+    }
+}
@@ -0,0 +1,257 @@
+//! core files, everything about a stopped cpu needed to look into why it
+//! stopped later in the monitor, `cpu_emu run --core` writes one and
+//! `cpu_emu monitor --core` loads it
+//!
+//! a file is a [`chunk`](crate::chunk) container starting `6502CORE`, at
+//! container version [`VERSION`], with these chunks, all at version 1
+//!
+//! | tag    | fields                                                     |
+//! |--------|------------------------------------------------------------|
+//! | `WHY ` | why the cpu stopped, UTF-8 text                            |
+//! | `REGS` | PC, SP, A, X, Y and the status flags                       |
+//! | `CNTR` | cycles then instructions since reset                       |
+//! | `HIST` | the PC history, its length then each PC and opcode         |
+//! | `MEM ` | 64K of memory as the cpu would read it, RAM under devices  |
+//!
+//! version 1 cores were these fields one after another without chunks,
+//! with the reason's length before it, and are still read
+
+use std::{fs, io, path::Path};
+
+use thiserror::Error;
+
+use crate::{
+    chunk::{self, Chunk, ChunkError, Tag, COUNTERS, REGISTERS},
+    cpu::{Cpu, CpuBuilder},
+    history::DEFAULT_HISTORY_LEN,
+    memory::{BusError, MAX_MEM},
+    registers::Registers,
+};
+
+/// the bytes every core file starts with
+const MAGIC: &[u8; 8] = b"6502CORE";
+
+/// the version of the format written
+pub const VERSION: u8 = 2;
+
+const REASON: Tag = *b"WHY ";
+const HISTORY: Tag = *b"HIST";
+const MEMORY: Tag = *b"MEM ";
+
+/// errors reading or writing a core file
+#[derive(Debug, Error)]
+pub enum CoreError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("not a core file")]
+    NotACore,
+    #[error("core file version {0} is newer than this build reads")]
+    Version(u8),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+/// a stopped cpu's state, saved for a post-mortem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreDump {
+    /// why the cpu stopped
+    pub reason: String,
+    pub registers: Registers,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// the pcs and opcodes executed before it stopped, oldest first
+    pub history: Vec<(u16, u8)>,
+    pub memory: Vec<u8>,
+}
+
+impl CoreDump {
+    /// capture `cpu` as it is, having stopped for `reason`
+    pub fn capture(cpu: &Cpu, reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+            registers: cpu.registers(),
+            cycles: cpu.cycles(),
+            instructions: cpu.instructions(),
+            history: cpu.history().iter().collect(),
+            memory: (0..MAX_MEM)
+                .map(|address| cpu.memory.peek_byte(address))
+                .collect(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = chunk::header(MAGIC, VERSION);
+        chunk::chunk(&mut bytes, REASON, self.reason.as_bytes());
+        chunk::write_registers(&mut bytes, &self.registers);
+        chunk::write_counters(&mut bytes, self.cycles, self.instructions);
+
+        let mut fields = (self.history.len() as u16).to_le_bytes().to_vec();
+        for (pc, opcode) in &self.history {
+            fields.extend_from_slice(&pc.to_le_bytes());
+            fields.push(*opcode);
+        }
+        chunk::chunk(&mut bytes, HISTORY, &fields);
+        chunk::chunk(&mut bytes, MEMORY, &self.memory);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CoreError> {
+        let (version, mut rest) = chunk::open(bytes, MAGIC).ok_or(CoreError::NotACore)?;
+        match version {
+            1 => return Ok(Self::from_version_1(&mut Chunk::new(*b"CORE", 1, rest))?),
+            VERSION => {}
+            version => return Err(CoreError::Version(version)),
+        }
+
+        let (mut reason, mut registers, mut counters, mut history, mut memory) =
+            (None, None, None, None, None);
+        while !rest.is_empty() {
+            let mut chunk = chunk::next(&mut rest)?;
+            match chunk.tag {
+                REASON => reason = Some(read_reason(&mut chunk)?),
+                REGISTERS => registers = Some(chunk::read_registers(&mut chunk)?),
+                COUNTERS => counters = Some(chunk::read_counters(&mut chunk)?),
+                HISTORY => history = Some(read_history(&mut chunk)?),
+                MEMORY => memory = Some(read_memory(&mut chunk)?),
+                // from a newer build, nothing this one needs
+                _ => {}
+            }
+        }
+
+        let (cycles, instructions) = counters.ok_or(ChunkError::Missing(COUNTERS))?;
+        Ok(Self {
+            reason: reason.ok_or(ChunkError::Missing(REASON))?,
+            registers: registers.ok_or(ChunkError::Missing(REGISTERS))?,
+            cycles,
+            instructions,
+            history: history.ok_or(ChunkError::Missing(HISTORY))?,
+            memory: memory.ok_or(ChunkError::Missing(MEMORY))?,
+        })
+    }
+
+    /// a core from before the chunked format, its fields read in order
+    fn from_version_1(fields: &mut Chunk) -> Result<Self, ChunkError> {
+        let len = fields.word()? as usize;
+        let reason = String::from_utf8_lossy(fields.take(len)?).into_owned();
+        let registers = chunk::read_registers(fields)?;
+        let (cycles, instructions) = chunk::read_counters(fields)?;
+        let history = read_history(fields)?;
+        let memory = read_memory(fields)?;
+        Ok(Self {
+            reason,
+            registers,
+            cycles,
+            instructions,
+            history,
+            memory,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CoreError> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CoreError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// a cpu from `builder` put back in the state the core was captured in,
+    /// the memory is loaded under any ROMs and devices the builder maps
+    pub fn restore(&self, builder: CpuBuilder) -> Result<Cpu, BusError> {
+        let mut cpu = builder
+            .history(self.history.len().max(DEFAULT_HISTORY_LEN))
+            .memory(0, self.memory.clone())
+            .build()?;
+        cpu.set_registers(self.registers);
+        cpu.restore_counters(self.cycles, self.instructions, &self.history);
+        Ok(cpu)
+    }
+}
+
+fn read_reason(chunk: &mut Chunk) -> Result<String, ChunkError> {
+    match chunk.version {
+        1 => Ok(String::from_utf8_lossy(chunk.rest()).into_owned()),
+        _ => Err(chunk.newer()),
+    }
+}
+
+fn read_history(chunk: &mut Chunk) -> Result<Vec<(u16, u8)>, ChunkError> {
+    match chunk.version {
+        1 => (0..chunk.word()?)
+            .map(|_| Ok((chunk.word()?, chunk.byte()?)))
+            .collect(),
+        _ => Err(chunk.newer()),
+    }
+}
+
+fn read_memory(chunk: &mut Chunk) -> Result<Vec<u8>, ChunkError> {
+    match chunk.version {
+        1 => Ok(chunk.take(MAX_MEM)?.to_vec()),
+        _ => Err(chunk.newer()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn cores_round_trip_into_a_new_cpu() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![LDA_IM, 0x42, PHA, TAX, 0xFF])
+            .build()
+            .unwrap();
+        let err = cpu.execute().unwrap_err();
+        let core = CoreDump::capture(&cpu, &err.to_string());
+        let bytes = core.to_bytes();
+        assert_eq!(CoreDump::from_bytes(&bytes).unwrap(), core);
+
+        let restored = core.restore(Cpu::builder()).unwrap();
+        assert_eq!(restored.registers(), cpu.registers());
+        assert_eq!(
+            (restored.cycles(), restored.instructions()),
+            (cpu.cycles(), cpu.instructions())
+        );
+        assert_eq!(restored.history().to_string(), cpu.history().to_string());
+        assert_eq!(restored.memory.read_byte(0x01FF), 0x42);
+        assert!(core.reason.contains("$0604"));
+    }
+
+    #[test]
+    fn damaged_cores_are_refused() {
+        let core = CoreDump::capture(&Cpu::new(), "halted").to_bytes();
+        assert!(matches!(
+            CoreDump::from_bytes(&core[..100]),
+            Err(CoreError::Chunk(ChunkError::Truncated(_)))
+        ));
+        assert!(matches!(
+            CoreDump::from_bytes(b"not a core"),
+            Err(CoreError::NotACore)
+        ));
+        let mut newer = core.clone();
+        newer[8] = VERSION + 1;
+        assert!(matches!(
+            CoreDump::from_bytes(&newer),
+            Err(CoreError::Version(_))
+        ));
+    }
+
+    #[test]
+    fn version_1_cores_still_load() {
+        let core = CoreDump::capture(&Cpu::new(), "halted");
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(1);
+        bytes.extend_from_slice(&6u16.to_le_bytes());
+        bytes.extend_from_slice(b"halted");
+        bytes.extend_from_slice(&core.registers.pc.to_le_bytes());
+        bytes.extend_from_slice(&core.registers.sp.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, core.registers.status.bits()]);
+        bytes.extend_from_slice(&[0; 16]);
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&core.memory);
+        assert_eq!(CoreDump::from_bytes(&bytes).unwrap(), core);
+    }
+}
@@ -1,12 +1,382 @@
 #![allow(unused)]
-use std::ops::Shr;
+use core::fmt;
+use std::{cell::RefCell, collections::BTreeMap, ops::Shr, rc::Rc};
+
+use thiserror::Error;
+use tracing::{debug, debug_span, enabled, info, trace, warn, Level};
 
 use crate::{
-    memory::{self, Memory},
+    block_cache::BlockCache,
+    device::{MappedDevice, SharedDevice},
+    events::{Access, Event, Observers, SharedObserver},
+    history::PcHistory,
+    memory::{self, BusConflicts, BusError, Memory, MemorySnapshot, RomImage},
     op_codes::*,
+    permissions::{Fault, Operation, PermissionMap, Permissions, ViolationPolicy},
     processor_status::ProcessorStatus,
+    random::{self, Random, RandomDevice},
+    register_break::{RegisterBreak, Snapshot},
+    registers::Registers,
+    trace::TraceFormat,
+    trap::{SharedTrap, Traps},
+    vcd::BusCycle,
 };
 
+/// tracing target per-instruction events are logged to when tracing is enabled on the builder
+pub const TRACE_TARGET: &str = "cpu_emu::trace";
+
+/// address of the non-maskable interrupt vector
+pub const NMI_VECTOR: u16 = 0xFFFA;
+/// address of the reset vector
+pub const RESET_VECTOR: u16 = 0xFFFC;
+/// address of the IRQ and BRK vector
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// executes a single decoded instruction, the opcode has already been fetched
+pub(crate) type Handler = fn(&mut Cpu);
+
+/// instruction handlers indexed by opcode, none for unimplemented opcodes
+/// and those the variant doesn't have
+/// NOP halts the cpu so it has no handler either
+pub(crate) fn handlers(variant: Variant) -> &'static [Option<Handler>; 256] {
+    match variant {
+        Variant::Nmos => &NMOS_HANDLERS,
+        Variant::Cmos => &CMOS_HANDLERS,
+    }
+}
+
+static NMOS_HANDLERS: [Option<Handler>; 256] = handler_table(Variant::Nmos);
+static CMOS_HANDLERS: [Option<Handler>; 256] = handler_table(Variant::Cmos);
+
+const fn handler_table(variant: Variant) -> [Option<Handler>; 256] {
+    let mut handlers: [Option<Handler>; 256] = [None; 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        if let Some(decoded) = Opcode::for_variant(opcode as u8, variant) {
+            handlers[opcode] = handler(decoded);
+        }
+        opcode += 1;
+    }
+    handlers
+}
+
+/// the handler implementing an opcode
+const fn handler(opcode: Opcode) -> Option<Handler> {
+    let handler: Handler = match opcode {
+        Opcode::LdaIm => Cpu::lda_immediate,
+        Opcode::LdaAbs => Cpu::lda_absolute,
+        Opcode::LdaAbsX => Cpu::lda_absolute_x_indexed,
+        Opcode::LdaAbsY => Cpu::lda_absolute_y_indexed,
+        Opcode::LdaZp => Cpu::lda_zp,
+        Opcode::LdaZpX => Cpu::lda_zp_x,
+        Opcode::LdaZpXi => Cpu::lda_x_indexed_zero_page_indirect,
+        Opcode::LdaZpIy => Cpu::lda_y_zero_page_indirect_indexed,
+        Opcode::LdxIm => Cpu::ldx_immediate,
+        Opcode::LdxAbs => Cpu::ldx_absolute,
+        Opcode::LdxZp => Cpu::ldx_zp,
+        Opcode::LdxZpY => Cpu::ldx_y_indexed_zero_page,
+        Opcode::LdxAbsY => Cpu::ldx_absolute_y_indexed,
+        Opcode::LdyIm => Cpu::ldy_immediate,
+        Opcode::LdyAbs => Cpu::ldy_absolute,
+        Opcode::LdyZp => Cpu::ldy_zp,
+        Opcode::LdyZpX => Cpu::ldy_x_indexed_zero_page,
+        Opcode::LdyAbsX => Cpu::ldy_absolute_x_indexed,
+        Opcode::LsrAcc => Cpu::lsr_acc,
+        Opcode::LsrAbs => Cpu::lsr_abs,
+        Opcode::LsrZp => Cpu::lsr_zp,
+        Opcode::LsrAbsX => Cpu::lsr_abs_x,
+        Opcode::LsrZpX => Cpu::lsr_zp_x,
+        Opcode::Pha => Cpu::pha,
+        Opcode::Php => Cpu::php,
+        Opcode::Pla => Cpu::pla,
+        Opcode::Plp => Cpu::plp,
+        Opcode::JmpAbs => Cpu::jump_absolute,
+        Opcode::JmpAbsInd => Cpu::jump_absolute_indirect,
+        Opcode::Jsr => Cpu::jump_subroutine,
+        Opcode::Rts => Cpu::return_subroutine,
+        Opcode::Brk => Cpu::force_break,
+        Opcode::Rti => Cpu::return_interrupt,
+        Opcode::Bcc => |cpu| cpu.branch(!cpu.carry()),
+        Opcode::Bcs => |cpu| cpu.branch(cpu.carry()),
+        Opcode::Beq => |cpu| cpu.branch(cpu.zero()),
+        Opcode::Bmi => |cpu| cpu.branch(cpu.negative()),
+        Opcode::Bne => |cpu| cpu.branch(!cpu.zero()),
+        Opcode::Bpl => |cpu| cpu.branch(!cpu.negative()),
+        Opcode::Bvc => |cpu| cpu.branch(!cpu.overflow()),
+        Opcode::Bvs => |cpu| cpu.branch(cpu.overflow()),
+        Opcode::Tax => Cpu::transfer_a_to_x,
+        Opcode::Tay => Cpu::transfer_a_to_y,
+        Opcode::Tsx => Cpu::transfer_sp_to_x,
+        Opcode::Txa => Cpu::transfer_x_to_a,
+        Opcode::Txs => Cpu::transfer_x_to_sp,
+        Opcode::Tya => Cpu::transfer_y_to_a,
+        Opcode::Sec => |cpu| cpu.set_carry_flag(true),
+        Opcode::Sed => Cpu::set_decimal_mode,
+        Opcode::Sei => Cpu::set_interrupt_disable_flag,
+        Opcode::Cli => |cpu| cpu.ps.remove(ProcessorStatus::I),
+        Opcode::Nop => return None,
+        _ => return declared_handler(opcode),
+    };
+    Some(handler)
+}
+
+/// declares instructions that operate on the value their operand refers to,
+/// generating a handler for each opcode from the one operation, along with
+/// `declared_handler` to dispatch to them
+///
+/// ```text
+/// instruction! {
+///     /// AND accumulator
+///     fn and(&mut self, value) {
+///         self.a &= value;
+///     }
+///     modes { AndaIm => Immediate, AndaZp => ZeroPage }
+/// }
+/// ```
+macro_rules! instruction {
+    ($(
+        $(#[$doc:meta])*
+        fn $name:ident(&mut $cpu:ident, $value:ident) $body:block
+        modes { $($opcode:ident => $mode:ident),* $(,)? }
+    )*) => {
+        impl Cpu {
+            $(
+                $(#[$doc])*
+                fn $name(&mut $cpu, $value: u8) $body
+            )*
+        }
+
+        /// handlers for the instructions declared with `instruction!`
+        const fn declared_handler(opcode: Opcode) -> Option<Handler> {
+            let handler: Handler = match opcode {
+                $($(
+                    Opcode::$opcode => |cpu| {
+                        let value = cpu.read_operand(AddressingMode::$mode);
+                        cpu.$name(value)
+                    },
+                )*)*
+                _ => return None,
+            };
+            Some(handler)
+        }
+    };
+}
+
+instruction! {
+    /// AND accumulator with memory
+    fn and(&mut self, value) {
+        self.a &= value;
+        self.set_negative_and_zero_flags();
+    }
+    modes {
+        AndaIm => Immediate,
+        AndaAbs => Absolute,
+        AndaXAbs => AbsoluteX,
+        AndaYAbs => AbsoluteY,
+        AndaZp => ZeroPage,
+        AndaZpX => ZeroPageX,
+        AndaZpXi => ZeroPageXIndirect,
+        AndaZpIy => ZeroPageIndirectY,
+    }
+
+    /// OR accumulator with memory
+    fn ora(&mut self, value) {
+        self.a |= value;
+        self.set_negative_and_zero_flags();
+    }
+    modes {
+        OraIm => Immediate,
+        OraAbs => Absolute,
+        OraXAbs => AbsoluteX,
+        OraYAbs => AbsoluteY,
+        OraZp => ZeroPage,
+        OraZpX => ZeroPageX,
+        OraZpXi => ZeroPageXIndirect,
+        OraZpIy => ZeroPageIndirectY,
+    }
+}
+
+/// errors that stop the cpu from executing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum CpuError {
+    /// the opcode fetched isn't an implemented instruction
+    #[error("unrecognized instruction ${opcode:02X} at ${pc:04X}")]
+    UnrecognizedInstruction { opcode: u8, pc: u16 },
+    /// one of the NMOS opcodes that locks the processor up was executed
+    #[error("cpu jammed by ${opcode:02X} at ${pc:04X}")]
+    Jammed { opcode: u8, pc: u16 },
+    /// `execute` ran the most instructions it was allowed to without halting
+    #[error("still running at ${pc:04X} after {limit} instructions")]
+    InstructionLimit { limit: u64, pc: u16 },
+    /// an access the permission map doesn't allow, with
+    /// [`ViolationPolicy::Stop`]
+    #[error("{operation} of ${address:04X} not allowed at ${pc:04X}")]
+    AccessViolation {
+        operation: Operation,
+        address: u16,
+        pc: u16,
+    },
+    /// a software breakpoint's BRK was reached, the opcode under it is put
+    /// back and the pc left on it
+    #[error("software breakpoint ${pc:04X} hit")]
+    SoftBreakpoint { pc: u16 },
+}
+
+impl From<Fault> for CpuError {
+    fn from(fault: Fault) -> Self {
+        CpuError::AccessViolation {
+            operation: fault.operation,
+            address: fault.address,
+            pc: fault.pc,
+        }
+    }
+}
+
+/// which revision of the processor is being emulated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// the original NMOS 6502
+    #[default]
+    Nmos,
+    /// the CMOS 65C02
+    Cmos,
+}
+
+/// behavior that differs between processor revisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// JMP ($xxFF) takes the high byte from $xx00 instead of crossing the page
+    pub indirect_jump_page_wrap: bool,
+    /// in accurate mode, read-modify-write instructions write the unmodified value
+    /// back before the result rather than reading the address twice
+    pub rmw_double_write: bool,
+    /// undefined opcodes run as NOPs of a defined length instead of faulting
+    pub undefined_opcodes_are_nops: bool,
+    /// in accurate mode, an NMI arriving before BRK or IRQ fetches its vector
+    /// takes over the sequence and jumps through the NMI vector instead
+    pub nmi_hijacks_interrupts: bool,
+}
+
+impl Variant {
+    /// how this revision behaves where the revisions differ
+    pub const fn quirks(&self) -> Quirks {
+        match self {
+            Variant::Nmos => Quirks {
+                indirect_jump_page_wrap: true,
+                rmw_double_write: true,
+                undefined_opcodes_are_nops: false,
+                nmi_hijacks_interrupts: true,
+            },
+            Variant::Cmos => Quirks {
+                indirect_jump_page_wrap: false,
+                rmw_double_write: false,
+                undefined_opcodes_are_nops: true,
+                nmi_hijacks_interrupts: false,
+            },
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Variant::default().quirks()
+    }
+}
+
+/// length and cycles of the NOP a 65C02 executes for an undefined opcode
+const fn cmos_undefined_nop(opcode: u8) -> Option<(u16, u8)> {
+    match opcode {
+        0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 => Some((2, 2)),
+        0x44 => Some((2, 3)),
+        0x54 | 0xD4 | 0xF4 => Some((2, 4)),
+        0x5C => Some((3, 8)),
+        0xDC | 0xFC => Some((3, 4)),
+        _ if opcode & 0x03 == 0x03 => Some((1, 1)),
+        _ => None,
+    }
+}
+
+/// what the cpu does with an opcode it has no handler for, after the
+/// variant's own undefined opcodes are dealt with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownOpcodePolicy {
+    /// stop with [`CpuError::UnrecognizedInstruction`]
+    #[default]
+    Error,
+    /// skip the opcode and its operand, taking as long as the NMOS 6502 would
+    Nop,
+    /// run what the NMOS 6502 does for its undocumented opcodes, the JAM
+    /// opcodes stop with [`CpuError::Jammed`] and documented instructions that
+    /// aren't implemented still fail, RRA, ISC and SBC $EB add in binary
+    Illegal,
+}
+
+impl UnknownOpcodePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(UnknownOpcodePolicy::Error),
+            "nop" => Some(UnknownOpcodePolicy::Nop),
+            "illegal" => Some(UnknownOpcodePolicy::Illegal),
+            _ => None,
+        }
+    }
+}
+
+/// the addressing mode the NMOS 6502 decodes an opcode with, documented or not
+const fn nmos_mode(opcode: u8) -> AddressingMode {
+    let row = opcode >> 5;
+    // the Y indexed stores and loads, STX, LDX, SAX, LAX and friends
+    let y_indexed = row == 4 || row == 5;
+    match (opcode & 0x03, (opcode >> 2) & 0x07) {
+        (0, 0) if opcode == 0x20 => AddressingMode::Absolute,
+        (0, 0) if row < 4 => AddressingMode::Implied,
+        (0, 3) if opcode == 0x6C => AddressingMode::Indirect,
+        (0, 4) => AddressingMode::Relative,
+        (0, 2 | 6) | (2, 4 | 6) => AddressingMode::Implied,
+        (2, 0) if row < 4 => AddressingMode::Implied,
+        (2, 2) if row < 4 => AddressingMode::Accumulator,
+        (2, 2) => AddressingMode::Implied,
+        (_, 0) if opcode & 0x01 == 0 => AddressingMode::Immediate,
+        (_, 0) => AddressingMode::ZeroPageXIndirect,
+        (_, 1) => AddressingMode::ZeroPage,
+        (_, 2) => AddressingMode::Immediate,
+        (_, 3) => AddressingMode::Absolute,
+        (_, 4) => AddressingMode::ZeroPageIndirectY,
+        (2 | 3, 5) if y_indexed => AddressingMode::ZeroPageY,
+        (_, 5) => AddressingMode::ZeroPageX,
+        (_, 6) => AddressingMode::AbsoluteY,
+        (2 | 3, 7) if y_indexed => AddressingMode::AbsoluteY,
+        _ => AddressingMode::AbsoluteX,
+    }
+}
+
+/// the NMOS opcodes that lock the processor up until it's reset
+const JAMS: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+];
+
+/// base cycles the NMOS 6502 takes for every opcode, documented or not
+#[rustfmt::skip]
+const NMOS_CYCLES: [u8; 256] = [
+    7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+    2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+    2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+];
+
+/// the 6502 processor and the memory attached to it
 #[derive(Debug, Default, Clone)]
 pub struct Cpu {
     /// program counter
@@ -21,149 +391,1585 @@ pub struct Cpu {
     y: u8,
     /// processor status (bitfield)
     ps: ProcessorStatus,
+    /// total cycles executed since reset
+    cycles: u64,
+    /// instructions retired since reset
+    instructions: u64,
+    /// cycle count at which a pending NMI edge arrives
+    nmi: Option<u64>,
+    /// whether the IRQ line is asserted
+    irq: bool,
+    /// whether a mapped device held the NMI line after the last instruction,
+    /// an NMI is taken when it goes from released to held
+    device_nmi: bool,
+    /// whether the IRQ line was held after the last instruction, kept while
+    /// observed to publish requests
+    irq_held: bool,
+    /// the stack pointer after the last push onto a byte of page 1 not
+    /// pushed to before since reset, where the stack is deepest
+    stack_low: Option<u16>,
+    /// the bytes of page 1 pushed to since reset, a bit each
+    stack_used: [u64; 4],
+    /// stack pointer reset leaves, $0100 if not set
+    reset_sp: Option<u16>,
+    /// flags reset leaves, only the unused bit if not set
+    reset_status: Option<ProcessorStatus>,
+    /// the reset, IRQ and NMI vectors the builder was given, written back
+    /// on every reset
+    vectors: [(u16, Option<u16>); 3],
+
+    /// processor revision being emulated
+    variant: Variant,
+    /// behavior of the emulated revision
+    quirks: Quirks,
+    /// whether SED actually enables decimal mode
+    decimal_mode: bool,
+    /// what to do with opcodes that have no handler
+    unknown_opcodes: UnknownOpcodePolicy,
+    /// instructions `execute` may retire before giving up, none for no limit
+    instruction_limit: Option<u64>,
+    /// log each instruction at info rather than trace level
+    trace: bool,
+    /// layout of the lines logged for each instruction
+    trace_format: TraceFormat,
+    /// functions called after every instruction
+    hooks: Vec<fn(&Cpu)>,
+    /// host code called when the pc reaches an address
+    traps: Traps,
+    /// the last instructions executed, for crash dumps
+    history: PcHistory,
+    /// conditions on registers that stop `execute`
+    register_breaks: Vec<RegisterBreak>,
+    /// the register break that stopped execution, if any
+    register_break_hit: Option<RegisterBreak>,
+    /// opcodes a BRK was written over for software breakpoints, by address
+    soft_breakpoints: BTreeMap<u16, u8>,
+    /// receivers of cpu and bus events
+    observers: Observers,
+    /// predecoded blocks used by `execute`, when enabled
+    block_cache: Option<BlockCache>,
+    /// operand of the instruction a cached block is running, low byte first,
+    /// handed to its handler in place of fetching it again
+    operand: u16,
+    /// bytes of `operand` the handler hasn't fetched yet
+    operand_len: u8,
+    /// skip cycle counting, hooks, observers and tracing
+    fast: bool,
+    /// emulate the extra bus accesses real hardware makes
+    accurate: bool,
+    /// every bus access made, when recording for a VCD export
+    bus_trace: Option<Vec<BusCycle>>,
+    /// cycle the next recorded bus access happens on
+    bus_cycle: u64,
+    /// what each region of memory may be used for, when checked
+    permissions: Option<PermissionMap>,
+    /// what everything random about the machine is derived from
+    seed: u64,
 
     /// Memory module
     pub memory: Memory,
 }
 
+/// a cpu's registers and ram saved by [`Cpu::snapshot`], cheap to clone
+#[derive(Debug, Clone)]
+pub struct CpuSnapshot {
+    registers: Registers,
+    cycles: u64,
+    instructions: u64,
+    memory: MemorySnapshot,
+}
+
+impl CpuSnapshot {
+    pub(crate) fn new(
+        registers: Registers,
+        cycles: u64,
+        instructions: u64,
+        memory: MemorySnapshot,
+    ) -> Self {
+        Self {
+            registers,
+            cycles,
+            instructions,
+            memory,
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.registers
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn memory(&self) -> &MemorySnapshot {
+        &self.memory
+    }
+}
+
+/// configures a [`Cpu`] before it's constructed
+#[derive(Debug, Default, Clone)]
+pub struct CpuBuilder {
+    variant: Variant,
+    quirks: Option<Quirks>,
+    pc: Option<u16>,
+    sp: Option<u16>,
+    a: u8,
+    x: u8,
+    y: u8,
+    status: Option<ProcessorStatus>,
+    reset_vector: Option<u16>,
+    irq_vector: Option<u16>,
+    nmi_vector: Option<u16>,
+    decimal_mode: bool,
+    unknown_opcodes: UnknownOpcodePolicy,
+    instruction_limit: Option<u64>,
+    trace: bool,
+    trace_format: TraceFormat,
+    hooks: Vec<fn(&Cpu)>,
+    traps: Traps,
+    history: Option<usize>,
+    observers: Observers,
+    block_cache: bool,
+    fast: bool,
+    accurate: bool,
+    bus_trace: bool,
+    address_lines: Option<u32>,
+    bus_conflicts: BusConflicts,
+    permissions: PermissionMap,
+    images: Vec<(usize, Vec<u8>)>,
+    roms: Vec<(usize, RomImage)>,
+    devices: Vec<MappedDevice>,
+    logged_devices: Vec<usize>,
+    seed: Option<u64>,
+    random_ram: bool,
+    random_device: Option<usize>,
+}
+
+impl CpuBuilder {
+    /// processor revision to emulate
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// override the behavior implied by the variant
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// what to do with opcodes that have no handler, an error by default
+    pub fn unknown_opcodes(mut self, policy: UnknownOpcodePolicy) -> Self {
+        self.unknown_opcodes = policy;
+        self
+    }
+
+    /// stop `execute` with [`CpuError::InstructionLimit`] once this many
+    /// instructions have been retired since reset, unlimited by default
+    pub fn instruction_limit(mut self, limit: u64) -> Self {
+        self.instruction_limit = Some(limit);
+        self
+    }
+
+    /// address execution starts from, written to the reset vector
+    pub fn pc(mut self, pc: u16) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+
+    /// stack pointer after reset, kept by later resets
+    pub fn sp(mut self, sp: u16) -> Self {
+        self.sp = Some(sp);
+        self
+    }
+
+    /// initial accumulator
+    pub fn a(mut self, a: u8) -> Self {
+        self.a = a;
+        self
+    }
+
+    /// initial x index register
+    pub fn x(mut self, x: u8) -> Self {
+        self.x = x;
+        self
+    }
+
+    /// initial y index register
+    pub fn y(mut self, y: u8) -> Self {
+        self.y = y;
+        self
+    }
+
+    /// processor status after reset, kept by later resets
+    pub fn status(mut self, status: ProcessorStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// a flag set or clear after reset, on top of [`status`](Self::status)
+    pub fn flag(mut self, flag: ProcessorStatus, set: bool) -> Self {
+        let mut status = self.status.unwrap_or_default();
+        status.set(flag, set);
+        self.status = Some(status);
+        self
+    }
+
+    /// address written to the reset vector, execution starts from it unless
+    /// [`pc`](Self::pc) is given as well
+    pub fn reset_vector(mut self, address: u16) -> Self {
+        self.reset_vector = Some(address);
+        self
+    }
+
+    /// address written to the IRQ and BRK vector
+    pub fn irq_vector(mut self, address: u16) -> Self {
+        self.irq_vector = Some(address);
+        self
+    }
+
+    /// address written to the NMI vector
+    pub fn nmi_vector(mut self, address: u16) -> Self {
+        self.nmi_vector = Some(address);
+        self
+    }
+
+    /// let SED set the decimal flag instead of being ignored
+    pub fn decimal_mode(mut self, enabled: bool) -> Self {
+        self.decimal_mode = enabled;
+        self
+    }
+
+    /// log each instruction to [`TRACE_TARGET`] at info level
+    /// instead of the default trace level
+    pub fn trace(mut self, enabled: bool) -> Self {
+        self.trace = enabled;
+        self
+    }
+
+    /// layout of the lines logged when tracing is enabled
+    pub fn trace_format(mut self, format: TraceFormat) -> Self {
+        self.trace_format = format;
+        self
+    }
+
+    /// call a function after every instruction
+    pub fn hook(mut self, hook: fn(&Cpu)) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// call host code whenever the pc reaches `address`, see [`Cpu::add_trap`]
+    pub fn trap(mut self, address: u16, trap: SharedTrap) -> Self {
+        self.traps.insert(address, trap);
+        self
+    }
+
+    /// number of executed instructions to remember for crash dumps,
+    /// zero disables the history
+    pub fn history(mut self, len: usize) -> Self {
+        self.history = Some(len);
+        self
+    }
+
+    /// publish cpu and bus events to an observer
+    pub fn observer(mut self, observer: SharedObserver) -> Self {
+        self.observers.subscribe(observer);
+        self
+    }
+
+    /// let `execute` run predecoded blocks of instructions instead of decoding every step
+    /// blocks are only used while no hooks, traps, observers or tracing are active
+    pub fn block_cache(mut self, enabled: bool) -> Self {
+        self.block_cache = enabled;
+        self
+    }
+
+    /// only compute architectural results, `execute` skips cycle counting,
+    /// hooks, observers, tracing and the extra bus accesses of accurate mode
+    pub fn fast(mut self, enabled: bool) -> Self {
+        self.fast = enabled;
+        self
+    }
+
+    /// emulate the extra bus accesses real hardware makes, such as the
+    /// unmodified write-back of read-modify-write instructions
+    /// these matter to memory-mapped hardware, but cost some speed
+    pub fn accurate(mut self, enabled: bool) -> Self {
+        self.accurate = enabled;
+        self
+    }
+
+    /// record the address, data, R/W and SYNC of every bus access along with
+    /// the interrupt lines, for exporting with [`crate::vcd`]
+    /// only takes effect in accurate mode, and not in fast mode
+    pub fn bus_trace(mut self, enabled: bool) -> Self {
+        self.bus_trace = enabled;
+        self
+    }
+
+    /// load a memory image at an address, images are loaded in order
+    pub fn memory(mut self, address: usize, image: Vec<u8>) -> Self {
+        self.images.push((address, image));
+        self
+    }
+
+    /// wire up fewer address lines, 13 for a 6507, see
+    /// [`Memory::set_address_lines`](crate::memory::Memory::set_address_lines)
+    pub fn address_lines(mut self, lines: u32) -> Self {
+        self.address_lines = Some(lines);
+        self
+    }
+
+    /// what to do about devices mapped over each other, refused with
+    /// [`BusError::Conflict`] from [`build`](Self::build) by default
+    pub fn bus_conflicts(mut self, conflicts: BusConflicts) -> Self {
+        self.bus_conflicts = conflicts;
+        self
+    }
+
+    /// check accesses over `len` addresses from `address` against `allowed`,
+    /// see [`crate::permissions`]
+    pub fn permissions(mut self, address: usize, len: usize, allowed: Permissions) -> Self {
+        self.permissions.add(address, len, allowed);
+        self
+    }
+
+    /// whether an access the permissions don't allow stops the cpu or is
+    /// only logged, logged by default
+    pub fn on_violation(mut self, policy: ViolationPolicy) -> Self {
+        self.permissions.policy = policy;
+        self
+    }
+
+    /// map a rom image at an address without copying it into memory
+    pub fn rom(mut self, address: usize, image: RomImage) -> Self {
+        self.roms.push((address, image));
+        self
+    }
+
+    /// map a device over `len` addresses from `address`
+    pub fn device(mut self, address: usize, len: usize, device: SharedDevice) -> Self {
+        self.devices.push(MappedDevice {
+            start: address,
+            len,
+            device,
+            // given out when the device is mapped
+            id: 0,
+            scheduled: false,
+            logged: false,
+        });
+        self
+    }
+
+    /// the seed everything random about the machine is derived from, one
+    /// from the clock if it isn't given, see [`crate::random`]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// fill RAM with noise before loading anything, like real RAM powering
+    /// up, to shake out programs that read memory they never wrote
+    pub fn random_ram(mut self, enabled: bool) -> Self {
+        self.random_ram = enabled;
+        self
+    }
+
+    /// map a device reading back a random byte at `address`, see
+    /// [`random::DEFAULT_BASE`]
+    pub fn random_device(mut self, address: usize) -> Self {
+        self.random_device = Some(address);
+        self
+    }
+
+    /// log accesses to the device mapped at `address`, see
+    /// [`Memory::log_device`](crate::memory::Memory::log_device)
+    pub fn log_device(mut self, address: usize) -> Self {
+        self.logged_devices.push(address);
+        self
+    }
+
+    /// construct the cpu in its reset state
+    /// fails if a memory image doesn't fit in the address space, or if
+    /// devices overlap and the bus refuses conflicts
+    /// vectors are written over the memory images, but a ROM mapped over
+    /// them keeps its own
+    pub fn build(self) -> Result<Cpu, BusError> {
+        let seed = self.seed.unwrap_or_else(random::entropy_seed);
+        let mut cpu = Cpu {
+            reset_sp: self.sp,
+            reset_status: self.status,
+            vectors: [
+                (RESET_VECTOR, self.reset_vector),
+                (IRQ_VECTOR, self.irq_vector),
+                (NMI_VECTOR, self.nmi_vector),
+            ],
+            variant: self.variant,
+            quirks: self.quirks.unwrap_or(self.variant.quirks()),
+            decimal_mode: self.decimal_mode,
+            unknown_opcodes: self.unknown_opcodes,
+            instruction_limit: self.instruction_limit,
+            trace: self.trace,
+            trace_format: self.trace_format,
+            hooks: self.hooks,
+            traps: self.traps,
+            history: self.history.map(PcHistory::new).unwrap_or_default(),
+            observers: self.observers,
+            block_cache: self.block_cache.then(BlockCache::default),
+            fast: self.fast,
+            accurate: self.accurate,
+            bus_trace: (self.bus_trace && self.accurate && !self.fast).then(Vec::new),
+            permissions: (!self.permissions.is_empty()).then_some(self.permissions),
+            seed,
+            ..Cpu::default()
+        };
+
+        if let Some(lines) = self.address_lines {
+            cpu.memory.set_address_lines(lines);
+        }
+        cpu.memory.set_bus_conflicts(self.bus_conflicts);
+        if self.random_ram {
+            Random::derive(seed, "ram").fill(&mut cpu.memory.data[..]);
+        }
+        for (address, image) in self.images {
+            cpu.load_program(address, image)?;
+        }
+        for (address, image) in self.roms {
+            cpu.memory.map_rom(address, image)?;
+        }
+
+        cpu.reset(self.pc);
+        for mapped in self.devices {
+            cpu.memory
+                .map_device(mapped.start, mapped.len, mapped.device)?;
+        }
+        if let Some(address) = self.random_device {
+            let device = RandomDevice::new(Random::derive(seed, "device"));
+            cpu.memory
+                .map_device(address, random::LEN, Rc::new(RefCell::new(device)))?;
+        }
+        for address in self.logged_devices {
+            if !cpu.memory.log_device(address, true) {
+                warn!("no device at ${address:04X} to log");
+            }
+        }
+        cpu.a = self.a;
+        cpu.x = self.x;
+        cpu.y = self.y;
+        Ok(cpu)
+    }
+}
+
+/// getters and setters for single status flags, forwarded to
+/// [`ProcessorStatus`]
+macro_rules! flag_accessors {
+    ($($get:ident, $set:ident;)*) => {
+        $(
+            #[doc = concat!("see [`ProcessorStatus::", stringify!($get), "`]")]
+            pub fn $get(&self) -> bool {
+                self.ps.$get()
+            }
+
+            #[doc = concat!("see [`ProcessorStatus::", stringify!($set), "`]")]
+            pub fn $set(&mut self, value: bool) {
+                self.ps.$set(value);
+            }
+        )*
+    };
+}
+
 impl Cpu {
     /// construct a new cpu
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// configure a new cpu
+    pub fn builder() -> CpuBuilder {
+        CpuBuilder::default()
+    }
+
+    /// publish cpu and bus events to an observer
+    pub fn subscribe(&mut self, observer: SharedObserver) {
+        self.observers.subscribe(observer);
+    }
+
+    /// call host code whenever the pc reaches `address`, before the
+    /// instruction there executes, traps run in fast mode too but keep
+    /// `execute` off the block cache
+    pub fn add_trap(&mut self, address: u16, trap: SharedTrap) {
+        self.traps.insert(address, trap);
+    }
+
+    /// processor revision being emulated
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// behavior of the emulated revision
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// what the cpu does with opcodes that have no handler
+    pub fn unknown_opcodes(&self) -> UnknownOpcodePolicy {
+        self.unknown_opcodes
+    }
+
     /// reset the cpu to initial state
     /// an optional address can be given to give the
     /// cpu a location to fetch instructions from after
-    /// the reset has finished
+    /// the reset has finished, it's written to the reset vector unless the
+    /// builder set one, which is then where execution starts without it
     pub fn reset(&mut self, address: Option<u16>) -> Self {
         self.pc = 0xFFFC;
-        self.sp = 0x0100;
+        self.sp = self.reset_sp.unwrap_or(0x0100);
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.ps = self.reset_status.unwrap_or_default();
+        self.cycles = 0;
+        self.instructions = 0;
+        self.nmi = None;
+        self.irq = false;
+        self.device_nmi = false;
+        self.irq_held = false;
+        self.stack_low = None;
+        self.stack_used = [0; 4];
+        self.clear_soft_breakpoints();
+        self.history.clear();
+        self.bus_cycle = 0;
+        if let Some(trace) = &mut self.bus_trace {
+            trace.clear();
+        }
+
+        for (vector, address) in self.vectors {
+            if let Some(address) = address {
+                self.memory.write_word(vector as usize, address);
+            }
+        }
+        let configured = self.vectors[0].1.is_some();
+        match address {
+            Some(address) if configured => self.pc = address,
+            // read 0xFFFC and 0xFFFD and
+            // jump to that address for instructions
+            Some(address) => {
+                self.memory.write_word(self.pc as usize, address);
+                self.pc = self.memory.read_word(RESET_VECTOR as usize);
+            }
+            None if configured => self.pc = self.memory.read_word(RESET_VECTOR as usize),
+            None => {}
+        }
+
+        self.to_owned()
+    }
+
+    /// put back the counters and history saved in a core dump
+    pub(crate) fn restore_counters(
+        &mut self,
+        cycles: u64,
+        instructions: u64,
+        history: &[(u16, u8)],
+    ) {
+        self.cycles = cycles;
+        self.instructions = instructions;
+        self.history.clear();
+        for (pc, opcode) in history {
+            self.history.push(*pc, *opcode);
+        }
+    }
+
+    /// put the registers and counters back to their state after reset and
+    /// start at `pc`, without touching memory or cloning the cpu like `reset`
+    pub fn reset_registers(&mut self, pc: u16) {
+        self.pc = pc;
+        self.sp = self.reset_sp.unwrap_or(0x0100);
         self.a = 0;
         self.x = 0;
         self.y = 0;
-        self.ps.clear();
+        self.ps = self.reset_status.unwrap_or_default();
+        self.cycles = 0;
+        self.instructions = 0;
+        self.nmi = None;
+        self.irq = false;
+        self.device_nmi = false;
+        self.irq_held = false;
+        self.stack_low = None;
+        self.stack_used = [0; 4];
+        self.register_break_hit = None;
+        self.clear_soft_breakpoints();
+        self.history.clear();
+        self.bus_cycle = 0;
+        if let Some(trace) = &mut self.bus_trace {
+            trace.clear();
+        }
+    }
+
+    /// save the registers and ram to go back to with `restore`
+    pub fn snapshot(&mut self) -> CpuSnapshot {
+        CpuSnapshot {
+            registers: self.registers(),
+            cycles: self.cycles,
+            instructions: self.instructions,
+            memory: self.memory.snapshot(),
+        }
+    }
+
+    /// go back to a snapshot, copying back only the pages written since when
+    /// it's the latest one, see [`Memory::snapshot`]
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        if let Some(cache) = &mut self.block_cache {
+            if self.memory.tracks(&snapshot.memory) {
+                self.memory
+                    .dirty_pages()
+                    .for_each(|page| cache.invalidate_page(page));
+            } else {
+                cache.clear();
+            }
+        }
+        self.memory.restore(&snapshot.memory);
+        self.reset_registers(snapshot.registers.pc);
+        self.set_registers(snapshot.registers);
+        self.cycles = snapshot.cycles;
+        self.instructions = snapshot.instructions;
+    }
+
+    /// current value of the program counter
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// move the program counter to a new address
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// overwrite the accumulator, for debuggers
+    pub fn set_a(&mut self, a: u8) {
+        self.a = a;
+    }
+
+    /// overwrite the x index register, for debuggers
+    pub fn set_x(&mut self, x: u8) {
+        self.x = x;
+    }
+
+    /// overwrite the y index register, for debuggers
+    pub fn set_y(&mut self, y: u8) {
+        self.y = y;
+    }
+
+    /// overwrite the stack pointer, for debuggers
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    /// overwrite the status flags, for debuggers
+    pub fn set_status(&mut self, status: ProcessorStatus) {
+        self.ps = status;
+    }
+
+    /// accumulator
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    /// x index register
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    /// y index register
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    /// stack pointer
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    /// the lowest the stack pointer has been pushed down to since reset, the
+    /// stack's high-water mark, none until something is pushed
+    /// the stack wraps within page 1, so from the $0100 reset leaves the
+    /// first push lands on $0100 and the mark carries on down from $01FF
+    pub fn stack_high_water(&self) -> Option<u16> {
+        self.stack_low
+    }
+
+    /// bytes of page 1 never pushed to since reset, the room the stack has
+    /// left below its high-water mark, 0 once it has used the whole page
+    pub fn stack_headroom(&self) -> Option<u16> {
+        let used: u32 = self.stack_used.iter().map(|bits| bits.count_ones()).sum();
+        self.stack_low.map(|_| 256 - used as u16)
+    }
+
+    /// processor status flags
+    pub fn status(&self) -> ProcessorStatus {
+        self.ps
+    }
+
+    flag_accessors! {
+        negative, set_negative;
+        overflow, set_overflow;
+        break_command, set_break_command;
+        decimal, set_decimal;
+        interrupt_disable, set_interrupt_disable;
+        zero, set_zero;
+        carry, set_carry;
+    }
+
+    /// a copy of every register
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            status: self.ps,
+        }
+    }
+
+    /// overwrite every register, restoring a saved copy
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.pc = registers.pc;
+        self.sp = registers.sp;
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.ps = registers.status;
+    }
+
+    /// total cycles executed since reset
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// instructions retired since reset, not counting the final halt
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// bus accesses recorded since reset, empty unless enabled on the builder
+    pub fn bus_trace(&self) -> &[BusCycle] {
+        self.bus_trace.as_deref().unwrap_or_default()
+    }
+
+    /// the last instructions executed, including one that failed to decode
+    /// not recorded in fast mode
+    pub fn history(&self) -> &PcHistory {
+        &self.history
+    }
+
+    /// signal an NMI, taken before the next instruction
+    pub fn nmi(&mut self) {
+        self.schedule_nmi(self.cycles);
+    }
+
+    /// signal an NMI arriving once the cycle count reaches `cycle`
+    /// it's taken at the first instruction boundary after that, cycles aren't
+    /// counted in fast mode so only NMIs due immediately are taken there
+    pub fn schedule_nmi(&mut self, cycle: u64) {
+        self.nmi = Some(cycle);
+        if self.observing() {
+            self.observers
+                .notify(Event::InterruptRequested { nmi: true, cycle });
+        }
+    }
+
+    /// assert or release the IRQ line
+    /// the interrupt is taken while the line is asserted, by this or by a
+    /// mapped device, and I is clear
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+        if self.observing() {
+            self.sample_irq();
+        }
+    }
+
+    /// stop `execute` after an instruction that trips the condition
+    /// register breaks are ignored in fast mode
+    pub fn add_register_break(&mut self, condition: RegisterBreak) {
+        self.register_breaks.push(condition);
+    }
+
+    pub fn register_breaks(&self) -> &[RegisterBreak] {
+        &self.register_breaks
+    }
+
+    pub fn clear_register_breaks(&mut self) {
+        self.register_breaks.clear();
+        self.register_break_hit = None;
+    }
+
+    /// the register break tripped by the last instruction, clearing it
+    pub fn take_register_break(&mut self) -> Option<RegisterBreak> {
+        self.register_break_hit.take()
+    }
+
+    /// stop when the pc reaches `address` by writing a BRK over the opcode
+    /// there, the way in-circuit monitors do, so running needs no checks for it
+    /// the opcode is put back once it's hit or cleared, until then the
+    /// program reads the BRK, false if the byte can't be written, under a ROM
+    pub fn set_soft_breakpoint(&mut self, address: u16) -> bool {
+        if self.soft_breakpoints.contains_key(&address) {
+            return true;
+        }
+        let original = self.memory.peek_byte(address as usize);
+        self.write_code(address, BRK);
+        if self.memory.peek_byte(address as usize) != BRK {
+            return false;
+        }
+        self.soft_breakpoints.insert(address, original);
+        true
+    }
+
+    /// put back the opcode under a software breakpoint, false if there's none
+    /// a BRK since overwritten by the program or the debugger is left alone
+    pub fn clear_soft_breakpoint(&mut self, address: u16) -> bool {
+        let Some(original) = self.soft_breakpoints.remove(&address) else {
+            return false;
+        };
+        self.restore_opcode(address, original);
+        true
+    }
+
+    pub fn clear_soft_breakpoints(&mut self) {
+        for (address, original) in std::mem::take(&mut self.soft_breakpoints) {
+            self.restore_opcode(address, original);
+        }
+    }
+
+    /// where software breakpoints are set
+    pub fn soft_breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.soft_breakpoints.keys().copied()
+    }
+
+    /// put back the opcode a software breakpoint's BRK replaced, if the BRK
+    /// is still there
+    fn restore_opcode(&mut self, address: u16, original: u8) {
+        if self.memory.peek_byte(address as usize) == BRK {
+            self.write_code(address, original);
+        }
+    }
+
+    /// stop at a software breakpoint's BRK, with its opcode put back and the
+    /// pc on it
+    fn hit_soft_breakpoint(&mut self, pc: u16) -> Result<(), CpuError> {
+        let Some(original) = self.soft_breakpoints.remove(&pc) else {
+            return Ok(());
+        };
+        self.write_code(pc, original);
+        self.pc = pc;
+        Err(CpuError::SoftBreakpoint { pc })
+    }
+
+    /// write a byte of code from outside the program, dropping any block
+    /// decoded from it
+    fn write_code(&mut self, address: u16, value: u8) {
+        self.memory.write_byte(address as usize, value);
+        if let Some(cache) = &mut self.block_cache {
+            cache.invalidate(address);
+        }
+    }
+
+    /// load a program into the cpu's memory at a given address
+    /// software breakpoints are cleared first, a new program doesn't keep them
+    pub fn load_program(&mut self, address: usize, program: Vec<u8>) -> Result<(), BusError> {
+        self.clear_soft_breakpoints();
+        self.invalidate_block_cache();
+        self.memory.write_bytes(address, &program)
+    }
+
+    /// drop all predecoded blocks
+    /// needed after modifying `memory` directly while the block cache is enabled
+    pub fn invalidate_block_cache(&mut self) {
+        if let Some(cache) = &mut self.block_cache {
+            cache.clear();
+        }
+    }
+
+    /// execute the program loaded in memory until it halts
+    pub fn execute(&mut self) -> Result<(), CpuError> {
+        let _span = debug_span!("execute", start = self.pc).entered();
+        if self.block_cache.is_some()
+            && self.traps.is_empty()
+            && (self.fast
+                || (!self.trace
+                    && self.hooks.is_empty()
+                    && self.observers.is_empty()
+                    && self.register_breaks.is_empty()
+                    && self.permissions.is_none()
+                    && self.bus_trace.is_none()))
+        {
+            self.execute_blocks()?;
+        } else if self.fast {
+            while self.step_fast()? {
+                self.check_instruction_limit()?;
+            }
+        } else {
+            while self.step()? && self.register_break_hit.is_none() {
+                self.check_instruction_limit()?;
+            }
+        }
+        debug!(pc = self.pc, cycles = self.cycles, "halted");
+        Ok(())
+    }
+
+    /// fail once the instruction limit has been reached
+    pub(crate) fn check_instruction_limit(&self) -> Result<(), CpuError> {
+        match self.instruction_limit {
+            Some(limit) if self.instructions >= limit => {
+                Err(CpuError::InstructionLimit { limit, pc: self.pc })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// execute using predecoded blocks until the cpu halts
+    /// anything a block can't run (halting, unknown opcodes) goes through `step`
+    fn execute_blocks(&mut self) -> Result<(), CpuError> {
+        loop {
+            let Some(cache) = &mut self.block_cache else {
+                return Ok(());
+            };
+            let generation = cache.generation();
+            let block = cache.get(self.pc, &self.memory, self.variant);
+
+            if block.instructions.is_empty() || self.interrupt_pending() {
+                let running = if self.fast {
+                    self.step_fast()?
+                } else {
+                    self.step()?
+                };
+                if !running {
+                    return Ok(());
+                }
+                self.check_instruction_limit()?;
+                continue;
+            }
+
+            for decoded in &block.instructions {
+                let start = self.cycles;
+                if !self.fast && self.memory.has_devices() {
+                    self.memory.set_cycle(start);
+                }
+                self.pc = decoded.pc.wrapping_add(1);
+                if !self.fast {
+                    self.cycles += decoded.cycles as u64;
+                    self.history.push(decoded.pc, decoded.opcode);
+                }
+                self.instructions += 1;
+                #[cfg(feature = "self-profile")]
+                crate::self_profile::record(|counters| {
+                    counters.block_instructions += 1;
+                    counters.dispatches[decoded.opcode as usize] += 1;
+                });
+                self.operand = decoded.operand;
+                self.operand_len = decoded.operand_len;
+                (decoded.handler)(self);
+                self.operand_len = 0;
+                if !self.fast && self.memory.has_devices() {
+                    self.memory.tick(self.cycles - start);
+                    self.sample_device_nmi();
+                }
+                if self.observing() {
+                    self.sample_irq();
+                }
+                self.check_instruction_limit()?;
+                if self.interrupt_pending() {
+                    break;
+                }
+
+                // the block rewrote itself, carry on from the pc with freshly decoded code
+                if self
+                    .block_cache
+                    .as_ref()
+                    .is_some_and(|cache| cache.generation() != generation)
+                {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// execute a single instruction
+    /// returns false once the cpu has halted (reached a NOP)
+    pub fn step(&mut self) -> Result<bool, CpuError> {
+        let before = (!self.register_breaks.is_empty()).then(|| Snapshot::of(self));
+        self.poll_interrupts();
+        self.run_trap();
+        self.trace_instruction();
+
+        let pc = self.pc;
+        self.check_execute(pc)?;
+        let start = self.cycles;
+        if self.memory.has_devices() {
+            self.memory.set_cycle(start);
+        }
+        let instruction = self.fetch_opcode();
+        if instruction == BRK {
+            self.hit_soft_breakpoint(pc)?;
+        }
+        self.history.push(pc, instruction);
+        self.cycles += CYCLES[instruction as usize] as u64;
+        if instruction == NOP {
+            return Ok(false);
+        }
+
+        #[cfg(feature = "self-profile")]
+        crate::self_profile::record(|counters| {
+            counters.steps += 1;
+            counters.dispatches[instruction as usize] += 1;
+        });
+        match handlers(self.variant)[instruction as usize] {
+            Some(handler) => handler(self),
+            None => {
+                if let Err(err) = self.unknown_opcode(instruction, pc) {
+                    debug!(pc, opcode = instruction, "{err}");
+                    self.debug_print();
+                    return Err(err);
+                }
+            }
+        }
+        self.instructions += 1;
+        if self.memory.has_devices() {
+            self.memory.tick(self.cycles - start);
+            self.sample_device_nmi();
+        }
+        if self.observing() {
+            self.sample_irq();
+        }
+
+        if self.observing() {
+            self.observers.notify(Event::InstructionRetired {
+                pc,
+                opcode: instruction,
+                cycles: self.cycles,
+            });
+        }
+
+        if let Some(before) = before {
+            let after = Snapshot::of(self);
+            self.register_break_hit = self
+                .register_breaks
+                .iter()
+                .find(|condition| condition.tripped(&before, &after))
+                .copied();
+        }
+
+        #[cfg(feature = "self-profile")]
+        let started = std::time::Instant::now();
+        for hook in &self.hooks {
+            hook(self);
+        }
+        #[cfg(feature = "self-profile")]
+        if !self.hooks.is_empty() {
+            let calls = self.hooks.len() as u64;
+            crate::self_profile::record(|counters| {
+                counters.hook_calls += calls;
+                counters.hook_time += started.elapsed();
+            });
+        }
+        self.pending_fault()?;
+        Ok(true)
+    }
+
+    /// call the trap at the pc, if there is one
+    fn run_trap(&mut self) {
+        if self.traps.is_empty() {
+            return;
+        }
+        if let Some(trap) = self.traps.get(self.pc) {
+            #[cfg(feature = "self-profile")]
+            let started = std::time::Instant::now();
+            (trap.borrow_mut())(self);
+            #[cfg(feature = "self-profile")]
+            crate::self_profile::record(|counters| {
+                counters.trap_calls += 1;
+                counters.trap_time += started.elapsed();
+            });
+        }
+    }
+
+    /// execute a single instruction without any of the bookkeeping `step` does
+    fn step_fast(&mut self) -> Result<bool, CpuError> {
+        self.poll_interrupts();
+        self.run_trap();
+        let pc = self.pc;
+        self.check_execute(pc)?;
+        let instruction = self.fetch_byte();
+        if instruction == NOP {
+            return Ok(false);
+        }
+        if instruction == BRK {
+            self.hit_soft_breakpoint(pc)?;
+        }
+
+        #[cfg(feature = "self-profile")]
+        crate::self_profile::record(|counters| {
+            counters.fast_steps += 1;
+            counters.dispatches[instruction as usize] += 1;
+        });
+        match handlers(self.variant)[instruction as usize] {
+            Some(handler) => handler(self),
+            None => self.unknown_opcode(instruction, pc)?,
+        }
+        self.instructions += 1;
+        self.pending_fault()?;
+        Ok(true)
+    }
+
+    /// check the instruction at `pc` may be run, stopping before it's fetched
+    /// if it can't and violations stop the cpu
+    fn check_execute(&mut self, pc: u16) -> Result<(), CpuError> {
+        if let Some(map) = &mut self.permissions {
+            map.begin(pc);
+        }
+        self.pending_fault()
+    }
+
+    /// stop for an access the permission map didn't allow
+    fn pending_fault(&mut self) -> Result<(), CpuError> {
+        match self
+            .permissions
+            .as_mut()
+            .and_then(PermissionMap::take_pending)
+        {
+            Some(fault) => Err(fault.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// the seed the machine's randomness was derived from, to repeat a run
+    /// exactly with [`CpuBuilder::seed`]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// accesses the permission map didn't allow since the cpu was built
+    pub fn faults(&self) -> &[Fault] {
+        self.permissions.as_ref().map_or(&[], PermissionMap::faults)
+    }
+
+    /// deal with an opcode that has no handler, the variant's undefined NOPs
+    /// first and then the unknown opcode policy
+    fn unknown_opcode(&mut self, opcode: u8, pc: u16) -> Result<(), CpuError> {
+        if self.undefined_nop(opcode) {
+            return Ok(());
+        }
+
+        let unrecognized = CpuError::UnrecognizedInstruction { opcode, pc };
+        match self.unknown_opcodes {
+            UnknownOpcodePolicy::Error => return Err(unrecognized),
+            UnknownOpcodePolicy::Nop => {
+                let len = nmos_mode(opcode).operand_len() as u16;
+                self.pc = self.pc.wrapping_add(len);
+            }
+            UnknownOpcodePolicy::Illegal if JAMS.contains(&opcode) => {
+                self.pc = pc;
+                return Err(CpuError::Jammed { opcode, pc });
+            }
+            UnknownOpcodePolicy::Illegal => {
+                if !self.illegal(opcode) {
+                    return Err(unrecognized);
+                }
+            }
+        }
+        if !self.fast {
+            self.cycles += NMOS_CYCLES[opcode as usize] as u64;
+        }
+        Ok(())
+    }
+
+    /// skip over an undefined opcode when the variant treats it as a NOP
+    /// returns false if the opcode should fault instead
+    fn undefined_nop(&mut self, opcode: u8) -> bool {
+        if !self.quirks.undefined_opcodes_are_nops {
+            return false;
+        }
+        let Some((size, cycles)) = cmos_undefined_nop(opcode) else {
+            return false;
+        };
+
+        self.pc = self.pc.wrapping_add(size - 1);
+        if !self.fast {
+            self.cycles += cycles as u64;
+        }
+        true
+    }
+
+    /// whether an interrupt will be taken before the next instruction
+    fn interrupt_pending(&self) -> bool {
+        self.nmi.is_some_and(|cycle| cycle <= self.cycles)
+            || (self.irq_asserted() && !self.ps.contains(ProcessorStatus::I))
+    }
+
+    /// whether the IRQ line is held, by the host or a mapped device
+    fn irq_asserted(&self) -> bool {
+        self.irq || (self.memory.has_devices() && self.memory.irq())
+    }
+
+    /// signal an NMI when a mapped device has just pulled the NMI line
+    fn sample_device_nmi(&mut self) {
+        let held = self.memory.nmi();
+        if held && !self.device_nmi {
+            self.nmi();
+        }
+        self.device_nmi = held;
+    }
+
+    /// publish a request when the IRQ line has just been pulled
+    fn sample_irq(&mut self) {
+        let held = self.irq_asserted();
+        if held && !self.irq_held {
+            self.observers.notify(Event::InterruptRequested {
+                nmi: false,
+                cycle: self.cycles,
+            });
+        }
+        self.irq_held = held;
+    }
+
+    /// take a pending interrupt, NMI has priority over IRQ
+    fn poll_interrupts(&mut self) {
+        if self.nmi.is_some_and(|cycle| cycle <= self.cycles) {
+            self.nmi = None;
+            self.interrupt(NMI_VECTOR, false);
+        } else if self.irq_asserted() && !self.ps.contains(ProcessorStatus::I) {
+            self.interrupt(IRQ_VECTOR, false);
+        }
+    }
+
+    /// push the pc and status then jump through an interrupt vector
+    /// BRK has already been charged its cycles, hardware interrupts take 7
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        let start = if brk {
+            self.cycles.saturating_sub(CYCLES[BRK as usize] as u64)
+        } else {
+            self.cycles
+        };
+        if !brk && !self.fast {
+            self.cycles += 7;
+        }
+
+        let [low, high] = self.pc.to_le_bytes();
+        self.push_byte(high);
+        self.push_byte(low);
+        let status = if brk {
+            self.ps.pushed()
+        } else {
+            self.ps.interrupt_pushed()
+        };
+        self.push_byte(status);
+        self.ps.insert(ProcessorStatus::I);
+
+        // the vector is fetched on the fifth cycle, an NMI edge seen before
+        // then redirects the sequence and is consumed by it
+        let vector = if vector != NMI_VECTOR
+            && self.accurate
+            && self.quirks.nmi_hijacks_interrupts
+            && self.nmi.is_some_and(|cycle| cycle <= start + 4)
+        {
+            self.nmi = None;
+            NMI_VECTOR
+        } else {
+            vector
+        };
+
+        self.pc = self.memory.read_word(vector as usize);
+        let [low, high] = self.pc.to_le_bytes();
+        self.record_bus(vector, low, false, false);
+        self.record_bus(vector.wrapping_add(1), high, false, false);
+        if self.observing() {
+            self.observers.notify(Event::InterruptTaken {
+                vector,
+                cycle: self.cycles,
+                brk,
+            });
+        }
+    }
+
+    /// whether events need to be published
+    fn observing(&self) -> bool {
+        !self.fast && !self.observers.is_empty()
+    }
+
+    /// log the instruction at the pc along with the current registers
+    fn trace_instruction(&self) {
+        if self.trace {
+            if enabled!(target: TRACE_TARGET, Level::INFO) {
+                info!(target: TRACE_TARGET, "{}", self.trace_format.format(self));
+            }
+        } else if enabled!(Level::TRACE) {
+            trace!(
+                pc = self.pc,
+                opcode = self.memory.read_byte(self.pc as usize),
+                a = self.a,
+                x = self.x,
+                y = self.y,
+                sp = self.sp,
+                ps = self.ps.bits(),
+                "instruction"
+            );
+        }
+    }
+
+    /// log contents of registers, pc, sp, and status flags and current instruction
+    /// along with the instructions that led there
+    /// useful when the emulator crashes, you can get a state of the machine
+    pub fn debug_print(&self) {
+        debug!("\n{self}\nhistory:\n{}", self.history);
+    }
+
+    /// fetch a word from memory while incrememting the pc each read (2 cycles)
+    fn fetch_word(&mut self) -> u16 {
+        let low = self.fetch_byte();
+        let high = self.fetch_byte();
+        u16::from_le_bytes([low, high])
+    }
+
+    /// fetch the opcode of the next instruction, the cycle SYNC is high on
+    fn fetch_opcode(&mut self) -> u8 {
+        if self.recording_bus() {
+            // cycles the last instruction spent without touching the bus
+            self.bus_cycle = self.bus_cycle.max(self.cycles);
+        }
+        self.fetch(true)
+    }
+
+    /// fetch a byte and increment the pc, from the operand a cached block
+    /// decoded when there's one
+    fn fetch_byte(&mut self) -> u8 {
+        if self.operand_len == 0 {
+            return self.fetch(false);
+        }
+        let byte = self.operand as u8;
+        self.operand >>= 8;
+        self.operand_len -= 1;
+        self.pc = self.pc.wrapping_add(1);
+        byte
+    }
+
+    /// fetch a byte and increment the pc, `sync` when it's an opcode
+    /// like real hardware the pc wraps from $FFFF to $0000
+    fn fetch(&mut self, sync: bool) -> u8 {
+        let data = self.memory.read_byte(self.pc as usize);
+        self.record_bus(self.pc, data, false, sync);
+        self.pc = self.pc.wrapping_add(1);
+        if self.pc == 0 {
+            warn!("program counter wrapped around from $FFFF to $0000");
+        }
+        data
+    }
+
+    /// read a byte of data from memory, publishing the read to observers
+    fn read_byte(&mut self, address: usize) -> u8 {
+        self.read(address, Access::Data)
+    }
+
+    /// read a little endian pointer from memory
+    fn read_pointer(&mut self, address: usize) -> u16 {
+        let low = self.read(address, Access::Pointer);
+        let high = self.read((address + 1) % memory::MAX_MEM, Access::Pointer);
+        u16::from_le_bytes([low, high])
+    }
+
+    /// read a byte from memory, publishing the read to observers
+    fn read(&mut self, address: usize, access: Access) -> u8 {
+        if let Some(map) = self
+            .permissions
+            .as_mut()
+            .filter(|_| access != Access::Dummy)
+        {
+            map.check(Operation::Read, address as u16);
+        }
+        let value = self.memory.read_byte(address);
+        self.record_bus(address as u16, value, false, false);
+        if self.observing() {
+            self.observers.notify(Event::MemoryRead {
+                address: address as u16,
+                value,
+                access,
+            });
+        }
+        value
+    }
+
+    /// whether bus accesses are recorded or published, which is only done in
+    /// accurate mode
+    fn recording_bus(&self) -> bool {
+        self.bus_trace.is_some() || (self.accurate && self.observing())
+    }
 
-        // read 0xFFFC and 0xFFFD and
-        // jump to that address for instructions
-        if let Some(address) = address {
-            self.memory.write_word(self.pc as usize, address);
-            self.pc = self.memory.read_word(0xFFFC);
+    /// note a bus access when recording a bus trace and publish it to
+    /// observers in accurate mode, `sync` when it fetches an opcode
+    fn record_bus(&mut self, address: u16, data: u8, write: bool, sync: bool) {
+        if !self.recording_bus() {
+            return;
+        }
+        let cycle = self.bus_cycle;
+        self.bus_cycle += 1;
+        let bus = BusCycle {
+            cycle,
+            address,
+            data,
+            write,
+            sync,
+            irq: self.irq_asserted(),
+            nmi: self.nmi.is_some_and(|arrival| arrival <= cycle),
+        };
+        if let Some(trace) = &mut self.bus_trace {
+            trace.push(bus);
+        }
+        if self.accurate && self.observing() {
+            self.observers.notify(Event::BusAccess(bus));
         }
+    }
 
-        self.to_owned()
+    /// write a byte from host code such as a trap the way the program would,
+    /// so observers see it and cached blocks over it are dropped
+    pub fn store(&mut self, address: u16, value: u8) {
+        self.write_byte(address as usize, value);
     }
 
-    /// load a program into the cpu's memory at a given address
-    pub fn load_program(&mut self, address: usize, program: Vec<u8>) {
-        todo!()
+    /// write a byte to memory, publishing the write to observers
+    fn write_byte(&mut self, address: usize, value: u8) {
+        if let Some(map) = &mut self.permissions {
+            map.check(Operation::Write, address as u16);
+        }
+        self.memory.write_byte(address, value);
+        self.record_bus(address as u16, value, true, false);
+        if let Some(cache) = &mut self.block_cache {
+            cache.invalidate(address as u16);
+        }
+        if self.observing() {
+            self.observers.notify(Event::MemoryWritten {
+                address: address as u16,
+                value,
+            });
+        }
     }
 
-    /// execute the program loaded in memory
-    pub fn execute(&mut self) {
-        loop {
-            let instruction = self.fetch_byte();
-            match instruction {
-                LDA_IM => self.lda_immediate(),
-                LDA_ABS => self.lda_absolute(),
-                LDA_ABS_X => self.lda_absolute_x_indexed(),
-                LDA_ABS_Y => self.lda_absolute_y_indexed(),
-                LDA_ZP => self.lda_zp(),
-                LDA_ZP_X => self.lda_zp_x(),
-                LDA_ZP_XI => self.lda_x_indexed_zero_page_indirect(),
-                LDA_ZP_IY => self.lda_y_zero_page_indirect_indexed(),
-                LDX_IM => self.ldx_immediate(),
-                LDX_ABS => self.ldx_absolute(),
-                LDX_ZP => self.ldx_zp(),
-                LDX_ZP_Y => self.ldx_y_indexed_zero_page(),
-                LDX_ABS_Y => self.ldx_absolute_y_indexed(),
-                LDY_IM => self.ldy_immediate(),
-                LDY_ABS => self.ldy_absolute(),
-                LDY_ZP => self.ldy_zp(),
-                LDY_ZP_X => self.ldy_x_indexed_zero_page(),
-                LDY_ABS_X => self.ldy_absolute_x_indexed(),
-                LSR_ACC => self.lsr_acc(),
-                LSR_ABS => self.lsr_abs(),
-                LSR_ZP => self.lsr_zp(),
-                LSR_ABS_X => self.lsr_abs_x(),
-                LSR_ZP_X => self.lsr_zp_x(),
-                PHA => self.pha(),
-                PHP => self.php(),
-                PLA => self.pla(),
-                PLP => self.plp(),
-                JMP_ABS => self.jump_absolute(),
-                JMP_ABS_IND => self.jump_absolute_indirect(),
-                JSR => self.jump_subroutine(),
-                RTS => self.return_subroutine(),
-                ANDA_IM => self.anda_im(),
-                ANDA_ABS => self.anda_abs(),
-                ANDA_X_ABS => self.anda_abs_x(),
-                ANDA_Y_ABS => self.anda_abs_y(),
-                ANDA_ZP => self.anda_zp(),
-                ANDA_ZP_X => self.anda_zp_x(),
-                ANDA_ZP_IY => self.anda_zp_iy(),
-                ANDA_ZP_XI => self.anda_zp_xi(),
-                ORA_IM => self.ora_im(),
-                ORA_ABS => self.ora_abs(),
-                ORA_X_ABS => self.ora_abs_x(),
-                ORA_Y_ABS => self.ora_abs_y(),
-                ORA_ZP => self.ora_zp(),
-                ORA_ZP_X => self.ora_zp_x(),
-                ORA_ZP_IY => self.ora_zp_iy(),
-                ORA_ZP_XI => self.ora_zp_xi(),
-                TAX => self.transfer_a_to_x(),
-                TAY => self.transfer_a_to_y(),
-                TSX => self.transfer_sp_to_x(),
-                TXA => self.transfer_x_to_a(),
-                TXS => self.transfer_x_to_sp(),
-                TYA => self.transfer_y_to_a(),
-                SEC => self.set_carry_flag(true),
-                SED => self.set_decimal_mode(),
-                SEI => self.set_interrupt_disable(),
-                NOP => break,
-                _ => {
-                    self.debug_print();
-                    panic!("reason: unrecognized instruction");
-                }
+    /// effective address of an indexed read
+    /// crossing a page costs a cycle, and in accurate mode the cpu first reads
+    /// the address formed before the carry into the high byte
+    fn index_read(&mut self, base: u16, index: u8) -> u16 {
+        let address = base.wrapping_add(index as u16);
+        if address & 0xFF00 == base & 0xFF00 || self.fast {
+            return address;
+        }
+
+        self.cycles += 1;
+        if self.accurate {
+            let uncarried = (base & 0xFF00) | (address & 0x00FF);
+            self.read(uncarried as usize, Access::Dummy);
+        }
+        address
+    }
+
+    /// write back the result of a read-modify-write instruction
+    /// in accurate mode the NMOS 6502 writes the unmodified value first,
+    /// the 65C02 reads the address again instead
+    fn write_modified(&mut self, address: usize, original: u8, modified: u8) {
+        if self.accurate && !self.fast {
+            if self.quirks.rmw_double_write {
+                self.write_byte(address, original);
+            } else {
+                self.read(address, Access::Dummy);
             }
         }
+        self.write_byte(address, modified);
     }
 
-    /// print contents of registers, pc, sp, and status flags and current instruction
-    /// useful when the emulator crashes, you can get a state of the machine
-    pub fn debug_print(&self) {
-        println!("pc: 0x{:04x}", self.pc);
-        println!("sp: 0x{:04x}", self.sp);
-        println!("a : 0x{:04x}", self.a);
-        println!("x : 0x{:04x}", self.x);
-        println!("y : 0x{:04x}", self.y);
-        println!("ps: {}", self.ps);
-        println!(
-            "current instruction: 0x{:02X}",
-            self.memory.read_byte(self.pc as usize)
-        );
+    /// fetch an instruction's operand and read the value it refers to
+    fn read_operand(&mut self, mode: AddressingMode) -> u8 {
+        match mode {
+            AddressingMode::Immediate => self.fetch_byte(),
+            AddressingMode::ZeroPage => {
+                let address = self.fetch_byte();
+                self.read_byte(address as usize)
+            }
+            AddressingMode::ZeroPageX => {
+                let address = self.fetch_byte().wrapping_add(self.x);
+                self.read_byte(address as usize)
+            }
+            AddressingMode::ZeroPageY => {
+                let address = self.fetch_byte().wrapping_add(self.y);
+                self.read_byte(address as usize)
+            }
+            AddressingMode::Absolute => {
+                let address = self.fetch_word();
+                self.read_byte(address as usize)
+            }
+            AddressingMode::AbsoluteX => {
+                let base = self.fetch_word();
+                let address = self.index_read(base, self.x);
+                self.read_byte(address as usize)
+            }
+            AddressingMode::AbsoluteY => {
+                let base = self.fetch_word();
+                let address = self.index_read(base, self.y);
+                self.read_byte(address as usize)
+            }
+            AddressingMode::ZeroPageXIndirect => {
+                let pointer = self.fetch_byte().wrapping_add(self.x);
+                let address = self.read_pointer(pointer as usize);
+                self.read(address as usize, Access::Indirect)
+            }
+            AddressingMode::ZeroPageIndirectY => {
+                let pointer = self.fetch_byte();
+                let base = self.read_pointer(pointer as usize);
+                let address = self.index_read(base, self.y);
+                self.read(address as usize, Access::Indirect)
+            }
+            AddressingMode::Implied
+            | AddressingMode::Accumulator
+            | AddressingMode::Indirect
+            | AddressingMode::Relative => unreachable!("{mode:?} operands aren't values"),
+        }
     }
 
-    /// fetch a word from memory while incrememting the pc each read (2 cycles)
-    fn fetch_word(&mut self) -> u16 {
-        let mut data = self.memory.data[self.pc as usize] as u16;
-        self.pc += 1;
-
-        data |= u16::from(self.memory.data[self.pc as usize]) << 8;
-        self.pc += 1;
-
-        data
+    /// mark a push to `address`, lowering the high-water mark to the stack
+    /// pointer when it's the first push there, by address rather than by the
+    /// stack pointer so a stack wrapped from $0100 to $01FF still counts
+    fn note_stack_depth(&mut self, address: u16) {
+        let (word, bit) = ((address as u8 >> 6) as usize, address & 0x3F);
+        if self.stack_used[word] & 1 << bit == 0 {
+            self.stack_used[word] |= 1 << bit;
+            self.stack_low = Some(self.sp);
+        }
     }
 
-    /// fetch a byte and increment the pc
-    fn fetch_byte(&mut self) -> u8 {
-        if self.pc as usize > memory::MAX_MEM {
-            panic!("PC exceeds max memory allocated {}", memory::MAX_MEM);
+    /// push a byte onto the stack
+    fn push_byte(&mut self, value: u8) {
+        let address = self.sp;
+        self.write_byte(address as usize, value);
+        // like the 8 bit register, the stack pointer wraps around page 1
+        self.sp = 0x0100 | (self.sp as u8).wrapping_sub(1) as u16;
+        self.note_stack_depth(address);
+        if self.observing() {
+            self.observers.notify(Event::StackPush { address, value });
         }
+    }
 
-        let data = self.memory.data[self.pc as usize];
-        self.pc += 1;
-        data
+    /// pull a byte from the stack
+    fn pull_byte(&mut self) -> u8 {
+        self.sp = 0x0100 | (self.sp as u8).wrapping_add(1) as u16;
+        let address = self.sp;
+        let value = self.memory.read_byte(address as usize);
+        self.record_bus(address, value, false, false);
+        if self.observing() {
+            self.observers.notify(Event::StackPull { address, value });
+        }
+        value
     }
 
     /* LOAD A INSTRUCTIONS */
@@ -176,51 +1982,53 @@ impl Cpu {
     /// load accumulator absolute
     fn lda_absolute(&mut self) {
         let abs_address = self.fetch_word();
-        self.a = self.memory.read_byte(abs_address as usize);
+        self.a = self.read_byte(abs_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load accumulator absolute x indexed
     fn lda_absolute_x_indexed(&mut self) {
-        let abs_address = self.fetch_word() + self.x as u16;
-        self.a = self.memory.read_byte(abs_address as usize);
+        let base = self.fetch_word();
+        let abs_address = self.index_read(base, self.x);
+        self.a = self.read_byte(abs_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load accumulator absolute y indexed
     fn lda_absolute_y_indexed(&mut self) {
-        let abs_address = self.fetch_word() + self.y as u16;
-        self.a = self.memory.read_byte(abs_address as usize);
+        let base = self.fetch_word();
+        let abs_address = self.index_read(base, self.y);
+        self.a = self.read_byte(abs_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load accumulator zero page
     fn lda_zp(&mut self) {
         let zero_page_address = self.fetch_byte();
-        self.a = self.memory.read_byte(zero_page_address as usize);
+        self.a = self.read_byte(zero_page_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load accumulator zero page x indexed
     fn lda_zp_x(&mut self) {
         let zero_page_address = self.fetch_byte();
-        self.a = self.memory.read_byte((zero_page_address) as usize) + self.x;
+        self.a = self.read_byte((zero_page_address) as usize) + self.x;
         self.set_negative_and_zero_flags();
     }
 
     /// load accumulator indexed zero page indirect
     fn lda_x_indexed_zero_page_indirect(&mut self) {
         let indirect_address = self.fetch_byte() + self.x;
-        self.a = self.memory.read_byte(indirect_address as usize);
+        self.a = self.read_byte(indirect_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load accumulator zero page indirect y indexed
     fn lda_y_zero_page_indirect_indexed(&mut self) {
         let zero_page_address = self.fetch_byte();
-        let effective_address = self.memory.read_word(zero_page_address as usize);
-        let effective_address_y = effective_address + self.y as u16;
-        self.a = self.memory.read_byte(effective_address_y as usize);
+        let effective_address = self.read_pointer(zero_page_address as usize);
+        let effective_address_y = self.index_read(effective_address, self.y);
+        self.a = self.read(effective_address_y as usize, Access::Indirect);
         self.set_negative_and_zero_flags();
     }
 
@@ -241,28 +2049,29 @@ impl Cpu {
     /// load x index absolute mode
     fn ldx_absolute(&mut self) {
         let abs_address = self.fetch_word();
-        self.x = self.memory.read_byte(abs_address as usize);
+        self.x = self.read_byte(abs_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load x index from zero page
     fn ldx_zp(&mut self) {
         let zero_page_address = self.fetch_byte();
-        self.x = self.memory.read_byte(zero_page_address as usize);
+        self.x = self.read_byte(zero_page_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load x index y indexed absolute
     fn ldx_absolute_y_indexed(&mut self) {
-        let abs_address = self.fetch_word() + self.y as u16;
-        self.x = self.memory.read_byte(abs_address as usize);
+        let base = self.fetch_word();
+        let abs_address = self.index_read(base, self.y);
+        self.x = self.read_byte(abs_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load x index y indexed zero page
     fn ldx_y_indexed_zero_page(&mut self) {
         let zero_page_address = self.fetch_byte();
-        self.x = self.memory.read_byte((zero_page_address) as usize) + self.y;
+        self.x = self.read_byte((zero_page_address) as usize) + self.y;
         self.set_negative_and_zero_flags();
     }
 
@@ -276,28 +2085,29 @@ impl Cpu {
     /// load y index absolute mode
     fn ldy_absolute(&mut self) {
         let abs_address = self.fetch_word();
-        self.y = self.memory.read_byte(abs_address as usize);
+        self.y = self.read_byte(abs_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load y index from zero page
     fn ldy_zp(&mut self) {
         let zero_page_address = self.fetch_byte();
-        self.y = self.memory.read_byte(zero_page_address as usize);
+        self.y = self.read_byte(zero_page_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load y index x indexed absolute
     fn ldy_absolute_x_indexed(&mut self) {
-        let abs_address = self.fetch_word() + self.x as u16;
-        self.y = self.memory.read_byte(abs_address as usize);
+        let base = self.fetch_word();
+        let abs_address = self.index_read(base, self.x);
+        self.y = self.read_byte(abs_address as usize);
         self.set_negative_and_zero_flags();
     }
 
     /// load x index y indexed zero page
     fn ldy_x_indexed_zero_page(&mut self) {
         let zero_page_address = self.fetch_byte();
-        self.y = self.memory.read_byte((zero_page_address) as usize) + self.x;
+        self.y = self.read_byte((zero_page_address) as usize) + self.x;
         self.set_negative_and_zero_flags();
     }
 
@@ -307,356 +2117,882 @@ impl Cpu {
 
     fn jump_absolute_indirect(&mut self) {
         let indirect_address = self.fetch_word() as usize;
-        let low_byte = self.memory.read_byte(indirect_address);
+        let low_byte = self.read(indirect_address, Access::Pointer);
 
-        // do not cross page boundary
-        let hi_byte_address = if indirect_address as u8 == 0xFF {
+        // the NMOS 6502 doesn't carry into the high byte of the pointer,
+        // the 65C02 does but spends an extra cycle
+        let hi_byte_address = if !self.quirks.indirect_jump_page_wrap {
+            if !self.fast {
+                self.cycles += 1;
+            }
+            (indirect_address + 1) & 0xFFFF
+        } else if indirect_address as u8 == 0xFF {
             indirect_address & 0xFF00
         } else {
             indirect_address + 1
         };
 
-        let hi_byte = self.memory.read_byte(hi_byte_address);
+        let hi_byte = self.read(hi_byte_address, Access::Pointer);
 
         self.pc = u16::from_le_bytes([low_byte, hi_byte]);
     }
 
     /// jump to a subroutine by pushing the pc onto the stack and modifying the pc
+    /// the address of the last byte of the JSR is pushed, high byte first
     fn jump_subroutine(&mut self) {
         let sub_address = self.fetch_word();
-        self.memory.write_word(self.sp as usize, (self.pc - 1));
-        self.sp -= 2;
+        let [low, high] = self.pc.wrapping_sub(1).to_le_bytes();
+        self.push_byte(high);
+        self.push_byte(low);
         self.pc = sub_address;
     }
 
     /// return from subroutine, taking PC from stack and continuing before the jump
     fn return_subroutine(&mut self) {
-        self.sp += 1;
-        let pch = self.memory.read_byte(self.sp as usize);
-        self.sp += 1;
-        let pcl = self.memory.read_byte(self.sp as usize);
-        self.pc = (((pch as u16) << 8) | pcl as u16) + 1;
+        let pcl = self.pull_byte();
+        let pch = self.pull_byte();
+        self.pc = u16::from_le_bytes([pcl, pch]).wrapping_add(1);
     }
 
-    /* AND Accumulator logical instructions */
-    /// AND accumulator immediate mode
-    fn anda_im(&mut self) {
-        self.a &= self.fetch_byte();
-        self.set_negative_and_zero_flags();
+    /// branch by the signed offset operand when `condition` holds
+    /// a taken branch costs an extra cycle, and another if it lands on a different page
+    fn branch(&mut self, condition: bool) {
+        let offset = self.fetch_byte() as i8;
+        if !condition {
+            return;
+        }
+
+        let target = self.pc.wrapping_add(offset as u16);
+        if !self.fast {
+            self.cycles += if target & 0xFF00 == self.pc & 0xFF00 {
+                1
+            } else {
+                2
+            };
+        }
+        self.pc = target;
     }
 
-    /// AND accumulator absolute mode
-    fn anda_abs(&mut self) {
-        let absolute_address = self.fetch_word();
-        let value = self.memory.read_byte(absolute_address as usize);
-        self.a &= value;
+    /* logical shift right instructions */
+    /// logical shift right accumulator mode
+    fn lsr_acc(&mut self) {
+        let carry = self.a & 1;
+        self.a >>= 1;
         self.set_negative_and_zero_flags();
+        self.set_carry_flag(carry > 0);
     }
 
-    /// AND accumulator absolute x indexed
-    fn anda_abs_x(&mut self) {
-        let absolute_address = self.fetch_word();
-        let effective_address = absolute_address + self.x as u16;
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a &= value;
-        self.set_negative_and_zero_flags();
+    /// logical shift right absolute mode
+    fn lsr_abs(&mut self) {
+        let abs_address = self.fetch_word() as usize;
+        let original = self.read_byte(abs_address);
+
+        let carry = original & 1;
+        let data = original >> 1;
+
+        self.write_modified(abs_address, original, data);
+
+        // set flags
+        self.ps.set(ProcessorStatus::N, false);
+        self.ps.set(ProcessorStatus::Z, data == 0);
+        self.set_carry_flag(carry > 0);
     }
 
-    /// AND accumulator absolute y indexed
-    fn anda_abs_y(&mut self) {
-        let absolute_address = self.fetch_word();
-        let effective_address = absolute_address + self.y as u16;
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a &= value;
-        self.set_negative_and_zero_flags();
+    /// logical shift right zero page
+    fn lsr_zp(&mut self) {
+        let zero_page_address = self.fetch_byte() as usize;
+        let original = self.read_byte(zero_page_address);
+
+        let carry = original & 1;
+        let data = original >> 1;
+        self.write_modified(zero_page_address, original, data);
+
+        // set flags
+        self.ps.set(ProcessorStatus::N, false);
+        self.ps.set(ProcessorStatus::Z, data == 0);
+        self.set_carry_flag(carry > 0);
     }
 
-    /// AND accumulator zero page
-    fn anda_zp(&mut self) {
-        let address = self.fetch_byte();
-        let value = self.memory.read_byte(address as usize);
-        self.a &= value;
-        self.set_negative_and_zero_flags();
+    /// logical shift right absolute x indexed
+    fn lsr_abs_x(&mut self) {
+        let abs_address = self.fetch_word() as usize;
+        let effective_address = abs_address + self.x as usize;
+
+        let original = self.read_byte(effective_address);
+        let carry = original & 1;
+        let data = original >> 1;
+
+        self.write_modified(effective_address, original, data);
+
+        // set flags
+        self.ps.set(ProcessorStatus::N, false);
+        self.ps.set(ProcessorStatus::Z, data == 0);
+        self.set_carry_flag(carry > 0);
     }
 
-    /// AND accumulator zero page x indexed
-    fn anda_zp_x(&mut self) {
-        let address = self.fetch_byte();
-        let effective_address = address + self.x;
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a &= value;
+    /// logical shift right zero page x indexed
+    fn lsr_zp_x(&mut self) {
+        let zero_page_address = self.fetch_byte() as usize;
+        let effective_address = zero_page_address + self.x as usize;
+        let data = self.read_byte(effective_address);
+
+        self.write_modified(effective_address, data, data >> 1);
+
         self.set_negative_and_zero_flags();
+        self.set_carry_flag((data & 1) > 0);
     }
 
-    /// AND accumulator zero page indirect y indexed
-    fn anda_zp_iy(&mut self) {
-        let zero_page_address = self.fetch_byte();
-        let indirect_address = self.memory.read_word(zero_page_address as usize) + self.y as u16;
-        let value = self.memory.read_byte(indirect_address as usize);
-        self.a &= value;
-        self.set_negative_and_zero_flags();
+    /// sets the carry bit if flag is true in processor status register
+    fn set_carry_flag(&mut self, flag: bool) {
+        self.ps.set(ProcessorStatus::C, flag);
     }
 
-    /// AND accumulator zero page x indexed indirect
-    fn anda_zp_xi(&mut self) {
-        let address = self.fetch_byte();
-        let indirect_address = address + self.x;
-        let effective_address = self.memory.read_word(indirect_address as usize);
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a &= value;
-        self.set_negative_and_zero_flags();
+    /// set decimal mode
+    /// This is a no-op unless decimal mode support is enabled on the builder
+    fn set_decimal_mode(&mut self) {
+        if self.decimal_mode {
+            self.ps.set(ProcessorStatus::D, true);
+        }
     }
 
-    /* OR Accumulator logical instructions */
-    /// OR accumulator immediate mode
-    fn ora_im(&mut self) {
-        self.a |= self.fetch_byte();
-        self.set_negative_and_zero_flags();
+    /// sets the interupt disable flag to true
+    fn set_interrupt_disable_flag(&mut self) {
+        self.ps.set(ProcessorStatus::I, true);
     }
 
-    /// OR accumulator absolute mode
-    fn ora_abs(&mut self) {
-        let absolute_address = self.fetch_word();
-        let value = self.memory.read_byte(absolute_address as usize);
-        self.a |= value;
-        self.set_negative_and_zero_flags();
+    /// software interrupt, the byte after BRK is skipped as padding
+    fn force_break(&mut self) {
+        self.pc = self.pc.wrapping_add(1);
+        self.interrupt(IRQ_VECTOR, true);
     }
 
-    /// OR accumulator absolute x indexed
-    fn ora_abs_x(&mut self) {
-        let absolute_address = self.fetch_word();
-        let effective_address = absolute_address + self.x as u16;
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a |= value;
-        self.set_negative_and_zero_flags();
+    /// return from interrupt, restoring the status and pc
+    fn return_interrupt(&mut self) {
+        let ps = self.pull_byte();
+        self.ps = ProcessorStatus::pulled(ps);
+        let low = self.pull_byte();
+        let high = self.pull_byte();
+        self.pc = u16::from_le_bytes([low, high]);
     }
 
-    /// OR accumulator absolute y indexed
-    fn ora_abs_y(&mut self) {
-        let absolute_address = self.fetch_word();
-        let effective_address = absolute_address + self.y as u16;
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a |= value;
-        self.set_negative_and_zero_flags();
+    /// push accumulator on the stack
+    fn pha(&mut self) {
+        self.push_byte(self.a);
     }
 
-    /// OR accumulator zero page
-    fn ora_zp(&mut self) {
-        let address = self.fetch_byte();
-        let value = self.memory.read_byte(address as usize);
-        self.a |= value;
-        self.set_negative_and_zero_flags();
+    /// push processor status on the stack
+    fn php(&mut self) {
+        self.push_byte(self.ps.pushed());
     }
 
-    /// OR accumulator zero page x indexed
-    fn ora_zp_x(&mut self) {
-        let address = self.fetch_byte();
-        let effective_address = address + self.x;
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a |= value;
+    /// pop accumulator from stack
+    fn pla(&mut self) {
+        self.a = self.pull_byte();
         self.set_negative_and_zero_flags();
     }
 
-    /// OR accumulator zero page indirect y indexed
-    fn ora_zp_iy(&mut self) {
-        let zero_page_address = self.fetch_byte();
-        let indirect_address = self.memory.read_word(zero_page_address as usize) + self.y as u16;
-        let value = self.memory.read_byte(indirect_address as usize);
-        self.a |= value;
-        self.set_negative_and_zero_flags();
+    /// pop processor status from stack
+    fn plp(&mut self) {
+        let ps = self.pull_byte();
+        self.ps = ProcessorStatus::pulled(ps);
     }
 
-    /// OR accumulator zero page x indexed indirect
-    fn ora_zp_xi(&mut self) {
-        let address = self.fetch_byte();
-        let indirect_address = address + self.x;
-        let effective_address = self.memory.read_word(indirect_address as usize);
-        let value = self.memory.read_byte(effective_address as usize);
-        self.a |= value;
+    /* Implied transfer instructions */
+    /// transfer accumulator to index x
+    fn transfer_a_to_x(&mut self) {
+        self.x = self.a;
+
+        self.ps.set(ProcessorStatus::Z, self.x == 0);
+        self.ps.set(ProcessorStatus::N, (self.x & 0x80) > 0);
+    }
+
+    /// transfer accumulator to index y
+    fn transfer_a_to_y(&mut self) {
+        self.y = self.a;
+
+        self.ps.set(ProcessorStatus::Z, self.y == 0);
+        self.ps.set(ProcessorStatus::N, (self.y & 0x80) > 0);
+    }
+
+    /// transfer stack pointer to x
+    fn transfer_sp_to_x(&mut self) {
+        // TODO: stack is a fixed area of memory at 0x0100 to 0x01FF
+        // but is represented as 16 bits. sp should be u8 and
+        // compensate for the high byte when pushing/pulling
+        self.x = self.sp as u8;
+
+        self.ps.set(ProcessorStatus::Z, self.x == 0);
+        self.ps.set(ProcessorStatus::N, (self.x & 0x80) > 0);
+    }
+
+    /// transfer index x to accumulator
+    fn transfer_x_to_a(&mut self) {
+        self.a = self.x;
         self.set_negative_and_zero_flags();
     }
 
-    /* logical shift right instructions */
-    /// logical shift right accumulator mode
-    fn lsr_acc(&mut self) {
-        let carry = self.a & 1;
-        self.a >>= 1;
+    /// transfer index x to stack pointer
+    fn transfer_x_to_sp(&mut self) {
+        self.sp = 0x0100 | (self.x as u16);
+    }
+
+    /// transfer index y to accumulator
+    fn transfer_y_to_a(&mut self) {
+        self.a = self.y;
         self.set_negative_and_zero_flags();
-        self.set_carry_flag(carry > 0);
     }
 
-    /// logical shift right absolute mode
-    fn lsr_abs(&mut self) {
-        let abs_address = self.fetch_word() as usize;
-        let mut data = self.memory.read_byte(abs_address);
+    /// no-op (do nothing)
+    fn nop(&mut self) {}
+
+    /// run one of the NMOS 6502's undocumented opcodes
+    /// returns false for opcodes that are documented, only not implemented
+    fn illegal(&mut self, opcode: u8) -> bool {
+        let mode = nmos_mode(opcode);
+        match opcode {
+            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => {}
+            0x04 | 0x44 | 0x64 | 0x0C | 0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 | 0x1C | 0x3C
+            | 0x5C | 0x7C | 0xDC | 0xFC | 0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => {
+                self.read_operand(mode);
+            }
+            // SLO
+            0x03 | 0x07 | 0x0F | 0x13 | 0x17 | 0x1B | 0x1F => {
+                self.a |= self.read_modify_write(mode, |cpu, value| cpu.shift_left(value, false));
+                self.set_negative_and_zero_flags();
+            }
+            // RLA
+            0x23 | 0x27 | 0x2F | 0x33 | 0x37 | 0x3B | 0x3F => {
+                self.a &= self.read_modify_write(mode, |cpu, value| {
+                    let carry = cpu.carry();
+                    cpu.shift_left(value, carry)
+                });
+                self.set_negative_and_zero_flags();
+            }
+            // SRE
+            0x43 | 0x47 | 0x4F | 0x53 | 0x57 | 0x5B | 0x5F => {
+                self.a ^= self.read_modify_write(mode, |cpu, value| cpu.shift_right(value, false));
+                self.set_negative_and_zero_flags();
+            }
+            // RRA
+            0x63 | 0x67 | 0x6F | 0x73 | 0x77 | 0x7B | 0x7F => {
+                let value = self.read_modify_write(mode, |cpu, value| {
+                    let carry = cpu.carry();
+                    cpu.shift_right(value, carry)
+                });
+                self.add_with_carry(value);
+            }
+            // SAX
+            0x83 | 0x87 | 0x8F | 0x97 => {
+                let address = self.operand_address(mode);
+                self.write_byte(address as usize, self.a & self.x);
+            }
+            // LAX
+            0xA3 | 0xA7 | 0xAF | 0xB3 | 0xB7 | 0xBF => {
+                self.a = self.read_operand(mode);
+                self.x = self.a;
+                self.set_negative_and_zero_flags();
+            }
+            // DCP
+            0xC3 | 0xC7 | 0xCF | 0xD3 | 0xD7 | 0xDB | 0xDF => {
+                let value = self.read_modify_write(mode, |_, value| value.wrapping_sub(1));
+                self.compare(self.a, value);
+            }
+            // ISC
+            0xE3 | 0xE7 | 0xEF | 0xF3 | 0xF7 | 0xFB | 0xFF => {
+                let value = self.read_modify_write(mode, |_, value| value.wrapping_add(1));
+                self.add_with_carry(!value);
+            }
+            // ANC
+            0x0B | 0x2B => {
+                self.a &= self.fetch_byte();
+                self.set_negative_and_zero_flags();
+                self.set_carry(self.negative());
+            }
+            // ALR
+            0x4B => {
+                let value = self.a & self.fetch_byte();
+                self.a = self.shift_right(value, false);
+                self.set_negative_and_zero_flags();
+            }
+            // ARR
+            0x6B => {
+                let value = self.a & self.fetch_byte();
+                self.a = (value >> 1) | ((self.carry() as u8) << 7);
+                self.set_negative_and_zero_flags();
+                self.set_carry(self.a & 0x40 != 0);
+                self.set_overflow((self.a ^ (self.a << 1)) & 0x40 != 0);
+            }
+            // XAA, with the magic constant most NMOS parts use
+            0x8B => {
+                self.a = (self.a | 0xEE) & self.x & self.fetch_byte();
+                self.set_negative_and_zero_flags();
+            }
+            // LXA
+            0xAB => {
+                self.a = (self.a | 0xEE) & self.fetch_byte();
+                self.x = self.a;
+                self.set_negative_and_zero_flags();
+            }
+            // SBX
+            0xCB => {
+                let value = self.fetch_byte();
+                let masked = self.a & self.x;
+                self.compare(masked, value);
+                self.x = masked.wrapping_sub(value);
+            }
+            // SBC
+            0xEB => {
+                let value = self.fetch_byte();
+                self.add_with_carry(!value);
+            }
+            // SHA ($nn),Y
+            0x93 => {
+                let pointer = self.fetch_byte();
+                let base = self.read_pointer(pointer as usize);
+                self.store_high_and(self.a & self.x, base, self.y);
+            }
+            // SHA, SHX, SHY and TAS $nnnn,X or Y
+            0x9F | 0x9E | 0x9C | 0x9B => {
+                let base = self.fetch_word();
+                let (value, index) = match opcode {
+                    0x9F => (self.a & self.x, self.y),
+                    0x9E => (self.x, self.y),
+                    0x9C => (self.y, self.x),
+                    _ => {
+                        self.sp = (self.sp & 0xFF00) | (self.a & self.x) as u16;
+                        (self.sp as u8, self.y)
+                    }
+                };
+                self.store_high_and(value, base, index);
+            }
+            // LAS
+            0xBB => {
+                let value = self.read_operand(mode) & self.sp as u8;
+                self.sp = (self.sp & 0xFF00) | value as u16;
+                self.a = value;
+                self.x = value;
+                self.set_negative_and_zero_flags();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// fetch an instruction's operand and work out the address it refers to,
+    /// for writes, which take the same time whether or not a page is crossed
+    fn operand_address(&mut self, mode: AddressingMode) -> u16 {
+        match mode {
+            AddressingMode::ZeroPage => self.fetch_byte() as u16,
+            AddressingMode::ZeroPageX => self.fetch_byte().wrapping_add(self.x) as u16,
+            AddressingMode::ZeroPageY => self.fetch_byte().wrapping_add(self.y) as u16,
+            AddressingMode::Absolute => self.fetch_word(),
+            AddressingMode::AbsoluteX => self.fetch_word().wrapping_add(self.x as u16),
+            AddressingMode::AbsoluteY => self.fetch_word().wrapping_add(self.y as u16),
+            AddressingMode::ZeroPageXIndirect => {
+                let pointer = self.fetch_byte().wrapping_add(self.x);
+                self.read_pointer(pointer as usize)
+            }
+            AddressingMode::ZeroPageIndirectY => {
+                let pointer = self.fetch_byte();
+                let base = self.read_pointer(pointer as usize);
+                base.wrapping_add(self.y as u16)
+            }
+            _ => unreachable!("{mode:?} operands aren't addresses"),
+        }
+    }
 
-        let carry = data & 1;
-        data >>= 1;
+    /// read the operand's address, change the value and write it back,
+    /// returning the new value
+    fn read_modify_write(&mut self, mode: AddressingMode, modify: fn(&mut Cpu, u8) -> u8) -> u8 {
+        let address = self.operand_address(mode) as usize;
+        let value = self.read_byte(address);
+        let modified = modify(self, value);
+        self.write_modified(address, value, modified);
+        modified
+    }
 
-        self.memory.write_byte(abs_address, data);
+    /// shift left, the top bit going to carry and `carry_in` filling bit 0
+    fn shift_left(&mut self, value: u8, carry_in: bool) -> u8 {
+        self.set_carry(value & 0x80 != 0);
+        (value << 1) | carry_in as u8
+    }
 
-        // set flags
-        self.ps.set(ProcessorStatus::N, false);
-        self.ps.set(ProcessorStatus::Z, data == 0);
-        self.set_carry_flag(carry > 0);
+    /// shift right, bit 0 going to carry and `carry_in` filling the top bit
+    fn shift_right(&mut self, value: u8, carry_in: bool) -> u8 {
+        self.set_carry(value & 0x01 != 0);
+        (value >> 1) | ((carry_in as u8) << 7)
     }
 
-    /// logical shift right zero page
-    fn lsr_zp(&mut self) {
-        let zero_page_address = self.fetch_byte() as usize;
-        let mut data = self.memory.read_byte(zero_page_address);
+    /// binary add with carry into the accumulator, subtracting is adding the
+    /// complement
+    fn add_with_carry(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + self.carry() as u16;
+        let result = sum as u8;
+        self.set_carry(sum > 0xFF);
+        self.set_overflow((!(self.a ^ value) & (self.a ^ result)) & 0x80 != 0);
+        self.a = result;
+        self.set_negative_and_zero_flags();
+    }
+
+    /// set the flags the way CMP does for `register` minus `value`
+    fn compare(&mut self, register: u8, value: u8) {
+        let difference = register.wrapping_sub(value);
+        self.set_carry(register >= value);
+        self.set_zero(difference == 0);
+        self.set_negative(difference & 0x80 != 0);
+    }
 
-        let carry = data & 1;
-        data >>= 1;
-        self.memory.write_byte(zero_page_address, data);
+    /// the unstable stores of SHA, SHX, SHY and TAS, which AND the value with
+    /// the high byte of the base address plus one
+    fn store_high_and(&mut self, value: u8, base: u16, index: u8) {
+        let address = base.wrapping_add(index as u16);
+        let high = ((base >> 8) as u8).wrapping_add(1);
+        self.write_byte(address as usize, value & high);
+    }
+}
 
-        // set flags
-        self.ps.set(ProcessorStatus::N, false);
-        self.ps.set(ProcessorStatus::Z, data == 0);
-        self.set_carry_flag(carry > 0);
+impl fmt::Display for Cpu {
+    /// contents of registers, pc, sp, and status flags and current instruction
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "pc: 0x{:04x}", self.pc)?;
+        writeln!(f, "sp: 0x{:04x}", self.sp)?;
+        writeln!(f, "a : 0x{:04x}", self.a)?;
+        writeln!(f, "x : 0x{:04x}", self.x)?;
+        writeln!(f, "y : 0x{:04x}", self.y)?;
+        writeln!(f, "ps: {}", self.ps)?;
+        write!(
+            f,
+            "current instruction: 0x{:02X}",
+            self.memory.read_byte(self.pc as usize)
+        )
     }
+}
 
-    /// logical shift right absolute x indexed
-    fn lsr_abs_x(&mut self) {
-        let abs_address = self.fetch_word() as usize;
-        let effective_address = abs_address + self.x as usize;
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{
+        nmos_mode, Cpu, CpuBuilder, CpuError, UnknownOpcodePolicy, Variant, IRQ_VECTOR, NMI_VECTOR,
+        NMOS_CYCLES, RESET_VECTOR,
+    };
+    use crate::events::{Access, Event, EventLog};
+    use crate::memory::{BusError, RomImage};
+    use crate::op_codes::*;
+    use crate::permissions::{Fault, Operation, Permissions, ViolationPolicy};
+    use crate::processor_status::ProcessorStatus;
+
+    #[test]
+    fn new_cpu_should_initialize_defaults() {
+        let cpu = Cpu::new().reset(None);
+        assert_eq!(cpu.pc, 0xFFFC);
+    }
+
+    #[test]
+    fn reset_cpu_with_address_should_fetch_from_correct_address() {
+        let cpu = Cpu::new().reset(0x0010.into());
+        assert_eq!(cpu.pc, 0x0010);
+    }
+
+    #[test]
+    fn load_program_should_copy_program_to_address() {
+        let mut cpu = Cpu::new().reset(0x0600.into());
+        cpu.load_program(0x0600, vec![LDA_IM, 0x42, NOP]).unwrap();
 
-        let mut data = self.memory.read_byte(effective_address);
-        let carry = data & 1;
-        data >>= 1;
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x42);
+    }
 
-        self.memory.write_byte(effective_address, data);
+    #[test]
+    fn step_should_return_false_when_halted() {
+        let mut cpu = Cpu::new().reset(0x0001.into());
+        cpu.memory.data[0x0001] = LDA_IM;
+        cpu.memory.data[0x0002] = 0x42;
+        cpu.memory.data[0x0003] = NOP;
 
-        // set flags
-        self.ps.set(ProcessorStatus::N, false);
-        self.ps.set(ProcessorStatus::Z, data == 0);
-        self.set_carry_flag(carry > 0);
+        assert!(cpu.step().unwrap());
+        assert!(!cpu.step().unwrap());
     }
 
-    /// logical shift right zero page x indexed
-    fn lsr_zp_x(&mut self) {
-        let zero_page_address = self.fetch_byte() as usize;
-        let effective_address = zero_page_address + self.x as usize;
-        let data = self.memory.read_byte(effective_address);
-
-        self.memory.write_byte(effective_address, data >> 1);
+    #[test]
+    fn load_program_should_fail_past_end_of_memory() {
+        let mut cpu = Cpu::new().reset(None);
+        assert_eq!(
+            cpu.load_program(0xFFFF, vec![NOP, NOP]),
+            Err(BusError::OutOfRange {
+                address: 0xFFFF,
+                len: 2
+            })
+        );
+    }
 
-        self.set_negative_and_zero_flags();
-        self.set_carry_flag((data & 1) > 0);
+    #[test]
+    fn step_should_fail_on_unrecognized_instruction() {
+        let mut cpu = Cpu::new().reset(0x0001.into());
+        cpu.memory.data[0x0001] = 0xFF;
+
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::UnrecognizedInstruction {
+                opcode: 0xFF,
+                pc: 0x0001
+            })
+        );
     }
 
-    /// sets the carry bit if flag is true in processor status register
-    fn set_carry_flag(&mut self, flag: bool) {
-        self.ps.set(ProcessorStatus::C, flag);
+    #[test]
+    fn every_opcode_but_nop_should_have_a_handler() {
+        for variant in [Variant::Nmos, Variant::Cmos] {
+            for info in INSTRUCTIONS {
+                let handled = super::handlers(variant)[u8::from(info.opcode) as usize].is_some();
+                let expected = info.opcode != Opcode::Nop && info.availability.includes(variant);
+                assert_eq!(handled, expected, "{:?} on {variant:?}", info.opcode);
+            }
+        }
     }
 
-    /// set decimal mode
-    /// This is a no-op and is not supported but is here for completeness
-    fn set_decimal_mode(&self) {}
+    #[test]
+    fn step_should_count_cycles() {
+        let mut cpu = Cpu::new().reset(0x0001.into());
+        cpu.memory.data[0x0001] = LDA_IM;
+        cpu.memory.data[0x0002] = 0x42;
+        cpu.memory.data[0x0003] = PHA;
+        cpu.memory.data[0x0004] = NOP;
 
-    /// sets the interupt disable flag to true
-    fn set_interrupt_disable(&mut self) {
-        self.ps.set(ProcessorStatus::I, true);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.cycles(), 7);
+        assert_eq!(cpu.instructions(), 2);
     }
 
-    /// push accumulator on the stack
-    fn pha(&mut self) {
-        self.memory.write_byte(self.sp as usize, self.a);
-        self.sp -= 1;
+    #[test]
+    fn builder_should_configure_cpu() {
+        let cpu = Cpu::builder()
+            .variant(Variant::Cmos)
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![LDA_IM, 0x42, NOP])
+            .build()
+            .unwrap();
+
+        assert_eq!(cpu.variant(), Variant::Cmos);
+        assert_eq!(cpu.pc, 0x0600);
+        assert_eq!(cpu.sp, 0x01FF);
+        assert_eq!(cpu.memory.read_byte(0x0601), 0x42);
     }
 
-    /// push processor status on the stack
-    fn php(&mut self) {
-        self.memory.write_byte(self.sp as usize, self.ps.bits());
-        self.sp -= 1;
+    #[test]
+    fn builder_should_map_roms() {
+        static ROM: [u8; 3] = [LDA_IM, 0x42, NOP];
+        let mut cpu = Cpu::builder()
+            .pc(0xF000)
+            .rom(0xF000, RomImage::Static(&ROM))
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x42);
     }
 
-    /// pop accumulator from stack
-    fn pla(&mut self) {
-        self.sp += 1;
-        self.a = self.memory.read_byte(self.sp as usize);
-        self.set_negative_and_zero_flags();
+    #[test]
+    fn builder_without_pc_should_start_at_reset_vector() {
+        let cpu = Cpu::builder().build().unwrap();
+        assert_eq!(cpu.pc, 0xFFFC);
     }
 
-    /// pop processor status from stack
-    fn plp(&mut self) {
-        self.sp += 1;
-        let ps = self.memory.read_byte(self.sp as usize);
-        self.ps = ProcessorStatus::from_bits_truncate(ps);
+    #[test]
+    fn builder_hooks_should_run_after_each_instruction() {
+        fn check_a(cpu: &Cpu) {
+            assert_eq!(cpu.a, 0x42);
+        }
+
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, TAX, NOP])
+            .hook(check_a)
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.x, 0x42);
     }
 
-    /* Implied transfer instructions */
-    /// transfer accumulator to index x
-    fn transfer_a_to_x(&mut self) {
-        self.x = self.a;
+    #[test]
+    fn traps_should_run_before_the_instruction_at_their_address() {
+        let set_a = |cpu: &mut Cpu| cpu.set_a(0x42);
+        for fast in [false, true] {
+            let mut cpu = Cpu::builder()
+                .pc(0x0600)
+                .memory(0x0600, vec![LDX_IM, 0x01, TAX, NOP])
+                .trap(0x0602, Rc::new(RefCell::new(set_a)))
+                .block_cache(true)
+                .fast(fast)
+                .build()
+                .unwrap();
+
+            cpu.execute().unwrap();
+            assert_eq!(cpu.x, 0x42);
+        }
+    }
 
-        self.ps.set(ProcessorStatus::Z, self.x == 0);
-        self.ps.set(ProcessorStatus::N, (self.x & 0x80) > 0);
+    #[test]
+    fn observers_should_receive_events() {
+        let log = Rc::new(RefCell::new(EventLog::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, PHA, PLA, LSR_ZP, 0x10, NOP])
+            .observer(log.clone())
+            .build()
+            .unwrap();
+        cpu.memory.data[0x0010] = 0x04;
+
+        cpu.execute().unwrap();
+        assert_eq!(
+            log.borrow().events,
+            vec![
+                Event::InstructionRetired {
+                    pc: 0x0600,
+                    opcode: LDA_IM,
+                    cycles: 2
+                },
+                Event::MemoryWritten {
+                    address: 0x0100,
+                    value: 0x42
+                },
+                Event::StackPush {
+                    address: 0x0100,
+                    value: 0x42
+                },
+                Event::InstructionRetired {
+                    pc: 0x0602,
+                    opcode: PHA,
+                    cycles: 5
+                },
+                Event::StackPull {
+                    address: 0x0100,
+                    value: 0x42
+                },
+                Event::InstructionRetired {
+                    pc: 0x0603,
+                    opcode: PLA,
+                    cycles: 9
+                },
+                Event::MemoryRead {
+                    address: 0x0010,
+                    value: 0x04,
+                    access: Access::Data
+                },
+                Event::MemoryWritten {
+                    address: 0x0010,
+                    value: 0x02
+                },
+                Event::InstructionRetired {
+                    pc: 0x0604,
+                    opcode: LSR_ZP,
+                    cycles: 14
+                },
+            ]
+        );
     }
 
-    /// transfer accumulator to index y
-    fn transfer_a_to_y(&mut self) {
-        self.y = self.a;
+    #[test]
+    fn accurate_mode_should_write_back_unmodified_value() {
+        let build = |accurate| {
+            let log = Rc::new(RefCell::new(EventLog::default()));
+            let mut cpu = Cpu::builder()
+                .pc(0x0600)
+                .memory(0x0600, vec![LSR_ZP, 0x10, NOP])
+                .memory(0x0010, vec![0x04])
+                .observer(log.clone())
+                .accurate(accurate)
+                .build()
+                .unwrap();
+            cpu.execute().unwrap();
+
+            let writes: Vec<u8> = log
+                .borrow()
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::MemoryWritten { value, .. } => Some(*value),
+                    _ => None,
+                })
+                .collect();
+            writes
+        };
 
-        self.ps.set(ProcessorStatus::Z, self.y == 0);
-        self.ps.set(ProcessorStatus::N, (self.y & 0x80) > 0);
+        assert_eq!(build(false), vec![0x02]);
+        assert_eq!(build(true), vec![0x04, 0x02]);
     }
 
-    /// transfer stack pointer to x
-    fn transfer_sp_to_x(&mut self) {
-        // TODO: stack is a fixed area of memory at 0x0100 to 0x01FF
-        // but is represented as 16 bits. sp should be u8 and
-        // compensate for the high byte when pushing/pulling
-        self.x = self.sp as u8;
+    #[test]
+    fn accurate_mode_should_publish_bus_cycles_with_sync_on_opcode_fetches() {
+        let build = |accurate| {
+            let log = Rc::new(RefCell::new(EventLog::default()));
+            let mut cpu = Cpu::builder()
+                .pc(0x0600)
+                .memory(0x0600, vec![LDA_ZP, 0x10, TAX, NOP])
+                .observer(log.clone())
+                .accurate(accurate)
+                .build()
+                .unwrap();
+            cpu.execute().unwrap();
+
+            let bus: Vec<(u64, u16, bool)> = log
+                .borrow()
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::BusAccess(bus) => Some((bus.cycle, bus.address, bus.sync)),
+                    _ => None,
+                })
+                .collect();
+            bus
+        };
 
-        self.ps.set(ProcessorStatus::Z, self.x == 0);
-        self.ps.set(ProcessorStatus::N, (self.x & 0x80) > 0);
+        assert_eq!(build(false), vec![]);
+        assert_eq!(
+            build(true),
+            vec![
+                (0, 0x0600, true),
+                (1, 0x0601, false),
+                (2, 0x0010, false),
+                (3, 0x0602, true),
+                (5, 0x0603, true),
+            ]
+        );
     }
 
-    /// transfer index x to accumulator
-    fn transfer_x_to_a(&mut self) {
-        self.a = self.x;
-        self.set_negative_and_zero_flags();
+    #[test]
+    fn indexed_reads_across_pages_should_cost_a_cycle_and_dummy_read() {
+        let build = |accurate| {
+            let log = Rc::new(RefCell::new(EventLog::default()));
+            let mut cpu = Cpu::builder()
+                .pc(0x0600)
+                .memory(0x0600, vec![LDX_IM, 0x20, LDA_ABS_X, 0xF0, 0x02, NOP])
+                .observer(log.clone())
+                .accurate(accurate)
+                .build()
+                .unwrap();
+            cpu.execute().unwrap();
+
+            let reads: Vec<(u16, Access)> = log
+                .borrow()
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::MemoryRead {
+                        address, access, ..
+                    } => Some((*address, *access)),
+                    _ => None,
+                })
+                .collect();
+            (cpu.cycles(), reads)
+        };
+
+        assert_eq!(build(false), (2 + 5 + 2, vec![(0x0310, Access::Data)]));
+        assert_eq!(
+            build(true),
+            (
+                2 + 5 + 2,
+                vec![(0x0210, Access::Dummy), (0x0310, Access::Data)]
+            )
+        );
     }
 
-    /// transfer index x to stack pointer
-    fn transfer_x_to_sp(&mut self) {
-        self.sp = 0x0100 | (self.x as u16);
+    #[test]
+    fn display_should_show_registers() {
+        let cpu = Cpu::builder().pc(0x0600).build().unwrap();
+        assert_eq!(
+            format!("{cpu}"),
+            "pc: 0x0600\nsp: 0x0100\na : 0x0000\nx : 0x0000\ny : 0x0000\nps: nv-bdizc\ncurrent instruction: 0x00"
+        );
     }
 
-    /// transfer index y to accumulator
-    fn transfer_y_to_a(&mut self) {
-        self.a = self.y;
-        self.set_negative_and_zero_flags();
+    #[test]
+    fn display_should_show_set_flags_in_uppercase() {
+        let mut cpu = Cpu::builder().pc(0x0600).build().unwrap();
+        cpu.set_zero(true);
+        cpu.set_carry(true);
+        assert!(cpu.zero() && !cpu.negative());
+        assert!(format!("{cpu}").contains("\nps: nv-bdiZC\n"));
     }
 
-    /// no-op (do nothing)
-    fn nop(&mut self) {}
-}
+    #[test]
+    fn block_cache_should_match_stepping() {
+        let program = vec![
+            LDA_IM, 0x0F, LSR_ACC, TAX, ORA_IM, 0x80, TAY, PHA, PLP, JMP_ABS, 0x20, 0x06,
+        ];
+        let mut tail = vec![0; 0x20 - program.len()];
+        tail.extend([LDX_IM, 0x42, TXA, NOP]);
+
+        let build = |block_cache| {
+            Cpu::builder()
+                .pc(0x0600)
+                .memory(0x0600, program.clone())
+                .memory(0x0600 + program.len(), tail.clone())
+                .block_cache(block_cache)
+                .build()
+                .unwrap()
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::Cpu;
-    use crate::op_codes::*;
-    use crate::processor_status::ProcessorStatus;
+        let mut stepped = build(false);
+        let mut cached = build(true);
+        stepped.execute().unwrap();
+        cached.execute().unwrap();
+
+        assert_eq!(format!("{cached}"), format!("{stepped}"));
+        assert_eq!(cached.cycles, stepped.cycles);
+        assert_eq!(cached.instructions, stepped.instructions);
+        assert_eq!(cached.history.to_string(), stepped.history.to_string());
+    }
 
     #[test]
-    fn new_cpu_should_initialize_defaults() {
-        let cpu = Cpu::new().reset(None);
-        assert_eq!(cpu.pc, 0xFFFC);
+    fn block_cache_should_see_self_modifying_code() {
+        // JSR $0030 / LSR $0031 / JSR $0030 / NOP
+        // the subroutine's LDA operand is halved between calls
+        let mut cpu = Cpu::builder()
+            .pc(0x0010)
+            .memory(
+                0x0010,
+                vec![JSR, 0x30, 0x00, LSR_ABS, 0x31, 0x00, JSR, 0x30, 0x00, NOP],
+            )
+            .memory(0x0030, vec![LDA_IM, 0x08, RTS])
+            .block_cache(true)
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x04);
     }
 
     #[test]
-    fn reset_cpu_with_address_should_fetch_from_correct_address() {
-        let cpu = Cpu::new().reset(0x0010.into());
-        assert_eq!(cpu.pc, 0x0010);
+    fn fast_mode_should_skip_cycles_and_hooks() {
+        fn unreachable_hook(_: &Cpu) {
+            panic!("hooks shouldn't run in fast mode");
+        }
+
+        let log = Rc::new(RefCell::new(EventLog::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, PHA, LSR_ACC, TAX, NOP])
+            .hook(unreachable_hook)
+            .observer(log.clone())
+            .fast(true)
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.x, 0x21);
+        assert_eq!(cpu.memory.read_byte(0x0100), 0x42);
+        assert_eq!(cpu.cycles(), 0);
+        assert!(log.borrow().events.is_empty());
     }
 
     #[test]
     fn set_carry_flag_should_set_correct_bit() {
         let mut cpu = Cpu::new().reset(None);
         cpu.set_carry_flag(true);
-        assert_eq!(cpu.ps, ProcessorStatus::C)
+        assert_eq!(cpu.ps, ProcessorStatus::C | ProcessorStatus::U)
     }
 
     #[test]
@@ -679,7 +3015,7 @@ mod tests {
         cpu.memory.data[0xBBBC] = 0xFF;
         cpu.memory.data[0xBBBD] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -697,7 +3033,7 @@ mod tests {
         cpu.memory.data[0xDDDE] = 0xFF;
         cpu.memory.data[0xDDDF] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -716,8 +3052,483 @@ mod tests {
         cpu.memory.data[0xBBBC] = 0xFF;
         cpu.memory.data[0xBBBD] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0xFF);
+    }
+
+    #[test]
+    fn branch_should_jump_by_signed_offset_when_taken() {
+        // LDX #$07 / loop: TXA / LSR A / TAX / BNE loop / NOP
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(
+                0x0600,
+                vec![LDX_IM, 0x07, TXA, LSR_ACC, TAX, BNE, 0xFB, NOP],
+            )
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.x, 0x00);
+        assert_eq!(cpu.pc, 0x0608);
+        // LDX + 2 * (TXA, LSR, TAX, taken BNE) + TXA, LSR, TAX, untaken BNE + NOP
+        assert_eq!(cpu.cycles(), 2 + 2 * 9 + 8 + 2);
+    }
+
+    #[test]
+    fn branch_should_cost_extra_cycle_across_pages() {
+        let mut cpu = Cpu::builder()
+            .pc(0x06F0)
+            .memory(0x06F0, vec![BCC, 0x10])
+            .memory(0x0702, vec![NOP])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x0703);
+        assert_eq!(cpu.cycles(), 4 + 2);
+    }
+
+    #[test]
+    fn cmos_jump_absolute_indirect_should_cross_page_boundary() {
+        let mut cpu = Cpu::builder()
+            .variant(Variant::Cmos)
+            .pc(0x0001)
+            .memory(0x0001, vec![JMP_ABS_IND, 0xFF, 0xAA])
+            .memory(0xAAFF, vec![0xBB, 0xCC])
+            .memory(0xCCBB, vec![LDA_IM, 0xFF, NOP])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
+        assert_eq!(cpu.cycles(), 6 + 2 + 2);
+    }
+
+    #[test]
+    fn cmos_undefined_opcodes_should_be_nops() {
+        let build = |variant| {
+            Cpu::builder()
+                .variant(variant)
+                .pc(0x0600)
+                .memory(
+                    0x0600,
+                    vec![0x03, 0x5C, 0x34, 0x12, 0x02, 0xFF, LDA_IM, 0x42, NOP],
+                )
+                .build()
+                .unwrap()
+        };
+
+        let mut cpu = build(Variant::Cmos);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.cycles(), 1 + 8 + 2 + 2 + 2);
+
+        assert_eq!(
+            build(Variant::Nmos).execute(),
+            Err(CpuError::UnrecognizedInstruction {
+                opcode: 0x03,
+                pc: 0x0600
+            })
+        );
+    }
+
+    #[test]
+    fn restore_should_undo_a_run() {
+        let mut cpu = Cpu::builder()
+            .block_cache(true)
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![LDA_IM, 0x42, PHA, LSR_ABS, 0x01, 0x06, NOP])
+            .build()
+            .unwrap();
+        let snapshot = cpu.snapshot();
+
+        for _ in 0..2 {
+            cpu.execute().unwrap();
+            assert_eq!(cpu.memory.read_byte(0x01FF), 0x42);
+            // the LSR halved the operand of LDA #$42
+            assert_eq!(cpu.memory.read_byte(0x0601), 0x21);
+            assert_eq!(cpu.instructions(), 3);
+
+            cpu.restore(&snapshot);
+            assert_eq!(cpu.registers(), snapshot.registers());
+            assert_eq!(cpu.memory.read_byte(0x01FF), 0x00);
+            assert_eq!(cpu.memory.read_byte(0x0601), 0x42);
+            assert_eq!((cpu.cycles(), cpu.instructions()), (0, 0));
+        }
+    }
+
+    #[test]
+    fn reset_registers_should_leave_memory_alone() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, TAX, NOP])
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+        cpu.reset_registers(0x0602);
+        assert_eq!((cpu.a(), cpu.x(), cpu.cycles()), (0, 0, 0));
+        assert_eq!(cpu.memory.read_word(RESET_VECTOR as usize), 0x0600);
+
+        cpu.execute().unwrap();
+        assert_eq!((cpu.a(), cpu.x()), (0, 0));
+        assert_eq!(cpu.instructions(), 1);
+    }
+
+    #[test]
+    fn builder_vectors_and_reset_state_should_survive_reset() {
+        let mut cpu = Cpu::builder()
+            .reset_vector(0x0600)
+            .irq_vector(0x0700)
+            .nmi_vector(0x0800)
+            .sp(0x01FF)
+            .flag(ProcessorStatus::I, true)
+            .memory(0x0600, vec![LDA_IM, 0x42, NOP])
+            .build()
+            .unwrap();
+        assert_eq!(cpu.pc(), 0x0600);
+        assert_eq!(cpu.memory.read_word(IRQ_VECTOR as usize), 0x0700);
+        assert_eq!(cpu.memory.read_word(NMI_VECTOR as usize), 0x0800);
+
+        cpu.execute().unwrap();
+        cpu.reset(Some(0x0600));
+        assert_eq!((cpu.pc(), cpu.sp(), cpu.a()), (0x0600, 0x01FF, 0));
+        assert!(cpu.interrupt_disable());
+        // the program overwrote the vectors, reset puts them back
+        cpu.memory.write_word(IRQ_VECTOR as usize, 0);
+        cpu.memory.write_word(RESET_VECTOR as usize, 0);
+        cpu.reset(None);
+        assert_eq!(cpu.pc(), 0x0600);
+        assert_eq!(cpu.memory.read_word(IRQ_VECTOR as usize), 0x0700);
+        cpu.reset_registers(0x0602);
+        assert_eq!(cpu.sp(), 0x01FF);
+        assert!(cpu.interrupt_disable());
+    }
+
+    #[test]
+    fn builder_pc_should_not_clobber_the_reset_vector() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0800)
+            .reset_vector(0x0600)
+            .build()
+            .unwrap();
+        assert_eq!(cpu.pc(), 0x0800);
+        assert_eq!(cpu.memory.read_word(RESET_VECTOR as usize), 0x0600);
+
+        cpu.reset(None);
+        assert_eq!(cpu.pc(), 0x0600);
+        cpu.reset(Some(0x0900));
+        assert_eq!(cpu.pc(), 0x0900);
+        assert_eq!(cpu.memory.read_word(RESET_VECTOR as usize), 0x0600);
+    }
+
+    #[test]
+    fn soft_breakpoints_stop_once_and_put_the_opcode_back() {
+        for block_cache in [false, true] {
+            let mut cpu = Cpu::builder()
+                .pc(0x0600)
+                .block_cache(block_cache)
+                .memory(0x0600, vec![LDA_IM, 0x01, TAX, TAY, NOP])
+                .build()
+                .unwrap();
+            assert!(cpu.set_soft_breakpoint(0x0603));
+            assert_eq!(cpu.memory.read_byte(0x0603), BRK);
+
+            assert_eq!(cpu.execute(), Err(CpuError::SoftBreakpoint { pc: 0x0603 }));
+            assert_eq!((cpu.pc(), cpu.x()), (0x0603, 0x01));
+            assert_eq!(cpu.memory.read_byte(0x0603), TAY);
+            assert_eq!(cpu.instructions(), 2);
+
+            cpu.execute().unwrap();
+            assert_eq!((cpu.pc(), cpu.y()), (0x0605, 0x01));
+        }
+
+        let mut cpu = Cpu::new();
+        cpu.memory
+            .map_rom(0xFF00, RomImage::Shared(vec![0xEA; 0x100].into()))
+            .unwrap();
+        assert!(!cpu.set_soft_breakpoint(0xFF00));
+        assert!(cpu.set_soft_breakpoint(0x0600));
+        cpu.clear_soft_breakpoints();
+        assert_eq!(cpu.soft_breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn clearing_soft_breakpoints_leaves_new_code_alone() {
+        let mut cpu = Cpu::new();
+        assert!(cpu.set_soft_breakpoint(0x0600));
+        assert!(cpu.set_soft_breakpoint(0x0601));
+        cpu.memory.write_byte(0x0600, TAX);
+        assert!(cpu.clear_soft_breakpoint(0x0600));
+        assert_eq!(cpu.memory.read_byte(0x0600), TAX);
+
+        cpu.load_program(0x0601, vec![TAY]).unwrap();
+        assert_eq!(cpu.soft_breakpoints().count(), 0);
+        assert_eq!(cpu.memory.read_byte(0x0601), TAY);
+    }
+
+    #[test]
+    fn seeded_machines_run_identically() {
+        let run = |seed| {
+            let mut cpu = Cpu::builder()
+                .seed(seed)
+                .random_ram(true)
+                .random_device(crate::random::DEFAULT_BASE as usize)
+                .pc(0x0600)
+                .memory(
+                    0x0600,
+                    vec![LDA_ZP, 0xFE, LDX_ZP, 0xFE, LDY_ABS, 0x00, 0x03, NOP],
+                )
+                .build()
+                .unwrap();
+            cpu.execute().unwrap();
+            assert_eq!(cpu.seed(), seed);
+            (cpu.registers(), cpu.memory.data[0x0200..0x0300].to_vec())
+        };
+        assert_eq!(run(0x6502), run(0x6502));
+        assert_ne!(run(0x6502), run(0x6510));
+
+        // nothing random unless asked for
+        let cpu = Cpu::builder().seed(1).build().unwrap();
+        assert!(cpu.memory.data.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn permission_faults_are_logged_or_stop_the_cpu() {
+        let build = |policy| {
+            Cpu::builder()
+                .pc(0x0600)
+                .sp(0x01FF)
+                .permissions(0x0000, 0x10000, Permissions::DATA)
+                .permissions(0x0600, 0x100, Permissions::CODE)
+                .permissions(0xD000, 0x100, Permissions::empty())
+                .on_violation(policy)
+                .memory(0x0600, vec![LDA_IM, 0x42, PHA, LSR_ABS, 0x00, 0x06, NOP])
+                .build()
+                .unwrap()
+        };
+
+        let mut cpu = build(ViolationPolicy::Warn);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.instructions(), 3);
+        assert_eq!(
+            cpu.faults(),
+            [Fault {
+                operation: Operation::Write,
+                address: 0x0600,
+                pc: 0x0603
+            }]
+        );
+
+        let mut cpu = build(ViolationPolicy::Stop);
+        assert_eq!(
+            cpu.execute(),
+            Err(CpuError::AccessViolation {
+                operation: Operation::Write,
+                address: 0x0600,
+                pc: 0x0603
+            })
+        );
+        assert_eq!(cpu.instructions(), 3);
+
+        // jumping into the hole stops before anything there runs
+        let mut cpu = build(ViolationPolicy::Stop);
+        cpu.memory
+            .write_bytes(0x0600, &[JMP_ABS, 0x00, 0xD0])
+            .unwrap();
+        let err = cpu.execute().unwrap_err();
+        assert_eq!(err.to_string(), "execute of $D000 not allowed at $D000");
+        assert_eq!((cpu.pc(), cpu.instructions()), (0xD000, 1));
+    }
+
+    #[test]
+    fn stack_pointer_should_wrap_around_page_one() {
+        // BRK through a zeroed vector lands on BRK again, pushing forever
+        let mut cpu = Cpu::builder()
+            .instruction_limit(1000)
+            .pc(0x0000)
+            .memory(0x0000, vec![BRK])
+            .build()
+            .unwrap();
+        assert!(matches!(
+            cpu.execute(),
+            Err(CpuError::InstructionLimit { .. })
+        ));
+        assert_eq!(cpu.sp() & 0xFF00, 0x0100);
+
+        // a subroutine calling itself
+        let mut cpu = Cpu::builder()
+            .instruction_limit(1000)
+            .pc(0x0600)
+            .memory(0x0600, vec![JSR, 0x00, 0x06])
+            .build()
+            .unwrap();
+        assert!(matches!(
+            cpu.execute(),
+            Err(CpuError::InstructionLimit { .. })
+        ));
+        assert_eq!(cpu.sp() & 0xFF00, 0x0100);
+    }
+
+    #[test]
+    fn execute_should_stop_at_the_instruction_limit() {
+        let build = |builder: CpuBuilder| {
+            builder
+                .instruction_limit(100)
+                .pc(0x0600)
+                .memory(0x0600, vec![TAX, JMP_ABS, 0x01, 0x06])
+                .build()
+                .unwrap()
+        };
+        for mut cpu in [
+            build(Cpu::builder()),
+            build(Cpu::builder().fast(true)),
+            build(Cpu::builder().block_cache(true)),
+        ] {
+            assert_eq!(
+                cpu.execute(),
+                Err(CpuError::InstructionLimit {
+                    limit: 100,
+                    pc: 0x0601
+                })
+            );
+            assert_eq!(cpu.instructions(), 100);
+        }
+
+        let mut cpu = Cpu::builder()
+            .instruction_limit(3)
+            .pc(0x0600)
+            .memory(0x0600, vec![TAX, TAX, NOP])
+            .build()
+            .unwrap();
+        assert_eq!(cpu.execute(), Ok(()));
+    }
+
+    #[test]
+    fn nmos_decoding_should_match_the_opcode_table() {
+        for info in INSTRUCTIONS.iter() {
+            let opcode = info.opcode as u8;
+            assert_eq!(nmos_mode(opcode), info.mode, "mode of ${opcode:02X}");
+            assert_eq!(
+                NMOS_CYCLES[opcode as usize], info.cycles,
+                "cycles of ${opcode:02X}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_can_be_skipped_as_nops() {
+        // STA $10 and SLO $1234,X aren't implemented
+        let mut cpu = Cpu::builder()
+            .unknown_opcodes(UnknownOpcodePolicy::Nop)
+            .pc(0x0600)
+            .memory(
+                0x0600,
+                vec![0x85, 0x10, 0x1F, 0x34, 0x12, LDA_IM, 0x42, NOP],
+            )
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.cycles(), 3 + 7 + 2 + 2);
+        assert_eq!(cpu.memory.data[0x0010], 0);
+    }
+
+    #[test]
+    fn illegal_opcodes_should_run_when_asked_for() {
+        let mut cpu = Cpu::builder()
+            .unknown_opcodes(UnknownOpcodePolicy::Illegal)
+            .pc(0x0600)
+            .memory(
+                0x0600,
+                vec![
+                    // LAX $10, SLO $11, DCP $12, SAX $13, SBX #$01
+                    0xA7, 0x10, 0x07, 0x11, 0xC7, 0x12, 0x87, 0x13, 0xCB, 0x01, NOP,
+                ],
+            )
+            .memory(0x0010, vec![0x81, 0x40, 0x81])
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.memory.data[0x0011], 0x80);
+        assert_eq!(cpu.memory.data[0x0012], 0x80);
+        assert_eq!(cpu.a, 0x81);
+        assert_eq!(cpu.memory.data[0x0013], 0x81);
+        assert_eq!(cpu.x, 0x80);
+        assert!(cpu.carry() && cpu.negative());
+        assert_eq!(cpu.cycles(), 3 + 5 + 5 + 3 + 2 + 2);
+
+        // STA is documented, so it still isn't run
+        let mut cpu = Cpu::builder()
+            .unknown_opcodes(UnknownOpcodePolicy::Illegal)
+            .pc(0x0600)
+            .memory(0x0600, vec![0x85, 0x10, 0x02])
+            .build()
+            .unwrap();
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::UnrecognizedInstruction {
+                opcode: 0x85,
+                pc: 0x0600
+            })
+        );
+        cpu.set_pc(0x0602);
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::Jammed {
+                opcode: 0x02,
+                pc: 0x0602
+            })
+        );
+        assert_eq!(cpu.pc(), 0x0602);
+    }
+
+    #[test]
+    fn cmos_accurate_mode_should_read_twice_instead_of_writing_twice() {
+        let log = Rc::new(RefCell::new(EventLog::default()));
+        let mut cpu = Cpu::builder()
+            .variant(Variant::Cmos)
+            .accurate(true)
+            .pc(0x0600)
+            .memory(0x0600, vec![LSR_ZP, 0x10, NOP])
+            .memory(0x0010, vec![0x04])
+            .observer(log.clone())
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+
+        let accesses: Vec<Event> = log
+            .borrow()
+            .events
+            .iter()
+            .filter(|event| {
+                !matches!(
+                    event,
+                    Event::InstructionRetired { .. } | Event::BusAccess(_)
+                )
+            })
+            .copied()
+            .collect();
+        assert_eq!(
+            accesses,
+            vec![
+                Event::MemoryRead {
+                    address: 0x0010,
+                    value: 0x04,
+                    access: Access::Data
+                },
+                Event::MemoryRead {
+                    address: 0x0010,
+                    value: 0x04,
+                    access: Access::Dummy
+                },
+                Event::MemoryWritten {
+                    address: 0x0010,
+                    value: 0x02
+                },
+            ]
+        );
     }
 
     #[test]
@@ -728,7 +3539,7 @@ mod tests {
         cpu.memory.data[0x0001] = TAX;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.x, 0xFF);
     }
 
@@ -740,7 +3551,7 @@ mod tests {
         cpu.memory.data[0x0001] = TAY;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.y, 0xFF);
     }
 
@@ -752,7 +3563,7 @@ mod tests {
         cpu.memory.data[0x0001] = TSX;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.x, 0x01);
     }
 
@@ -764,7 +3575,7 @@ mod tests {
         cpu.memory.data[0x0001] = TXA;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -776,7 +3587,7 @@ mod tests {
         cpu.memory.data[0x0001] = TYA;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -788,7 +3599,7 @@ mod tests {
         cpu.memory.data[0x0001] = TXS;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.sp, 0x01AA);
     }
 
@@ -799,8 +3610,8 @@ mod tests {
         cpu.memory.data[0x0001] = SEC;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
-        assert_eq!(cpu.ps, ProcessorStatus::C);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.ps, ProcessorStatus::C | ProcessorStatus::U);
     }
 
     #[test]
@@ -810,8 +3621,21 @@ mod tests {
         cpu.memory.data[0x0001] = SED;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
-        assert_eq!(cpu.ps, ProcessorStatus::empty());
+        cpu.execute().unwrap();
+        assert_eq!(cpu.ps, ProcessorStatus::U);
+    }
+
+    #[test]
+    fn set_decimal_mode_should_set_flag_when_enabled() {
+        let mut cpu = Cpu::builder()
+            .decimal_mode(true)
+            .pc(0x0001)
+            .memory(0x0001, vec![SED, NOP])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.ps, ProcessorStatus::D | ProcessorStatus::U);
     }
 
     #[test]
@@ -821,8 +3645,8 @@ mod tests {
         cpu.memory.data[0x0001] = SEI;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
-        assert_eq!(cpu.ps, ProcessorStatus::I);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.ps, ProcessorStatus::I | ProcessorStatus::U);
     }
 
     #[test]
@@ -835,7 +3659,7 @@ mod tests {
         cpu.memory.data[0x0004] = 0xFF;
         cpu.memory.data[0x0005] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -852,7 +3676,7 @@ mod tests {
         cpu.memory.data[0x0005] = 0x00;
         cpu.memory.data[0x0006] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -870,7 +3694,7 @@ mod tests {
         cpu.memory.data[0x0005] = 0x00;
         cpu.memory.data[0x0006] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -888,7 +3712,7 @@ mod tests {
         cpu.memory.data[0x0005] = 0x00;
         cpu.memory.data[0x0006] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -904,7 +3728,7 @@ mod tests {
         cpu.memory.data[0x00F0] = 0xFF;
         cpu.memory.data[0x0005] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -921,7 +3745,7 @@ mod tests {
         cpu.memory.data[0x0004] = 0xF0;
         cpu.memory.data[0x0005] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -939,7 +3763,7 @@ mod tests {
         cpu.memory.data[0x0002] = 0x11;
         cpu.memory.data[0x0003] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -957,7 +3781,7 @@ mod tests {
         cpu.memory.data[0x0002] = 0x11;
         cpu.memory.data[0x0003] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -971,7 +3795,7 @@ mod tests {
         cpu.memory.data[0x0004] = 0b1010_1010;
         cpu.memory.data[0x0005] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -988,7 +3812,7 @@ mod tests {
         cpu.memory.data[0x0005] = 0x00;
         cpu.memory.data[0x0006] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0x57); // 0x42 | 0x55 = 0x57
     }
@@ -1006,7 +3830,7 @@ mod tests {
         cpu.memory.data[0x0005] = 0x00;
         cpu.memory.data[0x0006] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0x57); // 0x42 | 0x55 = 0x57
     }
@@ -1024,7 +3848,7 @@ mod tests {
         cpu.memory.data[0x0005] = 0x00;
         cpu.memory.data[0x0006] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0x57); // 0x42 | 0x55 = 0x57
     }
@@ -1040,7 +3864,7 @@ mod tests {
         cpu.memory.data[0x00F0] = 0xFF;
         cpu.memory.data[0x0005] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -1057,7 +3881,7 @@ mod tests {
         cpu.memory.data[0x0004] = 0xF0;
         cpu.memory.data[0x0005] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         let address = cpu.memory.data[0xF1];
         assert_eq!(cpu.a, 0xFF);
     }
@@ -1076,7 +3900,7 @@ mod tests {
         cpu.memory.data[0x0002] = 0x11;
         cpu.memory.data[0x0003] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -1094,7 +3918,7 @@ mod tests {
         cpu.memory.data[0x0002] = 0x11;
         cpu.memory.data[0x0003] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0xFF);
     }
 
@@ -1110,7 +3934,7 @@ mod tests {
         cpu.memory.data[0x0006] = PLA;
         cpu.memory.data[0x0007] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.a, 0xFF);
     }
@@ -1123,7 +3947,7 @@ mod tests {
         cpu.memory.data[0x0001] = PLP;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.ps.bits(), ProcessorStatus::all().bits());
     }
@@ -1137,12 +3961,47 @@ mod tests {
         cpu.memory.data[0x0003] = PHA;
         cpu.memory.data[0x0004] = NOP;
 
-        cpu.execute();
-        let accumulator = cpu.memory.read_byte((cpu.sp + 1) as usize);
+        cpu.execute().unwrap();
+        let accumulator = cpu
+            .memory
+            .read_byte(0x0100 | (cpu.sp as u8).wrapping_add(1) as usize);
 
         assert_eq!(accumulator, 0xFF);
     }
 
+    #[test]
+    fn stack_high_water_should_keep_the_deepest_push() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![PHA, PHP, PHA, PLA, PLP, PLA, PHA, NOP])
+            .build()
+            .unwrap();
+        assert_eq!(cpu.stack_high_water(), None);
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.sp, 0x01FE);
+        assert_eq!(cpu.stack_high_water(), Some(0x01FC));
+        assert_eq!(cpu.stack_headroom(), Some(0xFD));
+        cpu.reset_registers(0x0600);
+        assert_eq!(cpu.stack_high_water(), None);
+    }
+
+    #[test]
+    fn stack_headroom_should_count_pushes_wrapping_from_the_default_sp() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![PHA, PHA, PHA, PLA, PLA, PHA, NOP])
+            .build()
+            .unwrap();
+        assert_eq!(cpu.sp, 0x0100);
+
+        // pushed to $0100, $01FF and $01FE
+        cpu.execute().unwrap();
+        assert_eq!(cpu.stack_high_water(), Some(0x01FD));
+        assert_eq!(cpu.stack_headroom(), Some(253));
+    }
+
     #[test]
     fn push_processor_status_should_push_ps_register_onto_stack() {
         let mut cpu = Cpu::new().reset(0x0001.into());
@@ -1150,8 +4009,10 @@ mod tests {
         cpu.memory.data[0x0001] = PHP;
         cpu.memory.data[0x0002] = NOP;
 
-        cpu.execute();
-        let ps = cpu.memory.read_byte((cpu.sp + 1) as usize);
+        cpu.execute().unwrap();
+        let ps = cpu
+            .memory
+            .read_byte(0x0100 | (cpu.sp as u8).wrapping_add(1) as usize);
 
         assert_eq!(ps, ProcessorStatus::all().bits());
     }
@@ -1168,7 +4029,7 @@ mod tests {
         cpu.memory.data[0x0005] = 0x00; // 0x0010
         cpu.memory.data[0x0006] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         let address = cpu.memory.read_byte(0x011); //0x10 + 1
         assert_eq!(address, 0x01);
     }
@@ -1184,7 +4045,7 @@ mod tests {
         cpu.memory.data[0x0004] = 0x10;
         cpu.memory.data[0x0005] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         let address = cpu.memory.read_byte(0x011); //0x10 + 1
         assert_eq!(address, 0x01);
     }
@@ -1198,7 +4059,7 @@ mod tests {
         cpu.memory.data[0x0002] = 0x10;
         cpu.memory.data[0x0003] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         let address = cpu.memory.read_byte(0x010);
         assert_eq!(address, 0x01);
     }
@@ -1212,8 +4073,11 @@ mod tests {
         cpu.memory.data[0x0002] = 0x10;
         cpu.memory.data[0x0003] = NOP;
 
-        cpu.execute();
-        assert_eq!(cpu.ps, ProcessorStatus::Z | ProcessorStatus::C);
+        cpu.execute().unwrap();
+        assert_eq!(
+            cpu.ps,
+            ProcessorStatus::Z | ProcessorStatus::C | ProcessorStatus::U
+        );
     }
 
     #[test]
@@ -1226,7 +4090,7 @@ mod tests {
         cpu.memory.data[0x0003] = 0x01; // 0x0100
         cpu.memory.data[0x0004] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         let address = cpu.memory.read_byte(0x0100);
         assert_eq!(address, 0x01);
     }
@@ -1239,7 +4103,7 @@ mod tests {
         cpu.memory.data[0x0003] = LSR_ACC;
         cpu.memory.data[0x0004] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x01);
     }
 
@@ -1251,8 +4115,8 @@ mod tests {
         cpu.memory.data[0x0003] = LSR_ACC;
         cpu.memory.data[0x0004] = NOP;
 
-        cpu.execute();
-        assert_eq!(format!("{}", cpu.ps), "00000000");
+        cpu.execute().unwrap();
+        assert_eq!(format!("{:08b}", cpu.ps), "00100000");
     }
 
     #[test]
@@ -1263,8 +4127,8 @@ mod tests {
         cpu.memory.data[0x0003] = LSR_ACC;
         cpu.memory.data[0x0004] = NOP;
 
-        cpu.execute();
-        assert_eq!(format!("{}", cpu.ps), "00000000");
+        cpu.execute().unwrap();
+        assert_eq!(format!("{:08b}", cpu.ps), "00100000");
     }
 
     #[test]
@@ -1275,8 +4139,8 @@ mod tests {
         cpu.memory.data[0x0003] = LSR_ACC;
         cpu.memory.data[0x0004] = NOP;
 
-        cpu.execute();
-        assert_eq!(format!("{}", cpu.ps), "00000011");
+        cpu.execute().unwrap();
+        assert_eq!(format!("{:08b}", cpu.ps), "00100011");
     }
 
     #[test]
@@ -1289,10 +4153,14 @@ mod tests {
         cpu.memory.data[0xFFFE] = 0x00; // JSR 0x0010
         cpu.memory.data[0x0010] = NOP;
 
-        cpu.execute();
-        // stack pointer should be 0xFF 0xFD (high byte first)
-        let expected_return_address = (cpu.sp + 2) as usize;
-        let stack_address = cpu.memory.read_word(expected_return_address);
+        cpu.execute().unwrap();
+        // JSR pushed the return address high byte first, so it reads back
+        // low byte first just above the stack pointer, wrapping within page 1
+        let stack = |offset: u8| {
+            cpu.memory
+                .read_byte(0x0100 | (cpu.sp as u8).wrapping_add(offset) as usize)
+        };
+        let stack_address = u16::from_le_bytes([stack(1), stack(2)]);
         // should get to no-op
         assert_eq!(cpu.pc, 0x0011);
         // return to last byte of last instruction
@@ -1311,11 +4179,45 @@ mod tests {
         cpu.memory.data[0x1001] = 0x01;
         cpu.memory.data[0x1002] = RTS;
 
-        cpu.execute();
+        cpu.execute().unwrap();
 
         assert_eq!(cpu.pc, 0x05);
     }
 
+    #[test]
+    fn jsr_and_rts_should_round_trip_outside_page_zero() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![JSR, 0x00, 0x07, LDX_IM, 0x01, NOP])
+            .memory(0x0700, vec![LDA_IM, 0x42, RTS])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!((cpu.pc, cpu.a, cpu.x, cpu.sp), (0x0606, 0x42, 0x01, 0x01FF));
+        // high byte pushed first, then low, the address of the JSR's last byte
+        assert_eq!(cpu.memory.data[0x01FF], 0x06);
+        assert_eq!(cpu.memory.data[0x01FE], 0x02);
+    }
+
+    #[test]
+    fn jsr_and_rts_should_round_trip_across_the_stack_wrap() {
+        // the return address straddles $0100 and $01FF
+        let mut cpu = Cpu::builder()
+            .pc(0x1234)
+            .sp(0x0100)
+            .memory(0x1234, vec![JSR, 0x00, 0x20, NOP])
+            .memory(0x2000, vec![RTS])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!((cpu.pc, cpu.sp), (0x1238, 0x0100));
+        assert_eq!(cpu.memory.data[0x0100], 0x12);
+        assert_eq!(cpu.memory.data[0x01FF], 0x36);
+    }
+
     #[test]
     fn ldy_immediate_should_load_y_register() {
         let mut cpu = Cpu::new().reset(None);
@@ -1324,7 +4226,7 @@ mod tests {
         cpu.memory.data[0xFFFD] = 0x42;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.y, 0x42);
     }
 
@@ -1341,7 +4243,7 @@ mod tests {
         cpu.memory.data[0x4480] = 0x37;
         cpu.memory.data[0xFFF3] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.y, 0x37);
     }
 
@@ -1360,7 +4262,7 @@ mod tests {
         cpu.memory.data[0x4481] = 0x37;
         cpu.memory.data[0xFFF3] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.y, 0x37);
     }
 
@@ -1373,7 +4275,7 @@ mod tests {
         cpu.memory.data[0x0042] = 0x84;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.y, 0x84);
     }
 
@@ -1388,7 +4290,7 @@ mod tests {
         cpu.memory.data[0x0042] = 0x84;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.y, 0x85);
     }
 
@@ -1400,7 +4302,7 @@ mod tests {
         cpu.memory.data[0xFFFD] = 0x42;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.x, 0x42);
     }
 
@@ -1417,10 +4319,24 @@ mod tests {
         cpu.memory.data[0x4480] = 0x37;
         cpu.memory.data[0xFFF3] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.x, 0x37);
     }
 
+    #[test]
+    fn ldx_absolute_should_be_encoded_as_ae() {
+        // written as raw bytes, the constant would follow a wrong encoding
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![0xAE, 0x80, 0x44, 0xEA])
+            .memory(0x4480, vec![0x37])
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.x, 0x37);
+        assert_eq!(Opcode::try_from(0xA3), Err(UnknownOpcode(0xA3)));
+    }
+
     #[test]
     fn ldx_absolute_y_indexed_should_load_x_register_with_correct_value() {
         let mut cpu = Cpu::new().reset(None);
@@ -1436,7 +4352,7 @@ mod tests {
         cpu.memory.data[0x4481] = 0x37;
         cpu.memory.data[0xFFF3] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.x, 0x37);
     }
 
@@ -1449,7 +4365,7 @@ mod tests {
         cpu.memory.data[0x0042] = 0x84;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.x, 0x84);
     }
 
@@ -1464,7 +4380,7 @@ mod tests {
         cpu.memory.data[0x0042] = 0x84;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.x, 0x85);
     }
 
@@ -1476,7 +4392,7 @@ mod tests {
         cpu.memory.data[0xFFFD] = 0x42;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x42);
     }
 
@@ -1493,7 +4409,7 @@ mod tests {
         cpu.memory.data[0x4480] = 0x37;
         cpu.memory.data[0xFFF3] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x37);
     }
 
@@ -1512,7 +4428,7 @@ mod tests {
         cpu.memory.data[0x4481] = 0x37;
         cpu.memory.data[0xFFF3] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x37);
     }
 
@@ -1531,7 +4447,7 @@ mod tests {
         cpu.memory.data[0x4481] = 0x37;
         cpu.memory.data[0xFFF3] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x37);
     }
 
@@ -1543,8 +4459,8 @@ mod tests {
         cpu.memory.data[0xFFFD] = 0x00;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
-        assert_eq!(format!("{}", cpu.ps), "00000010");
+        cpu.execute().unwrap();
+        assert_eq!(format!("{:08b}", cpu.ps), "00100010");
     }
 
     #[test]
@@ -1555,8 +4471,8 @@ mod tests {
         cpu.memory.data[0xFFFD] = 0b10000001;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
-        assert_eq!(format!("{}", cpu.ps), "10000000");
+        cpu.execute().unwrap();
+        assert_eq!(format!("{:08b}", cpu.ps), "10100000");
     }
 
     #[test]
@@ -1568,7 +4484,7 @@ mod tests {
         cpu.memory.data[0x0042] = 0x84;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x84);
     }
 
@@ -1583,7 +4499,7 @@ mod tests {
         cpu.memory.data[0x0042] = 0x84;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x85);
     }
 
@@ -1598,7 +4514,7 @@ mod tests {
         cpu.memory.data[0x0024] = 0x20;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x20);
     }
 
@@ -1615,10 +4531,24 @@ mod tests {
         cpu.memory.data[0x8004] = 0x37;
         cpu.memory.data[0xFFFE] = NOP;
 
-        cpu.execute();
+        cpu.execute().unwrap();
         assert_eq!(cpu.a, 0x37);
     }
 
+    #[test]
+    fn history_should_end_with_unrecognized_instruction() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .history(2)
+            .memory(0x0600, vec![LDA_IM, 0x01, TAX, TAY, 0xFF])
+            .build()
+            .unwrap();
+
+        assert!(cpu.execute().is_err());
+        let history: Vec<(u16, u8)> = cpu.history().iter().collect();
+        assert_eq!(history, [(0x0603, TAY), (0x0604, 0xFF)]);
+    }
+
     #[test]
     fn read_word() {
         let mut cpu = Cpu::new().reset(None);
@@ -1629,4 +4559,133 @@ mod tests {
 
         assert_eq!(word, 0x2020);
     }
+
+    #[test]
+    fn brk_pushes_pc_and_status_and_rti_returns() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![BRK, 0xFF, LDA_IM, 0x42, NOP])
+            .memory(0x0700, vec![LDX_IM, 0x07, RTI])
+            .memory(0xFFFE, vec![0x00, 0x07])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.x, 0x07);
+        assert_eq!(cpu.sp, 0x0100);
+        assert_eq!(cpu.memory.data[0x0100], 0x06);
+        assert_eq!(cpu.memory.data[0x01FF], 0x02);
+        assert_eq!(cpu.memory.data[0x01FE], 0b0011_0000);
+        assert_eq!(cpu.cycles, 7 + 2 + 6 + 2 + 2);
+    }
+
+    #[test]
+    fn irq_is_taken_only_while_interrupts_are_enabled() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![SEI, TAX, CLI, TAY, NOP])
+            .memory(0x0700, vec![NOP])
+            .memory(0xFFFE, vec![0x00, 0x07])
+            .build()
+            .unwrap();
+
+        cpu.step().unwrap();
+        cpu.set_irq(true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x0602);
+        cpu.step().unwrap();
+        assert!(!cpu.step().unwrap());
+
+        assert_eq!(cpu.pc, 0x0701);
+        assert!(cpu.ps.contains(ProcessorStatus::I));
+        assert_eq!(cpu.memory.data[0x0100], 0x06);
+        assert_eq!(cpu.memory.data[0x01FF], 0x03);
+        // pushed with the break bit clear
+        assert_eq!(cpu.memory.data[0x01FE], 0b0010_0010);
+        assert_eq!(cpu.cycles, 2 + 2 + 2 + 7 + 2);
+    }
+
+    #[test]
+    fn nmi_is_taken_before_the_next_instruction() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![SEI, TAX, NOP])
+            .memory(0x0800, vec![NOP])
+            .memory(0xFFFA, vec![0x00, 0x08])
+            .build()
+            .unwrap();
+
+        cpu.step().unwrap();
+        cpu.nmi();
+        assert!(!cpu.step().unwrap());
+        assert_eq!(cpu.pc, 0x0801);
+    }
+
+    #[test]
+    fn nmi_during_brk_hijacks_the_vector_on_accurate_nmos() {
+        let run = |variant: Variant, accurate: bool| {
+            let mut cpu = Cpu::builder()
+                .variant(variant)
+                .accurate(accurate)
+                .pc(0x0600)
+                .memory(0x0600, vec![BRK, 0x00])
+                .memory(0x0700, vec![NOP])
+                .memory(0x0800, vec![NOP])
+                .memory(0xFFFA, vec![0x00, 0x08])
+                .memory(0xFFFE, vec![0x00, 0x07])
+                .build()
+                .unwrap();
+            // arrives on BRK's third cycle, before the vector fetch
+            cpu.schedule_nmi(2);
+            cpu.execute().unwrap();
+            cpu
+        };
+
+        // BRK jumps straight to the NMI handler, with only its own frame pushed
+        let hijacked = run(Variant::Nmos, true);
+        assert_eq!(hijacked.pc, 0x0801);
+        assert_eq!(hijacked.sp, 0x01FD);
+        assert_eq!(hijacked.memory.data[0x01FE], 0b0011_0000);
+        assert_eq!(hijacked.nmi, None);
+
+        // otherwise the NMI is taken once BRK finishes, on top of BRK's frame
+        for cpu in [run(Variant::Nmos, false), run(Variant::Cmos, true)] {
+            assert_eq!(cpu.pc, 0x0801);
+            assert_eq!(cpu.sp, 0x01FA);
+            assert_eq!(cpu.memory.data[0x01FD], 0x07);
+            assert_eq!(cpu.memory.data[0x01FC], 0x00);
+        }
+    }
+
+    #[test]
+    fn pc_should_wrap_around_at_end_of_memory() {
+        let mut cpu = Cpu::builder()
+            .pc(0xFFFE)
+            .memory(0xFFFE, vec![LDA_IM, 0x42])
+            .memory(0x0000, vec![LDX_ABS, 0x34, 0x12, NOP])
+            .memory(0x1234, vec![0x07])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.x, 0x07);
+        assert_eq!(cpu.pc, 0x0004);
+    }
+
+    #[test]
+    fn operand_fetch_should_wrap_around_at_end_of_memory() {
+        let mut cpu = Cpu::builder()
+            .pc(0xFFFF)
+            .memory(0xFFFF, vec![LDA_ABS])
+            .memory(0x0000, vec![0x34, 0x12, NOP])
+            .memory(0x1234, vec![0x99])
+            .build()
+            .unwrap();
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a, 0x99);
+        assert_eq!(cpu.pc, 0x0003);
+    }
 }
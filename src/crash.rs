@@ -0,0 +1,155 @@
+use core::fmt;
+
+use crate::{
+    cpu::{Cpu, CpuError},
+    disassembler::{self, Line},
+    processor_status::ProcessorStatus,
+};
+
+/// instructions from the history shown before the faulting one
+const LINES_BEFORE: usize = 4;
+/// instructions shown from the faulting one onwards
+const LINES_AFTER: usize = 4;
+/// the page the 6502 keeps its stack in
+const STACK_PAGE: u16 = 0x0100;
+
+/// the state of the machine when execution failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub error: CpuError,
+    /// address of the instruction that failed
+    pub pc: u16,
+    pub sp: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: ProcessorStatus,
+    pub cycles: u64,
+    /// recently executed instructions followed by the code at the failing pc
+    pub disassembly: Vec<Line>,
+    /// contents of $0100-$01FF
+    pub stack: Vec<u8>,
+    /// the pcs and opcodes executed before the failure, oldest first
+    pub history: Vec<(u16, u8)>,
+}
+
+impl CrashReport {
+    /// capture the state of a cpu that just returned `error`
+    pub fn new(cpu: &Cpu, error: CpuError) -> Self {
+        let pc = match error {
+            CpuError::UnrecognizedInstruction { pc, .. }
+            | CpuError::Jammed { pc, .. }
+            | CpuError::InstructionLimit { pc, .. }
+            | CpuError::AccessViolation { pc, .. }
+            | CpuError::SoftBreakpoint { pc } => pc,
+        };
+
+        // addresses from the history are known to start instructions,
+        // unlike guessing where to start disassembling backwards from the pc
+        let history: Vec<(u16, u8)> = cpu.history().iter().collect();
+        let before = history
+            .iter()
+            .rev()
+            .map(|(address, _)| *address)
+            .skip_while(|address| *address == pc)
+            .take(LINES_BEFORE)
+            .collect::<Vec<_>>();
+        let mut disassembly: Vec<Line> = before
+            .iter()
+            .rev()
+            .map(|address| disassembler::disassemble(&cpu.memory, *address))
+            .collect();
+        disassembly.extend(disassembler::disassemble_range(
+            &cpu.memory,
+            pc,
+            LINES_AFTER,
+        ));
+
+        let stack = (STACK_PAGE..STACK_PAGE + 0x100)
+            .map(|address| cpu.memory.read_byte(address as usize))
+            .collect();
+
+        Self {
+            error,
+            pc,
+            sp: cpu.sp(),
+            a: cpu.a(),
+            x: cpu.x(),
+            y: cpu.y(),
+            status: cpu.status(),
+            cycles: cpu.cycles(),
+            disassembly,
+            stack,
+            history,
+        }
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error: {}", self.error)?;
+        writeln!(
+            f,
+            "pc: ${:04X}  sp: ${:04X}  a: ${:02X}  x: ${:02X}  y: ${:02X}  cycles: {}",
+            self.pc, self.sp, self.a, self.x, self.y, self.cycles
+        )?;
+        writeln!(f, "flags: {}", self.status)?;
+
+        writeln!(f, "\ncode:")?;
+        for line in &self.disassembly {
+            let marker = if line.address == self.pc { ">" } else { " " };
+            writeln!(f, "{marker} {line}")?;
+        }
+
+        writeln!(f, "\nstack:")?;
+        for (row, chunk) in self.stack.chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02X}")).collect();
+            writeln!(
+                f,
+                "${:04X}  {}",
+                STACK_PAGE as usize + row * 16,
+                hex.join(" ")
+            )?;
+        }
+
+        writeln!(f, "\nhistory:")?;
+        for (pc, opcode) in &self.history {
+            writeln!(f, "${pc:04X}  {opcode:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn report_captures_state_around_the_fault() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, PHA, TAX, 0xFF, TAY])
+            .memory(0x01FF, vec![0x99])
+            .build()
+            .unwrap();
+        let error = cpu.execute().unwrap_err();
+        let report = CrashReport::new(&cpu, error);
+
+        assert_eq!(report.pc, 0x0604);
+        assert_eq!(report.a, 0x42);
+        assert_eq!(report.x, 0x42);
+        assert_eq!(report.stack.len(), 0x100);
+        assert_eq!(report.stack[0], 0x42);
+        assert_eq!(report.stack[0xFF], 0x99);
+        assert_eq!(report.history.last(), Some(&(0x0604, 0xFF)));
+
+        let code: Vec<String> = report
+            .disassembly
+            .iter()
+            .map(|line| line.text.clone())
+            .collect();
+        assert_eq!(code[..5], ["LDA #$42", "PHA", "TAX", ".byte $FF", "TAY"]);
+        assert!(report.to_string().contains("> $0604  FF        .byte $FF"));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+/// hardware mapped into the address space, e.g. an io chip or a coprocessor
+pub trait Device {
+    /// the byte at an offset from the start of the device's range
+    fn read(&mut self, offset: u16) -> u8;
+
+    /// a byte written to an offset from the start of the device's range
+    fn write(&mut self, offset: u16, value: u8);
+
+    /// time passing, called after every instruction with the cycles it took
+    /// unless the device is scheduled
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// whether the device asks for the cycles it needs waking at with
+    /// `next_event` instead of being ticked after every instruction, read once
+    /// when it's mapped
+    fn scheduled(&self) -> bool {
+        false
+    }
+
+    /// the cycle a scheduled device next needs `event` called at, none while
+    /// it has nothing to do until the program touches it
+    /// asked again after every access and event
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+
+    /// the cycle count reached the device's next event, `cycle` is when it
+    /// was due, which can be a little earlier than the end of the
+    /// instruction it's called after
+    fn event(&mut self, _cycle: u64) {}
+
+    /// the cycle the cpu is at, given to a scheduled device before each
+    /// access so it can catch up
+    fn sync(&mut self, _cycle: u64) {}
+
+    /// whether the device is asserting the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// whether the device is holding the NMI line, the cpu takes an NMI each
+    /// time the line goes from released to held
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// what the device is called in access logs and traces, like `VIA`
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// the name of the register at an offset, like `T1C-L`
+    fn register_name(&self, _offset: u16) -> Option<&'static str> {
+        None
+    }
+}
+
+/// a device shared between the memory it's mapped into and its owner
+pub type SharedDevice = Rc<RefCell<dyn Device>>;
+
+/// a device mapped at a range of addresses
+#[derive(Clone)]
+pub(crate) struct MappedDevice {
+    pub start: usize,
+    pub len: usize,
+    pub device: SharedDevice,
+    /// tells the mapping apart from others in the scheduler
+    pub id: usize,
+    /// cached [`Device::scheduled`]
+    pub scheduled: bool,
+    /// whether accesses to the device are logged
+    pub logged: bool,
+}
+
+impl MappedDevice {
+    /// the offset of an address into the device, none if it's outside the range
+    pub fn offset(&self, address: usize) -> Option<u16> {
+        address
+            .checked_sub(self.start)
+            .filter(|offset| *offset < self.len)
+            .map(|offset| offset as u16)
+    }
+}
+
+/// how an access to a device is shown, `VIA.T1C-L` with names, `VIA+$04`
+/// with just the device's and `$D004` without
+pub(crate) fn describe(device: &dyn Device, address: usize, offset: u16) -> String {
+    match (device.name(), device.register_name(offset)) {
+        (Some(name), Some(register)) => format!("{name}.{register}"),
+        (Some(name), None) => format!("{name}+${offset:02X}"),
+        (None, _) => format!("${address:04X}"),
+    }
+}
+
+impl fmt::Debug for MappedDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MappedDevice(${:04X}-${:04X})",
+            self.start,
+            self.start + self.len - 1
+        )
+    }
+}
@@ -0,0 +1,257 @@
+use core::fmt;
+use std::{cell::RefCell, mem, rc::Rc};
+
+use crate::{
+    cpu::{Cpu, CpuBuilder, CpuError, Variant},
+    events::{Event, EventLog},
+    memory::BusError,
+    processor_status::ProcessorStatus,
+};
+
+/// the state of a cpu after one step, and the bus activity the step caused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepRecord {
+    /// address the instruction was fetched from
+    pub pc: u16,
+    pub next_pc: u16,
+    pub sp: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: ProcessorStatus,
+    pub cycles: u64,
+    /// memory, stack and interrupt events in the order they happened
+    pub bus: Vec<Event>,
+}
+
+/// how a single step of one run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    Ran(StepRecord),
+    Halted { pc: u16 },
+    Failed(CpuError),
+}
+
+/// the first step at which two runs stopped agreeing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// number of steps both runs agreed on before this one
+    pub step: u64,
+    pub left: StepOutcome,
+    pub right: StepOutcome,
+}
+
+/// what two runs have to agree on at every step
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    /// registers, cycle counts and every bus access
+    #[default]
+    Everything,
+    /// what the program can see, registers, flags and the memory written, so
+    /// revisions that differ only in timing and dummy accesses agree
+    Architectural,
+}
+
+impl StepOutcome {
+    fn agrees(&self, other: &StepOutcome, compare: Compare) -> bool {
+        match (compare, self, other) {
+            (Compare::Architectural, StepOutcome::Ran(left), StepOutcome::Ran(right)) => {
+                let writes = |record: &StepRecord| -> Vec<Event> {
+                    record
+                        .bus
+                        .iter()
+                        .filter(|event| matches!(event, Event::MemoryWritten { .. }))
+                        .cloned()
+                        .collect()
+                };
+                (left.pc, left.next_pc, left.sp, left.a, left.x, left.y)
+                    == (right.pc, right.next_pc, right.sp, right.a, right.x, right.y)
+                    && left.status == right.status
+                    && writes(left) == writes(right)
+            }
+            _ => self == other,
+        }
+    }
+}
+
+/// step two cpus in lockstep and report the first step where their state or bus
+/// activity differs, none if they agree until both halt or `max_steps` is reached
+/// both cpus get an observer subscribed to capture their bus activity
+pub fn diff_runs(left: Cpu, right: Cpu, max_steps: u64) -> Option<Divergence> {
+    diff_runs_comparing(left, right, max_steps, Compare::Everything)
+}
+
+/// run the same program on an NMOS 6502 and a 65C02 and report the first step
+/// where what the program can see differs, to find code that depends on the
+/// revision it runs on
+pub fn diff_variants(builder: CpuBuilder, max_steps: u64) -> Result<Option<Divergence>, BusError> {
+    let nmos = builder.clone().variant(Variant::Nmos).build()?;
+    let cmos = builder.variant(Variant::Cmos).build()?;
+    Ok(diff_runs_comparing(
+        nmos,
+        cmos,
+        max_steps,
+        Compare::Architectural,
+    ))
+}
+
+/// like [`diff_runs`], agreeing on what `compare` asks for
+pub fn diff_runs_comparing(
+    mut left: Cpu,
+    mut right: Cpu,
+    max_steps: u64,
+    compare: Compare,
+) -> Option<Divergence> {
+    let left_log = Rc::new(RefCell::new(EventLog::default()));
+    let right_log = Rc::new(RefCell::new(EventLog::default()));
+    left.subscribe(left_log.clone());
+    right.subscribe(right_log.clone());
+
+    for step in 0..max_steps {
+        let left_outcome = step_once(&mut left, &left_log);
+        let right_outcome = step_once(&mut right, &right_log);
+        if !left_outcome.agrees(&right_outcome, compare) {
+            return Some(Divergence {
+                step,
+                left: left_outcome,
+                right: right_outcome,
+            });
+        }
+        if !matches!(left_outcome, StepOutcome::Ran(_)) {
+            break;
+        }
+    }
+    None
+}
+
+fn step_once(cpu: &mut Cpu, log: &RefCell<EventLog>) -> StepOutcome {
+    let pc = cpu.pc();
+    let result = cpu.step();
+    let events = mem::take(&mut log.borrow_mut().events);
+
+    match result {
+        Ok(true) => StepOutcome::Ran(StepRecord {
+            pc,
+            next_pc: cpu.pc(),
+            sp: cpu.sp(),
+            a: cpu.a(),
+            x: cpu.x(),
+            y: cpu.y(),
+            status: cpu.status(),
+            cycles: cpu.cycles(),
+            // bus cycles only come from accurate cpus and repeat the reads
+            // and writes already there, keep them from failing every diff of
+            // an accurate run against one that isn't
+            bus: events
+                .into_iter()
+                .filter(|event| {
+                    !matches!(
+                        event,
+                        Event::InstructionRetired { .. } | Event::BusAccess(_)
+                    )
+                })
+                .collect(),
+        }),
+        Ok(false) => StepOutcome::Halted { pc },
+        Err(err) => StepOutcome::Failed(err),
+    }
+}
+
+impl fmt::Display for StepOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepOutcome::Ran(record) => {
+                write!(
+                    f,
+                    "${:04X} -> ${:04X}  A:{:02X} X:{:02X} Y:{:02X} SP:{:04X} P:{} CYC:{}",
+                    record.pc,
+                    record.next_pc,
+                    record.a,
+                    record.x,
+                    record.y,
+                    record.sp,
+                    record.status,
+                    record.cycles
+                )?;
+                for event in &record.bus {
+                    write!(f, "\n    {event:?}")?;
+                }
+                Ok(())
+            }
+            StepOutcome::Halted { pc } => write!(f, "halted at ${pc:04X}"),
+            StepOutcome::Failed(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "runs diverge at step {}", self.step)?;
+        writeln!(f, "left:  {}", self.left)?;
+        write!(f, "right: {}", self.right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    fn cpu(variant: Variant) -> Cpu {
+        Cpu::builder()
+            .variant(variant)
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x01, JMP_ABS_IND, 0xFF, 0x02, NOP])
+            .memory(0x02FF, vec![0x05, 0x07])
+            .memory(0x0200, vec![0x06])
+            .memory(0x0605, vec![TAX, NOP])
+            .memory(0x0705, vec![TAY, NOP])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn identical_runs_agree() {
+        assert_eq!(diff_runs(cpu(Variant::Nmos), cpu(Variant::Nmos), 100), None);
+    }
+
+    #[test]
+    fn reports_first_divergence() {
+        let divergence = diff_runs(cpu(Variant::Nmos), cpu(Variant::Cmos), 100).unwrap();
+        assert_eq!(divergence.step, 1);
+
+        let (StepOutcome::Ran(left), StepOutcome::Ran(right)) =
+            (&divergence.left, &divergence.right)
+        else {
+            panic!("both runs should have stepped");
+        };
+        assert_eq!(left.next_pc, 0x0605);
+        assert_eq!(right.next_pc, 0x0705);
+        assert!(divergence
+            .to_string()
+            .starts_with("runs diverge at step 1\nleft:  $0602 -> $0605"));
+    }
+
+    #[test]
+    fn variants_diverge_only_where_the_revisions_differ() {
+        // nothing here leans on a difference between the revisions
+        let builder = Cpu::builder()
+            .pc(0x0600)
+            .x(0x01)
+            .memory(0x0600, vec![LDA_ABS_X, 0xFF, 0x02, TAX, NOP])
+            .memory(0x0300, vec![0x42]);
+        assert_eq!(diff_variants(builder, 100).unwrap(), None);
+
+        let divergence = diff_variants(
+            Cpu::builder()
+                .pc(0x0600)
+                .memory(0x0600, vec![LDA_IM, 0x01, JMP_ABS_IND, 0xFF, 0x02, NOP])
+                .memory(0x02FF, vec![0x05, 0x07])
+                .memory(0x0200, vec![0x06]),
+            100,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(divergence.step, 1);
+    }
+}
@@ -0,0 +1,512 @@
+use core::fmt;
+use std::collections::BTreeSet;
+
+use crate::{
+    cdl::CodeDataLog,
+    memory::Memory,
+    op_codes::{self, AddressingMode, Opcode},
+};
+
+/// a single disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    /// opcode and operand bytes
+    pub bytes: Vec<u8>,
+    /// the instruction in assembler syntax, `.byte $nn` for unknown opcodes
+    pub text: String,
+}
+
+impl Line {
+    /// address of the instruction following this one
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        write!(
+            f,
+            "${:04X}  {:<9} {}",
+            self.address,
+            hex.join(" "),
+            self.text
+        )
+    }
+}
+
+/// assembler syntax to disassemble into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// what this crate's assembler reads, uppercase with absolute addresses
+    #[default]
+    Plain,
+    /// cc65's assembler, `.byte` data and `a:` forcing absolute addressing
+    Ca65,
+    /// the ACME cross assembler, `!byte` data and `+2` forcing absolute addressing
+    Acme,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(Dialect::Plain),
+            "ca65" => Some(Dialect::Ca65),
+            "acme" => Some(Dialect::Acme),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> String {
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("${byte:02X}")).collect();
+        match self {
+            Dialect::Acme => format!("!byte {}", bytes.join(", ")),
+            _ => format!(".byte {}", bytes.join(", ")),
+        }
+    }
+
+    /// the line placing what follows at an address, none for plain source
+    fn origin(&self, address: u16) -> Option<String> {
+        match self {
+            Dialect::Plain => None,
+            Dialect::Ca65 => Some(format!(".org ${address:04X}")),
+            Dialect::Acme => Some(format!("* = ${address:04X}")),
+        }
+    }
+
+    fn label(&self, name: &str) -> String {
+        match self {
+            Dialect::Ca65 => format!("{name}:"),
+            _ => name.to_string(),
+        }
+    }
+}
+
+/// disassemble the instruction at an address, as the cpu would read it
+pub fn disassemble(memory: &Memory, address: u16) -> Line {
+    disassemble_as(memory, address, Dialect::Plain)
+}
+
+/// disassemble the instruction at an address in an assembler's syntax
+pub fn disassemble_as(memory: &Memory, address: u16, dialect: Dialect) -> Line {
+    disassemble_labelled(memory, address, dialect, |_| None)
+}
+
+fn disassemble_labelled(
+    memory: &Memory,
+    address: u16,
+    dialect: Dialect,
+    label: impl Fn(u16) -> Option<String>,
+) -> Line {
+    let raw = memory.read_byte(address as usize);
+    let Ok(opcode) = Opcode::try_from(raw) else {
+        return Line {
+            address,
+            bytes: vec![raw],
+            text: dialect.bytes(&[raw]),
+        };
+    };
+    let info = opcode.info();
+
+    let bytes: Vec<u8> = (0..info.size())
+        .map(|offset| memory.read_byte(address.wrapping_add(offset as u16) as usize))
+        .collect();
+    let byte = bytes.get(1).copied().unwrap_or_default();
+    let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or_default()]);
+
+    let mut mnemonic = match dialect {
+        Dialect::Plain => info.mnemonic.to_string(),
+        _ => info.mnemonic.to_lowercase(),
+    };
+    // absolute addresses that would fit the zero page have to be forced wide
+    // for assemblers that pick the addressing mode from the value
+    let jump = matches!(opcode, Opcode::JmpAbs | Opcode::Jsr);
+    let forced = word <= 0xFF
+        && !jump
+        && matches!(
+            info.mode,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+        );
+    let absolute = match dialect {
+        _ if jump => label(word).unwrap_or_else(|| format!("${word:04X}")),
+        Dialect::Ca65 if forced => format!("a:${word:04X}"),
+        Dialect::Acme if forced => {
+            mnemonic.push_str("+2");
+            format!("${word:04X}")
+        }
+        _ => format!("${word:04X}"),
+    };
+
+    let operand = match info.mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => match dialect {
+            Dialect::Plain => "A".to_string(),
+            Dialect::Ca65 => "a".to_string(),
+            Dialect::Acme => String::new(),
+        },
+        AddressingMode::Immediate => format!("#${byte:02X}"),
+        AddressingMode::ZeroPage => format!("${byte:02X}"),
+        AddressingMode::ZeroPageX => format!("${byte:02X},X"),
+        AddressingMode::ZeroPageY => format!("${byte:02X},Y"),
+        AddressingMode::Absolute => absolute,
+        AddressingMode::AbsoluteX => format!("{absolute},X"),
+        AddressingMode::AbsoluteY => format!("{absolute},Y"),
+        AddressingMode::Indirect => format!("(${word:04X})"),
+        AddressingMode::ZeroPageXIndirect => format!("(${byte:02X},X)"),
+        AddressingMode::ZeroPageIndirectY => format!("(${byte:02X}),Y"),
+        AddressingMode::Relative => {
+            let target = address.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            label(target).unwrap_or_else(|| format!("${target:04X}"))
+        }
+    };
+    let operand = match dialect {
+        Dialect::Plain => operand,
+        _ => operand.replace(",X", ",x").replace(",Y", ",y"),
+    };
+
+    let text = if operand.is_empty() {
+        mnemonic
+    } else {
+        format!("{mnemonic} {operand}")
+    };
+    Line {
+        address,
+        bytes,
+        text,
+    }
+}
+
+/// disassemble `count` consecutive instructions starting at an address
+pub fn disassemble_range(memory: &Memory, address: u16, count: usize) -> Vec<Line> {
+    let mut lines = Vec::with_capacity(count);
+    let mut address = address;
+    for _ in 0..count {
+        let line = disassemble(memory, address);
+        address = line.next();
+        lines.push(line);
+    }
+    lines
+}
+
+/// the `count` instructions around `pc`, the first `before` of them leading
+/// up to it when the bytes before it disassemble into it cleanly
+/// branches and jumps to an address `label` names show the name instead
+pub fn around(
+    memory: &Memory,
+    pc: u16,
+    before: usize,
+    count: usize,
+    label: impl Fn(u16) -> Option<String>,
+) -> Vec<Line> {
+    // the longest run of instructions that ends exactly at the pc, tried from
+    // the furthest start back an instruction can be
+    let start = (1..=before * 3)
+        .rev()
+        .map(|offset| pc.wrapping_sub(offset as u16))
+        .find_map(|start| {
+            let mut address = start;
+            let mut starts = Vec::new();
+            while starts.len() <= before * 3 && address != pc {
+                starts.push(address);
+                address = disassemble(memory, address).next();
+            }
+            (address == pc && !starts.is_empty())
+                .then(|| starts[starts.len().saturating_sub(before)])
+        })
+        .unwrap_or(pc);
+
+    let mut lines = Vec::with_capacity(count);
+    let mut address = start;
+    for _ in 0..count {
+        let line = disassemble_labelled(memory, address, Dialect::Plain, &label);
+        address = line.next();
+        lines.push(line);
+    }
+    lines
+}
+
+/// where the branch or jump at an address goes
+pub fn target(memory: &Memory, address: u16) -> Option<u16> {
+    let opcode = Opcode::from_byte(memory.read_byte(address as usize))?;
+    let operand = address.wrapping_add(1) as usize;
+    match opcode {
+        _ if opcode.is_branch() => {
+            let offset = memory.read_byte(operand) as i8 as u16;
+            Some(address.wrapping_add(2).wrapping_add(offset))
+        }
+        Opcode::JmpAbs | Opcode::Jsr => Some(memory.read_word(operand)),
+        _ => None,
+    }
+}
+
+/// source for the `len` bytes from `start` that assembles back to the same
+/// bytes, branches and jumps to instructions in it get `Lnnnn` labels except
+/// in plain source, which has none
+/// unknown opcodes and an instruction running past the end are written as
+/// bytes, which this crate's assembler can't read back yet
+pub fn source(memory: &Memory, start: u16, len: usize, dialect: Dialect) -> String {
+    listing(memory, start, len, dialect, None)
+}
+
+/// like [`source`], but only bytes a run logged as code are disassembled,
+/// everything else is written as data rather than guessed at
+pub fn source_with_log(
+    memory: &Memory,
+    start: u16,
+    len: usize,
+    dialect: Dialect,
+    log: &CodeDataLog,
+) -> String {
+    listing(memory, start, len, dialect, Some(log))
+}
+
+/// longest run of data written on one line
+const BYTES_PER_LINE: usize = 8;
+
+/// a piece of the listing, starting at an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    Instruction(u16),
+    Data(u16),
+}
+
+fn listing(
+    memory: &Memory,
+    start: u16,
+    len: usize,
+    dialect: Dialect,
+    log: Option<&CodeDataLog>,
+) -> String {
+    let end = start as usize + len;
+    let mut chunks = Vec::new();
+    let mut address = start as usize;
+    while address < end {
+        let line = disassemble(memory, address as u16);
+        let size = line.bytes.len();
+        let logged = |offset| log.is_none_or(|log| log.is_code((address + offset) as u16));
+        let code = op_codes::instruction(line.bytes[0]).is_some()
+            && address + size <= end
+            && (0..size).all(logged);
+        if code {
+            chunks.push(Chunk::Instruction(address as u16));
+            address += size;
+        } else {
+            chunks.push(Chunk::Data(address as u16));
+            address += 1;
+        }
+    }
+
+    let starts: BTreeSet<u16> = chunks
+        .iter()
+        .map(|chunk| match chunk {
+            Chunk::Instruction(address) | Chunk::Data(address) => *address,
+        })
+        .collect();
+    let targets: BTreeSet<u16> = chunks
+        .iter()
+        .filter_map(|chunk| match chunk {
+            Chunk::Instruction(address) => target(memory, *address),
+            Chunk::Data(_) => None,
+        })
+        .filter(|target| dialect != Dialect::Plain && starts.contains(target))
+        .collect();
+
+    let indent = if dialect == Dialect::Plain {
+        ""
+    } else {
+        "        "
+    };
+    let mut source: Vec<String> = dialect.origin(start).into_iter().collect();
+    let mut data = Vec::new();
+    for chunk in chunks {
+        let address = match chunk {
+            Chunk::Instruction(address) | Chunk::Data(address) => address,
+        };
+        let labelled = targets.contains(&address);
+        // runs of data are broken by labels and instructions
+        let run_ends = labelled || matches!(chunk, Chunk::Instruction(_));
+        if !data.is_empty() && (run_ends || data.len() == BYTES_PER_LINE) {
+            source.push(format!("{indent}{}", dialect.bytes(&data)));
+            data.clear();
+        }
+        if labelled {
+            source.push(dialect.label(&format!("L{address:04X}")));
+        }
+
+        match chunk {
+            Chunk::Instruction(_) => {
+                let line = disassemble_labelled(memory, address, dialect, |target| {
+                    targets.contains(&target).then(|| format!("L{target:04X}"))
+                });
+                source.push(format!("{indent}{}", line.text));
+            }
+            Chunk::Data(_) => data.push(memory.read_byte(address as usize)),
+        }
+    }
+    if !data.is_empty() {
+        source.push(format!("{indent}{}", dialect.bytes(&data)));
+    }
+    source.push(String::new());
+    source.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler, cdl, op_codes::*};
+
+    #[test]
+    fn disassembly_reassembles() {
+        let mut memory = Memory::default();
+        let source = [
+            "LDA #$42",
+            "LDX $10,Y",
+            "ORA ($20),Y",
+            "AND ($30,X)",
+            "JMP ($1234)",
+            "LSR A",
+            "BNE $0600",
+            "TAX",
+        ];
+        let mut address = 0x0600;
+        for line in source {
+            let bytes = assembler::assemble_line_at(line, address).unwrap();
+            memory.write_bytes(address as usize, &bytes).unwrap();
+            address += bytes.len() as u16;
+        }
+
+        let lines = disassemble_range(&memory, 0x0600, source.len());
+        let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, source);
+    }
+
+    #[test]
+    fn views_around_the_pc_line_up_with_it() {
+        let mut memory = Memory::default();
+        let program = [LDA_IM, 0x01, TAX, LDA_ABS, 0x00, 0x02, BNE, 0xF8, NOP];
+        memory.write_bytes(0x0600, &program).unwrap();
+        let label = |address| (address == 0x0600).then(|| "start".to_string());
+
+        let lines = around(&memory, 0x0606, 2, 3, label);
+        let addresses: Vec<u16> = lines.iter().map(|line| line.address).collect();
+        assert_eq!(addresses, [0x0602, 0x0603, 0x0606]);
+        assert_eq!(lines[2].text, "BNE start");
+        assert_eq!(target(&memory, 0x0606), Some(0x0600));
+        assert_eq!(around(&memory, 0x0606, 0, 1, label)[0].address, 0x0606);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_bytes() {
+        let mut memory = Memory::default();
+        memory
+            .write_bytes(0x0600, &[0xFF, LDA_ABS, 0x34, 0x12])
+            .unwrap();
+
+        let lines = disassemble_range(&memory, 0x0600, 2);
+        assert_eq!(lines[0].to_string(), "$0600  FF        .byte $FF");
+        assert_eq!(lines[1].to_string(), "$0601  AD 34 12  LDA $1234");
+    }
+
+    fn listing() -> Memory {
+        let mut memory = Memory::default();
+        let program = assembler::assemble(
+            "LDX #$04\nLSR A\nLDA $0010,X\nBNE $0602\nJSR $0600\nJMP ($1234)\nLSR $20",
+            0x0600,
+        )
+        .unwrap();
+        memory.write_bytes(0x0600, &program).unwrap();
+        memory
+    }
+
+    #[test]
+    fn plain_source_reassembles_to_the_same_bytes() {
+        let memory = listing();
+        let source = source(&memory, 0x0600, 16, Dialect::Plain);
+        assert_eq!(
+            assembler::assemble(&source, 0x0600).unwrap(),
+            (0x0600..0x0610)
+                .map(|address| memory.read_byte(address))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sources_follow_the_dialect() {
+        let memory = listing();
+        assert_eq!(
+            source(&memory, 0x0600, 15, Dialect::Ca65),
+            "\
+.org $0600
+L0600:
+        ldx #$04
+L0602:
+        lsr a
+        lda a:$0010,x
+        bne L0602
+        jsr L0600
+        jmp ($1234)
+        .byte $46
+"
+        );
+        assert_eq!(
+            source(&memory, 0x0600, 16, Dialect::Acme),
+            "\
+* = $0600
+L0600
+        ldx #$04
+L0602
+        lsr
+        lda+2 $0010,x
+        bne L0602
+        jsr L0600
+        jmp ($1234)
+        lsr $20
+"
+        );
+        assert_eq!(Dialect::from_name("acme"), Some(Dialect::Acme));
+    }
+
+    #[test]
+    fn logged_data_is_not_disassembled() {
+        // a routine jumping over a table, then a byte the run never reached
+        let mut memory = Memory::default();
+        let program = [
+            JMP_ABS, 0x05, 0x06, 0xA9, 0xAA, LDA_ABS, 0x03, 0x06, BEQ, 0xF6, 0xFF,
+        ];
+        memory.write_bytes(0x0600, &program).unwrap();
+        let mut flags = vec![0; 0x0600];
+        flags.extend([
+            cdl::CODE,
+            cdl::CODE,
+            cdl::CODE,
+            cdl::DATA,
+            0,
+            cdl::CODE,
+            cdl::CODE,
+            cdl::CODE,
+            cdl::CODE,
+            cdl::CODE,
+        ]);
+        let log = CodeDataLog::from_bytes(&flags);
+
+        assert_eq!(
+            source_with_log(&memory, 0x0600, program.len(), Dialect::Ca65, &log),
+            "\
+.org $0600
+L0600:
+        jmp L0605
+        .byte $A9, $AA
+L0605:
+        lda $0603
+        beq L0600
+        .byte $FF
+"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use core::fmt;
+use std::{cell::RefCell, rc::Rc};
+
+use crate::vcd::BusCycle;
+
+/// something that happened on the cpu or bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// an instruction finished executing
+    InstructionRetired {
+        /// address the instruction was fetched from
+        pc: u16,
+        opcode: u8,
+        /// total cycles executed once the instruction finished
+        cycles: u64,
+    },
+    /// an instruction read a byte from memory, opcode and operand fetches aren't included
+    MemoryRead {
+        address: u16,
+        value: u8,
+        access: Access,
+    },
+    /// a byte was written to memory
+    MemoryWritten { address: u16, value: u8 },
+    /// an interrupt line went from released to held, IRQs held by the host or a
+    /// device are seen at the end of the instruction they're raised in, as the
+    /// cpu samples them
+    InterruptRequested {
+        nmi: bool,
+        /// the cycle the request arrived on
+        cycle: u64,
+    },
+    /// the cpu jumped through an interrupt vector
+    InterruptTaken {
+        vector: u16,
+        /// the cycle the handler's first instruction starts on
+        cycle: u64,
+        /// whether a BRK rather than an interrupt line got here
+        brk: bool,
+    },
+    /// a byte was pushed onto the stack
+    StackPush { address: u16, value: u8 },
+    /// a byte was pulled from the stack
+    StackPull { address: u16, value: u8 },
+    /// in accurate mode, a cycle the cpu used the bus on, opcode and operand
+    /// fetches and dummy reads included, SYNC marks opcode fetches
+    BusAccess(BusCycle),
+}
+
+/// why an instruction read memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// an operand read directly from its address
+    Data,
+    /// part of an address used by indirect addressing
+    Pointer,
+    /// an operand read through a pointer
+    Indirect,
+    /// a read real hardware makes whose value is thrown away
+    Dummy,
+}
+
+/// receives events published by the cpu
+pub trait Observer {
+    fn notify(&mut self, event: &Event);
+}
+
+/// a shared handle to an observer, kept by the caller to inspect it later
+pub type SharedObserver = Rc<RefCell<dyn Observer>>;
+
+/// the observers registered on a cpu
+#[derive(Default, Clone)]
+pub struct Observers(Vec<SharedObserver>);
+
+impl Observers {
+    /// register an observer to receive every future event
+    pub fn subscribe(&mut self, observer: SharedObserver) {
+        self.0.push(observer);
+    }
+
+    /// publish an event to every observer
+    pub fn notify(&self, event: Event) {
+        #[cfg(feature = "self-profile")]
+        let started = std::time::Instant::now();
+        for observer in &self.0 {
+            observer.borrow_mut().notify(&event);
+        }
+        #[cfg(feature = "self-profile")]
+        crate::self_profile::record(|counters| {
+            counters.observer_events += 1;
+            counters.observer_time += started.elapsed();
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+/// an observer that records every event, mostly useful in tests
+#[derive(Debug, Default, Clone)]
+pub struct EventLog {
+    pub events: Vec<Event>,
+}
+
+impl Observer for EventLog {
+    fn notify(&mut self, event: &Event) {
+        self.events.push(*event);
+    }
+}
@@ -0,0 +1,241 @@
+//! a teaching mode narrating each instruction run in plain English, like
+//! `LDA #$42: load $42 into A; N=0 Z=0`
+//!
+//! the narration is built from the opcode table, its mnemonic and addressing
+//! mode pick the wording and the flags shown are the ones the instruction can
+//! change, instructions without wording of their own fall back to the table's
+//! description
+
+use crate::{
+    cpu::{Cpu, CpuError},
+    disassembler::{self, Line},
+    op_codes::{self, AddressingMode},
+    processor_status::ProcessorStatus,
+    registers::Registers,
+};
+
+/// run one instruction and explain it, with whether the cpu is still running
+pub fn step(cpu: &mut Cpu) -> Result<(bool, String), CpuError> {
+    let line = disassembler::disassemble(&cpu.memory, cpu.pc());
+    let before = cpu.registers();
+    let running = cpu.step()?;
+    Ok((running, explain(&line, &before, cpu)))
+}
+
+/// explain the instruction at `line`, run from the registers in `before`
+/// and leaving `cpu` as it is now
+pub fn explain(line: &Line, before: &Registers, cpu: &Cpu) -> String {
+    let Some(info) = line
+        .bytes
+        .first()
+        .and_then(|opcode| op_codes::instruction(*opcode))
+    else {
+        return format!("{}: not an instruction", line.text);
+    };
+    let after = cpu.registers();
+    let operand = match line.bytes[1..] {
+        [low] => low as u16,
+        [low, high] => u16::from_le_bytes([low, high]),
+        _ => 0,
+    };
+    // the address a read-modify-write instruction changed
+    let address = match info.mode {
+        AddressingMode::ZeroPageX => (operand as u8).wrapping_add(before.x) as u16,
+        AddressingMode::AbsoluteX => operand.wrapping_add(before.x as u16),
+        _ => operand,
+    };
+    let register = |mnemonic: u8| match mnemonic {
+        b'A' => after.a,
+        b'X' => after.x,
+        _ => after.y,
+    };
+
+    let mnemonic = info.mnemonic.as_bytes();
+    let what = match info.mnemonic {
+        "LDA" | "LDX" | "LDY" => format!(
+            "load ${:02X} into {}",
+            register(mnemonic[2]),
+            mnemonic[2] as char
+        ),
+        "AND" => format!(
+            "keep the bits of A also set in {}, leaving ${:02X}",
+            operand_name(info.mode, operand),
+            after.a
+        ),
+        "ORA" => format!(
+            "set the bits of A set in {}, leaving ${:02X}",
+            operand_name(info.mode, operand),
+            after.a
+        ),
+        "LSR" if info.mode == AddressingMode::Accumulator => {
+            format!("shift A right a bit, leaving ${:02X}", after.a)
+        }
+        "LSR" => format!(
+            "shift ${address:04X} right a bit, leaving ${:02X}",
+            cpu.memory.read_byte(address as usize)
+        ),
+        "TAX" | "TAY" | "TXA" | "TYA" => {
+            let (from, to) = (mnemonic[1], mnemonic[2]);
+            format!(
+                "copy {} (${:02X}) into {}",
+                from as char,
+                register(from),
+                to as char
+            )
+        }
+        "TSX" => format!("copy the stack pointer (${:02X}) into X", after.x),
+        "TXS" => format!("copy X into the stack pointer, now ${:04X}", after.sp),
+        "PHA" => format!("push A (${:02X}) onto the stack", after.a),
+        "PHP" => "push the flags onto the stack".to_string(),
+        "PLA" => format!("pull ${:02X} off the stack into A", after.a),
+        "PLP" => "pull the flags off the stack".to_string(),
+        "JMP" => format!("jump to ${:04X}", after.pc),
+        "JSR" => format!("call the subroutine at ${:04X}", after.pc),
+        "RTS" => format!("return from a subroutine to ${:04X}", after.pc),
+        "RTI" => format!(
+            "return from an interrupt to ${:04X}, restoring the flags",
+            after.pc
+        ),
+        "BRK" => format!("break into the interrupt handler at ${:04X}", after.pc),
+        "SEC" => "set the carry flag".to_string(),
+        "SED" => "switch to decimal mode".to_string(),
+        "SEI" => "ignore interrupt requests".to_string(),
+        "CLI" => "accept interrupt requests".to_string(),
+        "NOP" => "do nothing, which halts the emulator".to_string(),
+        _ if op_codes::is_branch(line.bytes[0]) => {
+            let target = line
+                .address
+                .wrapping_add(2)
+                .wrapping_add(operand as i8 as u16);
+            format!(
+                "branch to ${target:04X} if {}, {}",
+                condition(info.mnemonic),
+                match after.pc == target {
+                    true => "taken",
+                    false => "not taken",
+                }
+            )
+        }
+        _ => info.description.to_string(),
+    };
+
+    let flags: Vec<String> = flags(info.mnemonic)
+        .iter()
+        .map(|(flag, name)| format!("{name}={}", after.status.contains(*flag) as u8))
+        .collect();
+    match flags.is_empty() {
+        true => format!("{}: {what}", line.text),
+        false => format!("{}: {what}; {}", line.text, flags.join(" ")),
+    }
+}
+
+/// how the operand of a logic instruction reads
+fn operand_name(mode: AddressingMode, operand: u16) -> String {
+    match mode {
+        AddressingMode::Immediate => format!("${operand:02X}"),
+        _ => "memory".to_string(),
+    }
+}
+
+/// what a branch is waiting for
+fn condition(mnemonic: &str) -> &'static str {
+    match mnemonic {
+        "BCC" => "carry is clear",
+        "BCS" => "carry is set",
+        "BEQ" => "the result was zero",
+        "BNE" => "the result wasn't zero",
+        "BMI" => "the result was negative",
+        "BPL" => "the result wasn't negative",
+        "BVC" => "overflow is clear",
+        _ => "overflow is set",
+    }
+}
+
+/// the flags an instruction can change, as they are shown
+fn flags(mnemonic: &str) -> &'static [(ProcessorStatus, &'static str)] {
+    const NZ: &[(ProcessorStatus, &str)] = &[(ProcessorStatus::N, "N"), (ProcessorStatus::Z, "Z")];
+    match mnemonic {
+        "LDA" | "LDX" | "LDY" | "AND" | "ORA" | "TAX" | "TAY" | "TSX" | "TXA" | "TYA" | "PLA" => NZ,
+        "LSR" => &[
+            (ProcessorStatus::N, "N"),
+            (ProcessorStatus::Z, "Z"),
+            (ProcessorStatus::C, "C"),
+        ],
+        "PLP" | "RTI" => &[
+            (ProcessorStatus::N, "N"),
+            (ProcessorStatus::V, "V"),
+            (ProcessorStatus::D, "D"),
+            (ProcessorStatus::I, "I"),
+            (ProcessorStatus::Z, "Z"),
+            (ProcessorStatus::C, "C"),
+        ],
+        "SEC" => &[(ProcessorStatus::C, "C")],
+        "SED" => &[(ProcessorStatus::D, "D")],
+        "SEI" | "CLI" | "BRK" => &[(ProcessorStatus::I, "I")],
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    fn narrate(program: Vec<u8>) -> Vec<String> {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, program)
+            .memory(0x0010, vec![0x03])
+            .build()
+            .unwrap();
+        let mut lines = Vec::new();
+        loop {
+            let (running, line) = step(&mut cpu).unwrap();
+            lines.push(line);
+            if !running {
+                return lines;
+            }
+        }
+    }
+
+    #[test]
+    fn instructions_are_narrated_with_their_flags() {
+        let lines = narrate(vec![LDA_IM, 0x42, TAX, ORA_IM, 0x80, PHA, SEC, NOP]);
+        assert_eq!(
+            lines,
+            [
+                "LDA #$42: load $42 into A; N=0 Z=0",
+                "TAX: copy A ($42) into X; N=0 Z=0",
+                "ORA #$80: set the bits of A set in $80, leaving $C2; N=1 Z=0",
+                "PHA: push A ($C2) onto the stack",
+                "SEC: set the carry flag; C=1",
+                "NOP: do nothing, which halts the emulator",
+            ]
+        );
+    }
+
+    #[test]
+    fn branches_say_whether_they_were_taken() {
+        let lines = narrate(vec![LSR_ZP, 0x10, BNE, 0xFC, NOP]);
+        assert_eq!(
+            lines[0],
+            "LSR $10: shift $0010 right a bit, leaving $01; N=0 Z=0 C=1"
+        );
+        assert_eq!(
+            lines[1],
+            "BNE $0600: branch to $0600 if the result wasn't zero, taken"
+        );
+        assert_eq!(
+            lines[3],
+            "BNE $0600: branch to $0600 if the result wasn't zero, not taken"
+        );
+    }
+
+    #[test]
+    fn every_instruction_has_words() {
+        for info in INSTRUCTIONS {
+            assert!(!info.description.is_empty(), "{:?}", info.opcode);
+        }
+    }
+}
@@ -0,0 +1,620 @@
+//! a graphical debugger, for demos where a terminal isn't the best fit
+//!
+//! every panel is a window that can be moved, resized and closed, and brought
+//! back from the toolbar: disassembly following the pc, registers and flags,
+//! a memory editor, the stack, breakpoints and the 32x32 display easy6502
+//! style programs draw to, and a text screen when one is mapped
+//!
+//! while running, the cpu is stepped for a slice of each frame so the window
+//! stays responsive, it stops at breakpoints, errors and when it halts
+
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::Write as _,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use eframe::egui::{self, Color32, ColorImage, Rect, Sense, TextureHandle, TextureOptions, Vec2};
+
+use crate::{
+    cpu::Cpu, disassembler, memory::MAX_MEM, processor_status::ProcessorStatus, session::Session,
+    text_video::TextVideo,
+};
+
+/// longest the cpu runs for in a frame before the window is redrawn
+const FRAME_BUDGET: Duration = Duration::from_millis(12);
+
+/// instructions run between checks of the frame budget
+const STEPS_PER_CHECK: usize = 1000;
+
+/// instructions shown in the disassembly window
+const DISASSEMBLY_LINES: usize = 24;
+
+/// bytes shown in the memory window, in rows of 16
+const MEMORY_ROWS: usize = 16;
+
+/// where the display's pixels start, a byte each, row by row
+const SCREEN: usize = 0x0200;
+
+/// width and height of the display in pixels
+const SCREEN_SIZE: usize = 32;
+
+/// the colors of the display's low nibble, as easy6502 draws them
+const PALETTE: [Color32; 16] = [
+    Color32::from_rgb(0x00, 0x00, 0x00),
+    Color32::from_rgb(0xFF, 0xFF, 0xFF),
+    Color32::from_rgb(0x88, 0x00, 0x00),
+    Color32::from_rgb(0xAA, 0xFF, 0xEE),
+    Color32::from_rgb(0xCC, 0x44, 0xCC),
+    Color32::from_rgb(0x00, 0xCC, 0x55),
+    Color32::from_rgb(0x00, 0x00, 0xAA),
+    Color32::from_rgb(0xEE, 0xEE, 0x77),
+    Color32::from_rgb(0xDD, 0x88, 0x55),
+    Color32::from_rgb(0x66, 0x44, 0x00),
+    Color32::from_rgb(0xFF, 0x77, 0x77),
+    Color32::from_rgb(0x33, 0x33, 0x33),
+    Color32::from_rgb(0x77, 0x77, 0x77),
+    Color32::from_rgb(0xAA, 0xFF, 0x66),
+    Color32::from_rgb(0x00, 0x88, 0xFF),
+    Color32::from_rgb(0xBB, 0xBB, 0xBB),
+];
+
+/// the flag checkboxes in the registers window, in NV-BDIZC order
+#[allow(clippy::type_complexity)]
+const FLAGS: [(
+    &str,
+    fn(&ProcessorStatus) -> bool,
+    fn(&mut ProcessorStatus, bool),
+); 7] = [
+    (
+        "N",
+        ProcessorStatus::negative,
+        ProcessorStatus::set_negative,
+    ),
+    (
+        "V",
+        ProcessorStatus::overflow,
+        ProcessorStatus::set_overflow,
+    ),
+    (
+        "B",
+        ProcessorStatus::break_command,
+        ProcessorStatus::set_break_command,
+    ),
+    ("D", ProcessorStatus::decimal, ProcessorStatus::set_decimal),
+    (
+        "I",
+        ProcessorStatus::interrupt_disable,
+        ProcessorStatus::set_interrupt_disable,
+    ),
+    ("Z", ProcessorStatus::zero, ProcessorStatus::set_zero),
+    ("C", ProcessorStatus::carry, ProcessorStatus::set_carry),
+];
+
+/// which windows are open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Windows {
+    disassembly: bool,
+    registers: bool,
+    memory: bool,
+    stack: bool,
+    breakpoints: bool,
+    display: bool,
+    text_screen: bool,
+}
+
+impl Default for Windows {
+    fn default() -> Self {
+        Self {
+            disassembly: true,
+            registers: true,
+            memory: true,
+            stack: true,
+            breakpoints: true,
+            display: true,
+            text_screen: true,
+        }
+    }
+}
+
+/// the debugger's state between frames
+pub struct Debugger {
+    cpu: Cpu,
+    session: Session,
+    windows: Windows,
+    running: bool,
+    /// why the cpu last stopped, shown in the toolbar
+    status: String,
+    /// first address shown in the memory window
+    memory_start: u16,
+    memory_start_text: String,
+    /// the byte being edited in the memory window and its text so far
+    editing: Option<(u16, String)>,
+    breakpoint_text: String,
+    text_video: Option<Rc<RefCell<TextVideo>>>,
+    /// the text screen as last drawn, uploaded once the window first shows
+    text_texture: Option<TextureHandle>,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            cpu,
+            session: Session::default(),
+            windows: Windows::default(),
+            running: false,
+            status: "stopped".to_string(),
+            memory_start: 0,
+            memory_start_text: "0000".to_string(),
+            editing: None,
+            breakpoint_text: String::new(),
+            text_video: None,
+            text_texture: None,
+        }
+    }
+
+    /// use the labels and breakpoints of a session
+    pub fn session(mut self, session: Session) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// show a text screen the cpu has mapped in its own window
+    pub fn text_video(mut self, video: Rc<RefCell<TextVideo>>) -> Self {
+        self.text_video = Some(video);
+        self
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// run one instruction, stopping if the cpu halts or fails
+    pub fn step(&mut self) {
+        match self.cpu.step() {
+            Ok(true) => self.status = format!("stepped to ${:04X}", self.cpu.pc()),
+            Ok(false) => self.stop("halted".to_string()),
+            Err(err) => self.stop(format!("error: {err}")),
+        }
+    }
+
+    /// run until a breakpoint, the cpu stops or `budget` runs out
+    pub fn run_for(&mut self, budget: Duration) {
+        let start = Instant::now();
+        while self.running && start.elapsed() < budget {
+            for _ in 0..STEPS_PER_CHECK {
+                self.step();
+                if !self.running {
+                    return;
+                }
+                if self.session.breaks_at(self.cpu.pc()) {
+                    self.stop(format!("breakpoint ${:04X} hit", self.cpu.pc()));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// start running from the next frame
+    pub fn run(&mut self) {
+        self.running = true;
+        self.status = "running".to_string();
+    }
+
+    /// run until the pc reaches `address`, through a temporary breakpoint
+    pub fn run_to(&mut self, address: u16) {
+        self.session.temporary.insert(address);
+        self.run();
+    }
+
+    fn stop(&mut self, status: String) {
+        self.running = false;
+        self.status = status;
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if self.running {
+                if ui.button("pause").clicked() {
+                    self.stop(format!("paused at ${:04X}", self.cpu.pc()));
+                }
+            } else {
+                if ui.button("run").clicked() {
+                    self.run();
+                }
+                if ui.button("step").clicked() {
+                    self.step();
+                }
+            }
+            ui.separator();
+            ui.toggle_value(&mut self.windows.disassembly, "disassembly");
+            ui.toggle_value(&mut self.windows.registers, "registers");
+            ui.toggle_value(&mut self.windows.memory, "memory");
+            ui.toggle_value(&mut self.windows.stack, "stack");
+            ui.toggle_value(&mut self.windows.breakpoints, "breakpoints");
+            ui.toggle_value(&mut self.windows.display, "display");
+            if self.text_video.is_some() {
+                ui.toggle_value(&mut self.windows.text_screen, "text screen");
+            }
+            ui.separator();
+            ui.label(&self.status);
+        });
+    }
+
+    /// instructions around the pc, click one to toggle a breakpoint on it or
+    /// right click to run to it
+    fn disassembly(&mut self, ui: &mut egui::Ui) {
+        for (address, row) in self.disassembly_rows() {
+            let mut text = egui::RichText::new(row).monospace();
+            if address == Some(self.cpu.pc()) {
+                text = text.strong();
+            }
+            let response = ui.add(egui::Label::new(text).sense(Sense::click()));
+            let Some(address) = address else {
+                continue;
+            };
+            if response.clicked() && !self.session.breakpoints.remove(&address) {
+                self.session.breakpoints.insert(address);
+            }
+            if response.secondary_clicked() && !self.running {
+                self.run_to(address);
+            }
+        }
+    }
+
+    /// the disassembly window's rows, instructions with their address and
+    /// labels above the instructions they name
+    /// branches and jumps point up or down to their target, by name when the
+    /// session has a label for it
+    fn disassembly_rows(&self) -> Vec<(Option<u16>, String)> {
+        let names: HashMap<u16, &str> = self
+            .session
+            .labels
+            .iter()
+            .map(|(name, address)| (*address, name.as_str()))
+            .collect();
+        let label = |address| names.get(&address).map(|name| name.to_string());
+        let pc = self.cpu.pc();
+        let lines = disassembler::around(
+            &self.cpu.memory,
+            pc,
+            DISASSEMBLY_LINES / 3,
+            DISASSEMBLY_LINES,
+            label,
+        );
+
+        let mut rows = Vec::new();
+        for line in lines {
+            if let Some(name) = names.get(&line.address) {
+                rows.push((None, format!("{name}:")));
+            }
+            let marker = match (
+                line.address == pc,
+                self.session.breakpoints.contains(&line.address)
+                    || self.session.temporary.contains(&line.address),
+            ) {
+                (true, true) => ">*",
+                (true, false) => "> ",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            let mut row = format!("{marker} {:<32}", line.to_string());
+            if let Some(target) = disassembler::target(&self.cpu.memory, line.address) {
+                let arrow = match target.cmp(&line.address) {
+                    Ordering::Less => '↑',
+                    Ordering::Equal => '↺',
+                    Ordering::Greater => '↓',
+                };
+                match names.get(&target) {
+                    Some(name) => write!(row, " {arrow} {name}").unwrap(),
+                    None => write!(row, " {arrow} ${target:04X}").unwrap(),
+                }
+            }
+            rows.push((Some(line.address), row.trim_end().to_string()));
+        }
+        rows
+    }
+
+    fn registers(&mut self, ui: &mut egui::Ui) {
+        let mut registers = self.cpu.registers();
+        egui::Grid::new("registers").show(ui, |ui| {
+            ui.label("pc");
+            ui.add(egui::DragValue::new(&mut registers.pc).hexadecimal(4, false, true));
+            ui.end_row();
+            ui.label("sp");
+            ui.add(egui::DragValue::new(&mut registers.sp).hexadecimal(4, false, true));
+            ui.end_row();
+            for (name, value) in [
+                ("a", &mut registers.a),
+                ("x", &mut registers.x),
+                ("y", &mut registers.y),
+            ] {
+                ui.label(name);
+                ui.add(egui::DragValue::new(value).hexadecimal(2, false, true));
+                ui.end_row();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            for (name, get, set) in FLAGS {
+                let mut value = get(&registers.status);
+                if ui.checkbox(&mut value, name).changed() {
+                    set(&mut registers.status, value);
+                }
+            }
+        });
+        ui.label(format!("cycles {}", self.cpu.cycles()));
+
+        if registers != self.cpu.registers() {
+            self.cpu.set_registers(registers);
+        }
+    }
+
+    /// a hex editor, click a byte to change it
+    fn memory(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("from $");
+            let edit =
+                ui.add(egui::TextEdit::singleline(&mut self.memory_start_text).desired_width(48.0));
+            if edit.changed() {
+                if let Ok(start) = u16::from_str_radix(&self.memory_start_text, 16) {
+                    self.memory_start = start & 0xFFF0;
+                }
+            }
+        });
+
+        egui::Grid::new("memory")
+            .spacing([4.0, 2.0])
+            .show(ui, |ui| {
+                for row in 0..MEMORY_ROWS {
+                    let row_start = self.memory_start as usize + row * 16;
+                    if row_start >= MAX_MEM {
+                        break;
+                    }
+                    ui.monospace(format!("{row_start:04X}"));
+                    for address in row_start..row_start + 16 {
+                        self.memory_cell(ui, address as u16);
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn memory_cell(&mut self, ui: &mut egui::Ui, address: u16) {
+        match &mut self.editing {
+            Some((editing, text)) if *editing == address => {
+                let edit = ui.add(egui::TextEdit::singleline(text).desired_width(18.0));
+                edit.request_focus();
+                if edit.lost_focus() {
+                    if let Ok(value) = u8::from_str_radix(text, 16) {
+                        self.cpu.memory.write_byte(address as usize, value);
+                        self.cpu.invalidate_block_cache();
+                    }
+                    self.editing = None;
+                }
+            }
+            _ => {
+                let value = self.cpu.memory.read_byte(address as usize);
+                let label =
+                    egui::Label::new(egui::RichText::new(format!("{value:02X}")).monospace())
+                        .sense(Sense::click());
+                if ui.add(label).clicked() {
+                    self.editing = Some((address, format!("{value:02X}")));
+                }
+            }
+        }
+    }
+
+    /// the bytes pushed so far, most recent first
+    fn stack(&mut self, ui: &mut egui::Ui) {
+        if let (Some(low), Some(free)) = (self.cpu.stack_high_water(), self.cpu.stack_headroom()) {
+            ui.label(format!("high-water {low:04X}, {free} bytes free"));
+            ui.separator();
+        }
+        let top = self.cpu.sp().wrapping_add(1);
+        let end = (top & 0xFF00) | 0x00FF;
+        if top > end {
+            ui.label("empty");
+            return;
+        }
+        for address in top..=end {
+            let value = self.cpu.memory.read_byte(address as usize);
+            ui.monospace(format!("{address:04X}  {value:02X}"));
+        }
+    }
+
+    fn breakpoints(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("$");
+            ui.add(egui::TextEdit::singleline(&mut self.breakpoint_text).desired_width(48.0));
+            if ui.button("add").clicked() {
+                if let Ok(address) = u16::from_str_radix(&self.breakpoint_text, 16) {
+                    self.session.breakpoints.insert(address);
+                    self.breakpoint_text.clear();
+                }
+            }
+        });
+        let mut removed = None;
+        for address in &self.session.breakpoints {
+            ui.horizontal(|ui| {
+                ui.monospace(format!("${address:04X}"));
+                if ui.small_button("remove").clicked() {
+                    removed = Some(*address);
+                }
+            });
+        }
+        if let Some(address) = removed {
+            self.session.breakpoints.remove(&address);
+        }
+    }
+
+    /// the 32x32 screen at $0200, a byte a pixel
+    fn display(&mut self, ui: &mut egui::Ui) {
+        let pixel = (ui.available_width() / SCREEN_SIZE as f32).clamp(4.0, 16.0);
+        let size = Vec2::splat(pixel * SCREEN_SIZE as f32);
+        let (response, painter) = ui.allocate_painter(size, Sense::hover());
+        let origin = response.rect.min;
+        for i in 0..SCREEN_SIZE * SCREEN_SIZE {
+            let (x, y) = ((i % SCREEN_SIZE) as f32, (i / SCREEN_SIZE) as f32);
+            let rect = Rect::from_min_size(origin + Vec2::new(x, y) * pixel, Vec2::splat(pixel));
+            painter.rect_filled(rect, 0.0, color(self.cpu.memory.read_byte(SCREEN + i)));
+        }
+    }
+}
+
+/// a text screen drawn through its character ROM, twice its size
+fn text_screen(ui: &mut egui::Ui, video: &TextVideo, texture: &mut Option<TextureHandle>) {
+    let pixels: Vec<Color32> = video.render().into_iter().map(color).collect();
+    let image = ColorImage::new([video.width(), video.height()], pixels);
+    let texture = match texture {
+        Some(texture) => {
+            texture.set(image, TextureOptions::NEAREST);
+            texture
+        }
+        None => texture.insert(ui.ctx().load_texture(
+            "text screen",
+            image,
+            TextureOptions::NEAREST,
+        )),
+    };
+    ui.image((texture.id(), texture.size_vec2() * 2.0));
+}
+
+/// the display color of a screen byte
+fn color(value: u8) -> Color32 {
+    PALETTE[(value & 0x0F) as usize]
+}
+
+impl eframe::App for Debugger {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.running {
+            self.run_for(FRAME_BUDGET);
+            ctx.request_repaint();
+        }
+
+        egui::TopBottomPanel::top("toolbar").show(ctx, |ui| self.toolbar(ui));
+        egui::CentralPanel::default().show(ctx, |_| {});
+
+        let mut windows = self.windows;
+        egui::Window::new("disassembly")
+            .open(&mut windows.disassembly)
+            .show(ctx, |ui| self.disassembly(ui));
+        egui::Window::new("registers")
+            .open(&mut windows.registers)
+            .show(ctx, |ui| self.registers(ui));
+        egui::Window::new("memory")
+            .open(&mut windows.memory)
+            .show(ctx, |ui| self.memory(ui));
+        egui::Window::new("stack")
+            .open(&mut windows.stack)
+            .vscroll(true)
+            .show(ctx, |ui| self.stack(ui));
+        egui::Window::new("breakpoints")
+            .open(&mut windows.breakpoints)
+            .show(ctx, |ui| self.breakpoints(ui));
+        egui::Window::new("display")
+            .open(&mut windows.display)
+            .show(ctx, |ui| self.display(ui));
+        if let Some(video) = &self.text_video {
+            egui::Window::new("text screen")
+                .open(&mut windows.text_screen)
+                .show(ctx, |ui| {
+                    text_screen(ui, &video.borrow(), &mut self.text_texture)
+                });
+        }
+        self.windows = windows;
+    }
+}
+
+/// open the debugger, returning once its window is closed
+pub fn run(debugger: Debugger) -> eframe::Result<()> {
+    eframe::run_native(
+        "cpu_emu",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Ok(Box::new(debugger))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    fn debugger() -> Debugger {
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![TAX, TAY, TAX, NOP])
+            .build()
+            .unwrap();
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn running_stops_at_breakpoints_and_halts() {
+        let mut debugger = debugger();
+        debugger.session_mut().breakpoints.insert(0x0602);
+        debugger.run();
+        debugger.run_for(Duration::from_secs(1));
+        assert!(!debugger.running());
+        assert_eq!(debugger.cpu().pc(), 0x0602);
+        assert_eq!(debugger.status, "breakpoint $0602 hit");
+
+        debugger.run();
+        debugger.run_for(Duration::from_secs(1));
+        assert_eq!(debugger.status, "halted");
+    }
+
+    #[test]
+    fn running_to_an_address_stops_there_once() {
+        let mut debugger = debugger();
+        debugger.run_to(0x0601);
+        debugger.run_for(Duration::from_secs(1));
+        assert_eq!(debugger.cpu().pc(), 0x0601);
+        assert!(debugger.session_mut().temporary.is_empty());
+    }
+
+    #[test]
+    fn disassembly_follows_the_pc_and_points_at_targets() {
+        let cpu = Cpu::builder()
+            .pc(0x0603)
+            .memory(
+                0x0600,
+                vec![LDA_IM, 0x01, TAX, BNE, 0xFB, JMP_ABS, 0x20, 0x06],
+            )
+            .build()
+            .unwrap();
+        let mut debugger = Debugger::new(cpu);
+        debugger
+            .session_mut()
+            .labels
+            .insert("top".to_string(), 0x0600);
+        debugger.session_mut().breakpoints.insert(0x0602);
+
+        // the zeroed memory before the program reads as BRKs
+        let rows = debugger.disassembly_rows();
+        let top = rows.iter().position(|row| row.0.is_none()).unwrap();
+        let rows = &rows[top..];
+        assert_eq!(rows[0], (None, "top:".to_string()));
+        assert_eq!(rows[1].0, Some(0x0600));
+        assert_eq!(rows[2].1, " * $0602  AA        TAX");
+        assert_eq!(rows[3].1, ">  $0603  D0 FB     BNE top         ↑ top");
+        assert!(rows[4].1.ends_with("JMP $0620       ↓ $0620"));
+    }
+
+    #[test]
+    fn screen_bytes_use_the_low_nibble() {
+        assert_eq!(color(0x01), Color32::WHITE);
+        assert_eq!(color(0xF0), Color32::BLACK);
+    }
+}
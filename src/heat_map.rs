@@ -0,0 +1,276 @@
+//! how often each address of the 64K address space was read, written and
+//! run, exported as a picture or json to spot hot loops, stack churn and
+//! stray writes at a glance
+//!
+//! pictures are 256x256, a pixel an address with a row a page, writes in the
+//! red channel, reads in green and instructions run in blue, each scaled
+//! logarithmically against its busiest address so a loop run a million times
+//! doesn't drown out a table read twice
+
+use std::{fmt::Write as _, fs, io, path::Path};
+
+use crate::{
+    events::{Access, Event, Observer},
+    memory::MAX_MEM,
+    op_codes,
+};
+
+/// pixels across and down an exported picture
+const SIDE: usize = 256;
+
+/// access counts for every address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeatMap {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    executes: Vec<u32>,
+}
+
+impl Default for HeatMap {
+    fn default() -> Self {
+        Self {
+            reads: vec![0; MAX_MEM],
+            writes: vec![0; MAX_MEM],
+            executes: vec![0; MAX_MEM],
+        }
+    }
+}
+
+impl HeatMap {
+    /// times an address was read as data, stack pulls included
+    pub fn reads(&self, address: u16) -> u32 {
+        self.reads[address as usize]
+    }
+
+    /// times an address was written, stack pushes included
+    pub fn writes(&self, address: u16) -> u32 {
+        self.writes[address as usize]
+    }
+
+    /// times an address was fetched as part of an instruction
+    pub fn executes(&self, address: u16) -> u32 {
+        self.executes[address as usize]
+    }
+
+    /// the map as 256x256 RGB pixels, row by row
+    pub fn pixels(&self) -> Vec<u8> {
+        let scale = |counts: &[u32]| {
+            let max = (*counts.iter().max().unwrap_or(&0) as f64).ln_1p();
+            move |count: u32| match count {
+                0 => 0,
+                // anything touched at all shows up
+                _ => (64.0 + 191.0 * (count as f64).ln_1p() / max) as u8,
+            }
+        };
+        let (red, green, blue) = (
+            scale(&self.writes),
+            scale(&self.reads),
+            scale(&self.executes),
+        );
+        let mut pixels = Vec::with_capacity(MAX_MEM * 3);
+        for address in 0..MAX_MEM {
+            pixels.push(red(self.writes[address]));
+            pixels.push(green(self.reads[address]));
+            pixels.push(blue(self.executes[address]));
+        }
+        pixels
+    }
+
+    /// the map as a binary PPM picture
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{SIDE} {SIDE}\n255\n").into_bytes();
+        ppm.extend(self.pixels());
+        ppm
+    }
+
+    /// the map as a PNG picture, stored uncompressed
+    pub fn to_png(&self) -> Vec<u8> {
+        // each row starts with its filter type, none
+        let mut raw = Vec::with_capacity(SIDE * (SIDE * 3 + 1));
+        for row in self.pixels().chunks(SIDE * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(SIDE as u32).to_be_bytes());
+        header.extend_from_slice(&(SIDE as u32).to_be_bytes());
+        // 8 bit RGB, deflate, no filtering beyond per row, not interlaced
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// addresses that were touched and their counts, as json
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for address in 0..MAX_MEM {
+            let (reads, writes, executes) = (
+                self.reads[address],
+                self.writes[address],
+                self.executes[address],
+            );
+            if reads == 0 && writes == 0 && executes == 0 {
+                continue;
+            }
+            if json.len() > 1 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"address\":{address},\"reads\":{reads},\"writes\":{writes},\"executes\":{executes}}}"
+            );
+        }
+        json.push(']');
+        json
+    }
+
+    /// write the map in the format its extension names, png, ppm or json
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        match extension {
+            Some("png") => fs::write(path, self.to_png()),
+            Some("ppm") => fs::write(path, self.to_ppm()),
+            Some("json") => fs::write(path, self.to_json()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "heat maps are saved as .png, .ppm or .json",
+            )),
+        }
+    }
+}
+
+impl Observer for HeatMap {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::InstructionRetired { pc, opcode, .. } => {
+                let size = op_codes::instruction(opcode).map_or(1, |info| info.size());
+                for offset in 0..size {
+                    self.executes[pc.wrapping_add(offset as u16) as usize] += 1;
+                }
+            }
+            Event::MemoryRead {
+                access: Access::Dummy,
+                ..
+            } => {}
+            Event::MemoryRead { address, .. } | Event::StackPull { address, .. } => {
+                self.reads[address as usize] += 1;
+            }
+            // pushes are published as writes too
+            Event::MemoryWritten { address, .. } => self.writes[address as usize] += 1,
+            _ => {}
+        }
+    }
+}
+
+/// append a PNG chunk with its length and checksum
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    fn heat_map() -> HeatMap {
+        let map = Rc::new(RefCell::new(HeatMap::default()));
+        // loop: PHA, PLA, LSR $10, BNE loop, NOP, three times round from $04
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![PHA, PLA, LSR_ZP, 0x10, BNE, 0xFA, NOP])
+            .memory(0x0010, vec![0x04])
+            .observer(map.clone())
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+        let map = map.borrow().clone();
+        map
+    }
+
+    #[test]
+    fn accesses_are_counted_by_address() {
+        let map = heat_map();
+        assert_eq!((map.executes(0x0600), map.executes(0x0603)), (3, 3));
+        assert_eq!((map.reads(0x0010), map.writes(0x0010)), (3, 3));
+        assert_eq!((map.reads(0x01FF), map.writes(0x01FF)), (3, 3));
+        assert_eq!(map.executes(0x0700), 0);
+    }
+
+    #[test]
+    fn exports_cover_the_address_space() {
+        let map = heat_map();
+        let ppm = map.to_ppm();
+        assert!(ppm.starts_with(b"P6\n256 256\n255\n"));
+        assert_eq!(ppm.len(), 15 + MAX_MEM * 3);
+        // $0010 was read and written as often as anything, but never run
+        let pixel = 15 + 0x10 * 3;
+        assert_eq!(ppm[pixel..pixel + 3], [255, 255, 0]);
+
+        let png = map.to_png();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+
+        let json = map.to_json();
+        assert!(json.starts_with("[{\"address\":16,\"reads\":3,\"writes\":3,\"executes\":0},"));
+        // the program up to the halting NOP, $0010 and the top of the stack
+        assert_eq!(json.matches("address").count(), 8);
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
@@ -0,0 +1,113 @@
+use core::fmt;
+
+use crate::op_codes;
+
+/// number of instructions a cpu remembers unless configured otherwise
+pub const DEFAULT_HISTORY_LEN: usize = 32;
+
+/// ring buffer of the most recently executed pcs and their opcodes
+#[derive(Debug, Clone)]
+pub struct PcHistory {
+    entries: Vec<(u16, u8)>,
+    /// index the next entry is written to once the buffer is full
+    next: usize,
+    capacity: usize,
+}
+
+impl Default for PcHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LEN)
+    }
+}
+
+impl PcHistory {
+    /// remember up to `capacity` instructions, zero disables the history
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            next: 0,
+            capacity,
+        }
+    }
+
+    /// record an instruction about to execute
+    pub fn push(&mut self, pc: u16, opcode: u8) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() < self.capacity {
+            self.entries.push((pc, opcode));
+        } else {
+            self.entries[self.next] = (pc, opcode);
+        }
+        // a compare rather than a division, this runs for every instruction
+        self.next += 1;
+        if self.next == self.capacity {
+            self.next = 0;
+        }
+    }
+
+    /// recorded pcs and opcodes, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        let (newer, older) = self.entries.split_at(self.next.min(self.entries.len()));
+        older.iter().chain(newer).copied()
+    }
+
+    /// the most recently recorded instruction
+    pub fn last(&self) -> Option<(u16, u8)> {
+        self.iter().last()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+}
+
+impl fmt::Display for PcHistory {
+    /// one instruction per line, oldest first
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (pc, opcode) in self.iter() {
+            let mnemonic = op_codes::instruction(opcode).map_or("???", |info| info.mnemonic);
+            writeln!(f, "${pc:04X}  {opcode:02X}  {mnemonic}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn history_keeps_the_most_recent_entries_in_order() {
+        let mut history = PcHistory::new(3);
+        for pc in 0..5 {
+            history.push(pc, TAX);
+        }
+
+        let pcs: Vec<u16> = history.iter().map(|(pc, _)| pc).collect();
+        assert_eq!(pcs, [2, 3, 4]);
+        assert_eq!(history.last(), Some((4, TAX)));
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn history_display() {
+        let mut history = PcHistory::new(4);
+        history.push(0x0600, LDA_IM);
+        history.push(0x0602, 0xFF);
+
+        assert_eq!(history.to_string(), "$0600  A9  LDA\n$0602  FF  ???\n");
+        assert!(PcHistory::new(0).is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+//! an interrupt controller combining the IRQ lines of several sources
+//!
+//! | offset | read                         | write                          |
+//! |--------|------------------------------|--------------------------------|
+//! | 0      | pending sources, a bit each  | acknowledge the sources set    |
+//! | 1      | enabled sources              | enable sources                 |
+//!
+//! a source stays pending from when it's asserted until it's acknowledged, by
+//! the program or the host, and the cpu's IRQ line is held while any enabled
+//! source is pending, every source starts enabled
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::device::Device;
+
+/// addresses the controller's registers take up
+pub const LEN: usize = 2;
+
+/// sources one controller can combine, one per bit of its registers
+pub const MAX_SOURCES: usize = 8;
+
+/// a controller shared between the memory it's mapped into and the devices
+/// asserting its lines
+pub type SharedInterruptController = Rc<RefCell<InterruptController>>;
+
+/// pending and enabled IRQ sources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptController {
+    pending: u8,
+    enabled: u8,
+    /// names of the sources connected so far, by bit
+    sources: Vec<String>,
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self {
+            pending: 0,
+            enabled: 0xFF,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// connect a source, returning the line it asserts
+    /// none once every source is taken
+    pub fn connect(controller: &SharedInterruptController, name: &str) -> Option<IrqLine> {
+        let mut inner = controller.borrow_mut();
+        if inner.sources.len() == MAX_SOURCES {
+            return None;
+        }
+        inner.sources.push(name.to_string());
+        Some(IrqLine {
+            controller: controller.clone(),
+            source: inner.sources.len() as u8 - 1,
+        })
+    }
+
+    /// names of the connected sources, the first is source 0
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    /// mark a source pending
+    pub fn assert(&mut self, source: u8) {
+        self.pending |= 1 << source;
+    }
+
+    /// clear a pending source
+    pub fn acknowledge(&mut self, source: u8) {
+        self.pending &= !(1 << source);
+    }
+
+    /// pending sources, a bit each, whether enabled or not
+    pub fn pending(&self) -> u8 {
+        self.pending
+    }
+
+    pub fn enabled(&self) -> u8 {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: u8) {
+        self.enabled = enabled;
+    }
+}
+
+impl Device for InterruptController {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => self.pending,
+            _ => self.enabled,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset {
+            0 => self.pending &= !value,
+            _ => self.enabled = value,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.pending & self.enabled != 0
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("IRQ")
+    }
+
+    fn register_name(&self, offset: u16) -> Option<&'static str> {
+        Some(if offset == 0 { "PENDING" } else { "ENABLE" })
+    }
+}
+
+/// one source's line into a controller, held by the device driving it
+#[derive(Debug, Clone)]
+pub struct IrqLine {
+    controller: SharedInterruptController,
+    source: u8,
+}
+
+impl IrqLine {
+    /// the bit the source has in the controller's registers
+    pub fn source(&self) -> u8 {
+        self.source
+    }
+
+    pub fn assert(&self) {
+        self.controller.borrow_mut().assert(self.source);
+    }
+
+    pub fn acknowledge(&self) {
+        self.controller.borrow_mut().acknowledge(self.source);
+    }
+
+    /// whether the source is waiting to be acknowledged
+    pub fn pending(&self) -> bool {
+        self.controller.borrow().pending & (1 << self.source) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    #[test]
+    fn sources_are_latched_until_acknowledged() {
+        let controller = Rc::new(RefCell::new(InterruptController::new()));
+        let timer = InterruptController::connect(&controller, "timer").unwrap();
+        let serial = InterruptController::connect(&controller, "serial").unwrap();
+        assert_eq!(serial.source(), 1);
+
+        serial.assert();
+        timer.assert();
+        let mut registers = controller.borrow_mut();
+        assert_eq!(registers.read(0), 0b11);
+        registers.write(1, 0b01);
+        registers.write(0, 0b01);
+        assert_eq!(registers.pending(), 0b10);
+        // the serial source is pending but disabled
+        assert!(!registers.irq());
+        drop(registers);
+
+        serial.acknowledge();
+        assert!(!serial.pending());
+
+        for name in 2..MAX_SOURCES {
+            InterruptController::connect(&controller, &name.to_string()).unwrap();
+        }
+        assert!(InterruptController::connect(&controller, "one too many").is_none());
+    }
+
+    #[test]
+    fn pending_sources_interrupt_the_cpu() {
+        let controller = Rc::new(RefCell::new(InterruptController::new()));
+        let line = InterruptController::connect(&controller, "timer").unwrap();
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![TAX, TAY, NOP])
+            .memory(0x0700, vec![LDA_ABS, 0x00, 0xD0, NOP])
+            .memory(0xFFFE, vec![0x00, 0x07])
+            .device(0xD000, LEN, controller.clone())
+            .build()
+            .unwrap();
+
+        cpu.step().unwrap();
+        line.assert();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc(), 0x0704);
+        assert_eq!(cpu.a(), 0b01);
+    }
+}
@@ -0,0 +1,246 @@
+//! interrupt timing, how long requests wait before their handler starts and
+//! how long handlers run until their RTI, to check interrupt driven code
+//! meets the deadlines real hardware gives it
+//!
+//! latency is counted from the cycle a line is pulled to the cycle the
+//! handler's first instruction starts, so it includes the 7 cycles of the
+//! interrupt sequence and any time spent with I set, requests that arrive
+//! while one of the same kind is waiting are folded into it
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    cpu::NMI_VECTOR,
+    events::{Event, Observer},
+    op_codes::RTI,
+};
+
+/// cycle counts seen, with their extremes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Timings {
+    /// how many times each count was seen
+    counts: BTreeMap<u64, u64>,
+    total: u64,
+}
+
+impl Timings {
+    fn record(&mut self, cycles: u64) {
+        *self.counts.entry(cycles).or_default() += 1;
+        self.total += cycles;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.counts.keys().next().copied()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.counts.keys().next_back().copied()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (!self.is_empty()).then(|| self.total as f64 / self.len() as f64)
+    }
+
+    /// how many times each count was seen, shortest first
+    pub fn histogram(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.counts.iter().map(|(cycles, times)| (*cycles, *times))
+    }
+}
+
+/// what a handler on the stack was entered for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Handler {
+    nmi: bool,
+    brk: bool,
+    entered: u64,
+}
+
+/// interrupt latencies and handler durations, by line
+#[derive(Debug, Default, Clone)]
+pub struct InterruptLatency {
+    pub irq_latency: Timings,
+    pub nmi_latency: Timings,
+    pub irq_handler: Timings,
+    pub nmi_handler: Timings,
+    /// requests not yet taken, the cycle they arrived on
+    irq_request: Option<u64>,
+    nmi_request: Option<u64>,
+    /// handlers running, innermost last
+    handlers: Vec<Handler>,
+}
+
+impl Observer for InterruptLatency {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::InterruptRequested { nmi, cycle } => {
+                let request = match nmi {
+                    true => &mut self.nmi_request,
+                    false => &mut self.irq_request,
+                };
+                request.get_or_insert(cycle);
+            }
+            Event::InterruptTaken { vector, cycle, brk } => {
+                let nmi = vector == NMI_VECTOR;
+                // a BRK still gets an RTI to pair up, but wasn't asked for
+                if !brk {
+                    let (request, latency) = match nmi {
+                        true => (self.nmi_request.take(), &mut self.nmi_latency),
+                        false => (self.irq_request.take(), &mut self.irq_latency),
+                    };
+                    if let Some(request) = request {
+                        latency.record(cycle.saturating_sub(request));
+                    }
+                }
+                self.handlers.push(Handler {
+                    nmi,
+                    brk,
+                    entered: cycle,
+                });
+            }
+            Event::InstructionRetired {
+                opcode: RTI,
+                cycles,
+                ..
+            } => match self.handlers.pop() {
+                Some(handler) if handler.brk => {}
+                Some(handler) if handler.nmi => self.nmi_handler.record(cycles - handler.entered),
+                Some(handler) => self.irq_handler.record(cycles - handler.entered),
+                None => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for InterruptLatency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "interrupt timing        count     min     max      mean")?;
+        let rows = [
+            ("IRQ latency", &self.irq_latency),
+            ("IRQ handler", &self.irq_handler),
+            ("NMI latency", &self.nmi_latency),
+            ("NMI handler", &self.nmi_handler),
+        ];
+        for (name, timings) in rows.iter().filter(|(_, timings)| !timings.is_empty()) {
+            write!(
+                f,
+                "\n{name:<20} {:>8} {:>7} {:>7} {:>9.1}",
+                timings.len(),
+                timings.min().unwrap_or(0),
+                timings.max().unwrap_or(0),
+                timings.mean().unwrap_or(0.0)
+            )?;
+        }
+        for (name, timings) in rows
+            .iter()
+            .filter(|(name, timings)| name.ends_with("latency") && !timings.is_empty())
+        {
+            write!(f, "\n\n{name}, cycles: count")?;
+            for (cycles, times) in timings.histogram() {
+                write!(f, "\n{cycles:>6}: {times}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        cpu::Cpu,
+        op_codes::*,
+        ticker::{Line, Ticker, DEFAULT_BASE, LEN},
+    };
+
+    /// a ticker interrupting a busy loop, the handler acknowledging it and
+    /// returning
+    fn run(line: Line, vector: u16) -> InterruptLatency {
+        let latency = Rc::new(RefCell::new(InterruptLatency::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![TAX, TAX, JMP_ABS, 0x00, 0x06])
+            .memory(0x0700, vec![LSR_ABS, 0x00, 0xD1, RTI])
+            .memory(vector as usize, vec![0x00, 0x07])
+            .device(
+                DEFAULT_BASE as usize,
+                LEN,
+                Rc::new(RefCell::new(Ticker::new(1000, 10).line(line))),
+            )
+            .observer(latency.clone())
+            .build()
+            .unwrap();
+        while cpu.cycles() < 1000 {
+            cpu.step().unwrap();
+        }
+        let latency = latency.borrow().clone();
+        latency
+    }
+
+    #[test]
+    fn irq_latency_and_handler_time_are_measured() {
+        let latency = run(Line::Irq, 0xFFFE);
+        // ticks every 100 cycles, seen at the end of the instruction they
+        // land in, then the 7 cycle sequence
+        assert_eq!(latency.irq_latency.len(), 9);
+        assert_eq!(latency.irq_latency.min(), Some(7));
+        assert_eq!(latency.irq_latency.max(), Some(7));
+        // LSR abs and RTI
+        assert_eq!(latency.irq_handler.max(), Some(12));
+        assert!(latency.nmi_latency.is_empty());
+        assert!(latency
+            .to_string()
+            .contains("\nIRQ latency                 9       7       7       7.0"));
+    }
+
+    #[test]
+    fn nmis_are_timed_separately() {
+        let latency = run(Line::Nmi, 0xFFFA);
+        assert_eq!(latency.nmi_latency.len(), 9);
+        assert_eq!(latency.nmi_latency.max(), Some(7));
+        assert_eq!(latency.nmi_handler.len(), 9);
+        assert!(latency.irq_latency.is_empty());
+    }
+
+    #[test]
+    fn masked_requests_wait_for_cli() {
+        let latency = Rc::new(RefCell::new(InterruptLatency::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![SEI, TAX, TAX, CLI, TAX, NOP])
+            .memory(0x0700, vec![RTI])
+            .memory(0xFFFE, vec![0x00, 0x07])
+            .observer(latency.clone())
+            .build()
+            .unwrap();
+        cpu.step().unwrap();
+        cpu.set_irq(true);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        // taken before the TAX, the handler's RTI runs in the same step
+        cpu.step().unwrap();
+        cpu.set_irq(false);
+        cpu.step().unwrap();
+
+        // asserted after SEI, held through two TAXs and CLI
+        let latency = latency.borrow();
+        assert_eq!(
+            latency.irq_latency.histogram().collect::<Vec<_>>(),
+            [(13, 1)]
+        );
+        assert_eq!(latency.irq_handler.min(), Some(6));
+    }
+}
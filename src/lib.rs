@@ -0,0 +1,101 @@
+//! A MOS 6502 emulator
+//!
+//! The [`Cpu`] owns its [`Memory`] and executes programs loaded into it
+//! until it halts. Opcode constants and instruction metadata live in
+//! [`op_codes`], and [`assembler`], [`monitor`] and [`runner`] provide
+//! the tooling used by the command line interface.
+//!
+//! ```
+//! use cpu_emu::{op_codes::*, Cpu};
+//!
+//! let mut cpu = Cpu::new().reset(Some(0x0600));
+//! cpu.load_program(0x0600, vec![LDA_IM, 0x42, NOP])?;
+//! cpu.execute()?;
+//! assert_eq!(cpu.a(), 0x42);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod acia;
+pub mod apple2;
+pub mod assembler;
+pub mod atari2600;
+pub mod audio;
+#[cfg(feature = "parallel")]
+pub mod batch;
+pub mod binary_monitor;
+pub mod block_cache;
+pub mod call_stack;
+pub mod cdl;
+pub mod char_device;
+pub mod chunk;
+pub mod core_dump;
+pub mod cpu;
+pub mod crash;
+pub mod device;
+pub mod diff;
+pub mod disassembler;
+pub mod events;
+pub mod explain;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod heat_map;
+pub mod history;
+pub mod interrupt;
+pub mod latency;
+pub mod loader;
+pub mod machine;
+pub mod memory;
+pub mod monitor;
+pub mod nvram;
+pub mod op_codes;
+pub mod patch;
+#[cfg(feature = "perfect6502")]
+pub mod perfect6502;
+pub mod permissions;
+pub mod processor_status;
+pub mod profiler;
+pub mod program;
+#[cfg(unix)]
+pub mod pty;
+pub mod quiz;
+pub mod random;
+pub mod register_break;
+pub mod registers;
+pub mod rom_id;
+pub mod runner;
+pub mod save_state;
+pub mod scheduler;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "self-profile")]
+pub mod self_profile;
+pub mod semihost;
+#[cfg(feature = "http")]
+pub mod server;
+pub mod session;
+pub mod sid;
+pub mod state_dump;
+pub mod stats;
+#[cfg(feature = "websocket")]
+pub mod stream;
+pub mod tape;
+pub mod testing;
+pub mod text_video;
+pub mod ticker;
+pub mod trace;
+pub mod trap;
+pub mod vcd;
+pub mod vic20;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use assembler::AssemblerError;
+pub use cpu::{Cpu, CpuBuilder, CpuError, CpuSnapshot, Quirks, UnknownOpcodePolicy, Variant};
+pub use crash::CrashReport;
+pub use device::{Device, SharedDevice};
+pub use loader::LoaderError;
+pub use memory::{BusError, Memory, MemorySnapshot, RomImage};
+pub use processor_status::ProcessorStatus;
+pub use registers::Registers;
+pub use state_dump::StateDump;
+pub use trap::SharedTrap;
@@ -0,0 +1,93 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{cpu::Cpu, memory::BusError};
+
+/// errors loading a program from disk
+#[derive(Debug, Error)]
+pub enum LoaderError {
+    /// the file couldn't be read
+    #[error("failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// the program doesn't fit at the requested address
+    #[error("failed to load {}: {source}", path.display())]
+    Bus {
+        path: PathBuf,
+        #[source]
+        source: BusError,
+    },
+}
+
+/// read a program binary from disk
+pub fn read_program(path: &Path) -> Result<Vec<u8>, LoaderError> {
+    fs::read(path).map_err(|source| LoaderError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// load a program binary from disk into the cpu's memory at an address
+/// returns the number of bytes loaded
+pub fn load_file(cpu: &mut Cpu, path: &Path, address: usize) -> Result<usize, LoaderError> {
+    let program = read_program(path)?;
+    let len = program.len();
+
+    cpu.load_program(address, program)
+        .map_err(|source| LoaderError::Bus {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn load_file_copies_program_into_memory() {
+        let path = std::env::temp_dir().join("cpu_emu_loader_program.bin");
+        fs::write(&path, [LDA_IM, 0x42, NOP]).unwrap();
+
+        let mut cpu = Cpu::new().reset(None);
+        assert_eq!(load_file(&mut cpu, &path, 0x0600).unwrap(), 3);
+        assert_eq!(cpu.memory.data[0x0600..0x0603], [LDA_IM, 0x42, NOP]);
+    }
+
+    #[test]
+    fn load_file_reports_missing_files() {
+        let path = Path::new("/nonexistent/program.bin");
+        let mut cpu = Cpu::new().reset(None);
+
+        let err = load_file(&mut cpu, path, 0x0600).unwrap_err();
+        assert!(matches!(err, LoaderError::Io { .. }));
+        assert!(err
+            .to_string()
+            .starts_with("failed to read /nonexistent/program.bin"));
+    }
+
+    #[test]
+    fn load_file_reports_programs_that_do_not_fit() {
+        let path = std::env::temp_dir().join("cpu_emu_loader_too_large.bin");
+        fs::write(&path, [NOP, NOP]).unwrap();
+
+        let mut cpu = Cpu::new().reset(None);
+        let err = load_file(&mut cpu, &path, 0xFFFF).unwrap_err();
+        assert!(matches!(
+            err,
+            LoaderError::Bus {
+                source: BusError::OutOfRange { .. },
+                ..
+            }
+        ));
+    }
+}
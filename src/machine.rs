@@ -0,0 +1,809 @@
+//! several cpus sharing memory, like arcade boards with a sound cpu or
+//! coprocessor setups
+//!
+//! each cpu keeps its own memory, what they share is mapped into every one of
+//! them as a [`SharedRam`] device
+//!
+//! the cpus are interleaved an instruction at a time, not a cycle at a time,
+//! the machine always runs a whole instruction on whichever cpu is furthest
+//! behind, ties going to the cpu added first, so their clocks stay within an
+//! instruction of each other, there's no arbitration of the shared memory
+//! within a cycle, an access lands whenever its cpu's instruction runs
+//!
+//! ```
+//! use std::{cell::RefCell, rc::Rc};
+//! use cpu_emu::{machine::{Machine, SharedRam}, op_codes::*, Cpu};
+//!
+//! let ram = Rc::new(RefCell::new(SharedRam::new(0x100)));
+//! let main = Cpu::builder()
+//!     .pc(0x0600)
+//!     .memory(0x0600, vec![LDA_IM, 0x42, PHA, NOP])
+//!     .sp(0x01FF)
+//!     .device(0x0100, 0x100, ram.clone())
+//!     .build()?;
+//! let sound = Cpu::builder()
+//!     .pc(0x0600)
+//!     .memory(0x0600, vec![TAX, TAX, TAX, LDA_ABS, 0xFF, 0x01, NOP])
+//!     .device(0x0100, 0x100, ram)
+//!     .build()?;
+//!
+//! let mut machine = Machine::builder().cpu(main).cpu(sound).build();
+//! machine.run(1000)?;
+//! assert_eq!(machine.cpu(1).a(), 0x42);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! front-ends drive a machine a frame at a time with [`Machine::run_frame`],
+//! which runs a frame's worth of cycles, can end it with a vblank NMI and
+//! hands the main cpu's framebuffer, the memory at [`Frame::framebuffer`],
+//! to a callback to draw
+//!
+//! a [`Board`] is the single cpu `cpu_emu run` builds from its options, the
+//! devices it maps and the settings it starts with
+
+use std::{
+    cell::RefCell,
+    io,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use thiserror::Error;
+
+use crate::{
+    acia::{self, Acia, TcpPort},
+    apple2::Apple2,
+    char_device::{self, CharDevice},
+    cpu::{Cpu, CpuBuilder, CpuError, UnknownOpcodePolicy},
+    device::{Device, SharedDevice},
+    loader::{self, LoaderError},
+    memory::{BusConflicts, BusError},
+    nvram::{self, Nvram},
+    permissions::{Permissions, ViolationPolicy},
+    rom_id::KnownRom,
+    semihost::Semihost,
+    tape::{self, Tape, TapeError},
+    ticker::{self, Ticker},
+    vic20::{Vic20, Vic20Error},
+};
+
+/// a cpu in the machine failed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("cpu {cpu}: {error}")]
+pub struct MachineError {
+    /// index of the cpu that failed
+    pub cpu: usize,
+    #[source]
+    pub error: CpuError,
+}
+
+/// ram mapped into several cpus' memories
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedRam {
+    bytes: Vec<u8>,
+}
+
+impl SharedRam {
+    pub fn new(len: usize) -> Self {
+        Self {
+            bytes: vec![0; len],
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Device for SharedRam {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.bytes[offset as usize]
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.bytes[offset as usize] = value;
+    }
+}
+
+/// how long a frame is and what happens at its end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    cycles: u64,
+    vblank_nmi: bool,
+    framebuffer: Range<u16>,
+}
+
+impl Frame {
+    /// frames of a number of cycles, with no vblank and the easy6502 screen
+    /// at $0200 as the framebuffer
+    pub fn new(cycles: u64) -> Self {
+        Self {
+            cycles: cycles.max(1),
+            vblank_nmi: false,
+            framebuffer: 0x0200..0x0600,
+        }
+    }
+
+    /// frames `rate` times a second at a clock speed in Hz, e.g. 60 at 1MHz
+    pub fn per_second(clock: u64, rate: u64) -> Self {
+        Self::new(clock / rate.max(1))
+    }
+
+    /// signal an NMI on the main cpu as each frame ends, like a video chip's
+    /// vertical blank
+    pub fn vblank_nmi(mut self, vblank_nmi: bool) -> Self {
+        self.vblank_nmi = vblank_nmi;
+        self
+    }
+
+    /// main cpu memory handed to the frame callback
+    pub fn framebuffer(mut self, framebuffer: Range<u16>) -> Self {
+        self.framebuffer = framebuffer;
+        self
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+}
+
+impl Default for Frame {
+    /// a 60Hz frame at 1MHz
+    fn default() -> Self {
+        Self::per_second(1_000_000, 60)
+    }
+}
+
+/// configures a [`Machine`] before it runs
+#[derive(Debug, Clone, Default)]
+pub struct MachineBuilder {
+    cpus: Vec<Cpu>,
+    frame: Frame,
+}
+
+impl MachineBuilder {
+    /// add a cpu, the first added is the main cpu frames are drawn from
+    pub fn cpu(mut self, cpu: Cpu) -> Self {
+        self.cpus.push(cpu);
+        self
+    }
+
+    /// run frames of a different length or with a vblank
+    pub fn frame(mut self, frame: Frame) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    pub fn build(self) -> Machine {
+        Machine::new(self.cpus).frame(self.frame)
+    }
+}
+
+/// cpus interleaved an instruction at a time, furthest behind first
+#[derive(Debug, Clone)]
+pub struct Machine {
+    cpus: Vec<Cpu>,
+    halted: Vec<bool>,
+    frame: Frame,
+    /// the cycle the current frame ends at
+    frame_end: u64,
+}
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+
+    /// a machine of cpus, the first is the main cpu that frames are drawn from
+    pub fn new(cpus: Vec<Cpu>) -> Self {
+        let frame = Frame::default();
+        Self {
+            halted: vec![false; cpus.len()],
+            frame_end: frame.cycles,
+            frame,
+            cpus,
+        }
+    }
+
+    /// run frames of a different length or with a vblank, starting from the
+    /// cycle the main cpu is at
+    pub fn frame(mut self, frame: Frame) -> Self {
+        self.frame_end = self.cpus.first().map_or(0, Cpu::cycles) + frame.cycles;
+        self.frame = frame;
+        self
+    }
+
+    pub fn cpus(&self) -> &[Cpu] {
+        &self.cpus
+    }
+
+    /// the cpu at an index, in the order they were given
+    pub fn cpu(&self, index: usize) -> &Cpu {
+        &self.cpus[index]
+    }
+
+    pub fn cpu_mut(&mut self, index: usize) -> &mut Cpu {
+        &mut self.cpus[index]
+    }
+
+    /// whether the cpu at an index has halted
+    pub fn halted(&self, index: usize) -> bool {
+        self.halted[index]
+    }
+
+    /// step the running cpu furthest behind, returning its index
+    /// none once every cpu has halted
+    pub fn step(&mut self) -> Result<Option<usize>, MachineError> {
+        let behind = (0..self.cpus.len())
+            .filter(|index| !self.halted[*index])
+            .min_by_key(|index| self.cpus[*index].cycles());
+        let Some(index) = behind else {
+            return Ok(None);
+        };
+
+        let running = self.cpus[index]
+            .step()
+            .map_err(|error| MachineError { cpu: index, error })?;
+        self.halted[index] = !running;
+        Ok(Some(index))
+    }
+
+    /// run every cpu up to the end of the frame, signal the vblank NMI when
+    /// the frame has one and call `draw` with the framebuffer
+    /// frames end on a fixed cycle grid, so an instruction running past the
+    /// end of one leaves the next that much shorter
+    /// returns whether any cpu is still running
+    pub fn run_frame(&mut self, draw: impl FnOnce(&[u8])) -> Result<bool, MachineError> {
+        loop {
+            let behind = (0..self.cpus.len())
+                .filter(|index| !self.halted[*index])
+                .map(|index| self.cpus[index].cycles())
+                .min();
+            match behind {
+                Some(cycles) if cycles < self.frame_end => self.step()?,
+                _ => break,
+            };
+        }
+        self.frame_end += self.frame.cycles;
+
+        let Some(main) = self.cpus.first_mut() else {
+            return Ok(false);
+        };
+        if self.frame.vblank_nmi {
+            main.nmi();
+        }
+        let framebuffer: Vec<u8> = self
+            .frame
+            .framebuffer
+            .clone()
+            .map(|address| main.memory.read_byte(address as usize))
+            .collect();
+        draw(&framebuffer);
+        Ok(self.halted.contains(&false))
+    }
+
+    /// step until every cpu halts or `max_steps` instructions have run
+    /// between them, returning the steps taken
+    pub fn run(&mut self, max_steps: u64) -> Result<u64, MachineError> {
+        let mut steps = 0;
+        while steps < max_steps && self.step()?.is_some() {
+            steps += 1;
+        }
+        Ok(steps)
+    }
+}
+
+/// where the ACIA's serial line goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Serial {
+    /// a tcp port listening on an address
+    Tcp(String),
+    /// a pseudo-terminal
+    #[cfg(unix)]
+    Pty,
+}
+
+/// the devices and settings a [`Board`] is made with
+#[derive(Debug, Clone)]
+pub struct BoardOptions {
+    pub serial: Option<Serial>,
+    pub acia_base: u16,
+    /// directory programs can open files under by calling $FFF0
+    pub semihost: Option<PathBuf>,
+    /// file the nvram is kept in
+    pub nvram: Option<PathBuf>,
+    pub nvram_base: u16,
+    pub nvram_len: usize,
+    /// cycles each write to the nvram takes to land
+    pub nvram_write_cycles: u64,
+    pub unknown_opcodes: UnknownOpcodePolicy,
+    /// wav file played through the tape device
+    pub tape: Option<PathBuf>,
+    /// wav file what's written to the tape device is saved to
+    pub tape_record: Option<PathBuf>,
+    pub tape_base: u16,
+    /// map stdin and stdout as a character device
+    pub console: bool,
+    pub console_base: u16,
+    /// run the Apple II monitor's routines on the host
+    pub apple2: bool,
+    /// load programs as .prg files into this VIC-20's memory map
+    pub vic20: Option<Vic20>,
+    /// interrupts a second from the ticker, none without one
+    pub ticker_rate: Option<u64>,
+    pub ticker_base: u16,
+    pub ticker_line: ticker::Line,
+    /// ranges and the accesses they allow, later ones win
+    pub protect: Vec<(RangeInclusive<u16>, Permissions)>,
+    pub on_violation: ViolationPolicy,
+    pub bus_conflicts: BusConflicts,
+    /// addresses of devices each access to is logged
+    pub logged_devices: Vec<u16>,
+    /// seed for random ram and the random device, picked at random without one
+    pub seed: Option<u64>,
+    pub random_ram: bool,
+    /// address of a device reading back random bytes
+    pub random_base: Option<u16>,
+    pub sp: Option<u16>,
+    pub irq_vector: Option<u16>,
+    pub nmi_vector: Option<u16>,
+    /// file all of ram is kept in
+    #[cfg(feature = "mmap")]
+    pub ram_file: Option<PathBuf>,
+    #[cfg(feature = "audio")]
+    pub beeper: bool,
+    #[cfg(feature = "audio")]
+    pub sid: bool,
+    #[cfg(feature = "audio")]
+    pub sid_base: u16,
+}
+
+impl Default for BoardOptions {
+    fn default() -> Self {
+        Self {
+            serial: None,
+            acia_base: acia::DEFAULT_BASE,
+            semihost: None,
+            nvram: None,
+            nvram_base: nvram::DEFAULT_BASE,
+            nvram_len: nvram::DEFAULT_LEN,
+            nvram_write_cycles: 0,
+            unknown_opcodes: UnknownOpcodePolicy::default(),
+            tape: None,
+            tape_record: None,
+            tape_base: tape::DEFAULT_BASE,
+            console: false,
+            console_base: char_device::DEFAULT_BASE,
+            apple2: false,
+            vic20: None,
+            ticker_rate: None,
+            ticker_base: ticker::DEFAULT_BASE,
+            ticker_line: ticker::Line::default(),
+            protect: Vec::new(),
+            on_violation: ViolationPolicy::default(),
+            bus_conflicts: BusConflicts::default(),
+            logged_devices: Vec::new(),
+            seed: None,
+            random_ram: false,
+            random_base: None,
+            sp: None,
+            irq_vector: None,
+            nmi_vector: None,
+            #[cfg(feature = "mmap")]
+            ram_file: None,
+            #[cfg(feature = "audio")]
+            beeper: false,
+            #[cfg(feature = "audio")]
+            sid: false,
+            #[cfg(feature = "audio")]
+            sid_base: crate::sid::DEFAULT_BASE,
+        }
+    }
+}
+
+/// errors making a board or loading a program onto it
+#[derive(Debug, Error)]
+pub enum BoardError {
+    #[error("failed to listen on {address}: {source}")]
+    Listen {
+        address: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to open a pseudo-terminal: {0}")]
+    Pty(#[source] io::Error),
+    #[error("failed to load nvram from {}: {source}", path.display())]
+    Nvram {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to load the tape {}: {source}", path.display())]
+    Tape {
+        path: PathBuf,
+        #[source]
+        source: TapeError,
+    },
+    #[cfg(feature = "audio")]
+    #[error("failed to open the speaker: {0}")]
+    Speaker(String),
+    #[cfg(feature = "mmap")]
+    #[error("failed to map {}: {source}", path.display())]
+    RamFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Loader(#[from] LoaderError),
+    #[error("failed to load {}: {source}", path.display())]
+    Bus {
+        path: PathBuf,
+        #[source]
+        source: BusError,
+    },
+    #[error("failed to load {}: {source}", path.display())]
+    Vic20 {
+        path: PathBuf,
+        #[source]
+        source: Vic20Error,
+    },
+}
+
+/// the devices a program is run with, made once so they outlive reloads of
+/// the program, a serial client stays connected and the nvram keeps its file
+pub struct Board {
+    options: BoardOptions,
+    /// where the serial line can be reached and the ACIA on it
+    serial: Option<(String, SharedDevice)>,
+    nvram: Option<Rc<RefCell<Nvram>>>,
+    tape: Option<Rc<RefCell<Tape>>>,
+}
+
+impl Board {
+    /// open the serial line, nvram and tape the options ask for
+    pub fn open(options: BoardOptions) -> Result<Self, BoardError> {
+        let serial = match &options.serial {
+            Some(Serial::Tcp(address)) => {
+                let port = TcpPort::bind(address).map_err(|source| BoardError::Listen {
+                    address: address.clone(),
+                    source,
+                })?;
+                let acia: SharedDevice = Rc::new(RefCell::new(Acia::new(port)));
+                Some((address.clone(), acia))
+            }
+            #[cfg(unix)]
+            Some(Serial::Pty) => {
+                let port = crate::pty::PtyPort::open().map_err(BoardError::Pty)?;
+                let path = port.path().display().to_string();
+                let acia: SharedDevice = Rc::new(RefCell::new(Acia::new(port)));
+                Some((path, acia))
+            }
+            None => None,
+        };
+        let nvram = match &options.nvram {
+            Some(path) => {
+                let nvram =
+                    Nvram::open(path, options.nvram_len).map_err(|source| BoardError::Nvram {
+                        path: path.clone(),
+                        source,
+                    })?;
+                Some(Rc::new(RefCell::new(
+                    nvram.write_cycles(options.nvram_write_cycles),
+                )))
+            }
+            None => None,
+        };
+        let tape = match (&options.tape, &options.tape_record) {
+            (None, None) => None,
+            (played, recorded) => {
+                let mut tape = match played {
+                    Some(path) => Tape::open(path).map_err(|source| BoardError::Tape {
+                        path: path.clone(),
+                        source,
+                    })?,
+                    None => Tape::default(),
+                };
+                if let Some(path) = recorded {
+                    tape = tape.record_to(path);
+                }
+                Some(Rc::new(RefCell::new(tape)))
+            }
+        };
+        Ok(Self {
+            options,
+            serial,
+            nvram,
+            tape,
+        })
+    }
+
+    pub fn options(&self) -> &BoardOptions {
+        &self.options
+    }
+
+    /// where the ACIA's serial line can be reached, an address or a
+    /// pseudo-terminal's path
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// map the board's devices into a cpu being built and give it the
+    /// board's settings
+    pub fn attach(&self, mut builder: CpuBuilder) -> Result<CpuBuilder, BoardError> {
+        let options = &self.options;
+        builder = builder.unknown_opcodes(options.unknown_opcodes);
+        for (range, allowed) in &options.protect {
+            let (start, end) = (*range.start() as usize, *range.end() as usize);
+            builder = builder.permissions(start, end - start + 1, *allowed);
+        }
+        builder = builder
+            .on_violation(options.on_violation)
+            .bus_conflicts(options.bus_conflicts);
+        for address in &options.logged_devices {
+            builder = builder.log_device(*address as usize);
+        }
+        if let Some(seed) = options.seed {
+            builder = builder.seed(seed);
+        }
+        if let Some(address) = options.random_base {
+            builder = builder.random_device(address as usize);
+        }
+        builder = builder.random_ram(options.random_ram);
+        if let Some(sp) = options.sp {
+            builder = builder.sp(sp);
+        }
+        if let Some(address) = options.irq_vector {
+            builder = builder.irq_vector(address);
+        }
+        if let Some(address) = options.nmi_vector {
+            builder = builder.nmi_vector(address);
+        }
+        if let Some((_, acia)) = &self.serial {
+            builder = builder.device(options.acia_base as usize, acia::LEN, acia.clone());
+        }
+        if let Some(nvram) = &self.nvram {
+            builder = builder.device(
+                options.nvram_base as usize,
+                options.nvram_len,
+                nvram.clone(),
+            );
+        }
+        if let Some(tape) = &self.tape {
+            builder = builder.device(options.tape_base as usize, tape::LEN, tape.clone());
+        }
+        if options.console {
+            builder = builder.device(
+                options.console_base as usize,
+                char_device::LEN,
+                Rc::new(RefCell::new(CharDevice::stdio())),
+            );
+        }
+        if options.apple2 {
+            builder = Apple2::stdio().attach(builder);
+        }
+        if let Some(rate) = options.ticker_rate {
+            let ticker = Ticker::new(ticker::DEFAULT_CLOCK, rate).line(options.ticker_line);
+            builder = builder.device(
+                options.ticker_base as usize,
+                ticker::LEN,
+                Rc::new(RefCell::new(ticker)),
+            );
+        }
+        #[cfg(feature = "audio")]
+        if options.beeper || options.sid {
+            use crate::audio;
+
+            let speaker = audio::speaker::Speaker::open().map_err(BoardError::Speaker)?;
+            let audio = Rc::new(RefCell::new(audio::AudioBridge::new(
+                ticker::DEFAULT_CLOCK,
+                speaker,
+            )));
+            if options.beeper {
+                builder = builder.device(
+                    audio::BEEPER_BASE as usize,
+                    1,
+                    Rc::new(RefCell::new(audio::Beeper::new(audio.clone()))),
+                );
+            }
+            if options.sid {
+                builder = builder.device(
+                    options.sid_base as usize,
+                    crate::sid::LEN,
+                    Rc::new(RefCell::new(crate::sid::Sid::new(audio))),
+                );
+            }
+        }
+        if let Some(root) = &options.semihost {
+            builder = Semihost::new(root).attach(builder);
+        }
+        Ok(builder)
+    }
+
+    /// build a cpu with the program at `path` loaded, as a .prg on a VIC-20,
+    /// where a recognised image belongs and from its entry point, or at
+    /// `origin` and from there
+    pub fn load(
+        &self,
+        path: &Path,
+        known: Option<&KnownRom>,
+        origin: u16,
+        builder: CpuBuilder,
+    ) -> Result<Cpu, BoardError> {
+        let program = loader::read_program(path)?;
+        let bus = |source| BoardError::Bus {
+            path: path.to_path_buf(),
+            source,
+        };
+        let cpu = match (&self.options.vic20, known) {
+            (Some(vic20), _) => vic20
+                .load(builder, &program)
+                .map_err(|source| BoardError::Vic20 {
+                    path: path.to_path_buf(),
+                    source,
+                })?
+                .build()
+                .map_err(bus)?,
+            (None, Some(known)) => {
+                let mut cpu = builder
+                    .memory(known.origin as usize, known.image(&program).to_vec())
+                    .build()
+                    .map_err(bus)?;
+                cpu.set_pc(known.entry(&program));
+                cpu
+            }
+            (None, None) => builder
+                .pc(origin)
+                .memory(origin as usize, program)
+                .build()
+                .map_err(bus)?,
+        };
+        #[cfg(feature = "mmap")]
+        let cpu = self.map_ram_file(cpu, path, known, origin)?;
+        Ok(cpu)
+    }
+
+    /// keep the cpu's ram in the board's ram file, loading a plain program
+    /// back over whatever it held, profiles place their own programs
+    #[cfg(feature = "mmap")]
+    fn map_ram_file(
+        &self,
+        mut cpu: Cpu,
+        path: &Path,
+        known: Option<&KnownRom>,
+        origin: u16,
+    ) -> Result<Cpu, BoardError> {
+        let Some(file) = &self.options.ram_file else {
+            return Ok(cpu);
+        };
+        cpu.memory
+            .map_ram_file(file)
+            .map_err(|source| BoardError::RamFile {
+                path: file.clone(),
+                source,
+            })?;
+        if self.options.vic20.is_none() && known.is_none() {
+            loader::load_file(&mut cpu, path, origin as usize)?;
+        }
+        Ok(cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn cpus_interleave_an_instruction_at_a_time() {
+        let program = vec![TAX, TAX, TAX, NOP];
+        let fast = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, program.clone())
+            .build()
+            .unwrap();
+        let slow = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_ABS, 0x00, 0x02, NOP])
+            .build()
+            .unwrap();
+        let mut machine = Machine::new(vec![fast, slow]);
+
+        let mut order = Vec::new();
+        while let Some(index) = machine.step().unwrap() {
+            order.push(index);
+        }
+        // TAX takes 2 cycles and LDA absolute 4
+        assert_eq!(order, [0, 1, 0, 0, 1, 0]);
+        assert!(machine.halted(0) && machine.halted(1));
+    }
+
+    #[test]
+    fn frames_run_a_fixed_number_of_cycles() {
+        // count frames in $10, one a vblank NMI
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![JMP_ABS, 0x00, 0x06])
+            .memory(0x0700, vec![LSR_ZP, 0x10, RTI])
+            .memory(0x0010, vec![0x80])
+            .memory(0xFFFA, vec![0x00, 0x07])
+            .build()
+            .unwrap();
+        let mut machine = Machine::builder()
+            .cpu(cpu)
+            .frame(
+                Frame::per_second(6000, 60)
+                    .vblank_nmi(true)
+                    .framebuffer(0x0010..0x0011),
+            )
+            .build();
+
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            let running = machine.run_frame(|framebuffer| frames.push(framebuffer[0]));
+            assert_eq!(running, Ok(true));
+        }
+        // the NMI is taken at the start of the next frame
+        assert_eq!(frames, [0x80, 0x40, 0x20]);
+        // JMP takes 3 cycles, so each frame overruns 100 by up to 2
+        assert!((300..303).contains(&machine.cpu(0).cycles()));
+    }
+
+    #[test]
+    fn frames_stop_early_once_every_cpu_halts() {
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![TAX, NOP])
+            .build()
+            .unwrap();
+        let mut machine = Machine::new(vec![cpu]);
+        assert_eq!(
+            machine.run_frame(|framebuffer| assert_eq!(framebuffer.len(), 0x400)),
+            Ok(false)
+        );
+        assert_eq!(Frame::default().cycles(), 16_666);
+    }
+
+    #[test]
+    fn failures_name_the_cpu() {
+        let ok = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![TAX, NOP])
+            .build()
+            .unwrap();
+        let broken = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![0x02])
+            .build()
+            .unwrap();
+        let mut machine = Machine::new(vec![ok, broken]);
+
+        let err = machine.run(10).unwrap_err();
+        assert_eq!(err.cpu, 1);
+        assert!(err.to_string().starts_with("cpu 1: "));
+    }
+
+    #[test]
+    fn shared_ram_is_seen_by_both_cpus() {
+        let ram = Rc::new(RefCell::new(SharedRam::new(2)));
+        let cpu = |program: Vec<u8>| {
+            Cpu::builder()
+                .pc(0x0600)
+                .memory(0x0600, program)
+                .device(0x0010, 2, ram.clone())
+                .build()
+                .unwrap()
+        };
+        // the first shifts the shared byte, the second reads it afterwards
+        let writer = cpu(vec![LSR_ZP, 0x10, NOP]);
+        let reader = cpu(vec![TAX, TAX, TAX, LDA_ZP, 0x10, NOP]);
+        ram.borrow_mut().write(0, 0x84);
+
+        let mut machine = Machine::new(vec![writer, reader]);
+        machine.run(100).unwrap();
+        assert_eq!(machine.cpu(1).a(), 0x42);
+        assert_eq!(ram.borrow().bytes(), [0x42, 0x00]);
+    }
+}
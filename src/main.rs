@@ -1,13 +1,1078 @@
-mod cpu;
-mod memory;
-mod op_codes;
-mod processor_status;
+use std::{
+    env,
+    fmt::Display,
+    fs::{self, File},
+    ops::RangeInclusive,
+    path::Path,
+    process,
+    time::Duration,
+};
 
-use cpu::Cpu;
-use op_codes::*;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    filter::filter_fn,
+    fmt::{
+        self as log_fmt,
+        format::{self, FormatEvent, FormatFields},
+        FmtContext,
+    },
+    prelude::*,
+    registry::LookupSpan,
+    EnvFilter,
+};
+
+use cpu_emu::{
+    assembler::Assembly,
+    cdl::CodeDataLog,
+    core_dump::CoreDump,
+    cpu::TRACE_TARGET,
+    diff,
+    disassembler::{self, Dialect},
+    loader,
+    machine::{Board, BoardOptions, Serial},
+    memory::{BusConflicts, Memory},
+    monitor::Monitor,
+    permissions::{Permissions, ViolationPolicy},
+    runner::{self, Program, RunOptions, RunnerOptions, DEFAULT_ORIGIN},
+    stats, ticker,
+    trace::{self, TraceFormat, TraceWriter},
+    vic20::Vic20,
+    Cpu, CpuBuilder, UnknownOpcodePolicy, Variant,
+};
+
+const USAGE: &str = "\
+usage: cpu_emu run <program> [--origin <address>] [--watch] [--trace] [--histogram]
+                           [--trace-format <default|nestest|vice|csv>] [--trace-file <file>]
+                           [--explain]
+                           [--profile] [--callgrind <file>] [--branches] [--cdl <file>]
+                           [--vcd <file>] [--heat-map <file>] [--latency] [--stack]
+                           [--summary] [--report <file>] [--report-memory <start>-<end>]...
+                           [--serial <address:port>] [--pty] [--acia <address>]
+                           [--semihost <dir>] [--nvram <file>] [--nvram-at <address>]
+                           [--nvram-size <n>] [--nvram-write-cycles <n>]
+                           [--unknown-opcodes <error|nop|illegal>] [--max-instructions <n>]
+                           [--console] [--console-at <address>] [--apple2]
+                           [--vic20 <unexpanded|3k|8k|16k|24k|35k>]
+                           [--tape <wav>] [--tape-record <wav>] [--tape-at <address>]
+                           [--ticker <hz>] [--ticker-at <address>] [--ticker-line <irq|nmi>]
+                           [--protect <start>-<end>:<rwx>]... [--on-violation <warn|stop>]
+                           [--bus-conflicts <refuse|last-wins|or>] [--log-device <address>]...
+                           [--seed <n>] [--random-ram] [--random-at <address>]
+                           [--core <file>] [--identify] [--sp <address>] [--irq-vector <address>] [--nmi-vector <address>]
+                           [--beeper] [--sid] [--sid-at <address>]    (audio feature)
+                           [--ram-file <file>]    (mmap feature)
+                           [--self-profile]    (self-profile feature)
+       cpu_emu monitor [program] [--origin <address>] [--core <file>]
+       cpu_emu test <dir> [--origin <address>] [--max-instructions <n>] [--report <file>]
+       cpu_emu bench <program> [--origin <address>] [--seconds <n>] [--block-cache] [--fast]
+                             [--restore]
+       cpu_emu diff <program> [--origin <address>] [--left <config>] [--right <config>]
+                            [--max-steps <n>] [--variants] [--architectural]
+       cpu_emu asm <source> -o <file> [--origin <address>] [--listing <file>] [--labels <file>]
+       cpu_emu disasm <program> [--origin <address>] [--dialect <plain|ca65|acme>] [--cdl <file>]
+       cpu_emu binmon [program] [--origin <address>] [--listen <address:port>]
+       cpu_emu gui [program] [--origin <address>] [--labels <file>] [--charrom <file>]
+                   [--text-at <address>]    (gui feature)
+       cpu_emu script <file> [program] [--origin <address>]    (scripting feature)
+       cpu_emu serve [program] [--origin <address>] [--listen <address:port>]    (http feature)
+       cpu_emu stream <program> [--origin <address>] [--listen <address:port>]    (websocket feature)
+
+diff configs are comma separated options out of nmos, cmos, accurate and decimal
+--trace lines go to stdout as they are, or with --trace-file to a file, for diffing against
+reference logs like nestest's
+--explain prints each instruction run with what it did in plain English and the flags it set
+--serial and --pty attach the ACIA's serial line to a tcp port or, on unix, a pseudo-terminal
+--semihost lets the program open files under a directory by calling $FFF0
+--nvram maps memory saved to a file, 2k at $9000 unless told otherwise, --ram-file keeps all
+64K of RAM in a file instead, the program is loaded over what it held before
+--heat-map counts reads, writes and instructions run at each address and saves them as a
+256x256 .png or .ppm picture, a row a page, or as .json
+--latency reports the cycles from each IRQ or NMI request to its handler and how long handlers
+run until RTI
+--summary prints instructions, cycles, wall time, speed, why the run stopped, the registers and
+how much of the program ran as code, after each run with --watch
+--report writes the stop reason, counts, registers and each --report-memory range as json,
+version 1 of a schema that only ever gains fields
+--stack reports the lowest address the stack pointer reached and the room left below it
+--vic20 loads the program as a .prg at its load address into a VIC-20 memory map with that much
+expansion RAM, starting from a SYS stub's address or a cartridge's cold start vector
+--console maps stdin and stdout to data and status registers at $D200
+--apple2 runs COUT, CROUT, HOME, PRBYTE and RDKEY on the host, drawing on the text page at $0400
+and echoing to stdout, with stdin behind the keyboard softswitches at $C000
+--tape plays a Kansas City Standard WAV file through a device at $D300, --tape-record
+saves what the program writes to it as one
+--protect only allows reads, writes or execution as given over a range, e.g. $C000-$FFFF:r-x,
+later ranges win, and logs accesses that break it or with --on-violation stop stops the run
+--bus-conflicts decides what happens when devices are mapped over each other, refused unless
+told otherwise, the last one mapped answers with last-wins and or answers with every one ORed
+--log-device logs each read and write of the device at an address, by register name where the
+device gives one, like ACIA.STATUS read $10
+--random-ram fills RAM with noise before loading the program and --random-at maps a device
+reading back random bytes, at $FE for easy6502 programs, from --seed or a printed seed to repeat
+--core writes the memory, registers, counters and pc history to a core file when the run stops,
+monitor --core loads one back for a post-mortem
+--identify prints the program's CRC32 and SHA-1, and loads Wozmon, EhBASIC, Klaus Dormann's
+functional test and nestest where they belong and from their entry point unless --origin is given
+--sp, --irq-vector and --nmi-vector set the stack pointer and vectors the program starts with
+--ticker interrupts that many times a second of a 1MHz clock, from a device at $D100
+--self-profile prints what the emulator itself spent the run on to stderr, the instructions each
+dispatch path ran and the most common opcodes, where bus accesses went and time in hooks,
+observers and traps
+--beeper plays a one bit speaker at $D400 and --sid a three voice sound chip at $D500, either
+paces the run to a 1MHz clock
+diff --variants runs the program on an NMOS 6502 on the left and a 65C02 on the right and, like
+--architectural, only compares registers, flags and memory written, not cycles or bus reads
+bench --restore reruns the program by restoring a snapshot instead of cloning the cpu
+--max-instructions fails a run that hasn't halted after that many instructions
+--unknown-opcodes nop skips opcodes without a handler, illegal runs the NMOS undocumented ones
+asm --labels writes VICE label commands the monitor can load with ll and gui with --labels
+gui --charrom maps a 40x25 text screen at $8000 drawn through an 8x8 character ROM
+disasm prints source that assembles back to the program, for this crate's assembler by default,
+given a code/data log saved by run --cdl only bytes run as code are disassembled";
+
+/// steps the diff subcommand compares before giving up
+const DEFAULT_DIFF_STEPS: u64 = 1_000_000;
+
+/// how long the bench subcommand runs for by default
+const DEFAULT_BENCH_SECONDS: f64 = 5.0;
+
+/// log filter used when RUST_LOG isn't set, shows instruction traces requested with --trace
+const DEFAULT_LOG_FILTER: &str = "warn,cpu_emu::trace=info,cpu_emu::access=info";
 
 fn main() {
-    let mut cpu = Cpu::new().reset(None);
-    cpu.memory.data[0xFFFC] = NOP;
-    cpu.execute();
+    // traces are written bare so they can be diffed against reference logs
+    tracing_subscriber::registry()
+        .with(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER)),
+        )
+        .with(
+            log_fmt::layer()
+                .without_time()
+                .with_target(false)
+                .with_filter(filter_fn(|meta| meta.target() != TRACE_TARGET)),
+        )
+        .with(
+            log_fmt::layer()
+                .event_format(Bare)
+                .with_ansi(false)
+                .with_writer(|| TraceWriter)
+                .with_filter(filter_fn(|meta| meta.target() == TRACE_TARGET)),
+        )
+        .init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("run") => run(&args[1..]),
+        Some("monitor") => monitor(&args[1..]),
+        Some("test") => test(&args[1..]),
+        Some("bench") => bench(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        Some("asm") => asm(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("binmon") => binmon(&args[1..]),
+        #[cfg(feature = "gui")]
+        Some("gui") => gui(&args[1..]),
+        #[cfg(feature = "scripting")]
+        Some("script") => script(&args[1..]),
+        #[cfg(feature = "http")]
+        Some("serve") => serve(&args[1..]),
+        #[cfg(feature = "websocket")]
+        Some("stream") => stream(&args[1..]),
+        _ => exit_with_usage(),
+    }
+}
+
+/// run a program binary, optionally reloading it whenever the file changes
+fn run(args: &[String]) {
+    let mut path = None;
+    let mut watch = false;
+    let mut options = RunOptions::default();
+    let mut board = BoardOptions::default();
+    let mut serial = None;
+    let mut pty = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watch = true,
+            "--trace" => options.trace = true,
+            "--trace-file" => {
+                let file = args.next().unwrap_or_else(|| exit_with_usage());
+                match File::create(file) {
+                    Ok(file) => trace::trace_to(file),
+                    Err(err) => {
+                        eprintln!("failed to create {file}: {err}");
+                        process::exit(1);
+                    }
+                }
+                options.trace = true;
+            }
+            "--trace-format" => {
+                options.trace_format = args
+                    .next()
+                    .and_then(|name| TraceFormat::from_name(name))
+                    .unwrap_or_else(|| exit_with_usage());
+                options.trace = true;
+            }
+            "--explain" => options.explain = true,
+            "--histogram" => options.reports.histogram = Some(Default::default()),
+            "--profile" => options.reports.profiler = Some(Default::default()),
+            "--branches" => options.reports.branches = Some(Default::default()),
+            "--callgrind" => {
+                options.reports.callgrind =
+                    Some(args.next().unwrap_or_else(|| exit_with_usage()).clone());
+                options
+                    .reports
+                    .profiler
+                    .get_or_insert_with(Default::default);
+            }
+            "--cdl" => {
+                let path = args.next().unwrap_or_else(|| exit_with_usage());
+                options.reports.cdl = Some((path.clone(), Default::default()));
+            }
+            "--latency" => options.reports.latency = Some(Default::default()),
+            "--stack" => options.reports.stack = true,
+            "--summary" => options.reports.summary = Some(Default::default()),
+            "--report" => {
+                options.reports.report =
+                    Some(args.next().unwrap_or_else(|| exit_with_usage()).clone())
+            }
+            "--report-memory" => options.reports.report_memory.push(
+                args.next()
+                    .and_then(|value| parse_range(value))
+                    .unwrap_or_else(|| exit_with_usage()),
+            ),
+            "--heat-map" => {
+                let path = args.next().unwrap_or_else(|| exit_with_usage());
+                options.reports.heat_map = Some((path.clone(), Default::default()));
+            }
+            "--vcd" => {
+                options.reports.vcd =
+                    Some(args.next().unwrap_or_else(|| exit_with_usage()).clone());
+            }
+            "--serial" => serial = Some(args.next().unwrap_or_else(|| exit_with_usage()).clone()),
+            #[cfg(unix)]
+            "--pty" => pty = true,
+            "--semihost" => {
+                board.semihost = Some(args.next().unwrap_or_else(|| exit_with_usage()).into())
+            }
+            "--nvram" => {
+                board.nvram = Some(args.next().unwrap_or_else(|| exit_with_usage()).into())
+            }
+            "--nvram-at" => {
+                board.nvram_base = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--nvram-size" => {
+                board.nvram_len = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .filter(|len| *len > 0)
+                    .unwrap_or_else(|| exit_with_usage()) as usize
+            }
+            "--nvram-write-cycles" => {
+                board.nvram_write_cycles = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--max-instructions" => {
+                options.max_instructions = Some(
+                    args.next()
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--unknown-opcodes" => {
+                board.unknown_opcodes = args
+                    .next()
+                    .and_then(|name| UnknownOpcodePolicy::from_name(name))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--tape" => board.tape = Some(args.next().unwrap_or_else(|| exit_with_usage()).into()),
+            "--tape-record" => {
+                board.tape_record = Some(args.next().unwrap_or_else(|| exit_with_usage()).into())
+            }
+            "--tape-at" => {
+                board.tape_base = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--console" => board.console = true,
+            "--apple2" => board.apple2 = true,
+            "--vic20" => {
+                board.vic20 = Some(
+                    args.next()
+                        .and_then(|name| Vic20::from_name(name))
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--console-at" => {
+                board.console_base = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--ticker" => {
+                board.ticker_rate = Some(
+                    args.next()
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--ticker-at" => {
+                board.ticker_base = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--ticker-line" => {
+                board.ticker_line = args
+                    .next()
+                    .and_then(|name| ticker::Line::from_name(name))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            #[cfg(feature = "mmap")]
+            "--ram-file" => {
+                board.ram_file = Some(args.next().unwrap_or_else(|| exit_with_usage()).into())
+            }
+            #[cfg(feature = "audio")]
+            "--beeper" => board.beeper = true,
+            #[cfg(feature = "audio")]
+            "--sid" => board.sid = true,
+            #[cfg(feature = "audio")]
+            "--sid-at" => {
+                board.sid_base = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--identify" => options.identify = true,
+            #[cfg(feature = "self-profile")]
+            "--self-profile" => options.self_profile = true,
+            "--protect" => board.protect.push(
+                args.next()
+                    .and_then(|value| parse_protection(value))
+                    .unwrap_or_else(|| exit_with_usage()),
+            ),
+            "--on-violation" => {
+                board.on_violation = args
+                    .next()
+                    .and_then(|name| ViolationPolicy::from_name(name))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--bus-conflicts" => {
+                board.bus_conflicts = args
+                    .next()
+                    .and_then(|name| BusConflicts::from_name(name))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--log-device" => board.logged_devices.push(
+                args.next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage()),
+            ),
+            "--seed" => {
+                board.seed = Some(
+                    args.next()
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--random-ram" => board.random_ram = true,
+            "--random-at" => {
+                board.random_base = Some(
+                    args.next()
+                        .and_then(|value| parse_address(value))
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--core" => {
+                options.core = Some(args.next().unwrap_or_else(|| exit_with_usage()).into())
+            }
+            "--sp" => {
+                board.sp = Some(
+                    args.next()
+                        .and_then(|value| parse_address(value))
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--irq-vector" => {
+                board.irq_vector = Some(
+                    args.next()
+                        .and_then(|value| parse_address(value))
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--nmi-vector" => {
+                board.nmi_vector = Some(
+                    args.next()
+                        .and_then(|value| parse_address(value))
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            "--acia" => {
+                board.acia_base = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--origin" => {
+                options.origin = Some(
+                    args.next()
+                        .and_then(|value| parse_address(value))
+                        .unwrap_or_else(|| exit_with_usage()),
+                )
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    board.serial = match (serial, pty) {
+        (Some(_), true) => exit_with_usage(),
+        (Some(address), false) => Some(Serial::Tcp(address)),
+        #[cfg(unix)]
+        (None, true) => Some(Serial::Pty),
+        _ => None,
+    };
+    let path = path.unwrap_or_else(|| exit_with_usage());
+
+    let board = Board::open(board).unwrap_or_else(|err| exit_with_error(err));
+    if let Some(line) = board.serial() {
+        println!(
+            "serial console on {line}, ACIA at ${:04X}",
+            board.options().acia_base
+        );
+    }
+    let program = Program::new(path, options, board).unwrap_or_else(|err| exit_with_error(err));
+    if let Some(identity) = program.identity() {
+        println!("{}: {identity}", program.path().display());
+    }
+
+    if watch {
+        if let Err(err) = program.watch() {
+            exit_with_error(err);
+        }
+        return;
+    }
+    match program.run() {
+        Ok(stop) if stop.result.is_ok() => {}
+        Ok(_) => {
+            // exiting skips saving the nvram and tape on drop
+            drop(program);
+            process::exit(1);
+        }
+        Err(err) => exit_with_error(err),
+    }
+}
+
+/// formats an event as just its message, without a level, target or colour
+struct Bare;
+
+impl<S, N> FormatEvent<S, N> for Bare
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// start the interactive monitor, optionally with a program loaded
+fn monitor(args: &[String]) {
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut core = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--core" => core = Some(args.next().unwrap_or_else(|| exit_with_usage()).clone()),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let cpu = match (core, path) {
+        (Some(_), Some(_)) => exit_with_usage(),
+        (Some(file), None) => {
+            let core = CoreDump::load(Path::new(&file)).unwrap_or_else(|err| {
+                eprintln!("failed to load {file}: {err}");
+                process::exit(1);
+            });
+            println!("{file}: {}", core.reason);
+            core.restore(Cpu::builder()).unwrap_or_else(|err| {
+                eprintln!("failed to load {file}: {err}");
+                process::exit(1);
+            })
+        }
+        (None, Some(path)) => load(Path::new(&path), origin, Cpu::builder()),
+        (None, None) => Cpu::new().reset(Some(origin)),
+    };
+
+    Monitor::new(cpu).run();
+}
+
+/// run every rom in a directory and emit a json report
+/// exits with a failure code if any rom didn't pass
+fn test(args: &[String]) {
+    let mut dir = None;
+    let mut report = None;
+    let mut options = RunnerOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                options.origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--max-instructions" => {
+                options.max_instructions = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--report" => report = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            _ if dir.is_none() => dir = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let dir = dir.unwrap_or_else(|| exit_with_usage());
+    let results = runner::run_dir(Path::new(&dir), options).unwrap_or_else(|err| {
+        eprintln!("failed to run roms in {dir}: {err}");
+        process::exit(1);
+    });
+
+    let json = runner::to_json(&results);
+    match report {
+        Some(report) => fs::write(report, json).unwrap_or_else(|err| {
+            eprintln!("failed to write {report}: {err}");
+            process::exit(1);
+        }),
+        None => println!("{json}"),
+    }
+
+    if results
+        .iter()
+        .any(|result| result.status != runner::Status::Pass)
+    {
+        process::exit(1);
+    }
+}
+
+/// run a halting program over and over and report the emulation speed
+fn bench(args: &[String]) {
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut seconds = DEFAULT_BENCH_SECONDS;
+    let mut restore = false;
+    let mut builder = Cpu::builder();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--block-cache" => builder = builder.block_cache(true),
+            "--fast" => builder = builder.fast(true),
+            "--restore" => restore = true,
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--seconds" => {
+                seconds = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|seconds: &f64| *seconds > 0.0)
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| exit_with_usage());
+    let cpu = load(Path::new(&path), origin, builder);
+
+    let duration = Duration::from_secs_f64(seconds);
+    let speed = if restore {
+        stats::bench_restore(&cpu, duration)
+    } else {
+        stats::bench(&cpu, duration)
+    };
+    match speed {
+        Ok(speed) => println!("{speed}"),
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// run a program under two configurations and report where they first differ
+fn diff(args: &[String]) {
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut max_steps = DEFAULT_DIFF_STEPS;
+    let mut left = Cpu::builder();
+    let mut right = Cpu::builder();
+    let mut compare = diff::Compare::Everything;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--left" => {
+                left = args
+                    .next()
+                    .and_then(|config| parse_config(config))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--right" => {
+                right = args
+                    .next()
+                    .and_then(|config| parse_config(config))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--variants" => {
+                left = Cpu::builder().variant(Variant::Nmos);
+                right = Cpu::builder().variant(Variant::Cmos);
+                compare = diff::Compare::Architectural;
+            }
+            "--architectural" => compare = diff::Compare::Architectural,
+            "--max-steps" => {
+                max_steps = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| exit_with_usage());
+    let left = load(Path::new(&path), origin, left);
+    let right = load(Path::new(&path), origin, right);
+
+    match diff::diff_runs_comparing(left, right, max_steps, compare) {
+        Some(divergence) => {
+            println!("{divergence}");
+            process::exit(1);
+        }
+        None => println!("no divergence"),
+    }
+}
+
+/// run a rhai script against a cpu, with a program loaded if one is given
+#[cfg(feature = "scripting")]
+fn script(args: &[String]) {
+    let mut paths = Vec::new();
+    let mut origin = DEFAULT_ORIGIN;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            _ if paths.len() < 2 => paths.push(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let Some(script) = paths.first() else {
+        exit_with_usage();
+    };
+    let source = fs::read_to_string(script).unwrap_or_else(|err| {
+        eprintln!("failed to read {script}: {err}");
+        process::exit(1);
+    });
+    let cpu = match paths.get(1) {
+        Some(program) => load(Path::new(program), origin, Cpu::builder()),
+        None => Cpu::new().reset(Some(origin)),
+    };
+
+    let script = cpu_emu::script::Script::new(cpu);
+    let result = script.eval(&source);
+    println!("{}", script.cpu());
+    if let Err(err) = result {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+}
+
+/// assemble a source file into a program binary
+fn asm(args: &[String]) {
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut output = None;
+    let mut listing = None;
+    let mut labels = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "-o" | "--output" => output = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "--listing" => listing = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "--labels" => labels = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| exit_with_usage());
+    let output = output.unwrap_or_else(|| exit_with_usage());
+    let assembly = Assembly::from_file(&path, origin).unwrap_or_else(|err| {
+        eprintln!("{path}: {err}");
+        process::exit(1);
+    });
+
+    let files = [
+        Some((output, assembly.bytes.clone())),
+        listing.map(|file| (file, assembly.listing_text().into_bytes())),
+        labels.map(|file| (file, assembly.vice_labels().into_bytes())),
+    ];
+    for (file, contents) in files.into_iter().flatten() {
+        if let Err(err) = fs::write(file, contents) {
+            eprintln!("failed to write {file}: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// print a program as source for an assembler
+fn disasm(args: &[String]) {
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut dialect = Dialect::default();
+    let mut log = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--dialect" => {
+                dialect = args
+                    .next()
+                    .and_then(|name| Dialect::from_name(name))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--cdl" => {
+                let path = args.next().unwrap_or_else(|| exit_with_usage());
+                log = Some(CodeDataLog::load(Path::new(path)).unwrap_or_else(|err| {
+                    eprintln!("failed to read {path}: {err}");
+                    process::exit(1);
+                }));
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| exit_with_usage());
+    let program = loader::read_program(Path::new(&path)).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    let mut memory = Memory::default();
+    if let Err(err) = memory.write_bytes(origin as usize, &program) {
+        eprintln!("failed to load {path}: {err}");
+        process::exit(1);
+    }
+    let source = match &log {
+        Some(log) => disassembler::source_with_log(&memory, origin, program.len(), dialect, log),
+        None => disassembler::source(&memory, origin, program.len(), dialect),
+    };
+    print!("{source}");
+}
+
+/// answer VICE binary monitor clients, with a program loaded if one is given
+fn binmon(args: &[String]) {
+    use cpu_emu::binary_monitor;
+    use std::net::TcpListener;
+
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut listen = binary_monitor::DEFAULT_ADDRESS.to_string();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| exit_with_usage()).clone(),
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let cpu = match path {
+        Some(path) => load(Path::new(&path), origin, Cpu::builder()),
+        None => Cpu::new().reset(Some(origin)),
+    };
+    let served = TcpListener::bind(&listen).and_then(|listener| {
+        println!("binary monitor listening on {listen}");
+        binary_monitor::serve(listener, cpu)
+    });
+    if let Err(err) = served {
+        eprintln!("failed to serve on {listen}: {err}");
+        process::exit(1);
+    }
+}
+
+/// open the graphical debugger, with a program loaded if one is given
+#[cfg(feature = "gui")]
+fn gui(args: &[String]) {
+    use std::{cell::RefCell, rc::Rc};
+
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut session = cpu_emu::session::Session::default();
+    let mut charrom = None;
+    let mut text_base = cpu_emu::text_video::DEFAULT_BASE;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--labels" => {
+                let path = args.next().unwrap_or_else(|| exit_with_usage());
+                match fs::read_to_string(path) {
+                    Ok(commands) => session.add_labels(&commands),
+                    Err(err) => {
+                        eprintln!("failed to read {path}: {err}");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--charrom" => {
+                let path = args.next().unwrap_or_else(|| exit_with_usage());
+                match cpu_emu::text_video::CharRom::open(Path::new(path)) {
+                    Ok(rom) => charrom = Some(rom),
+                    Err(err) => {
+                        eprintln!("failed to read {path}: {err}");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--text-at" => {
+                text_base = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let video = charrom.map(|rom| Rc::new(RefCell::new(cpu_emu::text_video::TextVideo::new(rom))));
+    let mut builder = Cpu::builder();
+    if let Some(video) = &video {
+        let len = video.borrow().mapped_len();
+        builder = builder.device(text_base as usize, len, video.clone());
+    }
+    let cpu = match path {
+        Some(path) => load(Path::new(&path), origin, builder),
+        None => builder.build().unwrap().reset(Some(origin)),
+    };
+    let mut debugger = cpu_emu::gui::Debugger::new(cpu).session(session);
+    if let Some(video) = video {
+        debugger = debugger.text_video(video);
+    }
+    if let Err(err) = cpu_emu::gui::run(debugger) {
+        eprintln!("failed to open the debugger: {err}");
+        process::exit(1);
+    }
+}
+
+/// answer http requests driving a cpu, with a program loaded if one is given
+#[cfg(feature = "http")]
+fn serve(args: &[String]) {
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut listen = cpu_emu::server::DEFAULT_ADDRESS.to_string();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| exit_with_usage()).clone(),
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let cpu = match path {
+        Some(path) => load(Path::new(&path), origin, Cpu::builder()),
+        None => Cpu::new().reset(Some(origin)),
+    };
+    println!("listening on http://{listen}");
+    if let Err(err) = cpu_emu::server::serve(&listen, cpu) {
+        eprintln!("failed to serve on {listen}: {err}");
+        process::exit(1);
+    }
+}
+
+/// run a program once a websocket client connects, streaming its trace and screen
+#[cfg(feature = "websocket")]
+fn stream(args: &[String]) {
+    use cpu_emu::stream;
+
+    let mut path = None;
+    let mut origin = DEFAULT_ORIGIN;
+    let mut listen = stream::DEFAULT_ADDRESS.to_string();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| exit_with_usage()).clone(),
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|value| parse_address(value))
+                    .unwrap_or_else(|| exit_with_usage())
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| exit_with_usage());
+    let mut cpu = load(Path::new(&path), origin, Cpu::builder());
+    let listener = std::net::TcpListener::bind(&listen).unwrap_or_else(|err| {
+        eprintln!("failed to listen on {listen}: {err}");
+        process::exit(1);
+    });
+
+    println!("waiting for a client on ws://{listen}");
+    let result = stream::serve(listener, &mut cpu, stream::DEFAULT_SCREEN);
+    println!("{cpu}");
+    if let Err(err) = result {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+}
+
+/// a cpu configuration given as comma separated options, e.g. `cmos,accurate`
+fn parse_config(config: &str) -> Option<CpuBuilder> {
+    let mut builder = Cpu::builder();
+    for option in config.split(',') {
+        builder = match option {
+            "nmos" => builder.variant(Variant::Nmos),
+            "cmos" => builder.variant(Variant::Cmos),
+            "accurate" => builder.accurate(true),
+            "decimal" => builder.decimal_mode(true),
+            _ => return None,
+        };
+    }
+    Some(builder)
+}
+
+/// create a cpu with the program at `path` loaded at `origin`
+fn load(path: &Path, origin: u16, builder: CpuBuilder) -> Cpu {
+    let program = loader::read_program(path).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+
+    builder
+        .pc(origin)
+        .memory(origin as usize, program)
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("failed to load {}: {err}", path.display());
+            process::exit(1);
+        })
+}
+
+/// parse an inclusive range of addresses given as `<start>-<end>`
+fn parse_range(value: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = value.split_once('-')?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    (start <= end).then_some(start..=end)
+}
+
+/// parse a range and what it allows, like `$C000-$FFFF:r-x`
+fn parse_protection(value: &str) -> Option<(RangeInclusive<u16>, Permissions)> {
+    let (range, allowed) = value.rsplit_once(':')?;
+    Some((parse_range(range)?, Permissions::from_name(allowed)?))
+}
+
+/// parse an address given as `$0600`, `0x0600` or decimal
+fn parse_address(value: &str) -> Option<u16> {
+    if let Some(hex) = value.strip_prefix('$').or_else(|| value.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+/// print why a subcommand couldn't go on and exit with a failure code
+fn exit_with_error(err: impl Display) -> ! {
+    eprintln!("{err}");
+    process::exit(1);
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(1);
 }
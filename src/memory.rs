@@ -1,37 +1,984 @@
+use core::fmt;
+use std::{
+    cell::RefCell,
+    ops::{Deref, DerefMut, Range},
+    sync::Arc,
+};
+
+use thiserror::Error;
+use tracing::info;
+
+use crate::{
+    device::{self, Device, MappedDevice, SharedDevice},
+    scheduler::Scheduler,
+};
+
+/// size of the addressable memory space
 pub const MAX_MEM: usize = 1024 * 64;
 
+/// bytes in a page, the unit writes are tracked in for snapshots
+const PAGE_LEN: usize = 0x100;
+
+/// the full 64K address space of the cpu
+/// kept on the heap so moving or cloning a cpu doesn't copy 64K through the stack
 #[derive(Debug, Clone)]
 pub struct Memory {
-    pub data: [u8; MAX_MEM],
+    pub data: Ram,
+    /// rom regions read in place of `data`, writes to them are ignored
+    roms: Vec<Rom>,
+    /// devices handling every access to their range
+    devices: Vec<MappedDevice>,
+    /// when scheduled devices need waking
+    scheduler: RefCell<Scheduler>,
+    /// id the next mapped device gets
+    next_device: usize,
+    /// the snapshot last taken or restored, writes since then are tracked
+    baseline: Option<Arc<[u8; MAX_MEM]>>,
+    /// pages written through `write_byte` and friends since the baseline,
+    /// a bit each
+    dirty: [u64; MAX_MEM / PAGE_LEN / 64],
+    /// the address lines wired up, accesses ignore the bits above them
+    address_mask: usize,
+    /// what happens where device ranges overlap
+    conflicts: BusConflicts,
+}
+
+/// the target accesses to logged devices are written to at info level, see
+/// [`Memory::log_device`]
+pub const ACCESS_TARGET: &str = "cpu_emu::access";
+
+fn log_access(mapped: &MappedDevice, address: usize, offset: u16, access: &str, value: u8) {
+    let register = device::describe(&*mapped.device.borrow(), address, offset);
+    info!(target: ACCESS_TARGET, "{register} {access} ${value:02X}");
+}
+
+/// what to do about devices mapped over each other
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BusConflicts {
+    /// mapping a device over another fails with [`BusError::Conflict`]
+    #[default]
+    Refuse,
+    /// the device mapped last answers, shadowing the ones under it
+    LastWins,
+    /// every device sees the write and reads are the devices' values ORed
+    /// together, like an open-collector bus pulled low by default
+    WiredOr,
+}
+
+impl BusConflicts {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "refuse" => Some(BusConflicts::Refuse),
+            "last-wins" => Some(BusConflicts::LastWins),
+            "or" => Some(BusConflicts::WiredOr),
+            _ => None,
+        }
+    }
+}
+
+/// the bytes behind ram, on the heap unless mapped from a file with
+/// [`Memory::map_ram_file`]
+pub enum Ram {
+    Heap(Box<[u8; MAX_MEM]>),
+    /// a file written through as the cpu writes, like battery-backed RAM
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::MmapMut),
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        let data = vec![0; MAX_MEM].into_boxed_slice();
+        Ram::Heap(data.try_into().expect("memory is MAX_MEM bytes"))
+    }
+}
+
+impl Clone for Ram {
+    /// a copy on the heap, a clone of a mapped machine doesn't write to its file
+    fn clone(&self) -> Self {
+        let data = self.to_vec().into_boxed_slice();
+        Ram::Heap(data.try_into().expect("memory is MAX_MEM bytes"))
+    }
+}
+
+impl Deref for Ram {
+    type Target = [u8; MAX_MEM];
+
+    fn deref(&self) -> &[u8; MAX_MEM] {
+        match self {
+            Ram::Heap(data) => data,
+            #[cfg(feature = "mmap")]
+            Ram::Mapped(map) => map[..].try_into().expect("mapped ram is MAX_MEM bytes"),
+        }
+    }
+}
+
+impl DerefMut for Ram {
+    fn deref_mut(&mut self) -> &mut [u8; MAX_MEM] {
+        match self {
+            Ram::Heap(data) => data,
+            #[cfg(feature = "mmap")]
+            Ram::Mapped(map) => (&mut map[..])
+                .try_into()
+                .expect("mapped ram is MAX_MEM bytes"),
+        }
+    }
+}
+
+impl fmt::Debug for Ram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// a copy of ram that can be restored, cheap to clone
+/// roms and devices aren't part of it
+#[derive(Debug, Clone)]
+pub struct MemorySnapshot {
+    data: Arc<[u8; MAX_MEM]>,
+}
+
+impl MemorySnapshot {
+    pub(crate) fn new(data: Box<[u8; MAX_MEM]>) -> Self {
+        Self { data: data.into() }
+    }
+
+    pub fn data(&self) -> &[u8; MAX_MEM] {
+        &self.data
+    }
+}
+
+/// bytes backing a rom region, referenced rather than copied into ram
+#[derive(Clone)]
+pub enum RomImage {
+    Static(&'static [u8]),
+    Shared(Arc<[u8]>),
+    /// a memory-mapped file
+    #[cfg(feature = "mmap")]
+    Mapped(Arc<memmap2::Mmap>),
+}
+
+impl RomImage {
+    /// map a file into memory without reading it
+    /// the file must not be modified while it's mapped
+    #[cfg(feature = "mmap")]
+    pub fn map_file(path: &std::path::Path) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the mapping is read-only and callers promise not to modify the file
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(RomImage::Mapped(Arc::new(map)))
+    }
+}
+
+impl Deref for RomImage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            RomImage::Static(bytes) => bytes,
+            RomImage::Shared(bytes) => bytes,
+            #[cfg(feature = "mmap")]
+            RomImage::Mapped(map) => map,
+        }
+    }
+}
+
+impl fmt::Debug for RomImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RomImage({} bytes)", self.len())
+    }
+}
+
+/// a rom image mapped at an address
+#[derive(Debug, Clone)]
+struct Rom {
+    start: usize,
+    image: RomImage,
+}
+
+impl Rom {
+    /// the rom's byte at an address, none if the address is outside the rom
+    fn get(&self, address: usize) -> Option<u8> {
+        address
+            .checked_sub(self.start)
+            .and_then(|offset| self.image.get(offset))
+            .copied()
+    }
+}
+
+/// errors accessing memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum BusError {
+    /// an access extends beyond the 64K address space
+    #[error("{len} bytes at ${address:04X} run past the end of memory")]
+    OutOfRange { address: usize, len: usize },
+    /// a device was mapped over part of another's range
+    #[error("a device at ${address:04X} overlaps the one mapped at ${other:04X}")]
+    Conflict { address: usize, other: usize },
 }
 
 impl Default for Memory {
     fn default() -> Self {
-        Self { data: [0; MAX_MEM] }
+        Self {
+            data: Ram::default(),
+            roms: Vec::new(),
+            devices: Vec::new(),
+            scheduler: RefCell::default(),
+            next_device: 0,
+            baseline: None,
+            dirty: Default::default(),
+            address_mask: MAX_MEM - 1,
+            conflicts: BusConflicts::default(),
+        }
     }
 }
 
 impl Memory {
+    /// keep ram in a file from now on, so it outlasts the emulator like
+    /// battery-backed RAM
+    /// an empty or new file starts with ram as it is, one that's already
+    /// MAX_MEM bytes replaces it
+    #[cfg(feature = "mmap")]
+    pub fn map_ram_file(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        use std::io;
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let fresh = match file.metadata()?.len() {
+            0 => true,
+            len if len == MAX_MEM as u64 => false,
+            len => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ram files are {MAX_MEM} bytes, not {len}"),
+                ))
+            }
+        };
+        file.set_len(MAX_MEM as u64)?;
+        // SAFETY: the file is this process's to write, callers promise not
+        // to change it from elsewhere while it's mapped
+        let mut map = unsafe { memmap2::MmapMut::map_mut(&file)? };
+        if fresh {
+            map.copy_from_slice(&self.data[..]);
+        }
+        self.data = Ram::Mapped(map);
+        Ok(())
+    }
+
+    /// write mapped ram out to its file now rather than whenever the host
+    /// gets round to it
+    #[cfg(feature = "mmap")]
+    pub fn flush_ram(&self) -> std::io::Result<()> {
+        match &self.data {
+            Ram::Mapped(map) => map.flush(),
+            Ram::Heap(_) => Ok(()),
+        }
+    }
+
     /// write a word (2 bytes) to an address in memory
     pub fn write_word(&mut self, address: usize, data: u16) {
         self.write_byte(address, (data & 0xFF) as u8);
-        self.write_byte(address + 1, (data >> 8) as u8);
+        self.write_byte((address + 1) % MAX_MEM, (data >> 8) as u8);
     }
 
     /// write a single byte to an address in memory
+    /// writes to mapped roms are ignored
     pub fn write_byte(&mut self, address: usize, data: u8) {
-        self.data[address] = data;
+        let address = address & self.address_mask;
+        if self.conflicts == BusConflicts::WiredOr {
+            let mut any = false;
+            for (mapped, offset) in self.devices_at(address) {
+                self.write_device(mapped, address, offset, data);
+                any = true;
+            }
+            if any {
+                #[cfg(feature = "self-profile")]
+                crate::self_profile::record(|counters| counters.device_writes += 1);
+                return;
+            }
+        } else if let Some((mapped, offset)) = self.device_at(address) {
+            #[cfg(feature = "self-profile")]
+            crate::self_profile::record(|counters| counters.device_writes += 1);
+            self.write_device(mapped, address, offset, data);
+            return;
+        }
+        if self.roms.is_empty() || self.rom_at(address).is_none() {
+            #[cfg(feature = "self-profile")]
+            crate::self_profile::record(|counters| counters.ram_writes += 1);
+            self.data[address] = data;
+            self.mark_dirty(address);
+        } else {
+            #[cfg(feature = "self-profile")]
+            crate::self_profile::record(|counters| counters.rom_writes += 1);
+        }
+    }
+
+    fn mark_dirty(&mut self, address: usize) {
+        let page = address / PAGE_LEN;
+        self.dirty[page / 64] |= 1 << (page % 64);
+    }
+
+    /// copy ram so it can be restored later
+    /// writes made after this through `write_byte`, `write_bytes` or `fill`
+    /// are tracked so restoring only copies the pages they touched, writes
+    /// straight to `data` aren't seen and need `restore_all`
+    pub fn snapshot(&mut self) -> MemorySnapshot {
+        let data: Arc<[u8; MAX_MEM]> = Arc::new(*self.data);
+        self.baseline = Some(data.clone());
+        self.dirty = Default::default();
+        MemorySnapshot { data }
+    }
+
+    /// put ram back the way it was when `snapshot` was taken
+    /// for the latest snapshot only the pages written since are copied
+    pub fn restore(&mut self, snapshot: &MemorySnapshot) {
+        if !self.tracks(snapshot) {
+            return self.restore_all(snapshot);
+        }
+        let dirty = std::mem::take(&mut self.dirty);
+        for page in pages(&dirty) {
+            let range = page * PAGE_LEN..(page + 1) * PAGE_LEN;
+            self.data[range.clone()].copy_from_slice(&snapshot.data[range]);
+        }
+    }
+
+    /// copy every byte of `snapshot` back into ram
+    pub fn restore_all(&mut self, snapshot: &MemorySnapshot) {
+        self.data.copy_from_slice(&snapshot.data[..]);
+        self.baseline = Some(snapshot.data.clone());
+        self.dirty = Default::default();
+    }
+
+    /// whether writes are being tracked against `snapshot`
+    pub fn tracks(&self, snapshot: &MemorySnapshot) -> bool {
+        self.baseline
+            .as_ref()
+            .is_some_and(|baseline| Arc::ptr_eq(baseline, &snapshot.data))
+    }
+
+    /// pages written since the last snapshot was taken or restored
+    pub fn dirty_pages(&self) -> impl Iterator<Item = usize> + '_ {
+        pages(&self.dirty)
+    }
+
+    /// map a rom image at an address, reads from the region come from the image
+    /// later mappings take precedence where roms overlap
+    pub fn map_rom(&mut self, address: usize, image: RomImage) -> Result<(), BusError> {
+        let fits = address
+            .checked_add(image.len())
+            .is_some_and(|end| end <= MAX_MEM);
+        if !fits {
+            return Err(BusError::OutOfRange {
+                address,
+                len: image.len(),
+            });
+        }
+
+        self.roms.insert(
+            0,
+            Rom {
+                start: address,
+                image,
+            },
+        );
+        Ok(())
+    }
+
+    /// remove every mapped rom, exposing the ram underneath
+    pub fn unmap_roms(&mut self) {
+        self.roms.clear();
+    }
+
+    /// map a device over `len` addresses from `address`, it takes precedence
+    /// over roms and ram and later mappings take precedence where devices overlap
+    pub fn map_device(
+        &mut self,
+        address: usize,
+        len: usize,
+        device: SharedDevice,
+    ) -> Result<(), BusError> {
+        let fits = address.checked_add(len).is_some_and(|end| end <= MAX_MEM);
+        if !fits || len == 0 {
+            return Err(BusError::OutOfRange { address, len });
+        }
+        let overlapping = self
+            .devices
+            .iter()
+            .find(|mapped| mapped.start < address + len && address < mapped.start + mapped.len);
+        if let Some(other) = overlapping.filter(|_| self.conflicts == BusConflicts::Refuse) {
+            return Err(BusError::Conflict {
+                address,
+                other: other.start,
+            });
+        }
+
+        let scheduled = device.borrow().scheduled();
+        let id = self.next_device;
+        self.next_device += 1;
+        if scheduled {
+            let next = device.borrow().next_event();
+            self.scheduler.get_mut().schedule(id, next);
+        }
+        self.devices.insert(
+            0,
+            MappedDevice {
+                start: address,
+                len,
+                device,
+                id,
+                scheduled,
+                logged: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// log every access to the device mapped at `address` to
+    /// [`ACCESS_TARGET`], or stop, false if there's no device there
+    pub fn log_device(&mut self, address: usize, logged: bool) -> bool {
+        let address = address & self.address_mask;
+        let mapped = self
+            .devices
+            .iter_mut()
+            .find(|mapped| mapped.offset(address).is_some());
+        mapped.map(|mapped| mapped.logged = logged).is_some()
+    }
+
+    /// the device register at `address` as `VIA.T1C-L`, if a device there
+    /// has a name
+    pub fn register_name(&self, address: usize) -> Option<String> {
+        let address = address & self.address_mask;
+        let (mapped, offset) = self.device_at(address)?;
+        let device = mapped.device.try_borrow().ok()?;
+        device.name()?;
+        Some(device::describe(&*device, address, offset))
+    }
+
+    /// how devices mapped over each other are dealt with from now on,
+    /// refused unless told otherwise
+    pub fn set_bus_conflicts(&mut self, conflicts: BusConflicts) {
+        self.conflicts = conflicts;
+    }
+
+    /// wire up only the low `lines` address lines, like the 6507's 13, so
+    /// the 8K they reach repeats through the address space
+    /// images, roms and devices are placed at the addresses the cpu reaches
+    /// them through once masked
+    pub fn set_address_lines(&mut self, lines: u32) {
+        self.address_mask = (1 << lines.min(16)) - 1;
+    }
+
+    /// the address lines wired up, 16 unless told otherwise
+    pub fn address_lines(&self) -> u32 {
+        self.address_mask.count_ones()
+    }
+
+    /// whether any devices are mapped
+    pub fn has_devices(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    /// whether a device answers at an address
+    pub fn is_device(&self, address: usize) -> bool {
+        self.device_at(address & self.address_mask).is_some()
+    }
+
+    /// let every mapped device know `cycles` have passed, ticking those that
+    /// aren't scheduled and waking scheduled ones whose events came due
+    pub fn tick(&self, cycles: u64) {
+        for mapped in self.devices.iter().filter(|mapped| !mapped.scheduled) {
+            mapped.device.borrow_mut().tick(cycles);
+        }
+        self.scheduler.borrow_mut().now += cycles;
+
+        loop {
+            // don't hold the scheduler while a device runs
+            let due = self.scheduler.borrow_mut().pop_due();
+            let Some((cycle, id)) = due else {
+                break;
+            };
+            let Some(mapped) = self.devices.iter().find(|mapped| mapped.id == id) else {
+                continue;
+            };
+            let mut device = mapped.device.borrow_mut();
+            // the device may have moved its event since this one was queued
+            if device.next_event() != Some(cycle) {
+                continue;
+            }
+            device.event(cycle);
+            let next = device.next_event().filter(|next| *next > cycle);
+            self.scheduler.borrow_mut().schedule(id, next);
+        }
+    }
+
+    /// the cycle the cpu is at, given to scheduled devices when they're accessed
+    pub(crate) fn set_cycle(&mut self, cycle: u64) {
+        self.scheduler.get_mut().now = cycle;
+    }
+
+    /// whether any mapped device is asserting the IRQ line
+    pub fn irq(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|mapped| mapped.device.borrow().irq())
+    }
+
+    /// whether any mapped device is holding the NMI line
+    pub fn nmi(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|mapped| mapped.device.borrow().nmi())
+    }
+
+    /// the device mapped over an address and the offset into it, if any
+    fn device_at(&self, address: usize) -> Option<(&MappedDevice, u16)> {
+        self.devices
+            .iter()
+            .find_map(|mapped| Some((mapped, mapped.offset(address)?)))
+    }
+
+    /// every device mapped over an address and the offsets into them
+    fn devices_at(&self, address: usize) -> impl Iterator<Item = (&MappedDevice, u16)> {
+        self.devices
+            .iter()
+            .filter_map(move |mapped| Some((mapped, mapped.offset(address)?)))
+    }
+
+    /// access a device, keeping a scheduled one in step with the cpu
+    fn access<T>(&self, mapped: &MappedDevice, access: impl FnOnce(&mut dyn Device) -> T) -> T {
+        let mut device = mapped.device.borrow_mut();
+        if !mapped.scheduled {
+            return access(&mut *device);
+        }
+        device.sync(self.scheduler.borrow().now);
+        let value = access(&mut *device);
+        self.scheduler
+            .borrow_mut()
+            .schedule(mapped.id, device.next_event());
+        value
+    }
+
+    /// read a device, logging the access if it's asked for
+    fn read_device(&self, mapped: &MappedDevice, address: usize, offset: u16) -> u8 {
+        let value = self.access(mapped, |device| device.read(offset));
+        if mapped.logged {
+            log_access(mapped, address, offset, "read", value);
+        }
+        value
+    }
+
+    /// write to a device, logging the access if it's asked for
+    fn write_device(&self, mapped: &MappedDevice, address: usize, offset: u16, value: u8) {
+        self.access(mapped, |device| device.write(offset, value));
+        if mapped.logged {
+            log_access(mapped, address, offset, "write", value);
+        }
+    }
+
+    /// the rom mapped over an address, if any
+    fn rom_at(&self, address: usize) -> Option<u8> {
+        self.roms.iter().find_map(|rom| rom.get(address))
+    }
+
+    /// write a block of bytes starting at an address in memory
+    pub fn write_bytes(&mut self, address: usize, data: &[u8]) -> Result<(), BusError> {
+        let out_of_range = BusError::OutOfRange {
+            address,
+            len: data.len(),
+        };
+        let end = address.checked_add(data.len()).ok_or(out_of_range)?;
+        self.data
+            .get_mut(address..end)
+            .ok_or(out_of_range)?
+            .copy_from_slice(data);
+        for page in (address / PAGE_LEN)..end.div_ceil(PAGE_LEN) {
+            self.mark_dirty(page * PAGE_LEN);
+        }
+        Ok(())
     }
 
     /// get a byte from an address in memory
+    /// reads from a device's range are passed to the device
     pub fn read_byte(&self, address: usize) -> u8 {
-        self.data[address]
+        let address = address & self.address_mask;
+        if let Some((mapped, offset)) = self.device_at(address) {
+            #[cfg(feature = "self-profile")]
+            crate::self_profile::record(|counters| counters.device_reads += 1);
+            if self.conflicts != BusConflicts::WiredOr {
+                return self.read_device(mapped, address, offset);
+            }
+            return self.devices_at(address).fold(0, |value, (mapped, offset)| {
+                value | self.read_device(mapped, address, offset)
+            });
+        }
+        if self.roms.is_empty() {
+            #[cfg(feature = "self-profile")]
+            crate::self_profile::record(|counters| counters.ram_reads += 1);
+            return self.data[address];
+        }
+        let rom = self.rom_at(address);
+        #[cfg(feature = "self-profile")]
+        crate::self_profile::record(|counters| match rom {
+            Some(_) => counters.rom_reads += 1,
+            None => counters.ram_reads += 1,
+        });
+        rom.unwrap_or(self.data[address])
+    }
+
+    /// a byte as the cpu would read it, without reading devices, the RAM
+    /// under a device's range is returned instead
+    pub fn peek_byte(&self, address: usize) -> u8 {
+        let address = address & self.address_mask;
+        if self.device_at(address).is_some() {
+            return self.data[address];
+        }
+        self.rom_at(address).unwrap_or(self.data[address])
+    }
+
+    /// addresses every occurrence of a byte pattern starts at, as read by the cpu
+    /// devices aren't read, so searching doesn't disturb them
+    pub fn find(&self, pattern: &[u8]) -> Vec<u16> {
+        if pattern.is_empty() {
+            return Vec::new();
+        }
+        let bytes: Vec<u8> = (0..MAX_MEM)
+            .map(|address| self.peek_byte(address))
+            .collect();
+        bytes
+            .windows(pattern.len())
+            .enumerate()
+            .filter(|(_, window)| *window == pattern)
+            .map(|(address, _)| address as u16)
+            .collect()
+    }
+
+    /// set every byte in a range of addresses to a value, roms are left untouched
+    pub fn fill(&mut self, range: Range<usize>, value: u8) -> Result<(), BusError> {
+        if range.end > MAX_MEM {
+            return Err(BusError::OutOfRange {
+                address: range.start,
+                len: range.len(),
+            });
+        }
+        for address in range {
+            self.write_byte(address, value);
+        }
+        Ok(())
     }
 
     /// get a word (2 bytes) from an address in memory
+    /// the high byte of a word at $FFFF comes from $0000
     pub fn read_word(&self, address: usize) -> u16 {
         let mut data = self.read_byte(address) as u16;
-        data |= u16::from(self.read_byte(address + 1)) << 8;
+        data |= u16::from(self.read_byte((address + 1) % MAX_MEM)) << 8;
         data
     }
 }
+
+/// the pages set in a bitset of pages
+fn pages(set: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    set.iter().enumerate().flat_map(|(word, bits)| {
+        let mut bits = *bits;
+        std::iter::from_fn(move || {
+            let bit = (bits != 0).then(|| bits.trailing_zeros() as usize)?;
+            bits &= bits - 1;
+            Some(word * 64 + bit)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{device::Device, interrupt::InterruptController};
+
+    static ROM: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
+
+    #[test]
+    fn mapped_rom_is_read_in_place_of_ram() {
+        let mut memory = Memory::default();
+        memory.map_rom(0xFFFC, RomImage::Static(&ROM)).unwrap();
+
+        assert_eq!(memory.read_word(0xFFFC), 0xADDE);
+        assert_eq!(memory.read_byte(0xFFFF), 0xEF);
+        assert_eq!(memory.read_byte(0xFFFB), 0x00);
+    }
+
+    #[test]
+    fn restoring_copies_back_written_pages() {
+        let mut memory = Memory::default();
+        memory.write_bytes(0x0600, &[1, 2, 3]).unwrap();
+        let snapshot = memory.snapshot();
+
+        memory.write_byte(0x0601, 0xFF);
+        memory.fill(0x10FF..0x1101, 0xAA).unwrap();
+        assert_eq!(memory.dirty_pages().collect::<Vec<_>>(), [0x06, 0x10, 0x11]);
+        memory.restore(&snapshot);
+        assert_eq!(memory.dirty_pages().count(), 0);
+        assert_eq!(memory.data[0x0600..0x0603], [1, 2, 3]);
+        assert_eq!(memory.data[0x10FF..0x1101], [0, 0]);
+
+        // an older snapshot isn't tracked so everything is copied
+        let older = snapshot.clone();
+        memory.write_byte(0x2000, 1);
+        memory.snapshot();
+        assert!(!memory.tracks(&older));
+        memory.restore(&older);
+        assert!(memory.tracks(&older));
+        assert_eq!(memory.data[0x2000], 0);
+    }
+
+    #[test]
+    fn writes_to_mapped_rom_are_ignored() {
+        let mut memory = Memory::default();
+        let image: Arc<[u8]> = Arc::from(vec![0x01, 0x02]);
+        memory.map_rom(0x8000, RomImage::Shared(image)).unwrap();
+
+        memory.write_byte(0x8000, 0xFF);
+        memory.write_byte(0x8002, 0xFF);
+        assert_eq!(memory.read_byte(0x8000), 0x01);
+        assert_eq!(memory.read_byte(0x8002), 0xFF);
+
+        memory.unmap_roms();
+        assert_eq!(memory.read_byte(0x8000), 0x00);
+    }
+
+    #[test]
+    fn map_rom_should_fail_past_end_of_memory() {
+        let mut memory = Memory::default();
+        assert_eq!(
+            memory.map_rom(0xFFFE, RomImage::Static(&ROM)),
+            Err(BusError::OutOfRange {
+                address: 0xFFFE,
+                len: 4
+            })
+        );
+    }
+
+    /// latches the last byte written and counts reads and ticked cycles
+    #[derive(Default)]
+    struct Latch {
+        value: u8,
+        reads: u64,
+        cycles: u64,
+    }
+
+    impl Device for Latch {
+        fn read(&mut self, offset: u16) -> u8 {
+            self.reads += 1;
+            self.value.wrapping_add(offset as u8)
+        }
+
+        fn write(&mut self, _offset: u16, value: u8) {
+            self.value = value;
+        }
+
+        fn tick(&mut self, cycles: u64) {
+            self.cycles += cycles;
+        }
+    }
+
+    #[test]
+    fn devices_handle_accesses_to_their_range() {
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        let mut memory = Memory::default();
+        memory.map_device(0xD000, 2, latch.clone()).unwrap();
+
+        memory.write_byte(0xD001, 0x41);
+        assert_eq!(memory.data[0xD001], 0x00);
+        assert_eq!(memory.read_byte(0xD000), 0x41);
+        assert_eq!(memory.read_byte(0xD001), 0x42);
+        assert_eq!(memory.read_byte(0xD002), 0x00);
+
+        memory.tick(3);
+        assert_eq!(latch.borrow().cycles, 3);
+        assert!(memory.map_device(0xFFFF, 2, latch).is_err());
+    }
+
+    #[test]
+    fn devices_are_logged_one_at_a_time() {
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        let controller = Rc::new(RefCell::new(InterruptController::default()));
+        let mut memory = Memory::default();
+        memory.map_device(0xD000, 2, latch).unwrap();
+        memory.map_device(0xD010, 2, controller).unwrap();
+
+        assert!(memory.log_device(0xD001, true));
+        assert!(!memory.log_device(0xD002, true));
+        memory.write_byte(0xD000, 0x41);
+        assert_eq!(memory.read_byte(0xD001), 0x42);
+
+        assert_eq!(memory.register_name(0xD011).as_deref(), Some("IRQ.ENABLE"));
+        // unnamed devices and RAM aren't annotated
+        assert_eq!(memory.register_name(0xD000), None);
+        assert_eq!(memory.register_name(0x0200), None);
+    }
+
+    #[test]
+    fn overlapping_devices_follow_the_conflict_policy() {
+        let low = Rc::new(RefCell::new(Latch {
+            value: 0x01,
+            ..Default::default()
+        }));
+        let high = Rc::new(RefCell::new(Latch {
+            value: 0x10,
+            ..Default::default()
+        }));
+        let mut memory = Memory::default();
+        memory.map_device(0xD000, 4, low.clone()).unwrap();
+        assert_eq!(
+            memory.map_device(0xD003, 4, high.clone()),
+            Err(BusError::Conflict {
+                address: 0xD003,
+                other: 0xD000
+            })
+        );
+        // next to it is fine
+        memory.map_device(0xD004, 4, high.clone()).unwrap();
+
+        let mut memory = Memory::default();
+        memory.set_bus_conflicts(BusConflicts::LastWins);
+        memory.map_device(0xD000, 4, low.clone()).unwrap();
+        memory.map_device(0xD000, 4, high.clone()).unwrap();
+        assert_eq!(memory.read_byte(0xD000), 0x10);
+
+        let mut memory = Memory::default();
+        memory.set_bus_conflicts(BusConflicts::WiredOr);
+        memory.map_device(0xD000, 4, low.clone()).unwrap();
+        memory.map_device(0xD000, 4, high.clone()).unwrap();
+        assert_eq!(memory.read_byte(0xD000), 0x11);
+        memory.write_byte(0xD000, 0x42);
+        assert_eq!((low.borrow().value, high.borrow().value), (0x42, 0x42));
+    }
+
+    /// wakes every `period` cycles once started by a write, recording when
+    #[derive(Default)]
+    struct Timer {
+        period: u64,
+        next: Option<u64>,
+        now: u64,
+        woken: Vec<u64>,
+    }
+
+    impl Device for Timer {
+        fn read(&mut self, _offset: u16) -> u8 {
+            self.woken.len() as u8
+        }
+
+        fn write(&mut self, _offset: u16, value: u8) {
+            self.period = value as u64;
+            self.next = Some(self.now + self.period);
+        }
+
+        fn tick(&mut self, _cycles: u64) {
+            panic!("scheduled devices aren't ticked");
+        }
+
+        fn scheduled(&self) -> bool {
+            true
+        }
+
+        fn next_event(&self) -> Option<u64> {
+            self.next
+        }
+
+        fn event(&mut self, cycle: u64) {
+            self.woken.push(cycle);
+            self.next = Some(cycle + self.period);
+        }
+
+        fn sync(&mut self, cycle: u64) {
+            self.now = cycle;
+        }
+    }
+
+    #[test]
+    fn scheduled_devices_wake_at_the_cycles_they_ask_for() {
+        let timer = Rc::new(RefCell::new(Timer::default()));
+        let mut memory = Memory::default();
+        memory.map_device(0xD000, 1, timer.clone()).unwrap();
+
+        memory.tick(100);
+        assert!(timer.borrow().woken.is_empty());
+
+        memory.set_cycle(5);
+        memory.write_byte(0xD000, 10);
+        memory.tick(4);
+        assert!(timer.borrow().woken.is_empty());
+        memory.tick(23);
+        assert_eq!(timer.borrow().woken, [15, 25]);
+
+        // restarting moves the next event, the one queued before is dropped
+        memory.write_byte(0xD000, 3);
+        memory.tick(3);
+        assert_eq!(timer.borrow().woken, [15, 25, 35]);
+    }
+
+    #[test]
+    fn words_wrap_around_the_address_space() {
+        let mut memory = Memory::default();
+        memory.write_word(0xFFFF, 0xBEEF);
+        assert_eq!(memory.data[0xFFFF], 0xEF);
+        assert_eq!(memory.data[0x0000], 0xBE);
+        assert_eq!(memory.read_word(0xFFFF), 0xBEEF);
+    }
+
+    #[test]
+    fn find_locates_patterns_including_roms() {
+        let mut memory = Memory::default();
+        memory.write_bytes(0x0200, b"HELLO").unwrap();
+        memory.write_bytes(0x0300, b"HELLO").unwrap();
+        memory.map_rom(0xFFFC, RomImage::Static(&ROM)).unwrap();
+
+        assert_eq!(memory.find(b"HELLO"), vec![0x0200, 0x0300]);
+        assert_eq!(memory.find(&[0xBE, 0xEF]), vec![0xFFFE]);
+        assert!(memory.find(&[]).is_empty());
+
+        // a search leaves devices alone
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        memory.map_device(0xD000, 2, latch.clone()).unwrap();
+        memory.find(b"HELLO");
+        assert_eq!(latch.borrow().reads, 0);
+    }
+
+    #[test]
+    fn fill_sets_a_range_and_skips_roms() {
+        let mut memory = Memory::default();
+        memory.map_rom(0x0104, RomImage::Static(&ROM)).unwrap();
+        memory.fill(0x0100..0x0108, 0xAA).unwrap();
+
+        assert_eq!(memory.data[0x0100..0x0104], [0xAA; 4]);
+        assert_eq!(memory.data[0x0104..0x0108], [0; 4]);
+        assert_eq!(memory.read_byte(0x0104), 0xDE);
+        assert_eq!(memory.data[0x0108], 0);
+        assert!(memory.fill(0xFFF0..0x10001, 0).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_ram_outlasts_the_memory() {
+        let path = std::env::temp_dir().join("cpu_emu_mapped_ram.bin");
+        let _ = std::fs::remove_file(&path);
+
+        let mut memory = Memory::default();
+        memory.write_byte(0x0200, 0x42);
+        memory.map_ram_file(&path).unwrap();
+        memory.write_byte(0x0201, 0x43);
+        let copy = memory.clone();
+        memory.flush_ram().unwrap();
+        drop(memory);
+
+        let mut memory = Memory::default();
+        memory.map_ram_file(&path).unwrap();
+        assert_eq!(memory.data[0x0200..0x0202], [0x42, 0x43]);
+        assert!(matches!(copy.data, Ram::Heap(_)));
+
+        std::fs::write(&path, [0; 10]).unwrap();
+        assert!(Memory::default().map_ram_file(&path).is_err());
+    }
+}
@@ -0,0 +1,1280 @@
+use std::{
+    cell::RefCell,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    assembler,
+    call_stack::{CallStack, FrameKind},
+    cpu::{Cpu, CpuError},
+    disassembler,
+    op_codes::{BRK, JSR, RTI, RTS},
+    patch::Patches,
+    register_break::RegisterBreak,
+    save_state::SaveState,
+    session::{Session, WatchKind, Watcher},
+};
+
+const HELP: &str = "\
+commands:
+  a [addr]        assemble lines into memory starting at addr (blank line to finish)
+  m <addr> [len]  dump memory
+  e [addr]        edit memory from addr: hex bytes or \"text\" overwrite at the cursor,
+                  + [n] and - [n] move it, @addr jumps (blank line to finish)
+  f <start> <end> <byte>
+                  fill memory from start to end inclusive with a byte
+  h <bytes|\"text\">
+                  hunt for a sequence of hex bytes or a quoted string
+  patch <addr> <bytes|\"text\">
+                  overwrite memory, keeping the bytes replaced, a bare patch lists them
+  unpatch [addr]  put back the bytes under the patch at addr, or under every patch
+  r               show registers
+  st [len]        show the stack above SP, marking return addresses of calls
+                  made since the first st
+  hist            show the last instructions run, oldest first
+  zp              show the zero page, as named variables when labels are set
+  s               execute a single instruction
+  skip            move the pc past the current instruction without running it
+  n               like s, but run a JSR until the subroutine returns (also next)
+  ret             run until the current subroutine returns (also finish)
+  g [addr]        run until the cpu halts or reaches a breakpoint or watchpoint
+  break [exec|load|store] <addr> [end]
+                  set a breakpoint, or a watchpoint with load/store (also b, bk)
+  tbreak <addr>   set a breakpoint removed once it's hit (also tb)
+  sbreak <addr>   set a software breakpoint, a BRK over the opcode until it's hit, so g
+                  runs at full speed up to it (also sb)
+  until <addr>    run until the pc reaches addr, or something else stops it (also un)
+  watch [load|store] <addr> [end]
+                  set a watchpoint on loads, stores or both (also w)
+  rb [cond]       break when a register or flag changes, e.g. rb d set, rb sp < 20,
+                  rb a = 42 or rb x changed, a bare rb lists them
+  drb             delete all register breakpoints
+  del [addr]      delete the checkpoints at addr, or all of them
+  al <addr> .name add a label, usable wherever an address is expected
+  dl .name        delete a label
+  shl             show labels
+  ss <file>       save breakpoints, watchpoints and labels as VICE commands
+  ls <file>       play back a session or VICE command file (also pb, ll)
+  dump <file>     save the registers, counters and RAM as a save state
+  undump <file>   load a save state back
+  q               quit
+
+addresses are hex with an optional $ or VICE C: prefix";
+
+/// commands tab completes, with their longer aliases
+const COMMANDS: &[&str] = &[
+    "a",
+    "add_label",
+    "al",
+    "b",
+    "bk",
+    "break",
+    "del",
+    "delete",
+    "delete_label",
+    "dl",
+    "drb",
+    "dump",
+    "e",
+    "edit",
+    "f",
+    "finish",
+    "g",
+    "h",
+    "help",
+    "hist",
+    "ll",
+    "load_labels",
+    "load_session",
+    "ls",
+    "m",
+    "n",
+    "next",
+    "patch",
+    "pb",
+    "playback",
+    "q",
+    "r",
+    "rb",
+    "ret",
+    "return",
+    "s",
+    "save_session",
+    "sb",
+    "sbreak",
+    "shl",
+    "show_labels",
+    "skip",
+    "ss",
+    "st",
+    "stack",
+    "tb",
+    "tbreak",
+    "un",
+    "undump",
+    "unpatch",
+    "until",
+    "w",
+    "watch",
+    "zp",
+];
+
+/// file in the home directory the monitor's history is kept in between runs
+#[cfg(feature = "readline")]
+const HISTORY_FILE: &str = ".cpu_emu_history";
+
+/// what the monitor's line editor completes words from, taken from the monitor
+/// before each line is read
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Completions {
+    labels: Vec<String>,
+    /// the pc, checkpoints and labelled addresses
+    addresses: Vec<u16>,
+}
+
+impl Completions {
+    /// where the word before `pos` starts and the ways it can be completed,
+    /// commands first on a line, `.labels` and hex addresses after
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before
+            .rfind(char::is_whitespace)
+            .map_or(0, |space| space + 1);
+        let word = &before[start..];
+        if before[..start].trim().is_empty() {
+            let commands = COMMANDS.iter().filter(|command| command.starts_with(word));
+            return (start, commands.map(|command| command.to_string()).collect());
+        }
+        if word.is_empty() {
+            return (start, Vec::new());
+        }
+
+        if let Some(prefix) = word.strip_prefix('.') {
+            let labels = self.labels.iter().filter(|label| label.starts_with(prefix));
+            return (start, labels.map(|label| format!(".{label}")).collect());
+        }
+        let (sigil, digits) = ["$", "C:", "c:"]
+            .iter()
+            .find_map(|sigil| Some((*sigil, word.strip_prefix(sigil)?)))
+            .unwrap_or(("", word));
+        if !digits.chars().all(|digit| digit.is_ascii_hexdigit()) {
+            return (start, Vec::new());
+        }
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|address| format!("{address:04X}"))
+            .filter(|address| address.starts_with(&digits.to_ascii_uppercase()))
+            .map(|address| format!("{sigil}{address}"));
+        (start, addresses.collect())
+    }
+}
+
+/// interactive machine monitor for inspecting and modifying a cpu
+pub struct Monitor {
+    pub cpu: Cpu,
+    /// address the next assembled line is written to when in assemble mode
+    assemble_address: Option<u16>,
+    /// the cursor of the memory editor while editing
+    edit_address: Option<u16>,
+    /// breakpoints, watchpoints and labels
+    pub session: Session,
+    /// subscribed to the cpu once the first watchpoint is set
+    watcher: Option<Rc<RefCell<Watcher>>>,
+    /// calls and interrupts that haven't returned, to find return addresses on
+    /// the stack, subscribed the first time the stack is viewed so running
+    /// from the monitor can use the block cache until then
+    call_stack: Option<Rc<RefCell<CallStack>>>,
+    /// bytes patched from the monitor and what they replaced
+    pub patches: Patches,
+}
+
+impl Monitor {
+    /// create a monitor attached to a cpu
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            cpu,
+            assemble_address: None,
+            edit_address: None,
+            session: Session::default(),
+            watcher: None,
+            call_stack: None,
+            patches: Patches::default(),
+        }
+    }
+
+    /// read commands until the user quits or input ends, with history and
+    /// tab completion when the terminal supports them
+    pub fn run(&mut self) {
+        #[cfg(feature = "readline")]
+        if self.run_line_editor().is_ok() {
+            return;
+        }
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("{}", self.prompt());
+            io::stdout().flush().ok();
+
+            match lines.next() {
+                Some(Ok(line)) if self.handle(&line) => {}
+                _ => break,
+            }
+        }
+    }
+
+    /// read commands with rustyline, keeping history in the home directory
+    #[cfg(feature = "readline")]
+    fn run_line_editor(&mut self) -> rustyline::Result<()> {
+        use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
+
+        let mut editor: Editor<LineHelper, DefaultHistory> = Editor::new()?;
+        let history = std::env::var_os("HOME")
+            .map(|home| Path::new(&home).join(HISTORY_FILE))
+            .unwrap_or_else(|| HISTORY_FILE.into());
+        // there's no history the first time
+        editor.load_history(&history).ok();
+
+        loop {
+            editor.set_helper(Some(LineHelper(self.completions())));
+            match editor.readline(&self.prompt()) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        editor.add_history_entry(line.as_str())?;
+                    }
+                    if !self.handle(&line) {
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if let Err(err) = editor.save_history(&history) {
+            println!("failed to save history to {}: {err}", history.display());
+        }
+        Ok(())
+    }
+
+    /// the labels and addresses tab completes from
+    pub fn completions(&self) -> Completions {
+        let mut addresses: Vec<u16> = self
+            .session
+            .breakpoints
+            .iter()
+            .chain(&self.session.temporary)
+            .chain(self.session.watchpoints.keys())
+            .chain(self.session.labels.values())
+            .copied()
+            .chain([self.cpu.pc()])
+            .collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        Completions {
+            labels: self.session.labels.keys().cloned().collect(),
+            addresses,
+        }
+    }
+
+    /// prompt to show, the current address while assembling or editing
+    pub fn prompt(&self) -> String {
+        match (self.assemble_address, self.edit_address) {
+            (Some(address), _) => format!("${address:04X}: "),
+            (None, Some(address)) => format!("e ${address:04X}: "),
+            (None, None) => "> ".to_string(),
+        }
+    }
+
+    /// handle a single line of input
+    /// returns false when the monitor should exit
+    pub fn handle(&mut self, line: &str) -> bool {
+        if let Some(address) = self.assemble_address {
+            self.assemble(address, line);
+            return true;
+        }
+        if let Some(address) = self.edit_address {
+            self.edit(address, line);
+            return true;
+        }
+
+        let mut args = line.split_whitespace();
+        match args.next() {
+            Some("a") => {
+                let address = args
+                    .next()
+                    .and_then(|arg| self.address(arg))
+                    .unwrap_or(self.cpu.pc());
+                self.assemble_address = Some(address);
+            }
+            Some("e" | "edit") => {
+                let address = args
+                    .next()
+                    .and_then(|arg| self.address(arg))
+                    .unwrap_or(self.cpu.pc());
+                self.edit_address = Some(address);
+                print!("{}", self.edit_view(address));
+            }
+            Some("m") => match args.next().and_then(|arg| self.address(arg)) {
+                Some(address) => {
+                    let len = args.next().and_then(parse_hex).unwrap_or(0x40);
+                    self.dump(address, len);
+                }
+                None => println!("usage: m <addr> [len]"),
+            },
+            Some("f") => {
+                let args: Option<Vec<u16>> = args.map(parse_hex).collect();
+                match args.as_deref() {
+                    Some(&[start, end, value]) if start <= end && value <= 0xFF => {
+                        let range = start as usize..end as usize + 1;
+                        self.cpu.invalidate_block_cache();
+                        if let Err(err) = self.cpu.memory.fill(range, value as u8) {
+                            println!("error: {err}");
+                        }
+                    }
+                    _ => println!("usage: f <start> <end> <byte>"),
+                }
+            }
+            Some("h") => match parse_pattern(line.trim_start()[1..].trim()) {
+                Some(pattern) if !pattern.is_empty() => {
+                    let found: Vec<String> = self
+                        .cpu
+                        .memory
+                        .find(&pattern)
+                        .iter()
+                        .map(|address| format!("${address:04X}"))
+                        .collect();
+                    if found.is_empty() {
+                        println!("not found");
+                    } else {
+                        println!("{}", found.join(" "));
+                    }
+                }
+                _ => println!("usage: h <bytes|\"text\">"),
+            },
+            Some("patch") => {
+                let address = args.next().map(|arg| self.address(arg));
+                let rest = line.trim_start().splitn(3, char::is_whitespace).nth(2);
+                match (address, rest.and_then(|rest| parse_pattern(rest.trim()))) {
+                    (None, _) => {
+                        for (address, original) in self.patches.iter() {
+                            let bytes = (0..original.len() as u16).map(|i| {
+                                self.cpu.memory.peek_byte(address.wrapping_add(i) as usize)
+                            });
+                            println!("${address:04X}  {} was {}", hex(bytes), hex(original));
+                        }
+                    }
+                    (Some(Some(address)), Some(bytes)) if !bytes.is_empty() => {
+                        self.patches.apply(&mut self.cpu.memory, address, &bytes)
+                    }
+                    _ => println!("usage: patch <addr> <bytes|\"text\">"),
+                }
+            }
+            Some("unpatch") => match args.next().map(|arg| self.address(arg)) {
+                Some(Some(address)) => {
+                    if !self.patches.revert(&mut self.cpu.memory, address) {
+                        println!("no patch at ${address:04X}");
+                    }
+                }
+                Some(None) => println!("usage: unpatch [addr]"),
+                None => self.patches.revert_all(&mut self.cpu.memory),
+            },
+            Some("r") => println!("{}", self.cpu),
+            Some("st" | "stack") => {
+                let len = args.next().and_then(parse_hex).unwrap_or(0x10);
+                print!("{}", self.stack_view(len));
+                if let (Some(low), Some(free)) =
+                    (self.cpu.stack_high_water(), self.cpu.stack_headroom())
+                {
+                    println!("high-water ${low:04X}, {free} bytes of page 1 free below it");
+                }
+            }
+            Some("hist") => print!("{}", self.cpu.history()),
+            Some("zp") => print!("{}", self.zero_page_view()),
+            Some("s") => {
+                if let Err(err) = self.cpu.step() {
+                    println!("error: {err}");
+                }
+                println!("{}", self.cpu);
+            }
+            Some("skip") => {
+                let next = disassembler::disassemble(&self.cpu.memory, self.cpu.pc()).next();
+                self.cpu.set_pc(next);
+                println!("{}", self.cpu);
+            }
+            Some("n" | "next") => {
+                self.next();
+                println!("{}", self.cpu);
+            }
+            Some("ret" | "return" | "finish") => {
+                self.finish();
+                println!("{}", self.cpu);
+            }
+            Some("g") => {
+                if let Some(address) = args.next().and_then(|arg| self.address(arg)) {
+                    self.cpu.set_pc(address);
+                }
+                self.go();
+                println!("{}", self.cpu);
+            }
+            Some("b" | "bk" | "break") => self.checkpoint(&args.collect::<Vec<_>>(), false),
+            Some("w" | "watch") => self.checkpoint(&args.collect::<Vec<_>>(), true),
+            Some("tb" | "tbreak") => match args.next().and_then(|arg| self.address(arg)) {
+                Some(address) => {
+                    self.session.temporary.insert(address);
+                }
+                None => println!("usage: tbreak <addr>"),
+            },
+            Some("sb" | "sbreak") => match args.next().and_then(|arg| self.address(arg)) {
+                Some(address) => {
+                    if !self.cpu.set_soft_breakpoint(address) {
+                        println!("can't write a BRK at ${address:04X}");
+                    }
+                }
+                None => println!("usage: sbreak <addr>"),
+            },
+            Some("un" | "until") => match args.next().and_then(|arg| self.address(arg)) {
+                Some(address) => {
+                    // only for this run, not left behind if something else stops it
+                    let added = self.session.temporary.insert(address);
+                    self.go();
+                    if added {
+                        self.session.temporary.remove(&address);
+                    }
+                    println!("{}", self.cpu);
+                }
+                None => println!("usage: until <addr>"),
+            },
+            Some("rb") => {
+                let condition = line.trim_start()[2..].trim();
+                if condition.is_empty() {
+                    for condition in self.cpu.register_breaks() {
+                        println!("rb {condition}");
+                    }
+                } else {
+                    match RegisterBreak::parse(condition) {
+                        Some(condition) => self.cpu.add_register_break(condition),
+                        None => println!("usage: rb <reg> <changed|set|clear|= n|< n|> n>"),
+                    }
+                }
+            }
+            Some("drb") => self.cpu.clear_register_breaks(),
+            Some("del" | "delete") => {
+                match args.next().map(|arg| self.address(arg)) {
+                    Some(Some(address)) => {
+                        self.session.breakpoints.remove(&address);
+                        self.session.temporary.remove(&address);
+                        self.cpu.clear_soft_breakpoint(address);
+                        self.session.watchpoints.remove(&address);
+                    }
+                    Some(None) => println!("usage: del [addr]"),
+                    None => {
+                        self.session.breakpoints.clear();
+                        self.session.temporary.clear();
+                        self.cpu.clear_soft_breakpoints();
+                        self.session.watchpoints.clear();
+                    }
+                }
+                self.sync_watcher();
+            }
+            Some("al" | "add_label") => {
+                let address = args.next().and_then(|arg| self.address(arg));
+                match (address, args.next()) {
+                    (Some(address), Some(name)) => {
+                        let name = name.trim_start_matches('.').to_string();
+                        self.session.labels.insert(name, address);
+                    }
+                    _ => println!("usage: al <addr> .name"),
+                }
+            }
+            Some("dl" | "delete_label") => match args.next() {
+                Some(name) => {
+                    self.session.labels.remove(name.trim_start_matches('.'));
+                }
+                None => println!("usage: dl .name"),
+            },
+            Some("shl" | "show_labels") => {
+                let mut labels: Vec<_> = self.session.labels.iter().collect();
+                labels.sort_by_key(|(_, address)| **address);
+                for (name, address) in labels {
+                    println!("${address:04X}  .{name}");
+                }
+            }
+            Some("ss" | "save_session") => match args.next() {
+                Some(path) => {
+                    if let Err(err) = self.session.save(Path::new(path)) {
+                        println!("error: {err}");
+                    }
+                }
+                None => println!("usage: ss <file>"),
+            },
+            Some("ls" | "load_session" | "pb" | "playback" | "ll" | "load_labels") => {
+                match args.next() {
+                    Some(path) => self.playback(Path::new(path)),
+                    None => println!("usage: ls <file>"),
+                }
+            }
+            Some("dump") => match args.next() {
+                Some(path) => {
+                    if let Err(err) = SaveState::capture(&mut self.cpu).save(Path::new(path)) {
+                        println!("error: {err}");
+                    }
+                }
+                None => println!("usage: dump <file>"),
+            },
+            Some("undump") => match args.next().map(|path| SaveState::load(Path::new(path))) {
+                Some(Ok(state)) => {
+                    state.restore(&mut self.cpu);
+                    println!("{}", self.cpu);
+                }
+                Some(Err(err)) => println!("error: {err}"),
+                None => println!("usage: undump <file>"),
+            },
+            Some("q") => return false,
+            Some("?") | Some("help") => println!("{HELP}"),
+            Some(command) => println!("unknown command {command}, ? for help"),
+            None => {}
+        }
+        true
+    }
+
+    /// run until the cpu halts, stepping one instruction at a time while
+    /// checkpoints are set so they can stop it
+    fn go(&mut self) {
+        if !self.session.has_checkpoints() && self.cpu.register_breaks().is_empty() {
+            if let Err(err) = self.cpu.execute() {
+                report_stop(err);
+            }
+            return;
+        }
+
+        self.run_until(|_, _| false);
+    }
+
+    /// step over a subroutine call, running it until it returns to the next instruction
+    fn next(&mut self) {
+        let pc = self.cpu.pc();
+        if self.cpu.memory.read_byte(pc as usize) != JSR {
+            if let Err(err) = self.cpu.step() {
+                println!("error: {err}");
+            }
+            return;
+        }
+
+        // a recursive call can pass through the return address, so it only
+        // counts once the stack is back where it was
+        let return_address = pc.wrapping_add(3);
+        let sp = self.cpu.sp();
+        self.run_until(|cpu, _| cpu.pc() == return_address && cpu.sp() >= sp);
+    }
+
+    /// run until the subroutine being executed returns, following nested calls
+    fn finish(&mut self) {
+        let mut depth = 0u32;
+        self.run_until(|_, opcode| match opcode {
+            JSR | BRK => {
+                depth += 1;
+                false
+            }
+            RTS | RTI if depth == 0 => true,
+            RTS | RTI => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        });
+    }
+
+    /// step until the cpu halts, reaches a checkpoint or `done` returns true
+    /// `done` is given the cpu and the opcode of each instruction after it executes
+    fn run_until(&mut self, mut done: impl FnMut(&Cpu, u8) -> bool) {
+        if let Some(watcher) = &self.watcher {
+            watcher.borrow_mut().hit = None;
+        }
+        loop {
+            let opcode = self.cpu.memory.read_byte(self.cpu.pc() as usize);
+            match self.cpu.step() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    report_stop(err);
+                    break;
+                }
+            }
+
+            let hit = self
+                .watcher
+                .as_ref()
+                .and_then(|watcher| watcher.borrow_mut().hit.take());
+            if let Some(address) = hit {
+                println!("watchpoint ${address:04X} hit");
+                break;
+            }
+            if let Some(condition) = self.cpu.take_register_break() {
+                println!("register break {condition} hit");
+                break;
+            }
+            if self.session.breaks_at(self.cpu.pc()) {
+                println!("breakpoint ${:04X} hit", self.cpu.pc());
+                break;
+            }
+            if done(&self.cpu, opcode) {
+                break;
+            }
+        }
+    }
+
+    /// handle the arguments of `break` or `watch` in VICE syntax
+    /// a bare `break` or `watch` lists the checkpoints
+    fn checkpoint(&mut self, args: &[&str], watch: bool) {
+        let (kind, args) = match args.split_first() {
+            Some((&"exec", rest)) => (None, rest),
+            Some((&"load", rest)) => (Some(WatchKind::Load), rest),
+            Some((&"store", rest)) => (Some(WatchKind::Store), rest),
+            _ => (watch.then_some(WatchKind::Any), args),
+        };
+        if args.is_empty() {
+            self.list_checkpoints();
+            return;
+        }
+
+        let addresses: Option<Vec<u16>> = args.iter().map(|arg| self.address(arg)).collect();
+        let (start, end) = match addresses.as_deref() {
+            Some(&[address]) => (address, address),
+            Some(&[start, end]) if start <= end => (start, end),
+            _ => {
+                println!("usage: break [exec|load|store] <addr> [end]");
+                return;
+            }
+        };
+        for address in start..=end {
+            match kind {
+                Some(kind) => {
+                    self.session.watchpoints.insert(address, kind);
+                }
+                None => {
+                    self.session.breakpoints.insert(address);
+                }
+            }
+        }
+        self.sync_watcher();
+    }
+
+    fn list_checkpoints(&self) {
+        for address in &self.session.breakpoints {
+            println!("break  ${address:04X}");
+        }
+        for address in &self.session.temporary {
+            println!("tbreak ${address:04X}");
+        }
+        for address in self.cpu.soft_breakpoints() {
+            println!("sbreak ${address:04X}");
+        }
+        for (address, kind) in &self.session.watchpoints {
+            let kind = match kind {
+                WatchKind::Load => "load",
+                WatchKind::Store => "store",
+                WatchKind::Any => "load store",
+            };
+            println!("watch  ${address:04X} {kind}");
+        }
+    }
+
+    /// hand the current watchpoints to the observer on the cpu,
+    /// subscribing it the first time one is set
+    fn sync_watcher(&mut self) {
+        if self.watcher.is_none() && self.session.watchpoints.is_empty() {
+            return;
+        }
+        let watcher = self.watcher.get_or_insert_with(|| {
+            let watcher = Rc::new(RefCell::new(Watcher::default()));
+            self.cpu.subscribe(watcher.clone());
+            watcher
+        });
+        watcher.borrow_mut().watchpoints = self.session.watchpoints.clone();
+    }
+
+    /// run every line of a command file, skipping blank lines and comments
+    fn playback(&mut self, path: &Path) {
+        let script = match fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(err) => {
+                println!("error: {err}");
+                return;
+            }
+        };
+        for line in script.lines().map(str::trim) {
+            if !line.is_empty() && !line.starts_with(';') && !line.starts_with('#') {
+                self.handle(line);
+            }
+        }
+    }
+
+    /// parse an address argument, a `.label` or hex with an optional `$` or `C:` prefix
+    fn address(&self, arg: &str) -> Option<u16> {
+        match arg.strip_prefix('.') {
+            Some(label) => self.session.labels.get(label).copied(),
+            None => parse_hex(
+                arg.strip_prefix("C:")
+                    .or_else(|| arg.strip_prefix("c:"))
+                    .unwrap_or(arg),
+            ),
+        }
+    }
+
+    /// assemble a line into memory at the cursor, a blank line leaves assemble mode
+    fn assemble(&mut self, address: u16, line: &str) {
+        if line.trim().is_empty() {
+            self.assemble_address = None;
+            return;
+        }
+
+        match assembler::assemble_line_at(line, address) {
+            Ok(bytes) => {
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                println!("${address:04X}  {:<9} {}", hex.join(" "), line.trim());
+
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let target = address.wrapping_add(offset as u16);
+                    self.cpu.memory.write_byte(target as usize, *byte);
+                }
+                self.assemble_address = Some(address.wrapping_add(bytes.len() as u16));
+            }
+            Err(err) => println!("error: {err}"),
+        }
+    }
+
+    /// handle a line of the memory editor, a blank line leaves it
+    fn edit(&mut self, address: u16, line: &str) {
+        let line = line.trim();
+        let mut words = line.split_whitespace();
+        let cursor = match words.next() {
+            None => {
+                self.edit_address = None;
+                return;
+            }
+            Some(jump) if jump.starts_with('@') => self.address(&jump[1..]),
+            Some(step @ ("+" | "-")) => {
+                let n = words.next().map_or(Some(0x10), parse_hex);
+                n.map(|n| match step {
+                    "+" => address.wrapping_add(n),
+                    _ => address.wrapping_sub(n),
+                })
+            }
+            Some(_) => parse_pattern(line).map(|bytes| {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let target = address.wrapping_add(offset as u16);
+                    self.cpu.memory.write_byte(target as usize, *byte);
+                }
+                address.wrapping_add(bytes.len() as u16)
+            }),
+        };
+
+        match cursor {
+            Some(cursor) => {
+                self.edit_address = Some(cursor);
+                print!("{}", self.edit_view(cursor));
+            }
+            None => println!("error: expected hex bytes, \"text\", + [n], - [n] or @addr"),
+        }
+    }
+
+    /// the row of 16 bytes holding the editor's cursor, with the byte under it
+    /// bracketed
+    fn edit_view(&self, cursor: u16) -> String {
+        let start = cursor & 0xFFF0;
+        let mut out = format!("${start:04X} ");
+        for address in start..=start | 0x000F {
+            let value = self.cpu.memory.read_byte(address as usize);
+            if address == cursor {
+                write!(out, "[{value:02X}]").unwrap();
+            } else if address == cursor.wrapping_add(1) {
+                write!(out, "{value:02X}").unwrap();
+            } else {
+                write!(out, " {value:02X}").unwrap();
+            }
+        }
+        out.push('\n');
+        out
+    }
+
+    /// up to `len` bytes above the stack pointer, within page 1, with the
+    /// return addresses of pending calls and interrupts marked, calls made
+    /// before the stack was first viewed aren't known
+    fn stack_view(&mut self, len: u16) -> String {
+        let sp = self.cpu.sp();
+        let call_stack = self.call_stack.get_or_insert_with(|| {
+            let call_stack = Rc::new(RefCell::new(CallStack::default()));
+            self.cpu.subscribe(call_stack.clone());
+            call_stack
+        });
+        let calls = call_stack.borrow();
+        let mut out = String::new();
+        for offset in 1..=len {
+            let Some(address) = sp.checked_add(offset).filter(|address| *address <= 0x01FF) else {
+                break;
+            };
+            let value = self.cpu.memory.read_byte(address as usize);
+            write!(out, "${address:04X}  SP+{offset:<3} {value:02X}").unwrap();
+            if let Some(frame) = calls.frame_at(address) {
+                let kind = match frame.kind {
+                    FrameKind::Subroutine => "JSR",
+                    FrameKind::Interrupt => "interrupt",
+                };
+                write!(
+                    out,
+                    "  <- {kind} returning to ${:04X}",
+                    frame.return_address
+                )
+                .unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// the zero page as labelled variables, or a plain dump when none are labelled
+    fn zero_page_view(&self) -> String {
+        let mut variables: Vec<(u16, &str)> = self
+            .session
+            .labels
+            .iter()
+            .filter(|(_, address)| **address < 0x100)
+            .map(|(name, address)| (*address, name.as_str()))
+            .collect();
+        variables.sort();
+
+        let mut out = String::new();
+        if variables.is_empty() {
+            for row in (0..0x100).step_by(16) {
+                let hex: Vec<String> = (row..row + 16)
+                    .map(|address| format!("{:02X}", self.cpu.memory.read_byte(address)))
+                    .collect();
+                writeln!(out, "${row:02X}  {}", hex.join(" ")).unwrap();
+            }
+            return out;
+        }
+
+        for (address, name) in variables {
+            let value = self.cpu.memory.read_byte(address as usize);
+            writeln!(out, "${address:02X}  .{name:<16} ${value:02X}  {value}").unwrap();
+        }
+        out
+    }
+
+    /// print `len` bytes of memory starting at `address`, 16 per row
+    fn dump(&self, address: u16, len: u16) {
+        let start = address as usize;
+        let end = (start + len as usize).min(self.cpu.memory.data.len());
+        let bytes: Vec<u8> = (start..end)
+            .map(|address| self.cpu.memory.read_byte(address))
+            .collect();
+
+        for (row, chunk) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02X}")).collect();
+            println!("${:04X}  {}", start + row * 16, hex.join(" "));
+        }
+    }
+}
+
+/// rustyline's view of the monitor, only completion is customised
+#[cfg(feature = "readline")]
+struct LineHelper(Completions);
+
+#[cfg(feature = "readline")]
+impl rustyline::completion::Completer for LineHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.0.complete(line, pos))
+    }
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::hint::Hinter for LineHelper {
+    type Hint = String;
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::highlight::Highlighter for LineHelper {}
+
+#[cfg(feature = "readline")]
+impl rustyline::validate::Validator for LineHelper {}
+
+#[cfg(feature = "readline")]
+impl rustyline::Helper for LineHelper {}
+
+/// say why running stopped, a software breakpoint isn't an error
+fn report_stop(err: CpuError) {
+    match err {
+        CpuError::SoftBreakpoint { .. } => println!("{err}"),
+        err => println!("error: {err}"),
+    }
+}
+
+/// parse a hex number with an optional `$` prefix
+fn parse_hex(value: &str) -> Option<u16> {
+    u16::from_str_radix(value.trim_start_matches('$'), 16).ok()
+}
+
+/// bytes as hex pairs separated by spaces
+fn hex(bytes: impl IntoIterator<Item = u8>) -> String {
+    let hex: Vec<String> = bytes
+        .into_iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect();
+    hex.join(" ")
+}
+
+/// parse a search pattern, either a quoted string or whitespace separated hex bytes
+fn parse_pattern(value: &str) -> Option<Vec<u8>> {
+    if let Some(text) = value.strip_prefix('"') {
+        return Some(text.strip_suffix('"').unwrap_or(text).as_bytes().to_vec());
+    }
+    value
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte.trim_start_matches('$'), 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn assemble_mode_writes_to_memory_and_advances() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+
+        monitor.handle("a 0600");
+        assert_eq!(monitor.prompt(), "$0600: ");
+
+        monitor.handle("LDA #$42");
+        monitor.handle("TAX");
+        assert_eq!(monitor.prompt(), "$0603: ");
+
+        monitor.handle("");
+        assert_eq!(monitor.prompt(), "> ");
+
+        assert_eq!(monitor.cpu.memory.data[0x0600..0x0603], [LDA_IM, 0x42, TAX]);
+    }
+
+    #[test]
+    fn edit_mode_overwrites_and_moves_the_cursor() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.handle("al 0210 .table");
+
+        monitor.handle("e 0200");
+        monitor.handle("A9 $42");
+        assert_eq!(monitor.prompt(), "e $0202: ");
+        assert_eq!(
+            monitor.edit_view(0x0202),
+            "$0200  A9 42[00]00 00 00 00 00 00 00 00 00 00 00 00 00\n"
+        );
+
+        monitor.handle("@.table");
+        monitor.handle("\"HI\"");
+        monitor.handle("- 4");
+        assert_eq!(monitor.prompt(), "e $020E: ");
+        monitor.handle("+");
+        monitor.handle("bogus");
+        assert_eq!(monitor.prompt(), "e $021E: ");
+
+        monitor.handle("");
+        assert_eq!(monitor.prompt(), "> ");
+        assert_eq!(monitor.cpu.memory.data[0x0210..0x0212], *b"HI");
+    }
+
+    #[test]
+    fn assemble_mode_keeps_cursor_on_error() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+
+        monitor.handle("a $0600");
+        monitor.handle("BOGUS");
+        assert_eq!(monitor.prompt(), "$0600: ");
+    }
+
+    #[test]
+    fn assemble_then_run() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+
+        monitor.handle("a 0600");
+        monitor.handle("LDA #$42");
+        monitor.handle("NOP");
+        monitor.handle("");
+        monitor.handle("g 0600");
+
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+    }
+
+    #[test]
+    fn tab_completes_commands_labels_and_addresses() {
+        let mut monitor = Monitor::new(Cpu::new().reset(Some(0x0600)));
+        monitor.handle("al 0610 .loop");
+        monitor.handle("al 0620 .load_byte");
+        monitor.handle("break 0604");
+        let completions = monitor.completions();
+
+        assert_eq!(
+            completions.complete("wa", 2),
+            (0, vec!["watch".to_string()])
+        );
+        assert_eq!(
+            completions.complete("break .lo", 9),
+            (6, vec![".load_byte".to_string(), ".loop".to_string()])
+        );
+        assert_eq!(
+            completions.complete("m $06", 5),
+            (
+                2,
+                ["$0600", "$0604", "$0610", "$0620"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+        assert_eq!(
+            completions.complete("del c:061", 9),
+            (4, vec!["c:0610".to_string()])
+        );
+        assert_eq!(completions.complete("g ", 2), (2, Vec::new()));
+    }
+
+    #[test]
+    fn undump_goes_back_to_the_dumped_state() {
+        let path = std::env::temp_dir().join("cpu_emu_monitor_dump.sav");
+        let mut monitor = Monitor::new(Cpu::new().reset(Some(0x0600)));
+        monitor.handle("f 0200 0201 42");
+        monitor.handle(&format!("dump {}", path.display()));
+        monitor.handle("f 0200 0201 00");
+        monitor.cpu.set_pc(0x0700);
+
+        monitor.handle(&format!("undump {}", path.display()));
+        assert_eq!(monitor.cpu.pc(), 0x0600);
+        assert_eq!(monitor.cpu.memory.read_byte(0x0201), 0x42);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn quit() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        assert!(!monitor.handle("q"));
+    }
+
+    #[test]
+    fn fill_command_writes_inclusive_range() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+
+        monitor.handle("f 0200 0203 $EA");
+        assert_eq!(
+            monitor.cpu.memory.data[0x0200..0x0205],
+            [NOP, NOP, NOP, NOP, 0]
+        );
+    }
+
+    #[test]
+    fn hunt_patterns() {
+        assert_eq!(parse_pattern("\"HI\""), Some(b"HI".to_vec()));
+        assert_eq!(parse_pattern("a9 $42"), Some(vec![LDA_IM, 0x42]));
+        assert_eq!(parse_pattern("a9 zz"), None);
+    }
+
+    #[test]
+    fn breakpoints_stop_go() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.cpu.memory.data[0x0600..0x0605].copy_from_slice(&[LDA_IM, 0x01, TAX, TAY, NOP]);
+
+        monitor.handle("al 0603 .here");
+        monitor.handle("bk .here");
+        monitor.handle("g 0600");
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+
+        monitor.handle("g");
+        assert_eq!(monitor.cpu.pc(), 0x0605);
+    }
+
+    #[test]
+    fn temporary_breakpoints_stop_once() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.cpu.memory.data[0x0600..0x0605].copy_from_slice(&[LDA_IM, 0x01, TAX, TAY, NOP]);
+
+        monitor.handle("tb 0602");
+        monitor.handle("g 0600");
+        assert_eq!(monitor.cpu.pc(), 0x0602);
+        monitor.handle("g 0600");
+        assert_eq!(monitor.cpu.pc(), 0x0605);
+
+        monitor.cpu.set_pc(0x0600);
+        monitor.handle("un 0603");
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+        assert!(monitor.session.temporary.is_empty());
+    }
+
+    #[test]
+    fn patches_are_reverted_and_instructions_skipped() {
+        let mut monitor = Monitor::new(Cpu::new().reset(Some(0x0600)));
+        monitor.cpu.memory.data[0x0600..0x0605].copy_from_slice(&[LDA_IM, 0x01, TAX, TAY, NOP]);
+
+        monitor.handle("skip");
+        assert_eq!(monitor.cpu.pc(), 0x0602);
+        monitor.handle("patch 0603 ea");
+        monitor.handle("patch 0600 \"AB\"");
+        assert_eq!(
+            monitor.cpu.memory.data[0x0600..0x0604],
+            [b'A', b'B', TAX, NOP]
+        );
+
+        monitor.handle("unpatch 0603");
+        assert_eq!(monitor.cpu.memory.data[0x0603], TAY);
+        monitor.handle("unpatch");
+        assert_eq!(monitor.cpu.memory.data[0x0600..0x0602], [LDA_IM, 0x01]);
+        assert!(monitor.patches.is_empty());
+    }
+
+    #[test]
+    fn software_breakpoints_stop_go() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.cpu.memory.data[0x0600..0x0605].copy_from_slice(&[LDA_IM, 0x01, TAX, TAY, NOP]);
+
+        monitor.handle("sb 0603");
+        monitor.handle("g 0600");
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+        assert_eq!(monitor.cpu.memory.data[0x0603], TAY);
+        monitor.handle("g");
+        assert_eq!(monitor.cpu.pc(), 0x0605);
+
+        monitor.handle("sb 0602");
+        monitor.handle("del");
+        assert_eq!(monitor.cpu.memory.data[0x0602], TAX);
+    }
+
+    #[test]
+    fn watchpoints_stop_go_on_matching_access() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.cpu.memory.data[0x0600..0x0607]
+            .copy_from_slice(&[LDA_ABS, 0x00, 0x02, PHA, TAX, TAY, NOP]);
+
+        monitor.handle("watch store C:0100");
+        monitor.handle("g 0600");
+        assert_eq!(monitor.cpu.pc(), 0x0604);
+
+        monitor.handle("del");
+        monitor.handle("break load $0200");
+        monitor.handle("g 0600");
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+    }
+
+    #[test]
+    fn register_breaks_stop_go() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.cpu.memory.data[0x0600..0x0605].copy_from_slice(&[LDA_IM, 0x42, TAX, TAY, NOP]);
+
+        monitor.handle("rb x = $42");
+        monitor.handle("g 0600");
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+
+        monitor.handle("drb");
+        monitor.handle("g");
+        assert_eq!(monitor.cpu.pc(), 0x0605);
+    }
+
+    #[test]
+    fn session_round_trips_through_file() {
+        let path = std::env::temp_dir().join("cpu_emu_monitor_session.txt");
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.handle("al C:0600 .start");
+        monitor.handle("break .start");
+        monitor.handle("watch load 0200 0201");
+        monitor.handle(&format!("ss {}", path.display()));
+
+        let mut restored = Monitor::new(Cpu::new().reset(None));
+        restored.handle(&format!("ls {}", path.display()));
+        assert_eq!(restored.session, monitor.session);
+        assert_eq!(restored.session.watchpoints.len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// JSR $0700 / LDX #$01 / NOP at $0600, the subroutine calls a nested
+    /// one at $0780
+    fn call_tree() -> Monitor {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        let data = &mut monitor.cpu.memory.data;
+        data[0x0600..0x0606].copy_from_slice(&[JSR, 0x00, 0x07, LDX_IM, 0x01, NOP]);
+        data[0x0700..0x0706].copy_from_slice(&[LDA_IM, 0x05, JSR, 0x80, 0x07, RTS]);
+        data[0x0780..0x0782].copy_from_slice(&[TAY, RTS]);
+        monitor.cpu.set_pc(0x0600);
+        monitor.cpu.set_sp(0x01FF);
+        monitor
+    }
+
+    #[test]
+    fn next_steps_over_subroutine_calls() {
+        let mut monitor = call_tree();
+
+        monitor.handle("n");
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+        assert_eq!(monitor.cpu.y(), 0x05);
+        assert_eq!(monitor.cpu.sp(), 0x01FF);
+
+        monitor.handle("next");
+        assert_eq!(monitor.cpu.pc(), 0x0605);
+        assert_eq!(monitor.cpu.x(), 0x01);
+    }
+
+    #[test]
+    fn finish_runs_until_the_subroutine_returns() {
+        let mut monitor = call_tree();
+
+        monitor.handle("s");
+        monitor.handle("s");
+        assert_eq!(monitor.cpu.pc(), 0x0702);
+
+        // steps out past the nested call, back to the instruction after the JSR
+        monitor.handle("finish");
+        assert_eq!(monitor.cpu.pc(), 0x0603);
+        assert_eq!(monitor.cpu.sp(), 0x01FF);
+        assert_eq!(monitor.cpu.y(), 0x05);
+        assert_eq!(monitor.cpu.x(), 0x00);
+
+        monitor.handle("s");
+        assert_eq!(monitor.cpu.x(), 0x01);
+    }
+
+    #[test]
+    fn stack_view_marks_return_addresses() {
+        let mut monitor = call_tree();
+        assert!(monitor.call_stack.is_none());
+        // calls are tracked from the first time the stack is viewed
+        monitor.handle("st");
+        monitor.handle("s");
+        monitor.handle("s");
+        monitor.handle("s");
+
+        let view = monitor.stack_view(4);
+        let lines: Vec<&str> = view.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "$01FC  SP+1   04  <- JSR returning to $0705");
+        assert_eq!(lines[1], "$01FD  SP+2   07  <- JSR returning to $0705");
+        assert_eq!(lines[2], "$01FE  SP+3   02  <- JSR returning to $0603");
+        assert_eq!(lines[3], "$01FF  SP+4   06  <- JSR returning to $0603");
+    }
+
+    #[test]
+    fn zero_page_view_uses_labels() {
+        let mut monitor = Monitor::new(Cpu::new().reset(None));
+        monitor.cpu.memory.data[0x0010] = 0x2A;
+        assert_eq!(monitor.zero_page_view().lines().count(), 16);
+
+        monitor.handle("al 10 .count");
+        monitor.handle("al 0600 .start");
+        assert_eq!(monitor.zero_page_view(), "$10  .count            $2A  42\n");
+    }
+}
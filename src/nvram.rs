@@ -0,0 +1,207 @@
+//! non-volatile memory backed by a host file, so settings firmware saves
+//! survive restarting the emulator
+//!
+//! the file holds the memory's contents byte for byte, it's created on the
+//! first flush and a short file is padded with erased `$FF` bytes, contents
+//! are flushed once the program stops writing for a while and when the device
+//! is dropped, the device is scheduled so an idle one costs nothing
+//!
+//! an EEPROM takes a few milliseconds to program a byte, with
+//! [`write_cycles`](Nvram::write_cycles) set the chip is busy for that long
+//! after a write: writes are ignored and reads return the byte being written
+//! with bit 7 inverted, the data polling real parts do
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+use crate::device::Device;
+
+/// address non-volatile memory is mapped at unless told otherwise
+pub const DEFAULT_BASE: u16 = 0x9000;
+
+/// size of the memory unless told otherwise, a 28C16's 2k
+pub const DEFAULT_LEN: usize = 0x0800;
+
+/// a byte nobody has written
+const ERASED: u8 = 0xFF;
+
+/// cycles after the last write before the contents are flushed to the file
+const FLUSH_DELAY: u64 = 1_000_000;
+
+/// byte addressable memory that keeps its contents in a host file
+#[derive(Debug)]
+pub struct Nvram {
+    contents: Vec<u8>,
+    path: Option<PathBuf>,
+    /// cycles a write keeps the chip busy for
+    write_cycles: u64,
+    /// the cycle the cpu was at when the memory was last accessed
+    now: u64,
+    /// cycle the write in progress finishes on
+    busy_until: u64,
+    last_written: u8,
+    /// cycle unsaved writes are flushed on, none when the file is current
+    flush_at: Option<u64>,
+}
+
+impl Nvram {
+    /// erased memory that isn't saved anywhere
+    pub fn new(len: usize) -> Self {
+        Self {
+            contents: vec![ERASED; len],
+            path: None,
+            write_cycles: 0,
+            now: 0,
+            busy_until: 0,
+            last_written: 0,
+            flush_at: None,
+        }
+    }
+
+    /// memory saved to `path`, loaded from it if it exists
+    /// fails if the file can't be read or is larger than the memory
+    pub fn open(path: impl Into<PathBuf>, len: usize) -> io::Result<Self> {
+        let path = path.into();
+        let mut nvram = Self::new(len);
+        match fs::read(&path) {
+            Ok(saved) if saved.len() > len => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} holds {} bytes, more than the {len} byte memory",
+                        path.display(),
+                        saved.len()
+                    ),
+                ))
+            }
+            Ok(saved) => nvram.contents[..saved.len()].copy_from_slice(&saved),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        nvram.path = Some(path);
+        Ok(nvram)
+    }
+
+    /// keep the chip busy for `cycles` after each write, like an EEPROM
+    pub fn write_cycles(mut self, cycles: u64) -> Self {
+        self.write_cycles = cycles;
+        self
+    }
+
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+
+    /// the file the contents are saved to
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// whether a write was still being programmed when last accessed
+    pub fn busy(&self) -> bool {
+        self.now < self.busy_until
+    }
+
+    /// save the contents now if anything changed since they were last saved
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let (Some(path), Some(_)) = (&self.path, self.flush_at) {
+            fs::write(path, &self.contents)?;
+        }
+        self.flush_at = None;
+        Ok(())
+    }
+
+    fn flush_or_warn(&mut self) {
+        if let Err(err) = self.flush() {
+            let path = self.path.as_deref().unwrap_or(Path::new("")).display();
+            warn!("failed to save nvram to {path}: {err}");
+        }
+    }
+}
+
+impl Device for Nvram {
+    fn read(&mut self, offset: u16) -> u8 {
+        if self.busy() {
+            return self.last_written ^ 0x80;
+        }
+        self.contents[offset as usize]
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if self.busy() {
+            return;
+        }
+        self.contents[offset as usize] = value;
+        self.last_written = value;
+        self.busy_until = self.now + self.write_cycles;
+        self.flush_at = Some(self.now + FLUSH_DELAY);
+    }
+
+    fn scheduled(&self) -> bool {
+        true
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        self.flush_at
+    }
+
+    fn event(&mut self, _cycle: u64) {
+        self.flush_or_warn();
+    }
+
+    fn sync(&mut self, cycle: u64) {
+        self.now = cycle;
+    }
+}
+
+impl Drop for Nvram {
+    fn drop(&mut self) {
+        self.flush_or_warn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_persist_through_the_file() {
+        let path = std::env::temp_dir().join("cpu_emu_nvram.bin");
+        let _ = fs::remove_file(&path);
+
+        let mut nvram = Nvram::open(&path, 4).unwrap();
+        assert_eq!(nvram.contents(), [ERASED; 4]);
+        nvram.write(1, 0x42);
+        assert_eq!(nvram.next_event(), Some(FLUSH_DELAY));
+        assert!(!path.exists());
+        nvram.event(FLUSH_DELAY);
+        assert_eq!(nvram.next_event(), None);
+        assert_eq!(fs::read(&path).unwrap(), [ERASED, 0x42, ERASED, ERASED]);
+
+        nvram.write(2, 0x07);
+        drop(nvram);
+        let mut nvram = Nvram::open(&path, 8).unwrap();
+        assert_eq!(nvram.read(2), 0x07);
+        assert_eq!(nvram.read(7), ERASED);
+
+        assert!(Nvram::open(&path, 2).is_err());
+    }
+
+    #[test]
+    fn writes_keep_the_chip_busy() {
+        let mut nvram = Nvram::new(4).write_cycles(100);
+        nvram.write(0, 0x81);
+        assert_eq!(nvram.read(0), 0x01);
+        nvram.sync(99);
+        nvram.write(1, 0x55);
+        assert!(nvram.busy());
+
+        nvram.sync(100);
+        assert_eq!(nvram.read(0), 0x81);
+        assert_eq!(nvram.read(1), ERASED);
+    }
+}
@@ -1,124 +1,320 @@
 #![allow(unused)]
-/// load accumulator immediate
-pub const LDA_IM: u8 = 0xA9;
-/// load accumulator absolute
-pub const LDA_ABS: u8 = 0xAD;
-/// load accumulator absolute x indexed
-pub const LDA_ABS_X: u8 = 0xBD;
-/// load accumulator absolute y indexed
-pub const LDA_ABS_Y: u8 = 0xB9;
-/// load accumulator zero page
-pub const LDA_ZP: u8 = 0xA5;
-/// load accumulator zero page x indexed
-pub const LDA_ZP_X: u8 = 0xB5;
-/// load accumulator zero page x indexed indirect
-pub const LDA_ZP_XI: u8 = 0xA1;
-/// load accumulator zero page y indirect indexed
-pub const LDA_ZP_IY: u8 = 0xB1;
-
-/// load x index immediate
-pub const LDX_IM: u8 = 0xA2;
-/// load x index absolute
-pub const LDX_ABS: u8 = 0xA3;
-/// load x index y indexed absolute
-pub const LDX_ABS_Y: u8 = 0xBE;
-/// load x index zero page
-pub const LDX_ZP: u8 = 0xA6;
-/// load x index y indexed zero page
-pub const LDX_ZP_Y: u8 = 0xB6;
-
-/// load y immediate
-pub const LDY_IM: u8 = 0xA0;
-/// load y index absolute
-pub const LDY_ABS: u8 = 0xAC;
-/// load y index x indexed absolute
-pub const LDY_ABS_X: u8 = 0xBC;
-/// load y index zero page
-pub const LDY_ZP: u8 = 0xA4;
-/// load y index x indexed zero page
-pub const LDY_ZP_X: u8 = 0xB4;
-
-/// no-op
-pub const NOP: u8 = 0xEA;
-/// jump subroutine
-pub const JSR: u8 = 0x20;
-/// jump absolute
-pub const JMP_ABS: u8 = 0x4C;
-/// jump absolute indirect
-pub const JMP_ABS_IND: u8 = 0x6C;
-/// return from subroutine
-pub const RTS: u8 = 0x60;
-
-/// logical shift right accumulator
-pub const LSR_ACC: u8 = 0x4A;
-/// logical shift right absolute
-pub const LSR_ABS: u8 = 0x4E;
-/// logical shift right zero page
-pub const LSR_ZP: u8 = 0x46;
-/// logical shift right absolute x indexed
-pub const LSR_ABS_X: u8 = 0x5E;
-/// logical shift right zero page x indexed
-pub const LSR_ZP_X: u8 = 0x56;
-
-/// push accumulator on the stack
-pub const PHA: u8 = 0x48;
-/// push processor status on the stack
-pub const PHP: u8 = 0x08;
-/// pop accumulator on the stack
-pub const PLA: u8 = 0x68;
-/// pop processor status on the stack
-pub const PLP: u8 = 0x28;
-
-/// or accumulator immediate
-pub const ANDA_IM: u8 = 0x29;
-/// or accumulator absolute
-pub const ANDA_ABS: u8 = 0x2D;
-/// or accumulator x indexed absolute
-pub const ANDA_X_ABS: u8 = 0x3D;
-/// or accumulator y indexed absolute
-pub const ANDA_Y_ABS: u8 = 0x39;
-/// or accumulator zero page
-pub const ANDA_ZP: u8 = 0x25;
-/// or accumulator x indexed zero page
-pub const ANDA_ZP_X: u8 = 0x35;
-/// or accumulator x indexed zero page indirect
-pub const ANDA_ZP_XI: u8 = 0x21;
-/// or accumulator zero page indirect y indexed
-pub const ANDA_ZP_IY: u8 = 0x31;
-
-/// and accumulator immediate
-pub const ORA_IM: u8 = 0x09;
-/// and accumulator absolute
-pub const ORA_ABS: u8 = 0x0D;
-/// and accumulator x indexed absolute
-pub const ORA_X_ABS: u8 = 0x1D;
-/// and accumulator y indexed absolute
-pub const ORA_Y_ABS: u8 = 0x19;
-/// and accumulator zero page
-pub const ORA_ZP: u8 = 0x05;
-/// and accumulator x indexed zero page
-pub const ORA_ZP_X: u8 = 0x15;
-/// and accumulator x indexed zero page indirect
-pub const ORA_ZP_XI: u8 = 0x01;
-/// and accumulator zero page indirect y indexed
-pub const ORA_ZP_IY: u8 = 0x11;
-
-/// transfer accumulator to index x
-pub const TAX: u8 = 0xAA;
-/// transfer accumulator to index y
-pub const TAY: u8 = 0xA8;
-/// transfer stack pointer to index x
-pub const TSX: u8 = 0xBA;
-/// transfer stack pointer to index x
-pub const TXA: u8 = 0x8A;
-/// transfer stack pointer to index x
-pub const TXS: u8 = 0x9A;
-/// transfer stack pointer to index x
-pub const TYA: u8 = 0x98;
-
-/// set carry flag
-pub const SEC: u8 = 0x38;
-/// set decimal mode
-pub const SED: u8 = 0xF8;
-/// set interrupt disable
-pub const SEI: u8 = 0x78;
+
+use thiserror::Error;
+
+use crate::cpu::Variant;
+
+// `Opcode`, a `u8` constant named after each opcode and `INSTRUCTIONS`,
+// generated by build.rs from op_codes.csv
+//
+// decoding goes through `Opcode`, while programs, including the ones tests
+// build, are written with the constants, a program is bytes with opcodes and
+// operands mixed and each constant is its `Opcode as u8`, so it can't name an
+// opcode the enum doesn't have
+include!(concat!(env!("OUT_DIR"), "/op_codes.rs"));
+
+/// a byte that isn't an implemented instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("unknown opcode ${0:02X}")]
+pub struct UnknownOpcode(pub u8);
+
+impl Opcode {
+    /// the instruction a byte encodes, usable in const contexts unlike `try_from`
+    pub const fn from_byte(byte: u8) -> Option<Self> {
+        DECODE[byte as usize]
+    }
+
+    /// the instruction a byte encodes on one processor revision
+    pub const fn for_variant(byte: u8, variant: Variant) -> Option<Self> {
+        match DECODE[byte as usize] {
+            Some(opcode) if AVAILABILITY[byte as usize].includes(variant) => Some(opcode),
+            _ => None,
+        }
+    }
+
+    /// metadata describing the instruction
+    pub fn info(self) -> &'static Instruction {
+        BY_OPCODE[self as usize].expect("every opcode has an entry in INSTRUCTIONS")
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        self.info().mnemonic
+    }
+
+    pub fn mode(self) -> AddressingMode {
+        self.info().mode
+    }
+
+    /// whether the instruction is a conditional branch
+    pub const fn is_branch(self) -> bool {
+        matches!(
+            self,
+            Opcode::Bcc
+                | Opcode::Bcs
+                | Opcode::Beq
+                | Opcode::Bmi
+                | Opcode::Bne
+                | Opcode::Bpl
+                | Opcode::Bvc
+                | Opcode::Bvs
+        )
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = UnknownOpcode;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::from_byte(byte).ok_or(UnknownOpcode(byte))
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        opcode as u8
+    }
+}
+
+/// addressing modes an instruction's operand can be given in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    /// no operand, e.g. `NOP`
+    Implied,
+    /// operates on the accumulator, e.g. `LSR A`
+    Accumulator,
+    /// `#$nn`
+    Immediate,
+    /// `$nn`
+    ZeroPage,
+    /// `$nn,X`
+    ZeroPageX,
+    /// `$nn,Y`
+    ZeroPageY,
+    /// `$nnnn`
+    Absolute,
+    /// `$nnnn,X`
+    AbsoluteX,
+    /// `$nnnn,Y`
+    AbsoluteY,
+    /// `($nnnn)`
+    Indirect,
+    /// `($nn,X)`
+    ZeroPageXIndirect,
+    /// `($nn),Y`
+    ZeroPageIndirectY,
+    /// signed offset from the next instruction, written as the target address
+    Relative,
+}
+
+impl AddressingMode {
+    /// number of operand bytes following the opcode
+    pub fn operand_len(&self) -> usize {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// the processor revisions an instruction exists on, the variant column of
+/// op_codes.csv
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    All,
+    /// only the NMOS 6502, like its undocumented opcodes
+    Nmos,
+    /// only the 65C02
+    Cmos,
+}
+
+impl Availability {
+    pub const fn includes(self, variant: Variant) -> bool {
+        matches!(
+            (self, variant),
+            (Availability::All, _)
+                | (Availability::Nmos, Variant::Nmos)
+                | (Availability::Cmos, Variant::Cmos)
+        )
+    }
+}
+
+/// metadata describing a single opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// base cycle count, not including page crossing penalties
+    pub cycles: u8,
+    pub availability: Availability,
+    /// what the instruction does, in words
+    pub description: &'static str,
+}
+
+impl Instruction {
+    const fn new(
+        opcode: Opcode,
+        mnemonic: &'static str,
+        mode: AddressingMode,
+        cycles: u8,
+        availability: Availability,
+        description: &'static str,
+    ) -> Self {
+        Self {
+            opcode,
+            mnemonic,
+            mode,
+            cycles,
+            availability,
+            description,
+        }
+    }
+
+    /// total length of the instruction in bytes including the opcode
+    pub fn size(&self) -> usize {
+        1 + self.mode.operand_len()
+    }
+}
+
+/// base cycle counts indexed by opcode, zero for unimplemented opcodes
+pub const CYCLES: [u8; 256] = {
+    let mut cycles = [0; 256];
+    let mut i = 0;
+    while i < INSTRUCTIONS.len() {
+        cycles[INSTRUCTIONS[i].opcode as usize] = INSTRUCTIONS[i].cycles;
+        i += 1;
+    }
+    cycles
+};
+
+/// opcodes indexed by the byte encoding them
+const DECODE: [Option<Opcode>; 256] = {
+    let mut decode = [None; 256];
+    let mut i = 0;
+    while i < INSTRUCTIONS.len() {
+        decode[INSTRUCTIONS[i].opcode as usize] = Some(INSTRUCTIONS[i].opcode);
+        i += 1;
+    }
+    decode
+};
+
+/// the revisions each opcode exists on, indexed by opcode
+const AVAILABILITY: [Availability; 256] = {
+    let mut availability = [Availability::All; 256];
+    let mut i = 0;
+    while i < INSTRUCTIONS.len() {
+        availability[INSTRUCTIONS[i].opcode as usize] = INSTRUCTIONS[i].availability;
+        i += 1;
+    }
+    availability
+};
+
+/// metadata indexed by opcode
+static BY_OPCODE: [Option<&Instruction>; 256] = {
+    let mut by_opcode = [None; 256];
+    let mut i = 0;
+    while i < INSTRUCTIONS.len() {
+        by_opcode[INSTRUCTIONS[i].opcode as usize] = Some(&INSTRUCTIONS[i]);
+        i += 1;
+    }
+    by_opcode
+};
+
+/// whether an opcode is a conditional branch
+pub fn is_branch(opcode: u8) -> bool {
+    Opcode::from_byte(opcode).is_some_and(Opcode::is_branch)
+}
+
+/// look up the metadata for an opcode
+pub fn instruction(opcode: u8) -> Option<&'static Instruction> {
+    BY_OPCODE[opcode as usize]
+}
+
+/// look up the opcode for a mnemonic in a given addressing mode
+pub fn find_opcode(mnemonic: &str, mode: AddressingMode) -> Option<u8> {
+    INSTRUCTIONS
+        .iter()
+        .find(|info| info.mnemonic == mnemonic && info.mode == mode)
+        .map(|info| info.opcode.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes_round_trip_through_bytes() {
+        for info in INSTRUCTIONS {
+            let byte = u8::from(info.opcode);
+            assert_eq!(Opcode::try_from(byte), Ok(info.opcode));
+            assert_eq!(instruction(byte), Some(info));
+            assert_eq!(info.opcode.info(), info);
+        }
+        assert_eq!(Opcode::try_from(0xFF), Err(UnknownOpcode(0xFF)));
+        assert_eq!(Opcode::LdaIm.mode(), AddressingMode::Immediate);
+        assert_eq!(Opcode::Bne.mnemonic(), "BNE");
+        assert!(Opcode::Bne.is_branch() && !Opcode::JmpAbs.is_branch());
+    }
+
+    #[test]
+    fn opcodes_decode_only_on_their_variants() {
+        assert!(Availability::All.includes(Variant::Cmos));
+        assert!(Availability::Nmos.includes(Variant::Nmos));
+        assert!(!Availability::Nmos.includes(Variant::Cmos));
+        assert!(!Availability::Cmos.includes(Variant::Nmos));
+        for variant in [Variant::Nmos, Variant::Cmos] {
+            assert_eq!(Opcode::for_variant(LDA_IM, variant), Some(Opcode::LdaIm));
+            assert_eq!(Opcode::for_variant(0xFF, variant), None);
+        }
+    }
+
+    #[test]
+    fn table_matches_the_reference_opcode_list() {
+        // opcodes.csv at the root lists every documented opcode, it's what
+        // assembler.py reads
+        let reference: Vec<Vec<&str>> = include_str!("../opcodes.csv")
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect())
+            .filter(|fields: &Vec<&str>| fields.len() == 6)
+            .collect();
+
+        for info in INSTRUCTIONS {
+            let opcode = u8::from(info.opcode);
+            let row = reference
+                .iter()
+                .find(|row| u8::from_str_radix(&row[0][2..], 16) == Ok(opcode))
+                .unwrap_or_else(|| panic!("{:?} isn't a documented opcode", info.opcode));
+            let mode = match row[2] {
+                "IMP" => AddressingMode::Implied,
+                "ACC" => AddressingMode::Accumulator,
+                "IMM" => AddressingMode::Immediate,
+                "ZP" => AddressingMode::ZeroPage,
+                "ZPX" => AddressingMode::ZeroPageX,
+                "ZPY" => AddressingMode::ZeroPageY,
+                "ABS" => AddressingMode::Absolute,
+                "ABSX" => AddressingMode::AbsoluteX,
+                "ABSY" => AddressingMode::AbsoluteY,
+                "IND" => AddressingMode::Indirect,
+                "INDX" => AddressingMode::ZeroPageXIndirect,
+                "INDY" => AddressingMode::ZeroPageIndirectY,
+                _ => AddressingMode::Relative,
+            };
+            assert_eq!(
+                (
+                    row[1],
+                    mode,
+                    row[3].parse(),
+                    row[4].split('/').next().unwrap().parse()
+                ),
+                (info.mnemonic, info.mode, Ok(info.size()), Ok(info.cycles)),
+                "{:?}",
+                info.opcode
+            );
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! bytes patched over memory from the debugger for "what if" experiments,
+//! remembering what they replaced so they can be put back
+//!
+//! patches are written like the cpu writes, so they take in RAM and devices
+//! but not over ROMs, and where patches overlap reverting either puts back
+//! the bytes from before both
+
+use std::collections::BTreeMap;
+
+use crate::memory::Memory;
+
+/// the patches made to a memory
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Patches {
+    /// the length of each patch by where it starts
+    patches: BTreeMap<u16, usize>,
+    /// each patched byte from before it was first patched
+    originals: BTreeMap<u16, u8>,
+}
+
+impl Patches {
+    /// write `bytes` from `address`, wrapping past $FFFF
+    pub fn apply(&mut self, memory: &mut Memory, address: u16, bytes: &[u8]) {
+        for (target, byte) in (0..).map(|i| address.wrapping_add(i)).zip(bytes) {
+            self.originals
+                .entry(target)
+                .or_insert_with(|| memory.peek_byte(target as usize));
+            memory.write_byte(target as usize, *byte);
+        }
+        let len = self.patches.entry(address).or_default();
+        *len = (*len).max(bytes.len());
+    }
+
+    /// put back the bytes under the patch starting at `address`, false if
+    /// none does
+    pub fn revert(&mut self, memory: &mut Memory, address: u16) -> bool {
+        let Some(len) = self.patches.remove(&address) else {
+            return false;
+        };
+        for target in (0..len as u16).map(|i| address.wrapping_add(i)) {
+            if let Some(original) = self.originals.remove(&target) {
+                memory.write_byte(target as usize, original);
+            }
+        }
+        true
+    }
+
+    /// put back every patched byte
+    pub fn revert_all(&mut self, memory: &mut Memory) {
+        for (address, original) in std::mem::take(&mut self.originals) {
+            memory.write_byte(address as usize, original);
+        }
+        self.patches.clear();
+    }
+
+    /// where each patch starts and the bytes it replaced
+    pub fn iter(&self) -> impl Iterator<Item = (u16, Vec<u8>)> + '_ {
+        self.patches.iter().map(|(address, len)| {
+            let original = (0..*len as u16)
+                .filter_map(|i| self.originals.get(&address.wrapping_add(i)).copied())
+                .collect();
+            (*address, original)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patches_revert_to_the_first_original() {
+        let mut memory = Memory::default();
+        memory.write_bytes(0x0600, &[1, 2, 3, 4]).unwrap();
+        let mut patches = Patches::default();
+        patches.apply(&mut memory, 0x0601, &[0xEA, 0xEA]);
+        patches.apply(&mut memory, 0x0602, &[0xFF, 0xFF]);
+        assert_eq!(memory.data[0x0600..0x0604], [1, 0xEA, 0xFF, 0xFF]);
+        assert_eq!(
+            patches.iter().collect::<Vec<_>>(),
+            [(0x0601, vec![2, 3]), (0x0602, vec![3, 4])]
+        );
+
+        assert!(patches.revert(&mut memory, 0x0601));
+        assert!(!patches.revert(&mut memory, 0x0601));
+        assert_eq!(memory.data[0x0600..0x0604], [1, 2, 3, 0xFF]);
+        patches.revert_all(&mut memory);
+        assert_eq!(memory.data[0x0600..0x0604], [1, 2, 3, 4]);
+        assert!(patches.is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+//! co-simulation against perfect6502, the transistor-level simulation of the
+//! NMOS 6502 built from the visual6502 netlist
+//!
+//! build with the `perfect6502` feature and `PERFECT6502_DIR` pointing at a
+//! checkout of <https://github.com/mist64/perfect6502>; its C sources are
+//! compiled and linked by the build script
+
+use core::fmt;
+use std::{
+    ffi::c_void,
+    ptr,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    cpu::Cpu,
+    memory::MAX_MEM,
+    op_codes::{self, *},
+    processor_status::ProcessorStatus,
+    random::Random,
+    vcd::BusCycle,
+};
+
+extern "C" {
+    fn initAndResetChip() -> *mut c_void;
+    fn destroyChip(state: *mut c_void);
+    fn step(state: *mut c_void);
+    fn readA(state: *mut c_void) -> u8;
+    fn readX(state: *mut c_void) -> u8;
+    fn readY(state: *mut c_void) -> u8;
+    fn readSP(state: *mut c_void) -> u8;
+    fn readP(state: *mut c_void) -> u8;
+    fn readRW(state: *mut c_void) -> u32;
+    fn readAddressBus(state: *mut c_void) -> u16;
+    fn readDataBus(state: *mut c_void) -> u8;
+    fn isNodeHigh(state: *mut c_void, node: u16) -> u32;
+
+    /// the simulation serves every bus access from this one global array
+    static mut memory: [u8; MAX_MEM];
+}
+
+/// perfect6502 keeps its memory in a global, so only one chip can run at a time
+static CHIP: Mutex<()> = Mutex::new(());
+
+/// the netlist node driving the SYNC pin, from visual6502's nodenames.js
+const SYNC_NODE: u16 = 539;
+
+/// cycles the chip may spend in its reset sequence before fetching from the reset vector
+const RESET_CYCLES: usize = 16;
+
+/// registers compared after every instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    /// status without B and the unused bit, which aren't real flip-flops
+    pub p: u8,
+}
+
+impl Registers {
+    fn of(cpu: &Cpu) -> Self {
+        Self {
+            a: cpu.a(),
+            x: cpu.x(),
+            y: cpu.y(),
+            sp: cpu.sp() as u8,
+            p: flags(cpu.status().bits()),
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X}",
+            self.a, self.x, self.y, self.sp, self.p
+        )
+    }
+}
+
+fn flags(p: u8) -> u8 {
+    p & !(ProcessorStatus::B | ProcessorStatus::U).bits()
+}
+
+/// the perfect6502 simulation, one instance at a time
+pub struct Chip {
+    state: *mut c_void,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Chip {
+    /// reset a chip with a copy of `image` as its memory
+    pub fn new(image: &[u8; MAX_MEM]) -> Self {
+        let guard = CHIP.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // SAFETY: the lock is held, so nothing else touches the simulation's memory
+        unsafe { ptr::addr_of_mut!(memory).write(*image) };
+        Self {
+            // SAFETY: the memory the reset sequence reads is initialized
+            state: unsafe { initAndResetChip() },
+            _guard: guard,
+        }
+    }
+
+    /// run one full clock cycle, returning the bus as it was during phi2
+    pub fn cycle(&mut self, cycle: u64) -> BusCycle {
+        // SAFETY: `state` came from initAndResetChip and isn't destroyed until drop
+        unsafe {
+            step(self.state);
+            step(self.state);
+            BusCycle {
+                cycle,
+                address: readAddressBus(self.state),
+                data: readDataBus(self.state),
+                write: readRW(self.state) == 0,
+                sync: isNodeHigh(self.state, SYNC_NODE) != 0,
+                irq: false,
+                nmi: false,
+            }
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        // SAFETY: as in `cycle`
+        unsafe {
+            Registers {
+                a: readA(self.state),
+                x: readX(self.state),
+                y: readY(self.state),
+                sp: readSP(self.state),
+                p: flags(readP(self.state)),
+            }
+        }
+    }
+}
+
+impl Drop for Chip {
+    fn drop(&mut self) {
+        // SAFETY: the state is destroyed exactly once, while the lock is still held
+        unsafe { destroyChip(self.state) }
+    }
+}
+
+/// the first place the emulator disagrees with the chip
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// the chip never fetched the program's first instruction
+    NoReset,
+    /// a bus access differs, `expected` is the chip's
+    Bus {
+        cycle: u64,
+        expected: BusCycle,
+        actual: BusCycle,
+    },
+    /// registers differ after the instruction at `pc`
+    Registers {
+        pc: u16,
+        cycle: u64,
+        expected: Registers,
+        actual: Registers,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = |bus: &BusCycle| {
+            let kind = match (bus.write, bus.sync) {
+                (true, _) => "write",
+                (false, true) => "opcode fetch",
+                (false, false) => "read",
+            };
+            format!("{kind} ${:04X} = ${:02X}", bus.address, bus.data)
+        };
+        match self {
+            Mismatch::NoReset => write!(f, "the chip never reached the program"),
+            Mismatch::Bus {
+                cycle,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "cycle {cycle}: chip did {}, emulator did {}",
+                access(expected),
+                access(actual)
+            ),
+            Mismatch::Registers {
+                pc,
+                cycle,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "after ${pc:04X} (cycle {cycle}): chip has {expected}, emulator has {actual}"
+            ),
+        }
+    }
+}
+
+/// run `cpu` to completion next to the chip and compare every bus access the
+/// emulator records, SYNC included, and the registers after every instruction, the cpu should
+/// be built accurate with a bus trace, registers are only compared once
+/// `compare_from` instructions have retired so setup code can bring the chip's
+/// undefined power-on state in line
+pub fn cosimulate(mut cpu: Cpu, max_steps: usize, compare_from: usize) -> Option<Mismatch> {
+    let start = cpu.pc();
+    let mut chip = Chip::new(&cpu.memory.data);
+
+    // the emulator starts at the first fetch, skip the chip's reset sequence
+    let mut first = None;
+    for _ in 0..RESET_CYCLES {
+        let bus = chip.cycle(0);
+        if !bus.write && bus.address == start {
+            first = Some(bus);
+            break;
+        }
+    }
+    let Some(first) = first else {
+        return Some(Mismatch::NoReset);
+    };
+    let mut chip_bus = vec![first];
+
+    let mut checked = 0;
+    for steps in 0..max_steps {
+        let pc = cpu.pc();
+        match cpu.step() {
+            Ok(true) => {}
+            _ => break,
+        }
+
+        // the chip finishes an instruction while fetching the next one
+        while chip_bus.len() as u64 <= cpu.cycles() {
+            chip_bus.push(chip.cycle(chip_bus.len() as u64));
+        }
+        for actual in &cpu.bus_trace()[checked..] {
+            let expected = chip_bus[actual.cycle as usize];
+            if (
+                expected.address,
+                expected.data,
+                expected.write,
+                expected.sync,
+            ) != (actual.address, actual.data, actual.write, actual.sync)
+            {
+                return Some(Mismatch::Bus {
+                    cycle: actual.cycle,
+                    expected,
+                    actual: *actual,
+                });
+            }
+        }
+        checked = cpu.bus_trace().len();
+
+        let (expected, actual) = (chip.registers(), Registers::of(&cpu));
+        if steps >= compare_from && expected != actual {
+            return Some(Mismatch::Registers {
+                pc,
+                cycle: cpu.cycles(),
+                expected,
+                actual,
+            });
+        }
+    }
+    None
+}
+
+/// instructions the chip's undefined power-on registers are cleared with
+pub const SETUP: [u8; 8] = [LDX_IM, 0xFF, TXS, LDA_IM, 0x00, PHA, PLP, TAX];
+
+/// number of instructions in [`SETUP`]
+pub const SETUP_INSTRUCTIONS: usize = 6;
+
+/// a random straight-line program of `length` instructions after [`SETUP`],
+/// control flow is left out so both sides run every instruction, and it ends
+/// with the NOP the emulator halts on
+pub fn random_program(random: &mut Random, length: usize) -> Vec<u8> {
+    let candidates: Vec<_> = op_codes::INSTRUCTIONS
+        .iter()
+        .filter(|info| {
+            !info.opcode.is_branch()
+                && !matches!(
+                    info.opcode,
+                    Opcode::Nop
+                        | Opcode::Jsr
+                        | Opcode::JmpAbs
+                        | Opcode::JmpAbsInd
+                        | Opcode::Rts
+                        | Opcode::Brk
+                        | Opcode::Rti
+                )
+        })
+        .collect();
+
+    let mut program = SETUP.to_vec();
+    for _ in 0..length {
+        let info = candidates[random.next_u64() as usize % candidates.len()];
+        program.push(info.opcode.into());
+        program.extend((1..info.size()).map(|_| random.byte()));
+    }
+    program.push(NOP);
+    program
+}
+
+/// co-simulate `count` random programs, returning the seed and mismatch of
+/// the first one that disagrees with the chip
+pub fn fuzz(seed: u64, count: usize, length: usize) -> Option<(u64, Mismatch)> {
+    let mut seeds = Random::new(seed);
+    for _ in 0..count {
+        let seed = seeds.next_u64();
+        let program = random_program(&mut Random::new(seed), length);
+        let cpu = Cpu::builder()
+            .pc(0x0200)
+            .decimal_mode(true)
+            .accurate(true)
+            .bus_trace(true)
+            .memory(0x0200, program)
+            .build()
+            .ok()?;
+        if let Some(mismatch) = cosimulate(cpu, length + SETUP_INSTRUCTIONS, SETUP_INSTRUCTIONS) {
+            return Some((seed, mismatch));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn straight_line_code_matches_the_chip() {
+        let cpu = Cpu::builder()
+            .pc(0x0200)
+            .accurate(true)
+            .bus_trace(true)
+            .memory(0x0200, [&SETUP[..], &[LDA_IM, 0x42, TAY, NOP]].concat())
+            .build()
+            .unwrap();
+        assert_eq!(cosimulate(cpu, 100, SETUP_INSTRUCTIONS), None);
+    }
+
+    #[test]
+    fn random_programs_match_the_chip() {
+        if let Some((seed, mismatch)) = fuzz(0x6502, 20, 32) {
+            panic!("program from seed {seed:#x}: {mismatch}");
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! what the cpu may do with each part of the address space, to catch wild
+//! pointers and runaway jumps in firmware as soon as they happen
+//!
+//! regions are given with [`CpuBuilder::permissions`](crate::CpuBuilder::permissions),
+//! later ones win where they overlap and addresses outside every region allow
+//! everything, the map only reports, the access itself still goes to
+//! whatever is at the address
+//!
+//! opcodes fetched are checked for execute, data and pointer reads for read
+//! and stores, pushes and read-modify-write results for write, the extra
+//! reads accurate mode makes aren't checked
+
+use core::fmt;
+use std::ops::Range;
+
+use bitflags::bitflags;
+use tracing::warn;
+
+bitflags! {
+    /// the accesses a region allows
+    pub struct Permissions: u8 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXECUTE = 1 << 2;
+    }
+}
+
+impl Permissions {
+    /// read and write but not execute, for device windows
+    pub const DATA: Permissions = Permissions {
+        bits: Self::READ.bits | Self::WRITE.bits,
+    };
+    /// read and execute, for firmware
+    pub const CODE: Permissions = Permissions {
+        bits: Self::READ.bits | Self::EXECUTE.bits,
+    };
+
+    /// permissions written like `rwx`, `r-x` or `---`, letters left out
+    /// aren't allowed
+    pub fn from_name(name: &str) -> Option<Self> {
+        name.chars().try_fold(Permissions::empty(), |allowed, c| {
+            Some(
+                allowed
+                    | match c {
+                        'r' => Permissions::READ,
+                        'w' => Permissions::WRITE,
+                        'x' => Permissions::EXECUTE,
+                        '-' => Permissions::empty(),
+                        _ => return None,
+                    },
+            )
+        })
+    }
+}
+
+/// an access the cpu made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Operation {
+    fn needs(self) -> Permissions {
+        match self {
+            Operation::Read => Permissions::READ,
+            Operation::Write => Permissions::WRITE,
+            Operation::Execute => Permissions::EXECUTE,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Execute => "execute",
+        })
+    }
+}
+
+/// an access a region doesn't allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub operation: Operation,
+    pub address: u16,
+    /// the instruction that made the access
+    pub pc: u16,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of ${:04X} by the instruction at ${:04X}",
+            self.operation, self.address, self.pc
+        )
+    }
+}
+
+/// what happens when an access breaks the map
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationPolicy {
+    /// log a warning, keep the fault and carry on
+    #[default]
+    Warn,
+    /// stop with [`CpuError::AccessViolation`](crate::cpu::CpuError::AccessViolation)
+    /// once the instruction finishes, or before it runs for an execute
+    Stop,
+}
+
+impl ViolationPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "warn" => Some(ViolationPolicy::Warn),
+            "stop" => Some(ViolationPolicy::Stop),
+            _ => None,
+        }
+    }
+}
+
+/// the regions a cpu checks its accesses against
+#[derive(Debug, Clone, Default)]
+pub struct PermissionMap {
+    regions: Vec<(Range<usize>, Permissions)>,
+    pub policy: ViolationPolicy,
+    faults: Vec<Fault>,
+    /// the fault the current instruction made, to stop on
+    pending: Option<Fault>,
+    /// the instruction being run
+    pc: u16,
+}
+
+impl PermissionMap {
+    /// allow only `allowed` over `len` addresses from `address`
+    pub fn add(&mut self, address: usize, len: usize, allowed: Permissions) {
+        self.regions.push((address..address + len, allowed));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// what the last region covering `address` allows
+    pub fn allowed(&self, address: u16) -> Permissions {
+        self.regions
+            .iter()
+            .rev()
+            .find(|(range, _)| range.contains(&(address as usize)))
+            .map_or(Permissions::all(), |(_, allowed)| *allowed)
+    }
+
+    /// start checking the accesses of the instruction at `pc`, its opcode
+    /// fetch first
+    pub(crate) fn begin(&mut self, pc: u16) {
+        self.pc = pc;
+        self.check(Operation::Execute, pc);
+    }
+
+    /// check an access, noting it if it isn't allowed
+    pub(crate) fn check(&mut self, operation: Operation, address: u16) {
+        if self.allowed(address).contains(operation.needs()) {
+            return;
+        }
+        let fault = Fault {
+            operation,
+            address,
+            pc: self.pc,
+        };
+        warn!("access violation: {fault}");
+        self.faults.push(fault);
+        if self.policy == ViolationPolicy::Stop {
+            self.pending.get_or_insert(fault);
+        }
+    }
+
+    /// the fault to stop on, if there is one
+    pub(crate) fn take_pending(&mut self) -> Option<Fault> {
+        self.pending.take()
+    }
+
+    /// every access that broke the map, oldest first
+    pub fn faults(&self) -> &[Fault] {
+        &self.faults
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_regions_win() {
+        let mut map = PermissionMap::default();
+        map.add(0x0000, 0x10000, Permissions::DATA);
+        map.add(0xC000, 0x4000, Permissions::CODE);
+        map.add(0xD000, 0x100, Permissions::empty());
+        assert_eq!(map.allowed(0x0200), Permissions::DATA);
+        assert_eq!(map.allowed(0xC000), Permissions::CODE);
+        assert_eq!(map.allowed(0xD0FF), Permissions::empty());
+
+        map.begin(0xC100);
+        map.check(Operation::Read, 0xC000);
+        map.check(Operation::Write, 0xC000);
+        assert_eq!(
+            map.faults(),
+            [Fault {
+                operation: Operation::Write,
+                address: 0xC000,
+                pc: 0xC100
+            }]
+        );
+        // only kept to stop on
+        assert_eq!(map.take_pending(), None);
+    }
+
+    #[test]
+    fn permissions_parse_like_ls() {
+        assert_eq!(Permissions::from_name("r-x"), Some(Permissions::CODE));
+        assert_eq!(Permissions::from_name("---"), Some(Permissions::empty()));
+        assert_eq!(Permissions::from_name("rwx"), Some(Permissions::all()));
+        assert_eq!(Permissions::from_name("rz"), None);
+    }
+}
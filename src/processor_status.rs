@@ -3,12 +3,14 @@ use core::fmt;
 use bitflags::bitflags;
 
 bitflags! {
-    #[derive(Default)]
+    /// processor status register flags
     pub struct ProcessorStatus: u8 {
         // Negative
         const N = 0b10000000;
         // Overflow
         const V = 0b01000000;
+        // Unused, always reads as set
+        const U = 0b00100000;
         // Break
         const B = 0b00010000;
         // Decimal
@@ -22,16 +24,111 @@ bitflags! {
     }
 }
 
+impl Default for ProcessorStatus {
+    /// every flag clear, only the unused bit is set
+    fn default() -> Self {
+        ProcessorStatus::U
+    }
+}
+
+macro_rules! flags {
+    ($($flag:ident => $get:ident, $set:ident, $name:literal;)*) => {
+        $(
+            #[doc = concat!("whether the ", $name, " flag is set")]
+            pub fn $get(&self) -> bool {
+                self.contains(ProcessorStatus::$flag)
+            }
+
+            #[doc = concat!("set or clear the ", $name, " flag")]
+            pub fn $set(&mut self, value: bool) {
+                self.set(ProcessorStatus::$flag, value);
+            }
+        )*
+
+        /// names of the flags that are set, negative first
+        pub fn names(&self) -> Vec<&'static str> {
+            [$((ProcessorStatus::$flag, $name)),*]
+                .into_iter()
+                .filter(|(flag, _)| self.contains(*flag))
+                .map(|(_, name)| name)
+                .collect()
+        }
+    };
+}
+
 impl ProcessorStatus {
+    flags! {
+        N => negative, set_negative, "negative";
+        V => overflow, set_overflow, "overflow";
+        B => break_command, set_break_command, "break";
+        D => decimal, set_decimal, "decimal";
+        I => interrupt_disable, set_interrupt_disable, "interrupt disable";
+        Z => zero, set_zero, "zero";
+        C => carry, set_carry, "carry";
+    }
+
+    /// clear every flag, the unused bit stays set
     pub fn clear(&mut self) -> &mut Self {
-        self.bits = 0;
+        self.bits = ProcessorStatus::U.bits;
         self
     }
+
+    /// the value PHP pushes onto the stack, with the break and unused bits set
+    pub fn pushed(&self) -> u8 {
+        self.bits | ProcessorStatus::B.bits | ProcessorStatus::U.bits
+    }
+
+    /// the value a hardware interrupt pushes, with the break bit clear
+    pub fn interrupt_pushed(&self) -> u8 {
+        (self.bits | ProcessorStatus::U.bits) & !ProcessorStatus::B.bits
+    }
+
+    /// the register value after pulling a byte from the stack
+    pub fn pulled(value: u8) -> Self {
+        ProcessorStatus::from_bits_truncate(value) | ProcessorStatus::U
+    }
+}
+
+/// serialized as the register's byte
+#[cfg(feature = "serde")]
+impl serde::Serialize for ProcessorStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.bits)
+    }
+}
+
+/// deserialized from the register's byte, the unused bit is always set
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ProcessorStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u8::deserialize(deserializer).map(ProcessorStatus::pulled)
+    }
 }
 
 impl fmt::Display for ProcessorStatus {
+    /// the flags as `NV-BDIZC`, lowercase when clear, e.g. `nv-BdIZc`
+    /// the raw bits are still available with `{:08b}`
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:08b}", self.bits)
+        let letters = [
+            (ProcessorStatus::N, 'N'),
+            (ProcessorStatus::V, 'V'),
+            (ProcessorStatus::B, 'B'),
+            (ProcessorStatus::D, 'D'),
+            (ProcessorStatus::I, 'I'),
+            (ProcessorStatus::Z, 'Z'),
+            (ProcessorStatus::C, 'C'),
+        ];
+        for (i, (flag, letter)) in letters.into_iter().enumerate() {
+            if i == 2 {
+                write!(f, "-")?;
+            }
+            if self.contains(flag) {
+                write!(f, "{letter}")?;
+            } else {
+                write!(f, "{}", letter.to_ascii_lowercase())?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -42,20 +139,58 @@ mod tests {
     #[test]
     fn default() {
         let bits = ProcessorStatus::default();
-        assert_eq!(format!("{bits}"), "00000000");
+        assert_eq!(format!("{bits:08b}"), "00100000");
     }
 
     #[test]
     fn clear() {
         let mut bits = ProcessorStatus::N;
         bits.clear();
-        assert_eq!(format!("{bits}"), "00000000");
+        assert_eq!(format!("{bits:08b}"), "00100000");
+    }
+
+    #[test]
+    fn display_names_flags_by_letter() {
+        assert_eq!(ProcessorStatus::default().to_string(), "nv-bdizc");
+        let bits =
+            ProcessorStatus::B | ProcessorStatus::I | ProcessorStatus::Z | ProcessorStatus::U;
+        assert_eq!(bits.to_string(), "nv-BdIZc");
+        assert_eq!(ProcessorStatus::all().to_string(), "NV-BDIZC");
     }
 
     #[test]
     fn bitwise_or() {
         let negative_flag = ProcessorStatus::N;
         let no_flags = ProcessorStatus::default();
-        assert_eq!(negative_flag | no_flags, ProcessorStatus::N);
+        assert_eq!(
+            negative_flag | no_flags,
+            ProcessorStatus::N | ProcessorStatus::U
+        );
+    }
+
+    #[test]
+    fn flags_by_name() {
+        let mut status = ProcessorStatus::default();
+        status.set_carry(true);
+        status.set_negative(true);
+        assert!(status.carry() && status.negative());
+        assert!(!status.zero() && !status.overflow());
+        assert_eq!(status.names(), ["negative", "carry"]);
+
+        status.set_carry(false);
+        assert_eq!(status, ProcessorStatus::N | ProcessorStatus::U);
+    }
+
+    #[test]
+    fn pushed_and_pulled_keep_unused_bit_set() {
+        assert_eq!(ProcessorStatus::C.pushed(), 0b0011_0001);
+        assert_eq!(
+            (ProcessorStatus::C | ProcessorStatus::B).interrupt_pushed(),
+            0b0010_0001
+        );
+        assert_eq!(
+            ProcessorStatus::pulled(0b0000_0001),
+            ProcessorStatus::C | ProcessorStatus::U
+        );
     }
 }
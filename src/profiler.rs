@@ -0,0 +1,500 @@
+use core::fmt;
+use std::collections::BTreeMap;
+
+use crate::{
+    events::{Event, Observer},
+    op_codes::{self, CYCLES, JSR, RTS},
+};
+
+/// per-opcode execution counts, collected by subscribing to a cpu
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: [u64; 256],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self { counts: [0; 256] }
+    }
+}
+
+impl Histogram {
+    /// times an opcode was executed
+    pub fn count(&self, opcode: u8) -> u64 {
+        self.counts[opcode as usize]
+    }
+
+    /// total instructions executed
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// executed opcodes and their counts, most frequent first
+    pub fn sorted(&self) -> Vec<(u8, u64)> {
+        let mut counts: Vec<(u8, u64)> = (0..=255u8)
+            .map(|opcode| (opcode, self.count(opcode)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts
+    }
+
+    pub fn clear(&mut self) {
+        self.counts = [0; 256];
+    }
+}
+
+impl Observer for Histogram {
+    fn notify(&mut self, event: &Event) {
+        if let Event::InstructionRetired { opcode, .. } = event {
+            self.counts[*opcode as usize] += 1;
+        }
+    }
+}
+
+impl fmt::Display for Histogram {
+    /// one line per executed opcode, most frequent first
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        writeln!(f, "opcode  mnemonic  addressing         count       %")?;
+        for (opcode, count) in self.sorted() {
+            let (mnemonic, mode) = match op_codes::instruction(opcode) {
+                Some(info) => (info.mnemonic, format!("{:?}", info.mode)),
+                None => ("???", String::new()),
+            };
+            writeln!(
+                f,
+                "${opcode:02X}     {mnemonic:<8}  {mode:<17} {count:>7}  {:>6.2}",
+                count as f64 * 100.0 / total as f64
+            )?;
+        }
+        write!(f, "total {total}")
+    }
+}
+
+/// a routine as reached through a particular chain of calls
+#[derive(Debug, Clone)]
+struct CallNode {
+    /// entry address of the routine
+    routine: u16,
+    parent: Option<usize>,
+    children: BTreeMap<u16, usize>,
+    calls: u64,
+    /// cycles spent in the routine itself, excluding callees
+    self_cycles: u64,
+}
+
+/// totals for a routine across every call path that reached it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutineProfile {
+    pub routine: u16,
+    pub calls: u64,
+    pub self_cycles: u64,
+    /// cycles including callees, recursive calls are only counted once
+    pub total_cycles: u64,
+}
+
+/// attributes cycles to subroutines by following JSR and RTS
+/// the routine execution starts in is the root of the call graph
+#[derive(Debug, Clone, Default)]
+pub struct CallProfiler {
+    nodes: Vec<CallNode>,
+    /// node of the routine currently executing
+    current: usize,
+    /// a JSR just retired, the next instruction is the callee's entry point
+    entering: bool,
+    /// cycle count after the previous instruction
+    last_cycles: u64,
+}
+
+impl CallProfiler {
+    fn enter(&mut self, routine: u16) {
+        if self.nodes.is_empty() {
+            self.nodes.push(CallNode {
+                routine,
+                parent: None,
+                children: BTreeMap::new(),
+                calls: 1,
+                self_cycles: 0,
+            });
+            return;
+        }
+
+        let parent = self.current;
+        let child = match self.nodes[parent].children.get(&routine) {
+            Some(child) => *child,
+            None => {
+                self.nodes.push(CallNode {
+                    routine,
+                    parent: Some(parent),
+                    children: BTreeMap::new(),
+                    calls: 0,
+                    self_cycles: 0,
+                });
+                let child = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(routine, child);
+                child
+            }
+        };
+        self.nodes[child].calls += 1;
+        self.current = child;
+    }
+
+    /// cycles spent in a node and everything it called
+    fn total_cycles(&self, node: usize) -> u64 {
+        let node = &self.nodes[node];
+        node.self_cycles
+            + node
+                .children
+                .values()
+                .map(|child| self.total_cycles(*child))
+                .sum::<u64>()
+    }
+
+    /// whether a node's routine also appears further up its call chain
+    fn recursive(&self, node: usize) -> bool {
+        let routine = self.nodes[node].routine;
+        let mut parent = self.nodes[node].parent;
+        while let Some(ancestor) = parent {
+            if self.nodes[ancestor].routine == routine {
+                return true;
+            }
+            parent = self.nodes[ancestor].parent;
+        }
+        false
+    }
+
+    /// per-routine totals, most expensive first
+    pub fn flat(&self) -> Vec<RoutineProfile> {
+        let mut routines: BTreeMap<u16, RoutineProfile> = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let routine = routines.entry(node.routine).or_insert(RoutineProfile {
+                routine: node.routine,
+                calls: 0,
+                self_cycles: 0,
+                total_cycles: 0,
+            });
+            routine.calls += node.calls;
+            routine.self_cycles += node.self_cycles;
+            if !self.recursive(index) {
+                routine.total_cycles += self.total_cycles(index);
+            }
+        }
+
+        let mut routines: Vec<RoutineProfile> = routines.into_values().collect();
+        routines.sort_by_key(|routine| std::cmp::Reverse(routine.total_cycles));
+        routines
+    }
+
+    /// call graph as an indented tree with inclusive cycles per call path
+    pub fn tree(&self) -> String {
+        let mut out = String::new();
+        if !self.nodes.is_empty() {
+            self.write_tree(&mut out, 0, 0);
+        }
+        out
+    }
+
+    fn write_tree(&self, out: &mut String, node: usize, depth: usize) {
+        let info = &self.nodes[node];
+        out.push_str(&format!(
+            "{:indent$}${:04X}  {} cycles, {} calls\n",
+            "",
+            info.routine,
+            self.total_cycles(node),
+            info.calls,
+            indent = depth * 2
+        ));
+        for child in info.children.values() {
+            self.write_tree(out, *child, depth + 1);
+        }
+    }
+
+    /// the profile in callgrind format, readable by kcachegrind and friends
+    pub fn callgrind(&self) -> String {
+        // self cycles per routine and (calls, inclusive cycles) per callee
+        type Callees = BTreeMap<u16, (u64, u64)>;
+        let mut routines: BTreeMap<u16, (u64, Callees)> = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            routines.entry(node.routine).or_default().0 += node.self_cycles;
+            if let Some(parent) = node.parent {
+                let caller = self.nodes[parent].routine;
+                let call = routines
+                    .entry(caller)
+                    .or_default()
+                    .1
+                    .entry(node.routine)
+                    .or_default();
+                call.0 += node.calls;
+                call.1 += self.total_cycles(index);
+            }
+        }
+
+        let mut out = String::from("# callgrind format\nevents: Cycles\n");
+        for (routine, (self_cycles, callees)) in routines {
+            out.push_str(&format!("\nfn=${routine:04X}\n0 {self_cycles}\n"));
+            for (callee, (calls, cycles)) in callees {
+                out.push_str(&format!("cfn=${callee:04X}\ncalls={calls} 0\n0 {cycles}\n"));
+            }
+        }
+        out
+    }
+}
+
+impl Observer for CallProfiler {
+    fn notify(&mut self, event: &Event) {
+        let Event::InstructionRetired { pc, opcode, cycles } = *event else {
+            return;
+        };
+
+        if self.nodes.is_empty() || self.entering {
+            self.enter(pc);
+            self.entering = false;
+        }
+
+        self.nodes[self.current].self_cycles += cycles - self.last_cycles;
+        self.last_cycles = cycles;
+
+        match opcode {
+            JSR => self.entering = true,
+            RTS => {
+                if let Some(parent) = self.nodes[self.current].parent {
+                    self.current = parent;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for CallProfiler {
+    /// flat profile followed by the call tree
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "routine      self     total   calls")?;
+        for routine in self.flat() {
+            writeln!(
+                f,
+                "${:04X}  {:>9} {:>9} {:>7}",
+                routine.routine, routine.self_cycles, routine.total_cycles, routine.calls
+            )?;
+        }
+        write!(f, "\n{}", self.tree().trim_end())
+    }
+}
+
+/// how often a single branch instruction was taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchSite {
+    pub pc: u16,
+    pub opcode: u8,
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// taken/not-taken counts for every branch executed
+/// a branch counts as taken when it used more than its base cycles
+#[derive(Debug, Clone, Default)]
+pub struct BranchStats {
+    sites: BTreeMap<u16, BranchSite>,
+    /// cycle count after the previous instruction
+    last_cycles: u64,
+}
+
+impl BranchStats {
+    /// the branch at an address, if it was executed
+    pub fn site(&self, pc: u16) -> Option<&BranchSite> {
+        self.sites.get(&pc)
+    }
+
+    /// every executed branch, most executed first
+    pub fn sites(&self) -> Vec<BranchSite> {
+        let mut sites: Vec<BranchSite> = self.sites.values().copied().collect();
+        sites.sort_by_key(|site| std::cmp::Reverse(site.taken + site.not_taken));
+        sites
+    }
+}
+
+impl Observer for BranchStats {
+    fn notify(&mut self, event: &Event) {
+        let Event::InstructionRetired { pc, opcode, cycles } = *event else {
+            return;
+        };
+        let spent = cycles - self.last_cycles;
+        self.last_cycles = cycles;
+
+        if op_codes::is_branch(opcode) {
+            let site = self.sites.entry(pc).or_insert(BranchSite {
+                pc,
+                opcode,
+                taken: 0,
+                not_taken: 0,
+            });
+            if spent > CYCLES[opcode as usize] as u64 {
+                site.taken += 1;
+            } else {
+                site.not_taken += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for BranchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "branch          taken  not taken")?;
+        for site in self.sites() {
+            let mnemonic = op_codes::instruction(site.opcode).map_or("???", |info| info.mnemonic);
+            write!(
+                f,
+                "\n${:04X}  {mnemonic}  {:>9}  {:>9}",
+                site.pc, site.taken, site.not_taken
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    #[test]
+    fn histogram_counts_retired_instructions() {
+        let histogram = Rc::new(RefCell::new(Histogram::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x01, TAX, TAX, TAY, NOP])
+            .observer(histogram.clone())
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+
+        let histogram = histogram.borrow();
+        assert_eq!(histogram.count(TAX), 2);
+        assert_eq!(histogram.total(), 4);
+        assert_eq!(histogram.sorted(), vec![(TAX, 2), (TAY, 1), (LDA_IM, 1)]);
+    }
+
+    #[test]
+    fn histogram_report() {
+        let mut histogram = Histogram::default();
+        for opcode in [TAX, TAX, TAX, LDA_IM] {
+            histogram.notify(&Event::InstructionRetired {
+                pc: 0,
+                opcode,
+                cycles: 0,
+            });
+        }
+
+        assert_eq!(
+            histogram.to_string(),
+            "opcode  mnemonic  addressing         count       %\n\
+             $AA     TAX       Implied                 3   75.00\n\
+             $A9     LDA       Immediate               1   25.00\n\
+             total 4"
+        );
+    }
+
+    /// $0600: JSR $0700 / JSR $0780 / LDX #$01 / NOP
+    /// $0700: JSR $0780 / RTS
+    /// $0780: TAX / RTS
+    fn profiled_program() -> CallProfiler {
+        let profiler = Rc::new(RefCell::new(CallProfiler::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(
+                0x0600,
+                vec![JSR, 0x00, 0x07, JSR, 0x80, 0x07, LDX_IM, 0x01, NOP],
+            )
+            .memory(0x0700, vec![JSR, 0x80, 0x07, RTS])
+            .memory(0x0780, vec![TAX, RTS])
+            .observer(profiler.clone())
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+        // both calls came back to the caller, which ran on to its NOP
+        assert_eq!((cpu.pc(), cpu.x(), cpu.sp()), (0x0609, 0x01, 0x01FF));
+
+        let profiler = profiler.borrow().clone();
+        profiler
+    }
+
+    #[test]
+    fn call_profiler_flat_profile() {
+        let profiler = profiled_program();
+        assert_eq!(
+            profiler.flat(),
+            vec![
+                // the LDX after the last return counts to the caller
+                RoutineProfile {
+                    routine: 0x0600,
+                    calls: 1,
+                    self_cycles: 14,
+                    total_cycles: 42
+                },
+                RoutineProfile {
+                    routine: 0x0700,
+                    calls: 1,
+                    self_cycles: 12,
+                    total_cycles: 20
+                },
+                RoutineProfile {
+                    routine: 0x0780,
+                    calls: 2,
+                    self_cycles: 16,
+                    total_cycles: 16
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn call_profiler_tree_and_callgrind() {
+        let profiler = profiled_program();
+        assert_eq!(
+            profiler.tree(),
+            "$0600  42 cycles, 1 calls\n  $0700  20 cycles, 1 calls\n    $0780  8 cycles, 1 calls\n  $0780  8 cycles, 1 calls\n"
+        );
+        assert_eq!(
+            profiler.callgrind(),
+            "# callgrind format\nevents: Cycles\n\
+             \nfn=$0600\n0 14\ncfn=$0700\ncalls=1 0\n0 20\ncfn=$0780\ncalls=1 0\n0 8\n\
+             \nfn=$0700\n0 12\ncfn=$0780\ncalls=1 0\n0 8\n\
+             \nfn=$0780\n0 16\n"
+        );
+    }
+
+    #[test]
+    fn branch_stats_count_taken_and_not_taken() {
+        // LDX #$07 / loop: TXA / LSR A / TAX / BNE loop / NOP
+        let stats = Rc::new(RefCell::new(BranchStats::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(
+                0x0600,
+                vec![LDX_IM, 0x07, TXA, LSR_ACC, TAX, BNE, 0xFB, NOP],
+            )
+            .observer(stats.clone())
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+
+        let stats = stats.borrow();
+        assert_eq!(
+            stats.sites(),
+            vec![BranchSite {
+                pc: 0x0605,
+                opcode: BNE,
+                taken: 2,
+                not_taken: 1
+            }]
+        );
+        assert_eq!(
+            stats.to_string(),
+            "branch          taken  not taken\n$0605  BNE          2          1"
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! build machine code from rust, without going through the assembler's text
+//!
+//! each implemented instruction has a method named after its mnemonic and
+//! addressing mode, jumps and branches take either an address or a label that
+//! can be defined before or after it's used
+//!
+//! ```
+//! use cpu_emu::{program::Program, Cpu};
+//!
+//! let program = Program::at(0x0600)
+//!     .ldx_imm(0x04)
+//!     .label("loop")
+//!     .lsr_acc()
+//!     .ldy_imm(0x00)
+//!     .beq("done")
+//!     .jmp_abs("loop")
+//!     .label("done")
+//!     .nop();
+//! let mut cpu = Cpu::builder()
+//!     .pc(program.origin())
+//!     .memory(program.origin() as usize, program.build()?)
+//!     .build()?;
+//! cpu.execute()?;
+//! assert_eq!(cpu.pc(), 0x060B);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::op_codes;
+
+/// errors found when a program's labels are resolved
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ProgramError {
+    /// a label was used but never defined
+    #[error("undefined label {0}")]
+    UndefinedLabel(String),
+    /// a label was defined twice
+    #[error("label {0} is defined more than once")]
+    DuplicateLabel(String),
+    /// a branch target is more than 128 bytes away
+    #[error("branch target ${0:04X} is out of range")]
+    BranchOutOfRange(u16),
+}
+
+/// where a jump or branch goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Address(u16),
+    Label(String),
+}
+
+impl From<u16> for Target {
+    fn from(address: u16) -> Self {
+        Target::Address(address)
+    }
+}
+
+impl From<&str> for Target {
+    fn from(label: &str) -> Self {
+        Target::Label(label.to_string())
+    }
+}
+
+impl From<String> for Target {
+    fn from(label: String) -> Self {
+        Target::Label(label)
+    }
+}
+
+/// an operand waiting on its target's address
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fixup {
+    /// offset of the operand in the program
+    offset: usize,
+    target: Target,
+    relative: bool,
+}
+
+/// machine code placed from an origin, with the labels defined in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    origin: u16,
+    bytes: Vec<u8>,
+    labels: HashMap<String, u16>,
+    fixups: Vec<Fixup>,
+    /// the first label defined twice, reported when the program is built
+    duplicate: Option<String>,
+}
+
+macro_rules! implied {
+    ($($name:ident => $opcode:ident, $syntax:literal;)*) => {
+        $(
+            #[doc = concat!("`", $syntax, "`")]
+            pub fn $name(self) -> Self {
+                self.byte(op_codes::$opcode)
+            }
+        )*
+    };
+}
+
+macro_rules! byte_operand {
+    ($($name:ident => $opcode:ident, $syntax:literal;)*) => {
+        $(
+            #[doc = concat!("`", $syntax, "`")]
+            pub fn $name(self, operand: u8) -> Self {
+                self.byte(op_codes::$opcode).byte(operand)
+            }
+        )*
+    };
+}
+
+macro_rules! word_operand {
+    ($($name:ident => $opcode:ident, $syntax:literal;)*) => {
+        $(
+            #[doc = concat!("`", $syntax, "`")]
+            pub fn $name(self, operand: u16) -> Self {
+                self.byte(op_codes::$opcode).word(operand)
+            }
+        )*
+    };
+}
+
+macro_rules! absolute_target {
+    ($($name:ident => $opcode:ident, $syntax:literal;)*) => {
+        $(
+            #[doc = concat!("`", $syntax, "`")]
+            pub fn $name(self, target: impl Into<Target>) -> Self {
+                self.byte(op_codes::$opcode).target(target.into(), false)
+            }
+        )*
+    };
+}
+
+macro_rules! branch {
+    ($($name:ident => $opcode:ident;)*) => {
+        $(
+            #[doc = concat!("`", stringify!($opcode), " target`")]
+            pub fn $name(self, target: impl Into<Target>) -> Self {
+                self.byte(op_codes::$opcode).target(target.into(), true)
+            }
+        )*
+    };
+}
+
+impl Program {
+    /// an empty program that will be placed at `origin`
+    pub fn at(origin: u16) -> Self {
+        Self {
+            origin,
+            bytes: Vec::new(),
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+            duplicate: None,
+        }
+    }
+
+    pub fn origin(&self) -> u16 {
+        self.origin
+    }
+
+    /// address the next byte will be placed at
+    pub fn here(&self) -> u16 {
+        self.origin.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// address of a label defined so far
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.labels.get(label).copied()
+    }
+
+    /// name the address the next byte will be placed at
+    pub fn label(mut self, name: &str) -> Self {
+        let here = self.here();
+        if self.labels.insert(name.to_string(), here).is_some() && self.duplicate.is_none() {
+            self.duplicate = Some(name.to_string());
+        }
+        self
+    }
+
+    /// emit a raw byte
+    pub fn byte(mut self, byte: u8) -> Self {
+        self.bytes.push(byte);
+        self
+    }
+
+    /// emit raw bytes, data tables or opcodes without a method
+    pub fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    /// emit a little endian word
+    pub fn word(self, word: u16) -> Self {
+        self.bytes(&word.to_le_bytes())
+    }
+
+    /// the machine code with every label resolved
+    pub fn build(&self) -> Result<Vec<u8>, ProgramError> {
+        if let Some(label) = &self.duplicate {
+            return Err(ProgramError::DuplicateLabel(label.clone()));
+        }
+
+        let mut bytes = self.bytes.clone();
+        for fixup in &self.fixups {
+            let address = match &fixup.target {
+                Target::Address(address) => *address,
+                Target::Label(label) => self
+                    .address_of(label)
+                    .ok_or_else(|| ProgramError::UndefinedLabel(label.clone()))?,
+            };
+            if fixup.relative {
+                let next = self.origin.wrapping_add(fixup.offset as u16 + 1);
+                let offset = address.wrapping_sub(next) as i16;
+                let offset =
+                    i8::try_from(offset).map_err(|_| ProgramError::BranchOutOfRange(address))?;
+                bytes[fixup.offset] = offset as u8;
+            } else {
+                bytes[fixup.offset..fixup.offset + 2].copy_from_slice(&address.to_le_bytes());
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// leave room for an operand resolved when the program is built
+    fn target(mut self, target: Target, relative: bool) -> Self {
+        self.fixups.push(Fixup {
+            offset: self.bytes.len(),
+            target,
+            relative,
+        });
+        let len = if relative { 1 } else { 2 };
+        self.bytes.resize(self.bytes.len() + len, 0);
+        self
+    }
+
+    implied! {
+        nop => NOP, "NOP";
+        rts => RTS, "RTS";
+        brk => BRK, "BRK";
+        rti => RTI, "RTI";
+        lsr_acc => LSR_ACC, "LSR A";
+        pha => PHA, "PHA";
+        php => PHP, "PHP";
+        pla => PLA, "PLA";
+        plp => PLP, "PLP";
+        tax => TAX, "TAX";
+        tay => TAY, "TAY";
+        tsx => TSX, "TSX";
+        txa => TXA, "TXA";
+        txs => TXS, "TXS";
+        tya => TYA, "TYA";
+        sec => SEC, "SEC";
+        sed => SED, "SED";
+        sei => SEI, "SEI";
+        cli => CLI, "CLI";
+    }
+
+    byte_operand! {
+        lda_imm => LDA_IM, "LDA #$nn";
+        lda_zp => LDA_ZP, "LDA $nn";
+        lda_zp_x => LDA_ZP_X, "LDA $nn,X";
+        lda_ind_x => LDA_ZP_XI, "LDA ($nn,X)";
+        lda_ind_y => LDA_ZP_IY, "LDA ($nn),Y";
+        ldx_imm => LDX_IM, "LDX #$nn";
+        ldx_zp => LDX_ZP, "LDX $nn";
+        ldx_zp_y => LDX_ZP_Y, "LDX $nn,Y";
+        ldy_imm => LDY_IM, "LDY #$nn";
+        ldy_zp => LDY_ZP, "LDY $nn";
+        ldy_zp_x => LDY_ZP_X, "LDY $nn,X";
+        lsr_zp => LSR_ZP, "LSR $nn";
+        lsr_zp_x => LSR_ZP_X, "LSR $nn,X";
+        and_imm => ANDA_IM, "AND #$nn";
+        and_zp => ANDA_ZP, "AND $nn";
+        and_zp_x => ANDA_ZP_X, "AND $nn,X";
+        and_ind_x => ANDA_ZP_XI, "AND ($nn,X)";
+        and_ind_y => ANDA_ZP_IY, "AND ($nn),Y";
+        ora_imm => ORA_IM, "ORA #$nn";
+        ora_zp => ORA_ZP, "ORA $nn";
+        ora_zp_x => ORA_ZP_X, "ORA $nn,X";
+        ora_ind_x => ORA_ZP_XI, "ORA ($nn,X)";
+        ora_ind_y => ORA_ZP_IY, "ORA ($nn),Y";
+    }
+
+    word_operand! {
+        lda_abs => LDA_ABS, "LDA $nnnn";
+        lda_abs_x => LDA_ABS_X, "LDA $nnnn,X";
+        lda_abs_y => LDA_ABS_Y, "LDA $nnnn,Y";
+        ldx_abs => LDX_ABS, "LDX $nnnn";
+        ldx_abs_y => LDX_ABS_Y, "LDX $nnnn,Y";
+        ldy_abs => LDY_ABS, "LDY $nnnn";
+        ldy_abs_x => LDY_ABS_X, "LDY $nnnn,X";
+        lsr_abs => LSR_ABS, "LSR $nnnn";
+        lsr_abs_x => LSR_ABS_X, "LSR $nnnn,X";
+        and_abs => ANDA_ABS, "AND $nnnn";
+        and_abs_x => ANDA_X_ABS, "AND $nnnn,X";
+        and_abs_y => ANDA_Y_ABS, "AND $nnnn,Y";
+        ora_abs => ORA_ABS, "ORA $nnnn";
+        ora_abs_x => ORA_X_ABS, "ORA $nnnn,X";
+        ora_abs_y => ORA_Y_ABS, "ORA $nnnn,Y";
+        jmp_ind => JMP_ABS_IND, "JMP ($nnnn)";
+    }
+
+    absolute_target! {
+        jmp_abs => JMP_ABS, "JMP target";
+        jsr => JSR, "JSR target";
+    }
+
+    branch! {
+        bcc => BCC;
+        bcs => BCS;
+        beq => BEQ;
+        bmi => BMI;
+        bne => BNE;
+        bpl => BPL;
+        bvc => BVC;
+        bvs => BVS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn programs_match_the_assembler() {
+        let program = Program::at(0x0600)
+            .label("start")
+            .lda_imm(0x42)
+            .ldx_abs_y(0x1234)
+            .lsr_zp(0x10)
+            .bne("start")
+            .jsr("end")
+            .label("end")
+            .nop();
+        let source = "LDA #$42\nLDX $1234,Y\nLSR $10\nBNE $0600\nJSR $060C\nNOP";
+        assert_eq!(program.build().unwrap(), assemble(source, 0x0600).unwrap());
+        assert_eq!(program.address_of("end"), Some(0x060C));
+        assert_eq!(program.here(), 0x060D);
+    }
+
+    #[test]
+    fn labels_must_resolve() {
+        assert_eq!(
+            Program::at(0).jmp_abs("nowhere").build(),
+            Err(ProgramError::UndefinedLabel("nowhere".to_string()))
+        );
+        assert_eq!(
+            Program::at(0).label("a").nop().label("a").build(),
+            Err(ProgramError::DuplicateLabel("a".to_string()))
+        );
+        assert_eq!(
+            Program::at(0x0600).beq(0x0700).build(),
+            Err(ProgramError::BranchOutOfRange(0x0700))
+        );
+        assert_eq!(
+            Program::at(0x0600).bcs(0x0600u16).build().unwrap(),
+            [op_codes::BCS, 0xFE]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! a pseudo-terminal serial port, so `screen`, `minicom` and anything else
+//! expecting a tty can attach to the ACIA like a real board's serial line
+
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    os::fd::{AsRawFd, FromRawFd},
+    path::{Path, PathBuf},
+};
+
+use crate::acia::SerialPort;
+
+/// the emulator's end of a pseudo-terminal, terminal programs open [`path`](PtyPort::path)
+///
+/// the terminal end is held open in raw mode so the line passes bytes through
+/// untouched and survives terminals detaching, bytes sent while the
+/// terminal's buffer is full are dropped
+#[derive(Debug)]
+pub struct PtyPort {
+    master: File,
+    /// kept open, see above
+    _terminal: File,
+    path: PathBuf,
+}
+
+impl PtyPort {
+    /// open a new pseudo-terminal
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: the descriptor is owned by the file as soon as it's open and
+        // the name ptsname returns is copied before anything else can call it
+        let (master, path) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = PathBuf::from(CStr::from_ptr(name).to_string_lossy().into_owned());
+            (master, path)
+        };
+
+        let terminal = OpenOptions::new().read(true).write(true).open(&path)?;
+        make_raw(&terminal)?;
+        Ok(Self {
+            master,
+            _terminal: terminal,
+            path,
+        })
+    }
+
+    /// the terminal device to attach to, e.g. `/dev/pts/3`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// turn off echo, line editing and newline translation on a terminal
+fn make_raw(terminal: &File) -> io::Result<()> {
+    // SAFETY: termios is plain data filled in by tcgetattr before it's used
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(terminal.as_raw_fd(), &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(terminal.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl SerialPort for PtyPort {
+    fn receive(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        match self.master.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        loop {
+            match self.master.write(&[byte]) {
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                _ => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{acia::Acia, device::Device};
+
+    #[test]
+    fn terminals_talk_to_the_acia() {
+        let port = PtyPort::open().unwrap();
+        let mut terminal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(port.path())
+            .unwrap();
+        let mut acia = Acia::new(port);
+
+        // raw mode passes newlines through as they are
+        terminal.write_all(b"\r").unwrap();
+        let mut received = None;
+        for _ in 0..100 {
+            received = Some(acia.read(0)).filter(|byte| *byte != 0);
+            if received.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(received, Some(b'\r'));
+
+        acia.write(0, b'\n');
+        let mut byte = [0];
+        terminal.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, *b"\n");
+    }
+}
@@ -0,0 +1,397 @@
+//! check a program against cases of starting and expected states, for
+//! exercises graded by running the submitted program
+//!
+//! ```
+//! use cpu_emu::{op_codes::*, quiz::{Quiz, State}};
+//!
+//! // copy A into X and halve A
+//! let report = Quiz::new(vec![TAX, LSR_ACC], 0x0600)
+//!     .case("halves", State::new().a(0x42), State::new().a(0x21).x(0x42))
+//!     .case("odd", State::new().a(0x03), State::new().a(0x01).carry(true))
+//!     .run()?;
+//! assert!(report.passed());
+//! # Ok::<(), cpu_emu::BusError>(())
+//! ```
+
+use core::fmt;
+
+use crate::{
+    cpu::{Cpu, CpuError},
+    memory::BusError,
+    op_codes::NOP,
+    processor_status::ProcessorStatus,
+    registers::Registers,
+};
+
+/// instructions a case may run before it's failed as never halting
+pub const DEFAULT_LIMIT: u64 = 1_000_000;
+
+/// registers, flags and memory a case starts from or should end with, parts
+/// left unset are left alone or not checked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    a: Option<u8>,
+    x: Option<u8>,
+    y: Option<u8>,
+    sp: Option<u16>,
+    flags: Vec<(ProcessorStatus, bool)>,
+    memory: Vec<(u16, Vec<u8>)>,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn a(mut self, a: u8) -> Self {
+        self.a = Some(a);
+        self
+    }
+
+    pub fn x(mut self, x: u8) -> Self {
+        self.x = Some(x);
+        self
+    }
+
+    pub fn y(mut self, y: u8) -> Self {
+        self.y = Some(y);
+        self
+    }
+
+    pub fn sp(mut self, sp: u16) -> Self {
+        self.sp = Some(sp);
+        self
+    }
+
+    /// a flag set or clear, e.g. `ProcessorStatus::Z`
+    pub fn flag(mut self, flag: ProcessorStatus, set: bool) -> Self {
+        self.flags.push((flag, set));
+        self
+    }
+
+    pub fn carry(self, set: bool) -> Self {
+        self.flag(ProcessorStatus::C, set)
+    }
+
+    pub fn zero(self, set: bool) -> Self {
+        self.flag(ProcessorStatus::Z, set)
+    }
+
+    pub fn negative(self, set: bool) -> Self {
+        self.flag(ProcessorStatus::N, set)
+    }
+
+    /// bytes in memory from `address`
+    pub fn memory(mut self, address: u16, bytes: Vec<u8>) -> Self {
+        self.memory.push((address, bytes));
+        self
+    }
+
+    fn apply(&self, cpu: &mut Cpu) {
+        let mut registers = cpu.registers();
+        registers.a = self.a.unwrap_or(registers.a);
+        registers.x = self.x.unwrap_or(registers.x);
+        registers.y = self.y.unwrap_or(registers.y);
+        registers.sp = self.sp.unwrap_or(registers.sp);
+        for (flag, set) in &self.flags {
+            registers.status.set(*flag, *set);
+        }
+        cpu.set_registers(registers);
+        for (address, bytes) in &self.memory {
+            for (offset, byte) in bytes.iter().enumerate() {
+                let address = address.wrapping_add(offset as u16);
+                cpu.memory.write_byte(address as usize, *byte);
+            }
+        }
+    }
+
+    /// where `cpu` differs from this state
+    fn compare(&self, cpu: &Cpu) -> Vec<Mismatch> {
+        let registers = cpu.registers();
+        let mut mismatches = Vec::new();
+        let bytes = [
+            ("A", self.a, registers.a),
+            ("X", self.x, registers.x),
+            ("Y", self.y, registers.y),
+        ];
+        for (name, expected, actual) in bytes {
+            if let Some(expected) = expected.filter(|expected| *expected != actual) {
+                mismatches.push(Mismatch::new(
+                    name,
+                    format!("${expected:02X}"),
+                    format!("${actual:02X}"),
+                ));
+            }
+        }
+        if let Some(expected) = self.sp.filter(|sp| *sp != registers.sp) {
+            mismatches.push(Mismatch::new(
+                "SP",
+                format!("${expected:04X}"),
+                format!("${:04X}", registers.sp),
+            ));
+        }
+        for (flag, set) in &self.flags {
+            let actual = registers.status.contains(*flag);
+            if actual != *set {
+                let state = |set: bool| if set { "set" } else { "clear" }.to_string();
+                mismatches.push(Mismatch::new(
+                    &format!("{} flag", flag_name(*flag)),
+                    state(*set),
+                    state(actual),
+                ));
+            }
+        }
+        for (start, bytes) in &self.memory {
+            for (offset, expected) in bytes.iter().enumerate() {
+                let address = start.wrapping_add(offset as u16);
+                let actual = cpu.memory.read_byte(address as usize);
+                if actual != *expected {
+                    mismatches.push(Mismatch::new(
+                        &format!("${address:04X}"),
+                        format!("${expected:02X}"),
+                        format!("${actual:02X}"),
+                    ));
+                }
+            }
+        }
+        mismatches
+    }
+}
+
+/// the flag's name, or its bits if it's several
+fn flag_name(flag: ProcessorStatus) -> String {
+    match flag.names()[..] {
+        [name] => name.to_string(),
+        _ => format!("{:#04x}", flag.bits()),
+    }
+}
+
+/// one thing a case left different from what was expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// the register, flag or address
+    pub what: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Mismatch {
+    fn new(what: &str, expected: String, actual: String) -> Self {
+        Self {
+            what: what.to_string(),
+            expected,
+            actual,
+        }
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, got {}",
+            self.what, self.expected, self.actual
+        )
+    }
+}
+
+/// how one case went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseResult {
+    pub name: String,
+    /// how the run stopped, an error fails the case whatever the state
+    pub result: Result<(), CpuError>,
+    pub mismatches: Vec<Mismatch>,
+    /// the registers the run ended with
+    pub registers: Registers,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.result.is_ok() && self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for CaseResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.passed() {
+            true => write!(f, "PASS {}", self.name)?,
+            false => write!(f, "FAIL {}", self.name)?,
+        }
+        if let Err(err) = &self.result {
+            write!(f, "\n  {err}")?;
+        }
+        for mismatch in &self.mismatches {
+            write!(f, "\n  {mismatch}")?;
+        }
+        Ok(())
+    }
+}
+
+/// every case's result, in the order they were added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub cases: Vec<CaseResult>,
+}
+
+impl Report {
+    /// whether every case passed
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(CaseResult::passed)
+    }
+
+    pub fn passed_count(&self) -> usize {
+        self.cases.iter().filter(|case| case.passed()).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for case in &self.cases {
+            writeln!(f, "{case}")?;
+        }
+        write!(f, "{} of {} passed", self.passed_count(), self.cases.len())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Case {
+    name: String,
+    given: State,
+    expect: State,
+}
+
+/// a program and the cases it's checked against
+#[derive(Debug, Clone)]
+pub struct Quiz {
+    program: Vec<u8>,
+    origin: u16,
+    limit: u64,
+    cases: Vec<Case>,
+}
+
+impl Quiz {
+    /// cases for `program` loaded and started at `origin`, a NOP is appended
+    /// so a program that runs off its end halts
+    pub fn new(program: Vec<u8>, origin: u16) -> Self {
+        Self {
+            program,
+            origin,
+            limit: DEFAULT_LIMIT,
+            cases: Vec::new(),
+        }
+    }
+
+    /// instructions a case may run before it fails as never halting
+    pub fn instruction_limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// a case starting from `given` that should end as `expect`
+    pub fn case(mut self, name: &str, given: State, expect: State) -> Self {
+        self.cases.push(Case {
+            name: name.to_string(),
+            given,
+            expect,
+        });
+        self
+    }
+
+    /// run every case from a fresh cpu, fails if the program runs past the
+    /// end of memory
+    pub fn run(&self) -> Result<Report, BusError> {
+        let mut program = self.program.clone();
+        program.push(NOP);
+        let fresh = Cpu::builder()
+            .pc(self.origin)
+            .memory(self.origin as usize, program)
+            .instruction_limit(self.limit)
+            .build()?;
+        let cases = self
+            .cases
+            .iter()
+            .map(|case| {
+                let mut cpu = fresh.clone();
+                case.given.apply(&mut cpu);
+                let result = cpu.execute();
+                CaseResult {
+                    name: case.name.clone(),
+                    mismatches: case.expect.compare(&cpu),
+                    result,
+                    registers: cpu.registers(),
+                }
+            })
+            .collect();
+        Ok(Report { cases })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    /// A's high nibble moved to its low nibble, and $10 halved
+    fn quiz() -> Quiz {
+        Quiz::new(
+            vec![LSR_ACC, LSR_ACC, LSR_ACC, LSR_ACC, LSR_ZP, 0x10],
+            0x0600,
+        )
+    }
+
+    #[test]
+    fn passing_cases_report_nothing_else() {
+        let report = quiz()
+            .case(
+                "high nibble",
+                State::new().a(0xF0).memory(0x10, vec![0x08]),
+                State::new().a(0x0F).zero(false).memory(0x10, vec![0x04]),
+            )
+            .run()
+            .unwrap();
+        assert!(report.passed());
+        assert_eq!(report.to_string(), "PASS high nibble\n1 of 1 passed");
+    }
+
+    #[test]
+    fn failing_cases_list_their_differences() {
+        let report = quiz()
+            .case("ok", State::new().a(0x10), State::new().a(0x01))
+            .case(
+                "wrong",
+                State::new().a(0x80).memory(0x10, vec![0x03]),
+                State::new()
+                    .a(0x09)
+                    .x(0x00)
+                    .carry(false)
+                    .memory(0x10, vec![0x01, 0x00]),
+            )
+            .run()
+            .unwrap();
+        assert!(!report.passed());
+        assert_eq!(report.passed_count(), 1);
+        assert_eq!(
+            report.cases[1].to_string(),
+            "FAIL wrong\n  A: expected $09, got $08\n  carry flag: expected clear, got set"
+        );
+        assert!(report.to_string().ends_with("\n1 of 2 passed"));
+    }
+
+    #[test]
+    fn runaway_programs_fail_at_the_limit() {
+        let report = Quiz::new(vec![JMP_ABS, 0x00, 0x06], 0x0600)
+            .instruction_limit(100)
+            .case("loops", State::new(), State::new())
+            .run()
+            .unwrap();
+        assert!(!report.passed());
+        assert_eq!(
+            report.cases[0].result,
+            Err(CpuError::InstructionLimit {
+                limit: 100,
+                pc: 0x0600
+            })
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! the randomness a machine has, RAM that powers up full of noise and a
+//! device reading back random bytes, all derived from the one seed given to
+//! [`CpuBuilder::seed`](crate::CpuBuilder::seed) so runs with the same seed
+//! are identical to the bit
+//!
+//! each part gets its own generator, seeded from the machine's seed and the
+//! part's name, so turning one on doesn't change what another produces
+//!
+//! the random device at its address
+//!
+//! | offset | read                 | write            |
+//! |--------|----------------------|------------------|
+//! | 0      | the next random byte | ignored          |
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::device::Device;
+
+/// addresses the random device takes up
+pub const LEN: usize = 1;
+
+/// where the random device is mapped unless told otherwise, the byte easy6502
+/// programs read for theirs
+pub const DEFAULT_BASE: u16 = 0x00FE;
+
+/// xorshift, enough for noise without pulling in a dependency
+#[derive(Debug, Clone)]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// the generator for one part of a machine seeded with `seed`
+    pub fn derive(seed: u64, part: &str) -> Self {
+        // FNV-1a over the name, then splitmix64 to spread it with the seed
+        let name = part.bytes().fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+        });
+        let mut mixed = (seed ^ name).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self::new(mixed ^ (mixed >> 31))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        self.next_u64() as u8
+    }
+
+    pub fn fill(&mut self, bytes: &mut [u8]) {
+        bytes.iter_mut().for_each(|byte| *byte = self.byte());
+    }
+}
+
+/// a seed from the clock, for machines not given one
+pub fn entropy_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.as_nanos() as u64)
+}
+
+/// reads back a random byte each time
+#[derive(Debug, Clone)]
+pub struct RandomDevice {
+    random: Random,
+}
+
+impl RandomDevice {
+    pub fn new(random: Random) -> Self {
+        Self { random }
+    }
+}
+
+impl Device for RandomDevice {
+    fn read(&mut self, _offset: u16) -> u8 {
+        self.random.byte()
+    }
+
+    fn write(&mut self, _offset: u16, _value: u8) {}
+
+    fn name(&self) -> Option<&str> {
+        Some("RNG")
+    }
+
+    fn register_name(&self, _offset: u16) -> Option<&'static str> {
+        Some("DATA")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_get_their_own_streams() {
+        let bytes = |seed, part| {
+            let mut random = Random::derive(seed, part);
+            (0..8).map(|_| random.byte()).collect::<Vec<_>>()
+        };
+        assert_eq!(bytes(42, "ram"), bytes(42, "ram"));
+        assert_ne!(bytes(42, "ram"), bytes(43, "ram"));
+        assert_ne!(bytes(42, "ram"), bytes(42, "device"));
+    }
+}
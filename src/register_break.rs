@@ -0,0 +1,226 @@
+use core::fmt;
+
+use crate::{cpu::Cpu, processor_status::ProcessorStatus};
+
+/// a register or status flag a breakpoint can watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    /// a single status flag, read as 0 or 1
+    Flag(ProcessorStatus),
+}
+
+/// register values before or after an instruction
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Snapshot {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    ps: ProcessorStatus,
+}
+
+impl Snapshot {
+    pub(crate) fn of(cpu: &Cpu) -> Self {
+        Self {
+            pc: cpu.pc(),
+            sp: cpu.sp(),
+            a: cpu.a(),
+            x: cpu.x(),
+            y: cpu.y(),
+            ps: cpu.status(),
+        }
+    }
+
+    fn read(&self, register: Register) -> u16 {
+        match register {
+            Register::A => self.a as u16,
+            Register::X => self.x as u16,
+            Register::Y => self.y as u16,
+            // the 8 bit register, without the page 1 the cpu keeps it in
+            Register::Sp => self.sp as u8 as u16,
+            Register::Pc => self.pc,
+            Register::Flag(flag) => self.ps.contains(flag) as u16,
+        }
+    }
+}
+
+/// what has to happen to the register for the breakpoint to trip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Changed,
+    Equals(u16),
+    Below(u16),
+    Above(u16),
+}
+
+/// stop execution when a register or flag changes in a chosen way
+/// comparisons trip when an instruction makes them true, not while they stay true
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterBreak {
+    pub register: Register,
+    pub trigger: Trigger,
+}
+
+impl RegisterBreak {
+    pub fn new(register: Register, trigger: Trigger) -> Self {
+        Self { register, trigger }
+    }
+
+    /// parse a condition such as `d set`, `sp < 20`, `a = 42` or `x changed`
+    /// values are hex with an optional `$` prefix
+    pub fn parse(condition: &str) -> Option<Self> {
+        let mut words = condition.split_whitespace();
+        let register = match words.next()?.to_ascii_lowercase().as_str() {
+            "a" => Register::A,
+            "x" => Register::X,
+            "y" => Register::Y,
+            "sp" => Register::Sp,
+            "pc" => Register::Pc,
+            flag => Register::Flag(parse_flag(flag)?),
+        };
+        let operator = words.next()?;
+        let value = words
+            .next()
+            .and_then(|value| u16::from_str_radix(value.trim_start_matches('$'), 16).ok());
+        let trigger = match operator {
+            "changed" => Trigger::Changed,
+            "set" => Trigger::Equals(1),
+            "clear" => Trigger::Equals(0),
+            "=" | "==" => Trigger::Equals(value?),
+            "<" => Trigger::Below(value?),
+            ">" => Trigger::Above(value?),
+            _ => return None,
+        };
+        Some(Self::new(register, trigger))
+    }
+
+    /// whether going from `before` to `after` trips the breakpoint
+    pub(crate) fn tripped(&self, before: &Snapshot, after: &Snapshot) -> bool {
+        let old = before.read(self.register);
+        let new = after.read(self.register);
+        match self.trigger {
+            Trigger::Changed => old != new,
+            Trigger::Equals(value) => new == value && old != value,
+            Trigger::Below(value) => new < value && old >= value,
+            Trigger::Above(value) => new > value && old <= value,
+        }
+    }
+}
+
+fn parse_flag(flag: &str) -> Option<ProcessorStatus> {
+    Some(match flag {
+        "n" => ProcessorStatus::N,
+        "v" => ProcessorStatus::V,
+        "b" => ProcessorStatus::B,
+        "d" => ProcessorStatus::D,
+        "i" => ProcessorStatus::I,
+        "z" => ProcessorStatus::Z,
+        "c" => ProcessorStatus::C,
+        _ => return None,
+    })
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Register::A => "a",
+            Register::X => "x",
+            Register::Y => "y",
+            Register::Sp => "sp",
+            Register::Pc => "pc",
+            Register::Flag(ProcessorStatus::N) => "n",
+            Register::Flag(ProcessorStatus::V) => "v",
+            Register::Flag(ProcessorStatus::B) => "b",
+            Register::Flag(ProcessorStatus::D) => "d",
+            Register::Flag(ProcessorStatus::I) => "i",
+            Register::Flag(ProcessorStatus::Z) => "z",
+            Register::Flag(ProcessorStatus::C) => "c",
+            Register::Flag(flag) => return write!(f, "{:08b}", flag.bits()),
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for RegisterBreak {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.register, self.trigger) {
+            (register, Trigger::Changed) => write!(f, "{register} changed"),
+            (Register::Flag(_), Trigger::Equals(1)) => write!(f, "{} set", self.register),
+            (Register::Flag(_), Trigger::Equals(0)) => write!(f, "{} clear", self.register),
+            (register, Trigger::Equals(value)) => write!(f, "{register} = ${value:02X}"),
+            (register, Trigger::Below(value)) => write!(f, "{register} < ${value:02X}"),
+            (register, Trigger::Above(value)) => write!(f, "{register} > ${value:02X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn parse_conditions() {
+        assert_eq!(
+            RegisterBreak::parse("d set"),
+            Some(RegisterBreak::new(
+                Register::Flag(ProcessorStatus::D),
+                Trigger::Equals(1)
+            ))
+        );
+        assert_eq!(
+            RegisterBreak::parse("SP < $20"),
+            Some(RegisterBreak::new(Register::Sp, Trigger::Below(0x20)))
+        );
+        assert_eq!(RegisterBreak::parse("q set"), None);
+        assert_eq!(RegisterBreak::parse("a <"), None);
+        assert_eq!(
+            RegisterBreak::parse("sp < 20").unwrap().to_string(),
+            "sp < $20"
+        );
+    }
+
+    #[test]
+    fn execute_stops_when_a_register_break_trips() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .decimal_mode(true)
+            .memory(0x0600, vec![LDA_IM, 0x01, SEC, SED, TAX, NOP])
+            .build()
+            .unwrap();
+        cpu.add_register_break(RegisterBreak::parse("d set").unwrap());
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc(), 0x0604);
+        assert_eq!(cpu.take_register_break(), RegisterBreak::parse("d set"));
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc(), 0x0606);
+        assert_eq!(cpu.take_register_break(), None);
+    }
+
+    #[test]
+    fn sp_conditions_compare_the_8_bit_register() {
+        let mut pushes = vec![LDX_IM, 0x25, TXS];
+        pushes.extend([PHA; 8]);
+        pushes.push(NOP);
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, pushes)
+            .build()
+            .unwrap();
+        cpu.add_register_break(RegisterBreak::parse("sp < 20").unwrap());
+
+        cpu.execute().unwrap();
+        // the sixth push takes SP from $20 to $1F
+        assert_eq!((cpu.pc(), cpu.sp()), (0x0609, 0x011F));
+        assert_eq!(cpu.take_register_break(), RegisterBreak::parse("sp < 20"));
+    }
+}
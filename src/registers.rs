@@ -0,0 +1,64 @@
+//! a copy of the cpu's registers, to save and restore them in one go
+//!
+//! with the `serde` feature [`Registers`] and
+//! [`ProcessorStatus`](crate::ProcessorStatus) serialize as plain numbers, the
+//! status as the register's byte
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::processor_status::ProcessorStatus;
+
+/// the registers of a cpu at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Registers {
+    pub pc: u16,
+    pub sp: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: ProcessorStatus,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{op_codes::*, Cpu};
+
+    #[test]
+    fn registers_round_trip_through_the_cpu() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x80, TAX, SEC, NOP])
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+        let saved: Registers = cpu.registers();
+        assert_eq!(saved.a, 0x80);
+        assert!(saved.status.negative() && saved.status.carry());
+
+        let mut other = Cpu::new();
+        other.set_registers(saved);
+        assert_eq!(other.registers(), saved);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn registers_serialize_as_numbers() {
+        let registers = Registers {
+            pc: 0x0600,
+            sp: 0x01FF,
+            a: 1,
+            x: 2,
+            y: 3,
+            status: ProcessorStatus::C | ProcessorStatus::U,
+        };
+        let json = serde_json::to_string(&registers).unwrap();
+        assert_eq!(
+            json,
+            r#"{"pc":1536,"sp":511,"a":1,"x":2,"y":3,"status":33}"#
+        );
+        assert_eq!(serde_json::from_str::<Registers>(&json).unwrap(), registers);
+    }
+}
@@ -0,0 +1,285 @@
+//! checksums of ROM images and the few well known images this crate can
+//! pick a load address and entry point for by itself
+//!
+//! images are reported by CRC32 and SHA-1, the same digests ROM databases
+//! and emulators list them by, and matched against [`KNOWN`] by size and a
+//! signature rather than by digest, so reassembled or patched builds of the
+//! same program are still recognised
+
+use core::{fmt, ops::Range};
+
+use crate::trace::TraceFormat;
+
+/// the CRC32 and SHA-1 of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksums {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+impl Checksums {
+    pub fn of(bytes: &[u8]) -> Self {
+        Self {
+            crc32: crc32(bytes),
+            sha1: sha1(bytes),
+        }
+    }
+}
+
+impl fmt::Display for Checksums {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CRC32 {:08x}, SHA-1 ", self.crc32)?;
+        for byte in self.sha1 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// what in an image marks it as a known one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signature {
+    /// these bytes at this offset
+    At(usize, &'static [u8]),
+    /// these bytes anywhere, like a banner
+    Contains(&'static [u8]),
+}
+
+impl Signature {
+    fn matches(&self, bytes: &[u8]) -> bool {
+        match self {
+            Signature::At(offset, signature) => {
+                bytes.get(*offset..offset + signature.len()) == Some(signature)
+            }
+            Signature::Contains(signature) => bytes
+                .windows(signature.len())
+                .any(|window| window == *signature),
+        }
+    }
+}
+
+/// where a known image starts running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    Address(u16),
+    /// through the reset vector in the image once it's loaded
+    ResetVector,
+}
+
+/// an image this crate knows how to load
+#[derive(Debug, Clone)]
+pub struct KnownRom {
+    pub name: &'static str,
+    /// the file's size, when only one size is known
+    pub len: Option<usize>,
+    pub signature: Signature,
+    /// the bytes of the file loaded, past any header
+    pub image: Range<usize>,
+    /// where the image is loaded
+    pub origin: u16,
+    pub entry: Entry,
+    /// the trace layout logs of it are usually compared in
+    pub trace_format: Option<TraceFormat>,
+}
+
+impl KnownRom {
+    fn matches(&self, bytes: &[u8]) -> bool {
+        self.len.is_none_or(|len| len == bytes.len()) && self.signature.matches(bytes)
+    }
+
+    /// the part of the file that's loaded
+    pub fn image<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        &bytes[self.image.clone()]
+    }
+
+    /// the address the image starts running from
+    pub fn entry(&self, bytes: &[u8]) -> u16 {
+        match self.entry {
+            Entry::Address(address) => address,
+            Entry::ResetVector => {
+                let vector = 0xFFFC - self.origin as usize;
+                let image = self.image(bytes);
+                u16::from_le_bytes([image[vector], image[vector + 1]])
+            }
+        }
+    }
+}
+
+/// the images recognised on load
+pub const KNOWN: [KnownRom; 4] = [
+    KnownRom {
+        name: "Wozmon",
+        len: Some(256),
+        // CLD, CLI, LDY #$7F, STY DSP
+        signature: Signature::At(0, &[0xD8, 0x58, 0xA0, 0x7F, 0x8C, 0x12, 0xD0]),
+        image: 0..256,
+        origin: 0xFF00,
+        entry: Entry::Address(0xFF00),
+        trace_format: None,
+    },
+    KnownRom {
+        name: "EhBASIC",
+        len: Some(0x4000),
+        signature: Signature::Contains(b"Enhanced BASIC"),
+        image: 0..0x4000,
+        origin: 0xC000,
+        entry: Entry::ResetVector,
+        trace_format: None,
+    },
+    KnownRom {
+        name: "Klaus Dormann's functional test",
+        len: Some(0x10000),
+        // CLD, LDX #$FF, TXS from the start of its code
+        signature: Signature::At(0x0400, &[0xD8, 0xA2, 0xFF, 0x9A]),
+        image: 0..0x10000,
+        origin: 0x0000,
+        entry: Entry::Address(0x0400),
+        trace_format: None,
+    },
+    KnownRom {
+        name: "nestest",
+        // an iNES header, 16K of PRG and 8K of CHR
+        len: Some(16 + 0x4000 + 0x2000),
+        signature: Signature::At(0, b"NES\x1A"),
+        // just the PRG, the CHR is the PPU's
+        image: 16..16 + 0x4000,
+        origin: 0xC000,
+        // where it runs without a PPU to show its menu on
+        entry: Entry::Address(0xC000),
+        trace_format: Some(TraceFormat::Nestest),
+    },
+];
+
+/// an image's checksums and what it was recognised as
+#[derive(Debug, Clone, Copy)]
+pub struct Identity {
+    pub checksums: Checksums,
+    pub known: Option<&'static KnownRom>,
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.checksums)?;
+        if let Some(known) = self.known {
+            write!(f, " ({})", known.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// checksum an image and look it up in [`KNOWN`]
+pub fn identify(bytes: &[u8]) -> Identity {
+    Identity {
+        checksums: Checksums::of(bytes),
+        known: KNOWN.iter().find(|known| known.matches(bytes)),
+    }
+}
+
+/// the CRC-32 zip and PNG use, reflected with polynomial $EDB88320
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0xEDB8_8320,
+            _ => crc >> 1,
+        })
+    });
+    !crc
+}
+
+/// the SHA-1 digest of `bytes`
+pub fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_the_reference_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            Checksums::of(b"abc").to_string(),
+            "CRC32 352441c2, SHA-1 a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // two blocks of padding
+        let long = Checksums::of(&[b'a'; 64]).to_string();
+        assert!(long.ends_with("0098ba824b5c16427bd7a1122a5a442a25ec644d"));
+    }
+
+    #[test]
+    fn known_images_are_recognised_by_their_signature() {
+        let mut wozmon = vec![0xEA; 256];
+        wozmon[..7].copy_from_slice(&[0xD8, 0x58, 0xA0, 0x7F, 0x8C, 0x12, 0xD0]);
+        let identity = identify(&wozmon);
+        assert_eq!(identity.known.map(|known| known.name), Some("Wozmon"));
+        assert!(identity.to_string().ends_with(" (Wozmon)"));
+
+        let mut basic = vec![0; 0x4000];
+        basic[0x100..0x10E].copy_from_slice(b"Enhanced BASIC");
+        basic[0x3FFC..0x3FFE].copy_from_slice(&[0x80, 0xFF]);
+        let known = identify(&basic).known.unwrap();
+        assert_eq!((known.origin, known.entry(&basic)), (0xC000, 0xFF80));
+
+        let mut nes = vec![0; 16 + 0x6000];
+        nes[..4].copy_from_slice(b"NES\x1A");
+        let known = identify(&nes).known.unwrap();
+        assert_eq!(known.image(&nes).len(), 0x4000);
+        assert!(matches!(known.trace_format, Some(TraceFormat::Nestest)));
+
+        assert!(identify(&wozmon[..255]).known.is_none());
+    }
+}
@@ -0,0 +1,849 @@
+use std::{
+    cell::RefCell,
+    fs, io,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    rc::Rc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use tracing::info;
+
+use crate::{
+    cdl::CodeDataLog,
+    core_dump::CoreDump,
+    cpu::{Cpu, CpuBuilder, CpuError, TRACE_TARGET},
+    crash::CrashReport,
+    explain,
+    heat_map::HeatMap,
+    latency::InterruptLatency,
+    loader::{self, LoaderError},
+    machine::{Board, BoardError},
+    profiler::{BranchStats, CallProfiler, Histogram},
+    rom_id::{self, Identity, KnownRom},
+    stats::Summary,
+    trace::{self, TraceFormat},
+    vcd,
+};
+
+/// address programs are loaded to when no origin is given
+pub const DEFAULT_ORIGIN: u16 = 0x0600;
+
+/// how often the program file is checked for changes while waiting for one
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// instructions run between checks of the program file while watching it
+const WATCH_STEPS: usize = 10_000;
+
+/// extension of program binaries picked up by the runner
+const ROM_EXTENSION: &str = "bin";
+
+/// extension of the optional sidecar file describing the expected final state
+const EXPECT_EXTENSION: &str = "expect";
+
+/// options controlling how each rom is run
+#[derive(Debug, Clone, Copy)]
+pub struct RunnerOptions {
+    /// address each rom is loaded to and started from
+    pub origin: u16,
+    /// instructions to execute before a rom is considered hung
+    pub max_instructions: u64,
+}
+
+impl Default for RunnerOptions {
+    fn default() -> Self {
+        Self {
+            origin: DEFAULT_ORIGIN,
+            max_instructions: 1_000_000,
+        }
+    }
+}
+
+/// outcome of running a single rom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// halted and matched every expectation
+    Pass,
+    /// halted but the final state diverged from the expectations
+    Fail,
+    /// stopped with a cpu error, e.g. an opcode the cpu doesn't implement
+    Crash,
+    /// didn't halt within the instruction limit
+    Timeout,
+    /// the rom or its expect file couldn't be read or loaded
+    Error,
+}
+
+impl Status {
+    /// name of the status as used in reports
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Crash => "crash",
+            Status::Timeout => "timeout",
+            Status::Error => "error",
+        }
+    }
+}
+
+/// a single expected value that didn't match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// register name or memory address, as written in the expect file
+    pub field: String,
+    pub expected: u16,
+    pub actual: u16,
+}
+
+/// result of running a single rom
+#[derive(Debug, Clone)]
+pub struct RomResult {
+    pub rom: String,
+    pub status: Status,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// program counter once the rom stopped
+    pub pc: u16,
+    pub divergence: Vec<Divergence>,
+    /// why the rom couldn't be run, for [`Status::Error`]
+    pub error: Option<String>,
+}
+
+impl RomResult {
+    /// a rom that couldn't be run
+    fn error(rom: &Path, err: io::Error) -> Self {
+        Self {
+            rom: file_name(rom),
+            status: Status::Error,
+            cycles: 0,
+            instructions: 0,
+            pc: 0,
+            divergence: Vec::new(),
+            error: Some(err.to_string()),
+        }
+    }
+}
+
+/// run every `.bin` rom in a directory, in file name order
+/// a rom passes when it halts and its final state matches `<name>.expect`, if present,
+/// roms that can't be read or loaded are reported with [`Status::Error`]
+pub fn run_dir(dir: &Path, options: RunnerOptions) -> io::Result<Vec<RomResult>> {
+    let mut roms: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == ROM_EXTENSION))
+        .collect();
+    roms.sort();
+
+    Ok(roms
+        .iter()
+        .map(|rom| run_rom(rom, options).unwrap_or_else(|err| RomResult::error(rom, err)))
+        .collect())
+}
+
+/// run a single rom and check it against its expect file
+pub fn run_rom(rom: &Path, options: RunnerOptions) -> io::Result<RomResult> {
+    let program = fs::read(rom)?;
+    let expectations = match fs::read_to_string(rom.with_extension(EXPECT_EXTENSION)) {
+        Ok(source) => parse_expectations(&source)
+            .map_err(|line| io::Error::new(io::ErrorKind::InvalidData, line))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err),
+    };
+
+    let mut cpu = Cpu::new().reset(Some(options.origin));
+    cpu.load_program(options.origin as usize, program)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let mut instructions = 0;
+    let mut status = Status::Timeout;
+    while instructions < options.max_instructions {
+        instructions += 1;
+        match cpu.step() {
+            Ok(true) => {}
+            Ok(false) => {
+                status = Status::Pass;
+                break;
+            }
+            Err(_) => {
+                status = Status::Crash;
+                break;
+            }
+        }
+    }
+
+    let mut divergence = Vec::new();
+    if status == Status::Pass {
+        divergence = check_expectations(&cpu, &expectations);
+        if !divergence.is_empty() {
+            status = Status::Fail;
+        }
+    }
+
+    Ok(RomResult {
+        rom: file_name(rom),
+        status,
+        cycles: cpu.cycles(),
+        instructions,
+        pc: cpu.pc(),
+        divergence,
+        error: None,
+    })
+}
+
+fn file_name(rom: &Path) -> String {
+    rom.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// a location in the final state an expect file can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    Status,
+    Memory(u16),
+}
+
+/// parse an expect file made of `<target> = <value>` lines
+/// targets are `a`, `x`, `y`, `sp`, `pc`, `ps` or a `$nnnn` memory address
+/// returns the offending line on error
+fn parse_expectations(source: &str) -> Result<Vec<(String, Target, u16)>, String> {
+    let mut expectations = Vec::new();
+
+    for line in source.lines() {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, value) = line.split_once('=').ok_or_else(|| line.to_string())?;
+        let name = name.trim().to_lowercase();
+        let target = match name.as_str() {
+            "a" => Target::A,
+            "x" => Target::X,
+            "y" => Target::Y,
+            "sp" => Target::Sp,
+            "pc" => Target::Pc,
+            "ps" => Target::Status,
+            address => Target::Memory(parse_number(address).ok_or_else(|| line.to_string())?),
+        };
+        let value = parse_number(value.trim()).ok_or_else(|| line.to_string())?;
+
+        expectations.push((name, target, value));
+    }
+
+    Ok(expectations)
+}
+
+fn check_expectations(cpu: &Cpu, expectations: &[(String, Target, u16)]) -> Vec<Divergence> {
+    expectations
+        .iter()
+        .filter_map(|(field, target, expected)| {
+            let actual = match target {
+                Target::A => cpu.a() as u16,
+                Target::X => cpu.x() as u16,
+                Target::Y => cpu.y() as u16,
+                Target::Sp => cpu.sp(),
+                Target::Pc => cpu.pc(),
+                Target::Status => cpu.status().bits() as u16,
+                Target::Memory(address) => cpu.memory.read_byte(*address as usize) as u16,
+            };
+
+            (actual != *expected).then(|| Divergence {
+                field: field.clone(),
+                expected: *expected,
+                actual,
+            })
+        })
+        .collect()
+}
+
+/// parse `$nn` hex or decimal numbers
+fn parse_number(value: &str) -> Option<u16> {
+    match value.strip_prefix('$') {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// render results as a json report
+pub fn to_json(results: &[RomResult]) -> String {
+    let passed = results.iter().filter(|r| r.status == Status::Pass).count();
+
+    let roms: Vec<String> = results
+        .iter()
+        .map(|result| {
+            let divergence: Vec<String> = result
+                .divergence
+                .iter()
+                .map(|d| {
+                    format!(
+                        "{{\"field\":{},\"expected\":{},\"actual\":{}}}",
+                        json_string(&d.field),
+                        d.expected,
+                        d.actual
+                    )
+                })
+                .collect();
+
+            let error = result
+                .error
+                .as_ref()
+                .map(|error| format!(",\"error\":{}", json_string(error)))
+                .unwrap_or_default();
+
+            format!(
+                "{{\"rom\":{},\"status\":\"{}\",\"cycles\":{},\"instructions\":{},\"pc\":{},\"divergence\":[{}]{}}}",
+                json_string(&result.rom),
+                result.status.as_str(),
+                result.cycles,
+                result.instructions,
+                result.pc,
+                divergence.join(","),
+                error
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\"total\":{},\"passed\":{},\"failed\":{},\"results\":[{}]}}",
+        results.len(),
+        passed,
+        results.len() - passed,
+        roms.join(",")
+    )
+}
+
+/// quote and escape a string for json
+pub(crate) fn json_string(value: &str) -> String {
+    let mut out = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// reports collected while running a program and printed once it stops
+#[derive(Default)]
+pub struct Reports {
+    pub histogram: Option<Rc<RefCell<Histogram>>>,
+    pub profiler: Option<Rc<RefCell<CallProfiler>>>,
+    /// file the profile is also written to in callgrind format
+    pub callgrind: Option<String>,
+    pub branches: Option<Rc<RefCell<BranchStats>>>,
+    /// file the code/data log is written to
+    pub cdl: Option<(String, Rc<RefCell<CodeDataLog>>)>,
+    /// file the bus activity is written to as a VCD waveform
+    pub vcd: Option<String>,
+    /// file the access counts are written to as a picture or json
+    pub heat_map: Option<(String, Rc<RefCell<HeatMap>>)>,
+    pub latency: Option<Rc<RefCell<InterruptLatency>>>,
+    /// whether to report how deep the stack got
+    pub stack: bool,
+    /// code run, for the coverage in the summary printed after a run
+    pub summary: Option<Rc<RefCell<CodeDataLog>>>,
+    /// file a json report of the run is written to
+    pub report: Option<String>,
+    /// memory included in the json report
+    pub report_memory: Vec<RangeInclusive<u16>>,
+}
+
+impl Reports {
+    /// reset the reports and subscribe them to the cpu being built
+    pub fn attach(&self, mut builder: CpuBuilder) -> CpuBuilder {
+        if let Some(histogram) = &self.histogram {
+            histogram.borrow_mut().clear();
+            builder = builder.observer(histogram.clone());
+        }
+        if let Some(profiler) = &self.profiler {
+            *profiler.borrow_mut() = CallProfiler::default();
+            builder = builder.observer(profiler.clone());
+        }
+        if let Some(branches) = &self.branches {
+            *branches.borrow_mut() = BranchStats::default();
+            builder = builder.observer(branches.clone());
+        }
+        if let Some((_, cdl)) = &self.cdl {
+            *cdl.borrow_mut() = CodeDataLog::default();
+            builder = builder.observer(cdl.clone());
+        }
+        if let Some((_, heat_map)) = &self.heat_map {
+            *heat_map.borrow_mut() = HeatMap::default();
+            builder = builder.observer(heat_map.clone());
+        }
+        if let Some(log) = &self.summary {
+            *log.borrow_mut() = CodeDataLog::default();
+            builder = builder.observer(log.clone());
+        }
+        if let Some(latency) = &self.latency {
+            *latency.borrow_mut() = InterruptLatency::default();
+            builder = builder.observer(latency.clone());
+        }
+        if self.vcd.is_some() {
+            builder = builder.accurate(true).bus_trace(true);
+        }
+        builder
+    }
+
+    /// print the reports and write the ones kept in files
+    pub fn print(&self, cpu: &Cpu) {
+        if let Some(histogram) = &self.histogram {
+            println!("{}", histogram.borrow());
+        }
+        if let Some(profiler) = &self.profiler {
+            let profiler = profiler.borrow();
+            match &self.callgrind {
+                Some(path) => {
+                    if let Err(err) = fs::write(path, profiler.callgrind()) {
+                        eprintln!("failed to write {path}: {err}");
+                    }
+                }
+                None => println!("{profiler}"),
+            }
+        }
+        if let Some(branches) = &self.branches {
+            println!("{}", branches.borrow());
+        }
+        if let Some(latency) = &self.latency {
+            println!("{}", latency.borrow());
+        }
+        if self.stack {
+            match (cpu.stack_high_water(), cpu.stack_headroom()) {
+                (Some(low), Some(free)) => {
+                    println!("stack high-water ${low:04X}, {free} bytes of page 1 free below it")
+                }
+                _ => println!("stack never pushed to"),
+            }
+        }
+        if let Some((path, cdl)) = &self.cdl {
+            if let Err(err) = cdl.borrow().save(Path::new(path)) {
+                eprintln!("failed to write {path}: {err}");
+            }
+        }
+        if let Some(path) = &self.vcd {
+            if let Err(err) = vcd::save(Path::new(path), cpu.bus_trace()) {
+                eprintln!("failed to write {path}: {err}");
+            }
+        }
+        if let Some((path, heat_map)) = &self.heat_map {
+            if let Err(err) = heat_map.borrow().save(Path::new(path)) {
+                eprintln!("failed to write {path}: {err}");
+            }
+        }
+    }
+}
+
+/// how a [`Program`] is run and what's reported once it stops
+#[derive(Default)]
+pub struct RunOptions {
+    /// address the program is loaded to and started from, unless it's
+    /// recognised, [`DEFAULT_ORIGIN`] if neither
+    pub origin: Option<u16>,
+    /// checksum the program and load a recognised image where it belongs
+    pub identify: bool,
+    pub trace: bool,
+    pub trace_format: TraceFormat,
+    /// print each instruction run with what it did
+    pub explain: bool,
+    /// instructions run before a program that hasn't halted fails
+    pub max_instructions: Option<u64>,
+    /// file a core is written to when the program stops
+    pub core: Option<PathBuf>,
+    pub reports: Reports,
+    /// print what the emulator itself spent the run on
+    #[cfg(feature = "self-profile")]
+    pub self_profile: bool,
+}
+
+/// how a run of a program ended
+#[derive(Debug)]
+pub struct Stop {
+    pub result: Result<(), CpuError>,
+    /// wall time the run took
+    pub elapsed: Duration,
+}
+
+/// a program file run on a [`Board`], loaded afresh for each run
+pub struct Program {
+    path: PathBuf,
+    origin: u16,
+    identity: Option<Identity>,
+    options: RunOptions,
+    board: Board,
+}
+
+impl Program {
+    /// a program, read once here to identify it when the options ask
+    pub fn new(
+        path: impl Into<PathBuf>,
+        mut options: RunOptions,
+        board: Board,
+    ) -> Result<Self, LoaderError> {
+        let path = path.into();
+        let identity = match options.identify {
+            true => Some(rom_id::identify(&loader::read_program(&path)?)),
+            false => None,
+        };
+        let mut program = Self {
+            path,
+            origin: options.origin.unwrap_or(DEFAULT_ORIGIN),
+            identity,
+            options: RunOptions::default(),
+            board,
+        };
+        if let Some(known) = program.known() {
+            program.origin = known.origin;
+            if let (Some(format), TraceFormat::Default) = (known.trace_format, options.trace_format)
+            {
+                options.trace_format = format;
+            }
+        }
+        program.options = options;
+        Ok(program)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// the program's checksums and what it was recognised as, when the
+    /// options asked to identify it
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// the image the program was recognised as, unless told where to load it
+    fn known(&self) -> Option<&'static KnownRom> {
+        self.identity
+            .and_then(|identity| identity.known)
+            .filter(|_| self.options.origin.is_none())
+    }
+
+    /// build a cpu on the board with the program loaded and the reports
+    /// subscribed to it
+    pub fn load(&self) -> Result<Cpu, BoardError> {
+        let options = &self.options;
+        let mut builder = options.reports.attach(
+            Cpu::builder()
+                .trace(options.trace)
+                .trace_format(options.trace_format),
+        );
+        if let Some(limit) = options.max_instructions {
+            builder = builder.instruction_limit(limit);
+        }
+        let builder = self.board.attach(builder)?;
+        if let Some(header) = options.trace_format.header().filter(|_| options.trace) {
+            info!(target: TRACE_TARGET, "{header}");
+        }
+        let cpu = self
+            .board
+            .load(&self.path, self.known(), self.origin, builder)?;
+
+        let board = self.board.options();
+        if board.seed.is_none() && (board.random_ram || board.random_base.is_some()) {
+            eprintln!("random seed {}, --seed {0} repeats this run", cpu.seed());
+        }
+        Ok(cpu)
+    }
+
+    /// load the program, run it until it halts or fails and report on it
+    pub fn run(&self) -> Result<Stop, BoardError> {
+        let mut cpu = self.load()?;
+        let stop = self
+            .execute(&mut cpu, None)
+            .expect("only a watched run is cut short");
+        self.report(&cpu, &stop);
+        Ok(stop)
+    }
+
+    /// run a loaded program until it halts or fails, or until `changed`
+    /// says its file changed, none when it did
+    /// explained and watched runs step in batches, checking the instruction
+    /// limit after each instruction the way [`Cpu::execute`] does
+    fn execute(&self, cpu: &mut Cpu, changed: Option<&dyn Fn() -> bool>) -> Option<Stop> {
+        #[cfg(feature = "self-profile")]
+        crate::self_profile::reset();
+        let start = Instant::now();
+        let stop = |result| Stop {
+            result,
+            elapsed: start.elapsed(),
+        };
+        if changed.is_none() && !self.options.explain {
+            return Some(stop(cpu.execute()));
+        }
+        loop {
+            for _ in 0..WATCH_STEPS {
+                let running = match self.options.explain {
+                    true => explain::step(cpu).map(|(running, line)| {
+                        println!("{line}");
+                        running
+                    }),
+                    false => cpu.step(),
+                };
+                let result = match running {
+                    Ok(true) => cpu.check_instruction_limit(),
+                    Ok(false) => return Some(stop(Ok(()))),
+                    Err(err) => Err(err),
+                };
+                if result.is_err() {
+                    return Some(stop(result));
+                }
+            }
+            if changed.is_some_and(|changed| changed()) {
+                return None;
+            }
+        }
+    }
+
+    /// print the cpu and the reports once a run stops, and write the files
+    /// the options asked for
+    fn report(&self, cpu: &Cpu, stop: &Stop) {
+        trace::flush();
+        #[cfg(feature = "self-profile")]
+        if self.options.self_profile {
+            eprintln!("{}", crate::self_profile::counters());
+        }
+        println!("{cpu}");
+        let reports = &self.options.reports;
+        reports.print(cpu);
+        #[cfg(feature = "mmap")]
+        if let Err(err) = cpu.memory.flush_ram() {
+            eprintln!("failed to save ram: {err}");
+        }
+        if let Some(file) = &self.options.core {
+            let reason = match &stop.result {
+                Ok(()) => "halted".to_string(),
+                Err(err) => err.to_string(),
+            };
+            if let Err(err) = CoreDump::capture(cpu, &reason).save(file) {
+                eprintln!("failed to write {}: {err}", file.display());
+            }
+        }
+        if reports.summary.is_some() || reports.report.is_some() {
+            let mut summary = Summary::new(cpu, stop.elapsed, &stop.result);
+            if let Some(log) = &reports.summary {
+                let len = fs::metadata(&self.path).map_or(0, |meta| meta.len() as usize);
+                let program = self.origin as usize..self.origin as usize + len;
+                summary = summary.coverage(&log.borrow(), program);
+                println!("{summary}");
+            }
+            if let Some(report) = &reports.report {
+                for range in &reports.report_memory {
+                    summary = summary.memory(cpu, range.clone());
+                }
+                if let Err(err) = fs::write(report, summary.to_json()) {
+                    eprintln!("failed to write {report}: {err}");
+                }
+            }
+        }
+        if let Err(err) = &stop.result {
+            eprint!("{}", CrashReport::new(cpu, *err));
+        }
+    }
+
+    /// run the program, and again from the start each time its file
+    /// changes, returning only when it can't be loaded
+    pub fn watch(&self) -> Result<(), BoardError> {
+        loop {
+            let modified = self.modified();
+            let mut cpu = self.load()?;
+
+            // step in batches so a program that never halts can still be reloaded
+            if let Some(stop) = self.execute(&mut cpu, Some(&|| self.modified() != modified)) {
+                self.report(&cpu, &stop);
+                println!("waiting for {} to change...", self.path.display());
+                while self.modified() == modified {
+                    thread::sleep(WATCH_INTERVAL);
+                }
+            }
+
+            println!("{} changed, reloading", self.path.display());
+        }
+    }
+
+    /// last modification time of the file, none if it can't be read, e.g.
+    /// mid-write
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    /// write roms into a fresh temporary directory
+    fn rom_dir(name: &str, roms: &[(&str, &[u8], Option<&str>)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("cpu_emu_runner_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        for (rom, program, expect) in roms {
+            fs::write(dir.join(format!("{rom}.bin")), program).unwrap();
+            if let Some(expect) = expect {
+                fs::write(dir.join(format!("{rom}.expect")), expect).unwrap();
+            }
+        }
+        dir
+    }
+
+    #[test]
+    fn parse_expectations_reads_registers_and_memory() {
+        let expectations = parse_expectations("a = $42 ; comment\n\n$0200 = 1").unwrap();
+        assert_eq!(
+            expectations,
+            vec![
+                ("a".to_string(), Target::A, 0x42),
+                ("$0200".to_string(), Target::Memory(0x0200), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_expectations_rejects_bad_lines() {
+        assert_eq!(parse_expectations("a 42"), Err("a 42".to_string()));
+    }
+
+    #[test]
+    fn run_dir_reports_each_status() {
+        let dir = rom_dir(
+            "statuses",
+            &[
+                ("crash", &[0xFF], None),
+                ("fail", &[LDA_IM, 0x01, NOP], Some("a = $42")),
+                ("pass", &[LDA_IM, 0x42, NOP], Some("a = $42")),
+                ("timeout", &[JMP_ABS, 0x00, 0x06], None),
+            ],
+        );
+
+        let options = RunnerOptions {
+            max_instructions: 100,
+            ..Default::default()
+        };
+        let results = run_dir(&dir, options).unwrap();
+        let statuses: Vec<Status> = results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![Status::Crash, Status::Fail, Status::Pass, Status::Timeout]
+        );
+
+        assert_eq!(
+            results[1].divergence,
+            vec![Divergence {
+                field: "a".to_string(),
+                expected: 0x42,
+                actual: 0x01
+            }]
+        );
+        assert_eq!(results[2].cycles, 4);
+        assert_eq!(results[3].instructions, 100);
+    }
+
+    #[test]
+    fn run_dir_reports_roms_it_cant_run_and_carries_on() {
+        let dir = rom_dir(
+            "errors",
+            &[
+                ("bad_expect", &[NOP], Some("a 42")),
+                ("pass", &[NOP], None),
+                ("too_big", &[0; 0x10000], None),
+            ],
+        );
+
+        let results = run_dir(&dir, RunnerOptions::default()).unwrap();
+        let statuses: Vec<Status> = results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![Status::Error, Status::Pass, Status::Error]);
+        assert_eq!(results[0].error.as_deref(), Some("a 42"));
+        assert!(to_json(&results).contains("\"status\":\"error\",\"cycles\":0"));
+    }
+
+    #[test]
+    fn to_json_summarizes_results() {
+        let results = vec![RomResult {
+            rom: "a\"b.bin".to_string(),
+            status: Status::Fail,
+            cycles: 4,
+            instructions: 2,
+            pc: 0x0603,
+            divergence: vec![Divergence {
+                field: "a".to_string(),
+                expected: 1,
+                actual: 2,
+            }],
+            error: None,
+        }];
+
+        assert_eq!(
+            to_json(&results),
+            "{\"total\":1,\"passed\":0,\"failed\":1,\"results\":[{\"rom\":\"a\\\"b.bin\",\"status\":\"fail\",\"cycles\":4,\"instructions\":2,\"pc\":1539,\"divergence\":[{\"field\":\"a\",\"expected\":1,\"actual\":2}]}]}"
+        );
+    }
+
+    #[test]
+    fn programs_load_onto_their_board_and_run_to_a_halt() {
+        let dir = rom_dir("program", &[("program", &[LDX_IM, 0x01, NOP], None)]);
+        let board = Board::open(crate::machine::BoardOptions {
+            sp: Some(0x01FF),
+            ..Default::default()
+        })
+        .unwrap();
+        let options = RunOptions {
+            origin: Some(0x0700),
+            ..Default::default()
+        };
+        let program = Program::new(dir.join("program.bin"), options, board).unwrap();
+
+        let cpu = program.load().unwrap();
+        assert_eq!((cpu.pc(), cpu.sp()), (0x0700, 0x01FF));
+        assert_eq!(program.run().unwrap().result, Ok(()));
+    }
+
+    #[test]
+    fn watched_and_explained_runs_stop_at_the_instruction_limit() {
+        let dir = rom_dir("spin", &[("spin", &[JMP_ABS, 0x00, 0x06], None)]);
+        let program = |max_instructions, explain| {
+            let options = RunOptions {
+                max_instructions,
+                explain,
+                ..Default::default()
+            };
+            let board = Board::open(Default::default()).unwrap();
+            Program::new(dir.join("spin.bin"), options, board).unwrap()
+        };
+        let limit = Err(CpuError::InstructionLimit {
+            limit: 1000,
+            pc: 0x0600,
+        });
+
+        let watched = program(Some(1000), false);
+        let mut cpu = watched.load().unwrap();
+        let stop = watched.execute(&mut cpu, Some(&|| false)).unwrap();
+        assert_eq!((stop.result, cpu.instructions()), (limit, 1000));
+        assert_eq!(program(Some(1000), true).run().unwrap().result, limit);
+
+        // without a limit only a change to the file stops it
+        let unlimited = program(None, false);
+        let mut cpu = unlimited.load().unwrap();
+        assert!(unlimited.execute(&mut cpu, Some(&|| true)).is_none());
+        assert_eq!(cpu.instructions(), WATCH_STEPS as u64);
+    }
+}
@@ -0,0 +1,215 @@
+//! save states, a cpu's registers, counters and RAM in a file later builds of
+//! this crate can still load
+//!
+//! a file is a [`chunk`](crate::chunk) container starting `6502SAVE`, at
+//! container version [`VERSION`], with these chunks, all at version 1
+//!
+//! | tag    | fields                                      |
+//! |--------|---------------------------------------------|
+//! | `REGS` | PC, SP, A, X, Y and the status flags        |
+//! | `CNTR` | cycles then instructions since reset        |
+//! | `RAM ` | 64K of RAM, without ROMs or devices         |
+
+use std::{fs, io, path::Path};
+
+use thiserror::Error;
+
+use crate::{
+    chunk::{self, Chunk, ChunkError, Tag, COUNTERS, REGISTERS},
+    cpu::{Cpu, CpuSnapshot},
+    memory::{MemorySnapshot, MAX_MEM},
+    registers::Registers,
+};
+
+/// the bytes every save state starts with
+const MAGIC: &[u8; 8] = b"6502SAVE";
+
+/// the version of the container written
+pub const VERSION: u8 = 1;
+
+const RAM: Tag = *b"RAM ";
+
+/// errors reading or writing a save state
+#[derive(Debug, Error)]
+pub enum SaveStateError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("not a save state")]
+    NotASaveState,
+    #[error("save state version {0} is newer than this build reads")]
+    Version(u8),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+/// a cpu's state, saved to carry on from later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub registers: Registers,
+    pub cycles: u64,
+    pub instructions: u64,
+    pub ram: Box<[u8; MAX_MEM]>,
+}
+
+impl SaveState {
+    /// save `cpu` as it is
+    pub fn capture(cpu: &mut Cpu) -> Self {
+        Self::from(&cpu.snapshot())
+    }
+
+    /// put `cpu` back in the saved state, ROMs and devices are left alone
+    pub fn restore(&self, cpu: &mut Cpu) {
+        cpu.restore(&self.snapshot());
+    }
+
+    /// the state as a snapshot to restore with [`Cpu::restore`]
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot::new(
+            self.registers,
+            self.cycles,
+            self.instructions,
+            MemorySnapshot::new(self.ram.clone()),
+        )
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = chunk::header(MAGIC, VERSION);
+        chunk::write_registers(&mut bytes, &self.registers);
+        chunk::write_counters(&mut bytes, self.cycles, self.instructions);
+        chunk::chunk(&mut bytes, RAM, &self.ram[..]);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        let (version, mut rest) = chunk::open(bytes, MAGIC).ok_or(SaveStateError::NotASaveState)?;
+        if version > VERSION {
+            return Err(SaveStateError::Version(version));
+        }
+
+        let (mut registers, mut counters, mut ram) = (None, None, None);
+        while !rest.is_empty() {
+            let mut chunk = chunk::next(&mut rest)?;
+            match chunk.tag {
+                REGISTERS => registers = Some(chunk::read_registers(&mut chunk)?),
+                COUNTERS => counters = Some(chunk::read_counters(&mut chunk)?),
+                RAM => ram = Some(read_ram(&mut chunk)?),
+                // from a newer build, nothing this one needs
+                _ => {}
+            }
+        }
+
+        let (cycles, instructions) = counters.ok_or(ChunkError::Missing(COUNTERS))?;
+        Ok(Self {
+            registers: registers.ok_or(ChunkError::Missing(REGISTERS))?,
+            cycles,
+            instructions,
+            ram: ram.ok_or(ChunkError::Missing(RAM))?,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SaveStateError> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SaveStateError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+impl From<&CpuSnapshot> for SaveState {
+    fn from(snapshot: &CpuSnapshot) -> Self {
+        Self {
+            registers: snapshot.registers(),
+            cycles: snapshot.cycles(),
+            instructions: snapshot.instructions(),
+            ram: boxed(snapshot.memory().data()),
+        }
+    }
+}
+
+/// 64K copied onto the heap, `Box::new` would copy it through the stack
+fn boxed(ram: &[u8]) -> Box<[u8; MAX_MEM]> {
+    ram.to_vec()
+        .into_boxed_slice()
+        .try_into()
+        .expect("ram is MAX_MEM bytes")
+}
+
+fn read_ram(chunk: &mut Chunk) -> Result<Box<[u8; MAX_MEM]>, ChunkError> {
+    match chunk.version {
+        1 => Ok(boxed(chunk.take(MAX_MEM)?)),
+        _ => Err(chunk.newer()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    fn state() -> SaveState {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![LDA_IM, 0x42, PHA, TAX, 0xFF])
+            .build()
+            .unwrap();
+        cpu.execute().unwrap_err();
+        SaveState::capture(&mut cpu)
+    }
+
+    #[test]
+    fn states_round_trip_into_a_cpu() {
+        let state = state();
+        let loaded = SaveState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(loaded, state);
+
+        let mut cpu = Cpu::new();
+        loaded.restore(&mut cpu);
+        assert_eq!(cpu.registers(), state.registers);
+        assert_eq!((cpu.x(), cpu.cycles()), (0x42, state.cycles));
+        assert_eq!(cpu.memory.read_byte(0x01FF), 0x42);
+    }
+
+    #[test]
+    fn states_from_newer_builds_load_when_compatible() {
+        let state = state();
+        let mut bytes = state.to_bytes();
+        // a field appended to the counters and a chunk this build doesn't know
+        let counters = bytes.windows(4).position(|tag| tag == COUNTERS).unwrap();
+        bytes[counters + 5] += 1;
+        bytes.insert(counters + 9 + 16, 0xAA);
+        chunk::chunk(&mut bytes, *b"VIA1", &[1, 2, 3]);
+        assert_eq!(SaveState::from_bytes(&bytes).unwrap(), state);
+
+        // an incompatible counters chunk
+        bytes[counters + 4] = 2;
+        let err = SaveState::from_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the CNTR chunk is version 2, newer than this build reads"
+        );
+    }
+
+    #[test]
+    fn damaged_states_are_refused() {
+        let bytes = state().to_bytes();
+        let err = SaveState::from_bytes(&bytes[..100]).unwrap_err();
+        assert_eq!(err.to_string(), "the RAM chunk ends early");
+        assert!(matches!(
+            SaveState::from_bytes(b"6502CORE"),
+            Err(SaveStateError::NotASaveState)
+        ));
+        assert!(matches!(
+            SaveState::from_bytes(&bytes[..9]),
+            Err(SaveStateError::Chunk(ChunkError::Missing(_)))
+        ));
+
+        let mut newer = bytes;
+        newer[8] = VERSION + 1;
+        assert!(matches!(
+            SaveState::from_bytes(&newer),
+            Err(SaveStateError::Version(_))
+        ));
+    }
+}
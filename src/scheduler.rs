@@ -0,0 +1,66 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+};
+
+/// the cycles scheduled devices asked to be woken at, kept by the memory
+/// they're mapped into
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Scheduler {
+    /// the cycle the cpu has reached
+    pub now: u64,
+    /// due cycles and the ids of the devices to wake, stale entries are
+    /// skipped when they come due
+    events: BinaryHeap<Reverse<(u64, usize)>>,
+    /// the cycle each device was last queued for, so accessing a device
+    /// without moving its event doesn't queue it again
+    queued: BTreeMap<usize, u64>,
+}
+
+impl Scheduler {
+    /// wake a device at a cycle, nothing when it's idle
+    pub fn schedule(&mut self, device: usize, cycle: Option<u64>) {
+        let Some(cycle) = cycle else {
+            return;
+        };
+        if self.queued.insert(device, cycle) != Some(cycle) {
+            self.events.push(Reverse((cycle, device)));
+        }
+    }
+
+    /// the earliest event due by now, removed from the queue
+    pub fn pop_due(&mut self) -> Option<(u64, usize)> {
+        let Reverse((cycle, _)) = self.events.peek()?;
+        if *cycle > self.now {
+            return None;
+        }
+        let (cycle, device) = self.events.pop()?.0;
+        if self.queued.get(&device) == Some(&cycle) {
+            self.queued.remove(&device);
+        }
+        Some((cycle, device))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_events_are_queued_once() {
+        let mut scheduler = Scheduler::default();
+        for _ in 0..100 {
+            scheduler.schedule(0, Some(50));
+        }
+        scheduler.schedule(0, Some(40));
+        assert_eq!(scheduler.events.len(), 2);
+
+        scheduler.now = 50;
+        assert_eq!(scheduler.pop_due(), Some((40, 0)));
+        assert_eq!(scheduler.pop_due(), Some((50, 0)));
+        assert_eq!(scheduler.pop_due(), None);
+        // once woken, the same cycle can be queued again
+        scheduler.schedule(0, Some(50));
+        assert_eq!(scheduler.pop_due(), Some((50, 0)));
+    }
+}
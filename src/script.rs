@@ -0,0 +1,386 @@
+//! rhai scripts that drive a cpu, for automation that shouldn't need a rebuild
+//!
+//! scripts get these functions on top of rhai's standard library:
+//!
+//! - `pc()`, `a()`, `x()`, `y()`, `sp()`, `cycles()` read registers
+//! - `peek(address)` and `poke(address, byte)` access memory
+//! - `dump(start, end)` formats the inclusive range as hex rows
+//! - `step()` runs one instruction, false once the cpu halts
+//! - `run()` and `run(max)` step until the cpu halts, returning the steps taken
+//! - `on_pc(address, handler)` calls `handler` whenever `run` reaches the address
+//! - `device(address, len, #{ read: .., write: .., tick: .. })` maps a device
+//!   over `len` addresses, calling `read(offset)`, `write(offset, byte)` and
+//!   `tick(cycles)` for it, any of which can be left out
+//!
+//! ```text
+//! on_pc(0xC000, || print(dump(0x0200, 0x02FF)));
+//! run();
+//! ```
+//!
+//! devices are a way to try out hardware before writing a [`Device`] in rust,
+//! e.g. a multiplier taking its operands at $D000 and $D001
+//!
+//! ```text
+//! let operands = [0, 0];
+//! device(0xD000, 2, #{
+//!     write: |offset, value| operands[offset] = value,
+//!     read: |offset| (operands[0] * operands[1]) >> (offset * 8) & 0xFF,
+//! });
+//! ```
+//!
+//! device callbacks run in the middle of an instruction, they can't touch the
+//! cpu through `peek`, `poke` or the register functions
+
+use std::{
+    cell::{OnceCell, Ref, RefCell, RefMut},
+    fmt::Write as _,
+    rc::{Rc, Weak},
+};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map, NativeCallContext, AST, INT};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{cpu::Cpu, device::Device};
+
+/// a script that failed to parse or raised an error while running
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct ScriptError(#[from] Box<EvalAltResult>);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// a cpu along with the engine scripts drive it through
+pub struct Script {
+    engine: Rc<Engine>,
+    cpu: Rc<RefCell<Cpu>>,
+    /// functions and closures from every script evaluated, for devices to call
+    functions: Rc<RefCell<AST>>,
+    /// where `print` and `debug` output goes
+    printer: Rc<RefCell<Printer>>,
+}
+
+type Printer = Box<dyn Fn(&str)>;
+
+impl Script {
+    /// make a cpu scriptable
+    pub fn new(cpu: Cpu) -> Self {
+        let cpu = Rc::new(RefCell::new(cpu));
+        let handlers: Rc<RefCell<Vec<(u16, FnPtr)>>> = Rc::default();
+        let mut engine = Engine::new();
+
+        for (name, register) in [
+            ("pc", (|cpu| cpu.pc() as INT) as fn(&Cpu) -> INT),
+            ("a", |cpu| cpu.a() as INT),
+            ("x", |cpu| cpu.x() as INT),
+            ("y", |cpu| cpu.y() as INT),
+            ("sp", |cpu| cpu.sp() as INT),
+            ("cycles", |cpu| cpu.cycles() as INT),
+        ] {
+            let cpu = cpu.clone();
+            engine.register_fn(name, move || -> ScriptResult<INT> {
+                Ok(register(&*borrow(&cpu)?))
+            });
+        }
+
+        let shared = cpu.clone();
+        engine.register_fn("peek", move |address: INT| -> ScriptResult<INT> {
+            Ok(borrow(&shared)?
+                .memory
+                .read_byte(address_of(address)? as usize) as INT)
+        });
+        let shared = cpu.clone();
+        engine.register_fn(
+            "poke",
+            move |address: INT, value: INT| -> ScriptResult<()> {
+                let value = u8::try_from(value).map_err(|_| format!("{value} isn't a byte"))?;
+                borrow_mut(&shared)?
+                    .memory
+                    .write_byte(address_of(address)? as usize, value);
+                Ok(())
+            },
+        );
+        let shared = cpu.clone();
+        engine.register_fn(
+            "dump",
+            move |start: INT, end: INT| -> ScriptResult<String> {
+                Ok(dump(
+                    &*borrow(&shared)?,
+                    address_of(start)?,
+                    address_of(end)?,
+                ))
+            },
+        );
+
+        let shared = cpu.clone();
+        engine.register_fn("step", move || -> ScriptResult<bool> {
+            borrow_mut(&shared)?
+                .step()
+                .map_err(|err| err.to_string().into())
+        });
+
+        let registered = handlers.clone();
+        engine.register_fn(
+            "on_pc",
+            move |address: INT, handler: FnPtr| -> ScriptResult<()> {
+                registered
+                    .borrow_mut()
+                    .push((address_of(address)?, handler));
+                Ok(())
+            },
+        );
+
+        let (shared, registered) = (cpu.clone(), handlers.clone());
+        engine.register_fn("run", move |context: NativeCallContext| {
+            run(&context, &shared, &registered, INT::MAX)
+        });
+        let (shared, registered) = (cpu.clone(), handlers);
+        engine.register_fn("run", move |context: NativeCallContext, max: INT| {
+            run(&context, &shared, &registered, max)
+        });
+
+        // devices call back into the engine long after `device` returns
+        let script_engine: Rc<OnceCell<Weak<Engine>>> = Rc::default();
+        let functions: Rc<RefCell<AST>> = Rc::default();
+        let (shared, device_engine, device_functions) =
+            (cpu.clone(), script_engine.clone(), functions.clone());
+        engine.register_fn(
+            "device",
+            move |address: INT, len: INT, callbacks: Map| -> ScriptResult<()> {
+                let callback = |name: &str| {
+                    callbacks
+                        .get(name)
+                        .and_then(|callback| callback.clone().try_cast::<FnPtr>())
+                };
+                let device = ScriptDevice {
+                    engine: device_engine.get().cloned().unwrap_or_default(),
+                    functions: device_functions.clone(),
+                    read: callback("read"),
+                    write: callback("write"),
+                    tick: callback("tick"),
+                };
+                let len = usize::try_from(len).map_err(|_| format!("{len} isn't a length"))?;
+                borrow_mut(&shared)?
+                    .memory
+                    .map_device(
+                        address_of(address)? as usize,
+                        len,
+                        Rc::new(RefCell::new(device)),
+                    )
+                    .map_err(|err| err.to_string().into())
+            },
+        );
+
+        let printer: Rc<RefCell<Printer>> =
+            Rc::new(RefCell::new(Box::new(|text| println!("{text}"))));
+        let (print, debug) = (printer.clone(), printer.clone());
+        engine.on_print(move |text| print.borrow()(text));
+        engine.on_debug(move |text, _, _| debug.borrow()(text));
+
+        let engine = Rc::new(engine);
+        script_engine.get_or_init(|| Rc::downgrade(&engine));
+        Self {
+            engine,
+            cpu,
+            functions,
+            printer,
+        }
+    }
+
+    /// evaluate a script against the cpu
+    pub fn eval(&self, source: &str) -> Result<(), ScriptError> {
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|err| Box::new(err.into()))?;
+        self.functions
+            .borrow_mut()
+            .combine(ast.clone_functions_only());
+        Ok(self.engine.run_ast(&ast)?)
+    }
+
+    /// the cpu the script drives
+    pub fn cpu(&self) -> Ref<'_, Cpu> {
+        self.cpu.borrow()
+    }
+
+    /// send `print` and `debug` output from scripts somewhere other than stdout
+    pub fn on_print(&self, print: impl Fn(&str) + 'static) {
+        *self.printer.borrow_mut() = Box::new(print);
+    }
+}
+
+/// step until the cpu halts or `max` steps have run, calling the handlers
+/// registered for each pc reached
+fn run(
+    context: &NativeCallContext,
+    cpu: &RefCell<Cpu>,
+    handlers: &RefCell<Vec<(u16, FnPtr)>>,
+    max: INT,
+) -> ScriptResult<INT> {
+    let mut steps = 0;
+    while steps < max {
+        let pc = borrow(cpu)?.pc();
+        // handlers may register more handlers or touch the cpu, don't hold borrows over them
+        let matching: Vec<FnPtr> = handlers
+            .borrow()
+            .iter()
+            .filter(|(address, _)| *address == pc)
+            .map(|(_, handler)| handler.clone())
+            .collect();
+        for handler in matching {
+            // whatever the handler evaluates to is of no use here
+            let _: Dynamic = handler.call_within_context(context, ())?;
+        }
+
+        let ran = borrow_mut(cpu)?.step().map_err(|err| err.to_string())?;
+        if !ran {
+            break;
+        }
+        steps += 1;
+    }
+    Ok(steps)
+}
+
+/// a device whose behavior is defined by script callbacks
+struct ScriptDevice {
+    engine: Weak<Engine>,
+    functions: Rc<RefCell<AST>>,
+    read: Option<FnPtr>,
+    write: Option<FnPtr>,
+    tick: Option<FnPtr>,
+}
+
+impl ScriptDevice {
+    /// call a callback, errors are logged since the bus has nowhere to report them
+    fn call(&self, callback: &Option<FnPtr>, args: impl rhai::FuncArgs) -> Option<Dynamic> {
+        let (callback, engine) = (callback.as_ref()?, self.engine.upgrade()?);
+        callback
+            .call(&engine, &self.functions.borrow(), args)
+            .map_err(|err| warn!(%err, "device callback failed"))
+            .ok()
+    }
+}
+
+impl Device for ScriptDevice {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.call(&self.read, (offset as INT,))
+            .and_then(|value| value.as_int().ok())
+            .map_or(0xFF, |value| value as u8)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.call(&self.write, (offset as INT, value as INT));
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.call(&self.tick, (cycles as INT,));
+    }
+}
+
+/// the cpu, unless a device callback is running in the middle of an instruction
+fn borrow(cpu: &RefCell<Cpu>) -> ScriptResult<Ref<'_, Cpu>> {
+    cpu.try_borrow()
+        .map_err(|_| "the cpu can't be used from a device callback".into())
+}
+
+fn borrow_mut(cpu: &RefCell<Cpu>) -> ScriptResult<RefMut<'_, Cpu>> {
+    cpu.try_borrow_mut()
+        .map_err(|_| "the cpu can't be used from a device callback".into())
+}
+
+fn address_of(value: INT) -> ScriptResult<u16> {
+    u16::try_from(value).map_err(|_| format!("{value} isn't an address").into())
+}
+
+/// rows of 16 bytes from `start` to `end` inclusive
+fn dump(cpu: &Cpu, start: u16, end: u16) -> String {
+    let bytes: Vec<u8> = (start..=end)
+        .map(|address| cpu.memory.read_byte(address as usize))
+        .collect();
+
+    let mut out = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02X}")).collect();
+        writeln!(out, "${:04X}  {}", start as usize + row * 16, hex.join(" ")).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    fn script() -> (Script, Rc<RefCell<Vec<String>>>) {
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, TAX, LDA_ABS, 0x00, 0x02, NOP])
+            .build()
+            .unwrap();
+        let script = Script::new(cpu);
+        let output: Rc<RefCell<Vec<String>>> = Rc::default();
+        let printed = output.clone();
+        script.on_print(move |text| printed.borrow_mut().push(text.to_string()));
+        (script, output)
+    }
+
+    #[test]
+    fn handlers_run_when_pc_is_reached() {
+        let (script, output) = script();
+        script
+            .eval(
+                r#"
+                on_pc(0x0603, || {
+                    print(`X=${x()}`);
+                    poke(0x0200, 0x99);
+                    print(dump(0x0200, 0x0201));
+                });
+                print(run());
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(*output.borrow(), ["X=66", "$0200  99 00\n", "3"]);
+        assert_eq!(script.cpu().a(), 0x99);
+    }
+
+    #[test]
+    fn scripted_devices_are_mapped_into_memory() {
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LSR_ABS, 0x00, 0xD0, LDA_ABS, 0x00, 0xD0, NOP])
+            .build()
+            .unwrap();
+        let script = Script::new(cpu);
+        script
+            .eval(
+                r#"
+                let state = #{ value: 0x40, cycles: 0 };
+                device(0xD000, 1, #{
+                    read: |offset| state.value,
+                    write: |offset, value| state.value = value * 3,
+                    tick: |cycles| state.cycles += cycles,
+                });
+                run();
+                if state.cycles != 10 { throw `ticked ${state.cycles} cycles` }
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(script.cpu().a(), 0x60);
+        assert_eq!(script.cpu().memory.data[0xD000], 0x00);
+    }
+
+    #[test]
+    fn scripts_can_step_and_peek() {
+        let (script, _) = script();
+        script
+            .eval("step(); if a() != 0x42 || peek(0x0601) != 0x42 { throw \"wrong\" }")
+            .unwrap();
+        assert_eq!(script.cpu().pc(), 0x0602);
+
+        let err = script.eval("poke(0x10000, 1)").unwrap_err();
+        assert!(err.to_string().contains("65536 isn't an address"));
+    }
+}
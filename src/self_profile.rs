@@ -0,0 +1,182 @@
+//! counters inside the emulator, to see where its own time goes on a real
+//! workload before optimizing it, built only with the `self-profile` feature
+//! so normal builds pay nothing for them
+//!
+//! counted are the instructions each dispatch path ran and by opcode, the
+//! blocks the block cache decoded, where bus reads and writes were routed,
+//! and the calls to and time spent in hooks, observers and traps
+//!
+//! the counters are per thread, [`counters`] reads the calling thread's
+
+use core::fmt;
+use std::{cell::RefCell, time::Duration};
+
+use crate::op_codes;
+
+/// opcodes listed in the report
+const TOP_OPCODES: usize = 10;
+
+thread_local! {
+    static COUNTERS: RefCell<Counters> = RefCell::new(Counters::default());
+}
+
+/// what the emulator did since the counters were last reset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counters {
+    /// instructions dispatched by opcode, whichever path ran them
+    pub dispatches: [u64; 256],
+    /// instructions run by `Cpu::step`, `step_fast` and from cached blocks
+    pub steps: u64,
+    pub fast_steps: u64,
+    pub block_instructions: u64,
+    pub blocks_decoded: u64,
+    pub ram_reads: u64,
+    pub rom_reads: u64,
+    pub device_reads: u64,
+    pub ram_writes: u64,
+    /// writes dropped because a ROM is mapped over the address
+    pub rom_writes: u64,
+    pub device_writes: u64,
+    pub hook_calls: u64,
+    pub hook_time: Duration,
+    pub observer_events: u64,
+    pub observer_time: Duration,
+    pub trap_calls: u64,
+    pub trap_time: Duration,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            dispatches: [0; 256],
+            steps: 0,
+            fast_steps: 0,
+            block_instructions: 0,
+            blocks_decoded: 0,
+            ram_reads: 0,
+            rom_reads: 0,
+            device_reads: 0,
+            ram_writes: 0,
+            rom_writes: 0,
+            device_writes: 0,
+            hook_calls: 0,
+            hook_time: Duration::ZERO,
+            observer_events: 0,
+            observer_time: Duration::ZERO,
+            trap_calls: 0,
+            trap_time: Duration::ZERO,
+        }
+    }
+}
+
+impl Counters {
+    pub fn instructions(&self) -> u64 {
+        self.steps + self.fast_steps + self.block_instructions
+    }
+}
+
+/// `part` as a percentage of `whole`
+fn percent(part: u64, whole: u64) -> f64 {
+    part as f64 * 100.0 / whole.max(1) as f64
+}
+
+impl fmt::Display for Counters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instructions = self.instructions();
+        writeln!(
+            f,
+            "dispatch: {instructions} instructions, {} stepped, {} fast, {} from {} decoded blocks",
+            self.steps, self.fast_steps, self.block_instructions, self.blocks_decoded
+        )?;
+        let mut opcodes: Vec<(usize, u64)> = self
+            .dispatches
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (opcode, count) in opcodes.into_iter().take(TOP_OPCODES) {
+            let mnemonic = op_codes::instruction(opcode as u8).map_or("???", |info| info.mnemonic);
+            writeln!(
+                f,
+                "  ${opcode:02X} {mnemonic}  {count:>12}  {:5.1}%",
+                percent(count, instructions)
+            )?;
+        }
+
+        let reads = self.ram_reads + self.rom_reads + self.device_reads;
+        let writes = self.ram_writes + self.rom_writes + self.device_writes;
+        writeln!(
+            f,
+            "bus: {reads} reads, {:.1}% RAM, {:.1}% ROM, {:.1}% devices",
+            percent(self.ram_reads, reads),
+            percent(self.rom_reads, reads),
+            percent(self.device_reads, reads)
+        )?;
+        writeln!(
+            f,
+            "     {writes} writes, {:.1}% RAM, {:.1}% dropped on ROM, {:.1}% devices",
+            percent(self.ram_writes, writes),
+            percent(self.rom_writes, writes),
+            percent(self.device_writes, writes)
+        )?;
+        write!(
+            f,
+            "overhead: {} hook calls in {:?}, {} observer events in {:?}, {} traps in {:?}",
+            self.hook_calls,
+            self.hook_time,
+            self.observer_events,
+            self.observer_time,
+            self.trap_calls,
+            self.trap_time
+        )
+    }
+}
+
+/// update this thread's counters
+pub(crate) fn record(update: impl FnOnce(&mut Counters)) {
+    COUNTERS.with(|counters| update(&mut counters.borrow_mut()));
+}
+
+/// this thread's counters
+pub fn counters() -> Counters {
+    COUNTERS.with(|counters| counters.borrow().clone())
+}
+
+/// zero this thread's counters, to profile one part of a run
+pub fn reset() {
+    record(|counters| *counters = Counters::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, memory::RomImage, op_codes::*};
+
+    #[test]
+    fn runs_are_counted_by_path_and_bus_route() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .hook(|_| {})
+            .memory(0x0600, vec![LDA_ABS, 0x00, 0xFF, PHA, TAX, NOP])
+            .build()
+            .unwrap();
+        cpu.memory
+            .map_rom(0xFF00, RomImage::Shared(vec![0x42; 0x100].into()))
+            .unwrap();
+        reset();
+        cpu.execute().unwrap();
+
+        let counters = counters();
+        assert_eq!(counters.steps, 3);
+        assert_eq!(counters.dispatches[TAX as usize], 1);
+        assert_eq!(counters.rom_reads, 1);
+        assert_eq!(counters.ram_writes, 1);
+        assert_eq!(counters.hook_calls, 3);
+        let report = counters.to_string();
+        assert!(report.starts_with("dispatch: 3 instructions, 3 stepped"));
+        assert!(report.contains("bus: "));
+    }
+}
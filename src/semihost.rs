@@ -0,0 +1,352 @@
+//! host file access for 6502 programs through a trap
+//!
+//! a program calls `JSR $FFF0` with the call in A and the address of a
+//! parameter block in X (low) and Y (high), the host does the work and the
+//! RTS installed at the trap returns, carry clear on success or set with an
+//! error code in A
+//!
+//! | A | call  | parameter block                                          |
+//! |---|-------|----------------------------------------------------------|
+//! | 1 | open  | handle (out), name address, mode                         |
+//! | 2 | close | handle                                                   |
+//! | 3 | read  | handle, buffer address, length (in), bytes read (out)    |
+//! | 4 | write | handle, buffer address, length (in), bytes written (out) |
+//!
+//! addresses and lengths are 16 bit little endian, names are NUL terminated
+//! paths relative to the directory the host gave, modes are 0 read, 1 write
+//! (created or truncated) and 2 append, handles 0, 1 and 2 are the host's
+//! stdin, stdout and stderr
+
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    cpu::{Cpu, CpuBuilder},
+    op_codes::RTS,
+};
+
+/// address programs call to reach the host
+pub const TRAP_ADDRESS: u16 = 0xFFF0;
+
+pub const OPEN: u8 = 1;
+pub const CLOSE: u8 = 2;
+pub const READ: u8 = 3;
+pub const WRITE: u8 = 4;
+
+/// error codes returned in A with carry set
+pub const NOT_FOUND: u8 = 1;
+pub const PERMISSION_DENIED: u8 = 2;
+pub const BAD_HANDLE: u8 = 3;
+pub const BAD_CALL: u8 = 4;
+pub const IO_ERROR: u8 = 5;
+pub const TOO_MANY_FILES: u8 = 6;
+
+/// handles open at once, including the standard streams
+const MAX_HANDLES: usize = 16;
+
+/// longest name read before giving up on finding its NUL
+const MAX_NAME: u16 = 255;
+
+/// something a handle refers to
+#[derive(Debug)]
+enum Handle {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+/// the files a program has open and the directory it can reach
+#[derive(Debug)]
+pub struct Semihost {
+    root: PathBuf,
+    handles: Vec<Option<Handle>>,
+}
+
+impl Semihost {
+    /// give programs access to the files under `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            handles: vec![
+                Some(Handle::Stdin),
+                Some(Handle::Stdout),
+                Some(Handle::Stderr),
+            ],
+        }
+    }
+
+    /// install the trap and its RTS on a cpu being built
+    pub fn attach(self, builder: CpuBuilder) -> CpuBuilder {
+        let semihost = RefCell::new(self);
+        builder.memory(TRAP_ADDRESS as usize, vec![RTS]).trap(
+            TRAP_ADDRESS,
+            Rc::new(RefCell::new(move |cpu: &mut Cpu| {
+                semihost.borrow_mut().call(cpu)
+            })),
+        )
+    }
+
+    /// carry out the call the cpu's registers ask for
+    pub fn call(&mut self, cpu: &mut Cpu) {
+        let block = u16::from_le_bytes([cpu.x(), cpu.y()]);
+        let result = match cpu.a() {
+            OPEN => self.open(cpu, block),
+            CLOSE => self.close(cpu, block),
+            READ => self.transfer(cpu, block, false),
+            WRITE => self.transfer(cpu, block, true),
+            _ => Err(BAD_CALL),
+        };
+
+        cpu.set_carry(result.is_err());
+        if let Err(code) = result {
+            cpu.set_a(code);
+        }
+    }
+
+    fn open(&mut self, cpu: &mut Cpu, block: u16) -> Result<(), u8> {
+        let name = read_name(cpu, read_word(cpu, block.wrapping_add(1)))?;
+        let mode = read_byte(cpu, block.wrapping_add(3));
+        let path = self.resolve(&name)?;
+        let mut options = OpenOptions::new();
+        match mode {
+            0 => options.read(true),
+            1 => options.write(true).create(true).truncate(true),
+            2 => options.append(true).create(true),
+            _ => return Err(BAD_CALL),
+        };
+
+        let Some(free) = self.handles.iter().position(Option::is_none).or_else(|| {
+            (self.handles.len() < MAX_HANDLES).then(|| {
+                self.handles.push(None);
+                self.handles.len() - 1
+            })
+        }) else {
+            return Err(TOO_MANY_FILES);
+        };
+        let file = options.open(path).map_err(error_code)?;
+        self.handles[free] = Some(Handle::File(file));
+        cpu.store(block, free as u8);
+        Ok(())
+    }
+
+    fn close(&mut self, cpu: &Cpu, block: u16) -> Result<(), u8> {
+        let handle = read_byte(cpu, block) as usize;
+        match self.handles.get_mut(handle) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(BAD_HANDLE),
+        }
+    }
+
+    /// read or write a buffer, recording how many bytes made it
+    fn transfer(&mut self, cpu: &mut Cpu, block: u16, write: bool) -> Result<(), u8> {
+        let handle = read_byte(cpu, block) as usize;
+        let buffer = read_word(cpu, block.wrapping_add(1));
+        let len = read_word(cpu, block.wrapping_add(3));
+        let Some(Some(handle)) = self.handles.get_mut(handle) else {
+            return Err(BAD_HANDLE);
+        };
+
+        let addresses = (0..len).map(|offset| buffer.wrapping_add(offset) as usize);
+        let count = if write {
+            let bytes: Vec<u8> = addresses
+                .map(|address| read_byte(cpu, address as u16))
+                .collect();
+            let written = match handle {
+                Handle::Stdout => io::stdout().write_all(&bytes),
+                Handle::Stderr => io::stderr().write_all(&bytes),
+                Handle::File(file) => file.write_all(&bytes),
+                Handle::Stdin => Err(ErrorKind::PermissionDenied.into()),
+            };
+            written.map_err(error_code)?;
+            len
+        } else {
+            let mut bytes = vec![0; len as usize];
+            let read = match handle {
+                Handle::Stdin => io::stdin().read(&mut bytes),
+                Handle::File(file) => file.read(&mut bytes),
+                Handle::Stdout | Handle::Stderr => Err(ErrorKind::PermissionDenied.into()),
+            }
+            .map_err(error_code)?;
+            for (address, byte) in addresses.zip(&bytes[..read]) {
+                cpu.store(address as u16, *byte);
+            }
+            read as u16
+        };
+
+        let count_at = block.wrapping_add(5);
+        let [low, high] = count.to_le_bytes();
+        cpu.store(count_at, low);
+        cpu.store(count_at.wrapping_add(1), high);
+        Ok(())
+    }
+
+    /// where a name points under the root, refusing anything that would leave it
+    fn resolve(&self, name: &str) -> Result<PathBuf, u8> {
+        let relative = Path::new(name);
+        let inside = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !inside {
+            return Err(PERMISSION_DENIED);
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+fn read_byte(cpu: &Cpu, address: u16) -> u8 {
+    cpu.memory.read_byte(address as usize)
+}
+
+fn read_word(cpu: &Cpu, address: u16) -> u16 {
+    u16::from_le_bytes([
+        read_byte(cpu, address),
+        read_byte(cpu, address.wrapping_add(1)),
+    ])
+}
+
+fn read_name(cpu: &Cpu, address: u16) -> Result<String, u8> {
+    let bytes: Vec<u8> = (0..=MAX_NAME)
+        .map(|offset| read_byte(cpu, address.wrapping_add(offset)))
+        .take_while(|byte| *byte != 0)
+        .collect();
+    if bytes.len() > MAX_NAME as usize {
+        return Err(BAD_CALL);
+    }
+    String::from_utf8(bytes).map_err(|_| BAD_CALL)
+}
+
+fn error_code(err: io::Error) -> u8 {
+    match err.kind() {
+        ErrorKind::NotFound => NOT_FOUND,
+        ErrorKind::PermissionDenied => PERMISSION_DENIED,
+        _ => IO_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::{Event, EventLog},
+        op_codes::{JSR, LDA_IM, LDX_IM, LDY_IM, NOP},
+    };
+    use std::fs;
+
+    #[test]
+    fn programs_read_host_files() {
+        let root = std::env::temp_dir().join("cpu_emu_semihost_read");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("data.bin"), [0xAB, 0xCD]).unwrap();
+
+        // open data.bin with the parameter block at $0010, then carry on
+        // after the call
+        let [trap_low, trap_high] = TRAP_ADDRESS.to_le_bytes();
+        let log = Rc::new(RefCell::new(EventLog::default()));
+        let builder = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(
+                0x0600,
+                vec![
+                    LDA_IM, OPEN, LDX_IM, 0x10, LDY_IM, 0x00, JSR, trap_low, trap_high, LDY_IM,
+                    0x01, NOP,
+                ],
+            )
+            .memory(0x10, vec![0, 0x20, 0, 0])
+            .memory(0x20, b"data.bin\0".to_vec())
+            .observer(log.clone());
+        let mut cpu = Semihost::new(&root).attach(builder).build().unwrap();
+        cpu.execute().unwrap();
+        assert!(!cpu.carry());
+        assert_eq!(cpu.memory.read_byte(0x10), 3);
+        assert_eq!((cpu.pc(), cpu.y(), cpu.sp()), (0x060C, 0x01, 0x01FF));
+        // the handle is written the way the program would write it
+        assert!(log.borrow().events.contains(&Event::MemoryWritten {
+            address: 0x10,
+            value: 3
+        }));
+
+        let mut semihost = Semihost::new(&root);
+        let mut cpu = Cpu::builder()
+            .memory(0x10, vec![0, 0x20, 0, 0])
+            .memory(0x20, b"data.bin\0".to_vec())
+            .a(OPEN)
+            .x(0x10)
+            .build()
+            .unwrap();
+        semihost.call(&mut cpu);
+        let handle = cpu.memory.read_byte(0x10);
+
+        // read up to 4 bytes into $30
+        cpu.memory
+            .write_bytes(0x10, &[handle, 0x30, 0, 4, 0])
+            .unwrap();
+        cpu.set_a(READ);
+        semihost.call(&mut cpu);
+        assert!(!cpu.carry());
+        assert_eq!(cpu.memory.read_word(0x15), 2);
+        assert_eq!(cpu.memory.read_word(0x30), 0xCDAB);
+
+        cpu.set_a(CLOSE);
+        semihost.call(&mut cpu);
+        semihost.call(&mut cpu);
+        assert!(cpu.carry());
+        assert_eq!(cpu.a(), BAD_HANDLE);
+    }
+
+    #[test]
+    fn programs_write_host_files() {
+        let root = std::env::temp_dir().join("cpu_emu_semihost_write");
+        fs::create_dir_all(&root).unwrap();
+
+        let mut semihost = Semihost::new(&root);
+        let mut cpu = Cpu::builder()
+            .memory(0x10, vec![0, 0x20, 0, 1])
+            .memory(0x20, b"out.txt\0".to_vec())
+            .memory(0x30, b"hi".to_vec())
+            .a(OPEN)
+            .x(0x10)
+            .build()
+            .unwrap();
+        semihost.call(&mut cpu);
+        let handle = cpu.memory.read_byte(0x10);
+
+        cpu.memory
+            .write_bytes(0x10, &[handle, 0x30, 0, 2, 0])
+            .unwrap();
+        cpu.set_a(WRITE);
+        semihost.call(&mut cpu);
+        cpu.set_a(CLOSE);
+        semihost.call(&mut cpu);
+        assert_eq!(fs::read(root.join("out.txt")).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn names_cannot_leave_the_root() {
+        let mut semihost = Semihost::new(std::env::temp_dir());
+        for name in [&b"../etc/passwd\0"[..], b"/etc/passwd\0", b"\0"] {
+            let mut cpu = Cpu::builder()
+                .memory(0x10, vec![0, 0x20, 0, 0])
+                .memory(0x20, name.to_vec())
+                .a(OPEN)
+                .x(0x10)
+                .build()
+                .unwrap();
+            semihost.call(&mut cpu);
+            assert_eq!(cpu.a(), PERMISSION_DENIED);
+        }
+
+        let mut cpu = Cpu::builder().a(0x42).build().unwrap();
+        semihost.call(&mut cpu);
+        assert_eq!(cpu.a(), BAD_CALL);
+    }
+}
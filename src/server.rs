@@ -0,0 +1,362 @@
+//! a small http api for driving a cpu from test infrastructure or dashboards
+//!
+//! | request                              | does                                       |
+//! |--------------------------------------|--------------------------------------------|
+//! | `GET /state`                         | registers and counters                     |
+//! | `POST /load?origin=$0600`            | write the body at the origin and reset     |
+//! | `POST /reset?pc=$0600`               | reset, to the last origin unless given     |
+//! | `POST /step?count=1`                 | execute instructions                       |
+//! | `POST /run?max=1000000`              | execute until the cpu halts or `max` steps |
+//! | `GET /peek?address=$0200&len=16`     | read memory                                |
+//! | `POST /poke?address=$0200`           | write the body to memory                   |
+//! | `GET /trace`                         | the last instructions stepped, one a line  |
+//!
+//! numbers are `$` or `0x` prefixed hex or decimal, responses are json except
+//! for the trace
+
+use std::{collections::VecDeque, io};
+
+use tiny_http::{Header, Request};
+
+use crate::{cpu::Cpu, memory::MAX_MEM, runner::json_string, trace::TraceFormat};
+
+/// address `cpu_emu serve` listens on unless told otherwise
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6502";
+
+/// steps `/run` takes before giving up on a program that doesn't halt
+const DEFAULT_RUN_STEPS: u64 = 1_000_000;
+
+/// trace lines kept for `/trace`
+const TRACE_LEN: usize = 1024;
+
+/// the answer to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            ..Self::json(format!("{{\"error\":{}}}", json_string(message)))
+        }
+    }
+}
+
+/// a cpu and the requests that drive it, separate from the socket so it can be
+/// used from any server
+pub struct Controller {
+    cpu: Cpu,
+    /// where `/reset` starts execution from, the origin of the last `/load`
+    origin: u16,
+    trace: VecDeque<String>,
+}
+
+impl Controller {
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            origin: cpu.pc(),
+            cpu,
+            trace: VecDeque::new(),
+        }
+    }
+
+    /// the cpu being driven
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// answer a request for `url`, e.g. `/peek?address=$0200`
+    pub fn handle(&mut self, method: &str, url: &str, body: &[u8]) -> Response {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let param = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| parse_number(value).ok_or(value))
+                .transpose()
+        };
+        let param = |name: &str| {
+            param(name).map_err(|value| Response::error(400, &format!("invalid {name} {value}")))
+        };
+        let address = |name: &str| {
+            param(name)?
+                .map(|value| {
+                    u16::try_from(value)
+                        .map_err(|_| Response::error(400, &format!("{name} {value} is past $FFFF")))
+                })
+                .transpose()
+        };
+
+        let result = match (method, path) {
+            ("GET", "/state") => Ok(Response::json(self.state())),
+            ("POST", "/load") => address("origin").and_then(|origin| self.load(origin, body)),
+            ("POST", "/reset") => address("pc").map(|pc| {
+                self.reset(pc.unwrap_or(self.origin));
+                Response::json(self.state())
+            }),
+            ("POST", "/step") => param("count").map(|count| self.step(count.unwrap_or(1))),
+            ("POST", "/run") => param("max").map(|max| self.step(max.unwrap_or(DEFAULT_RUN_STEPS))),
+            ("GET", "/peek") => {
+                param("address").and_then(|address| Ok(self.peek(address, param("len")?)))
+            }
+            ("POST", "/poke") => param("address").map(|address| self.poke(address, body)),
+            ("GET", "/trace") => Ok(Response {
+                status: 200,
+                content_type: "text/plain",
+                body: self.trace.iter().map(|line| format!("{line}\n")).collect(),
+            }),
+            _ => Err(Response::error(404, &format!("no {method} {path}"))),
+        };
+        result.unwrap_or_else(|response| response)
+    }
+
+    fn state(&self) -> String {
+        let cpu = &self.cpu;
+        format!(
+            "{{\"pc\":{},\"a\":{},\"x\":{},\"y\":{},\"sp\":{},\"status\":{},\"cycles\":{},\"instructions\":{}}}",
+            cpu.pc(),
+            cpu.a(),
+            cpu.x(),
+            cpu.y(),
+            cpu.sp(),
+            cpu.status().bits(),
+            cpu.cycles(),
+            cpu.instructions()
+        )
+    }
+
+    fn reset(&mut self, pc: u16) {
+        self.cpu.reset(Some(pc));
+        self.trace.clear();
+    }
+
+    fn load(&mut self, origin: Option<u16>, program: &[u8]) -> Result<Response, Response> {
+        let origin = origin.unwrap_or(self.origin);
+        self.cpu
+            .load_program(origin as usize, program.to_vec())
+            .map_err(|err| Response::error(400, &err.to_string()))?;
+        self.origin = origin;
+        self.reset(origin);
+        Ok(Response::json(self.state()))
+    }
+
+    fn step(&mut self, count: u64) -> Response {
+        let mut steps = 0;
+        let mut halted = false;
+        let mut error = None;
+        while steps < count {
+            if self.trace.len() == TRACE_LEN {
+                self.trace.pop_front();
+            }
+            self.trace.push_back(TraceFormat::Default.format(&self.cpu));
+            match self.cpu.step() {
+                Ok(true) => steps += 1,
+                Ok(false) => {
+                    halted = true;
+                    break;
+                }
+                Err(err) => {
+                    error = Some(err.to_string());
+                    break;
+                }
+            }
+        }
+
+        let error = error.map_or("null".to_string(), |error| json_string(&error));
+        Response::json(format!(
+            "{{\"steps\":{steps},\"halted\":{halted},\"error\":{error},\"state\":{}}}",
+            self.state()
+        ))
+    }
+
+    fn peek(&self, address: Option<u64>, len: Option<u64>) -> Response {
+        let Some(address) = address.filter(|address| *address < MAX_MEM as u64) else {
+            return Response::error(400, "peek needs an address");
+        };
+        let end = address.saturating_add(len.unwrap_or(1)).min(MAX_MEM as u64);
+        let bytes: Vec<String> = (address..end)
+            .map(|address| self.cpu.memory.peek_byte(address as usize).to_string())
+            .collect();
+        Response::json(format!(
+            "{{\"address\":{address},\"bytes\":[{}]}}",
+            bytes.join(",")
+        ))
+    }
+
+    fn poke(&mut self, address: Option<u64>, bytes: &[u8]) -> Response {
+        let fits = address.is_some_and(|address| {
+            address
+                .checked_add(bytes.len() as u64)
+                .is_some_and(|end| end <= MAX_MEM as u64)
+        });
+        let (Some(address), true) = (address, fits) else {
+            return Response::error(400, "poke needs an address with room for the body");
+        };
+        self.cpu.invalidate_block_cache();
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.cpu.memory.write_byte(address as usize + offset, *byte);
+        }
+        Response::json(format!("{{\"written\":{}}}", bytes.len()))
+    }
+}
+
+/// parse `$0600`, `%240600` (an escaped `$`), `0x0600` or decimal
+fn parse_number(value: &str) -> Option<u64> {
+    let hex = value
+        .strip_prefix('$')
+        .or_else(|| value.strip_prefix("%24"))
+        .or_else(|| value.strip_prefix("0x"));
+    match hex {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// listen on `address` and answer requests for a cpu until the process exits,
+/// requests are handled one at a time on the calling thread
+pub fn serve(address: &str, cpu: Cpu) -> io::Result<()> {
+    let server = tiny_http::Server::http(address).map_err(io::Error::other)?;
+    let mut controller = Controller::new(cpu);
+    for request in server.incoming_requests() {
+        respond(&mut controller, request)?;
+    }
+    Ok(())
+}
+
+fn respond(controller: &mut Controller, mut request: Request) -> io::Result<()> {
+    let mut body = Vec::new();
+    request.as_reader().read_to_end(&mut body)?;
+    let response = controller.handle(request.method().as_str(), request.url(), &body);
+
+    let content_type = Header::from_bytes("Content-Type", response.content_type)
+        .expect("content types are valid headers");
+    request.respond(
+        tiny_http::Response::from_string(response.body)
+            .with_status_code(response.status)
+            .with_header(content_type),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        acia::{Acia, BufferPort},
+        device::Device,
+        op_codes::*,
+        testing::DEFAULT_ORIGIN,
+    };
+
+    #[test]
+    fn load_step_and_inspect() {
+        let mut controller = Controller::new(Cpu::new().reset(Some(DEFAULT_ORIGIN)));
+
+        let program = [LDA_IM, 0x42, TAX, NOP];
+        let loaded = controller.handle("POST", "/load?origin=%240200", &program);
+        assert_eq!(loaded.status, 200);
+        assert_eq!(controller.cpu().pc(), 0x0200);
+
+        let stepped = controller.handle("POST", "/step", &[]);
+        assert!(stepped
+            .body
+            .starts_with("{\"steps\":1,\"halted\":false,\"error\":null"));
+        let ran = controller.handle("POST", "/run", &[]);
+        assert!(ran.body.starts_with("{\"steps\":1,\"halted\":true"));
+        assert_eq!(controller.cpu().x(), 0x42);
+
+        let trace = controller.handle("GET", "/trace", &[]).body;
+        assert_eq!(trace.lines().count(), 3);
+        assert!(trace.starts_with("0200  A9  LDA"));
+
+        assert_eq!(
+            controller
+                .handle("GET", "/peek?address=0x0200&len=2", &[])
+                .body,
+            "{\"address\":512,\"bytes\":[169,66]}"
+        );
+    }
+
+    #[test]
+    fn poke_and_errors() {
+        let mut controller = Controller::new(Cpu::new().reset(Some(DEFAULT_ORIGIN)));
+
+        assert_eq!(
+            controller
+                .handle("POST", "/poke?address=$10", &[1, 2])
+                .status,
+            200
+        );
+        assert_eq!(controller.cpu().memory.read_word(0x10), 0x0201);
+
+        assert_eq!(
+            controller
+                .handle("POST", "/poke?address=$FFFF", &[1, 2])
+                .status,
+            400
+        );
+        assert_eq!(
+            controller.handle("GET", "/peek?address=nope", &[]).status,
+            400
+        );
+        assert_eq!(
+            controller
+                .handle("POST", "/poke?address=18446744073709551615", &[1])
+                .status,
+            400
+        );
+        for url in ["/reset?pc=$10000", "/load?origin=70000"] {
+            let response = controller.handle("POST", url, &[NOP]);
+            assert_eq!(response.status, 400);
+        }
+        assert_eq!(controller.cpu().pc(), DEFAULT_ORIGIN);
+        assert_eq!(controller.handle("DELETE", "/state", &[]).status, 404);
+    }
+
+    #[test]
+    fn pokes_drop_cached_blocks() {
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .block_cache(true)
+            .memory(0x0600, vec![LDA_IM, 0x01, NOP])
+            .build()
+            .unwrap();
+        let mut controller = Controller::new(cpu);
+        controller.cpu.execute().unwrap();
+        assert_eq!(controller.cpu().a(), 0x01);
+
+        // the cached block still holds the LDA unless the poke dropped it
+        controller.handle("POST", "/poke?address=$0600", &[LDX_IM, 0x02]);
+        controller.cpu.reset_registers(0x0600);
+        controller.cpu.execute().unwrap();
+        assert_eq!((controller.cpu().a(), controller.cpu().x()), (0x00, 0x02));
+    }
+
+    #[test]
+    fn peeks_leave_devices_alone() {
+        let acia = Rc::new(RefCell::new(Acia::new(BufferPort::default())));
+        acia.borrow_mut().port_mut().input.extend(b"x");
+        let cpu = Cpu::builder()
+            .device(0xD000, 4, acia.clone())
+            .build()
+            .unwrap();
+        let mut controller = Controller::new(cpu);
+        controller.handle("GET", "/peek?address=$D000&len=4", &[]);
+        // the received byte is still waiting for the program
+        assert_eq!(acia.borrow_mut().read(0), b'x');
+    }
+}
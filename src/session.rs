@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs, io,
+    path::Path,
+};
+
+use crate::events::{Access, Event, Observer};
+
+/// accesses that trigger a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Load,
+    Store,
+    /// loads and stores
+    Any,
+}
+
+impl WatchKind {
+    /// the keyword VICE uses for the kind, none when it watches both
+    fn keyword(&self) -> Option<&'static str> {
+        match self {
+            WatchKind::Load => Some("load"),
+            WatchKind::Store => Some("store"),
+            WatchKind::Any => None,
+        }
+    }
+}
+
+/// breakpoints, watchpoints and labels set from the monitor
+/// saved as a script of VICE monitor commands, so loading a session and
+/// importing a VICE command file are the same thing
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub breakpoints: BTreeSet<u16>,
+    pub watchpoints: BTreeMap<u16, WatchKind>,
+    /// label names without VICE's leading `.`
+    pub labels: BTreeMap<String, u16>,
+    /// one-shot breakpoints, gone once hit and never saved
+    pub temporary: BTreeSet<u16>,
+}
+
+impl Session {
+    /// whether any breakpoints or watchpoints are set
+    pub fn has_checkpoints(&self) -> bool {
+        !self.breakpoints.is_empty() || !self.watchpoints.is_empty() || !self.temporary.is_empty()
+    }
+
+    /// whether a breakpoint stops the cpu at `pc`, a temporary one is
+    /// removed as it's hit
+    pub fn breaks_at(&mut self, pc: u16) -> bool {
+        self.temporary.remove(&pc) | self.breakpoints.contains(&pc)
+    }
+
+    /// the session as VICE monitor commands, one per line
+    pub fn to_commands(&self) -> String {
+        let mut out = String::new();
+        for (name, address) in &self.labels {
+            writeln!(out, "al C:{address:04X} .{name}").unwrap();
+        }
+        for address in &self.breakpoints {
+            writeln!(out, "break C:{address:04X}").unwrap();
+        }
+        for (address, kind) in &self.watchpoints {
+            match kind.keyword() {
+                Some(keyword) => writeln!(out, "watch {keyword} C:{address:04X}").unwrap(),
+                None => writeln!(out, "watch C:{address:04X}").unwrap(),
+            }
+        }
+        out
+    }
+
+    /// add the labels from the `al` lines of VICE monitor commands, like the
+    /// files `asm --labels` writes, ignoring every other command
+    pub fn add_labels(&mut self, commands: &str) {
+        for line in commands.lines() {
+            let mut words = line.split_whitespace();
+            if !matches!(words.next(), Some("al" | "add_label")) {
+                continue;
+            }
+            let address = words.next().and_then(|address| {
+                let address = address.trim_start_matches("C:").trim_start_matches('$');
+                u16::from_str_radix(address, 16).ok()
+            });
+            let name = words.next().and_then(|name| name.strip_prefix('.'));
+            if let (Some(address), Some(name)) = (address, name) {
+                self.labels.insert(name.to_string(), address);
+            }
+        }
+    }
+
+    /// write the session to a file that `Monitor` can play back
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_commands())
+    }
+}
+
+/// observer remembering the first access to a watched address
+#[derive(Debug, Default)]
+pub struct Watcher {
+    pub watchpoints: BTreeMap<u16, WatchKind>,
+    /// address of the access that tripped a watchpoint
+    pub hit: Option<u16>,
+}
+
+impl Observer for Watcher {
+    fn notify(&mut self, event: &Event) {
+        let (address, store) = match *event {
+            Event::MemoryRead {
+                access: Access::Dummy,
+                ..
+            } => return,
+            Event::MemoryRead { address, .. } | Event::StackPull { address, .. } => {
+                (address, false)
+            }
+            Event::MemoryWritten { address, .. } => (address, true),
+            _ => return,
+        };
+        let tripped = match self.watchpoints.get(&address) {
+            Some(WatchKind::Any) => true,
+            Some(WatchKind::Load) => !store,
+            Some(WatchKind::Store) => store,
+            None => false,
+        };
+        if tripped && self.hit.is_none() {
+            self.hit = Some(address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temporary_breakpoints_go_once_hit() {
+        let mut session = Session::default();
+        session.breakpoints.insert(0x0600);
+        session.temporary.extend([0x0600, 0x0604]);
+        assert!(session.breaks_at(0x0604));
+        assert!(!session.breaks_at(0x0604));
+        assert!(session.breaks_at(0x0600));
+        assert!(session.breaks_at(0x0600));
+        assert!(session.temporary.is_empty());
+        assert!(!session.to_commands().contains("0604"));
+    }
+
+    #[test]
+    fn session_is_written_as_vice_commands() {
+        let mut session = Session::default();
+        session.labels.insert("start".to_string(), 0x0600);
+        session.breakpoints.insert(0x0604);
+        session.watchpoints.insert(0x0200, WatchKind::Store);
+        session.watchpoints.insert(0x0300, WatchKind::Any);
+
+        assert_eq!(
+            session.to_commands(),
+            "al C:0600 .start\nbreak C:0604\nwatch store C:0200\nwatch C:0300\n"
+        );
+    }
+
+    #[test]
+    fn labels_are_read_back_from_commands() {
+        let mut session = Session::default();
+        session.add_labels("al C:0600 .start\nbreak C:0604\nal $0610 .loop\nal bogus .x\n");
+        assert_eq!(
+            session.labels,
+            BTreeMap::from([("loop".to_string(), 0x0610), ("start".to_string(), 0x0600)])
+        );
+        assert!(session.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn watcher_only_trips_on_matching_accesses() {
+        let mut watcher = Watcher::default();
+        watcher.watchpoints.insert(0x0200, WatchKind::Store);
+
+        watcher.notify(&Event::MemoryRead {
+            address: 0x0200,
+            value: 0,
+            access: Access::Data,
+        });
+        assert_eq!(watcher.hit, None);
+
+        watcher.notify(&Event::MemoryWritten {
+            address: 0x0200,
+            value: 1,
+        });
+        assert_eq!(watcher.hit, Some(0x0200));
+    }
+}
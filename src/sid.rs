@@ -0,0 +1,276 @@
+//! a three voice sound chip, a much simplified SID, rendering through an
+//! [`AudioBridge`]
+//!
+//! | offset | read and write                               |
+//! |--------|----------------------------------------------|
+//! | 0, 1   | voice 1 frequency, low then high byte        |
+//! | 2      | voice 1 control, waveform and gate           |
+//! | 3      | voice 1 volume, 0 to 15                      |
+//! | 4-7    | voice 2, laid out like voice 1               |
+//! | 8-11   | voice 3, laid out like voice 1               |
+//! | 12     | master volume, 0 to 15                       |
+//!
+//! frequencies count like the SID's, a voice plays `frequency * clock / 2^24`
+//! Hz, so the SID's note tables work unchanged
+//!
+//! a control byte picks a waveform with its high bits and starts the note with
+//! bit 0, the highest waveform bit set wins, and a voice with none set or its
+//! gate clear is silent, there are no envelopes, filters or ring modulation
+
+use crate::{
+    audio::{AudioBackend, SharedAudio},
+    device::Device,
+};
+
+/// address the chip is mapped at unless told otherwise
+pub const DEFAULT_BASE: u16 = 0xD500;
+
+/// addresses the chip's registers take up
+pub const LEN: usize = 13;
+
+/// control bit starting a voice's note
+pub const GATE: u8 = 0x01;
+/// control bit picking the triangle wave
+pub const TRIANGLE: u8 = 0x10;
+/// control bit picking the sawtooth wave
+pub const SAWTOOTH: u8 = 0x20;
+/// control bit picking the square wave
+pub const PULSE: u8 = 0x40;
+/// control bit picking noise
+pub const NOISE: u8 = 0x80;
+
+/// registers each voice takes up
+const VOICE_LEN: u16 = 4;
+/// the register holding the master volume
+const MASTER: u16 = 12;
+
+/// the oscillators count in 24 bits
+const PHASE_MASK: u32 = 0x00FF_FFFF;
+/// the phase bit clocking the noise generator when it rises
+const NOISE_CLOCK: u32 = 0x0008_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Voice {
+    frequency: u16,
+    control: u8,
+    volume: u8,
+    phase: u32,
+    /// the noise generator's shift register
+    noise: u32,
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Self {
+            frequency: 0,
+            control: 0,
+            volume: 0,
+            phase: 0,
+            noise: 0x007F_FFF8,
+        }
+    }
+}
+
+impl Voice {
+    fn advance(&mut self, cycles: u64) {
+        let before = self.phase;
+        let step = self.frequency as u64 * cycles;
+        self.phase = ((before as u64 + step) & PHASE_MASK as u64) as u32;
+        // a long gap between updates clocks the noise only once, which
+        // can't be heard at sample rate anyway
+        if before & NOISE_CLOCK == 0 && (self.phase & NOISE_CLOCK != 0 || step > PHASE_MASK as u64)
+        {
+            let bit = (self.noise >> 22 ^ self.noise >> 17) & 1;
+            self.noise = (self.noise << 1 | bit) & 0x007F_FFFF;
+        }
+    }
+
+    /// the waveform's level between -1 and 1, before volume
+    fn wave(&self) -> f32 {
+        let phase = self.phase as f32 / (PHASE_MASK + 1) as f32;
+        if self.control & NOISE != 0 {
+            (self.noise & 0xFF) as f32 / 127.5 - 1.0
+        } else if self.control & PULSE != 0 {
+            if phase < 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
+        } else if self.control & SAWTOOTH != 0 {
+            phase * 2.0 - 1.0
+        } else if self.control & TRIANGLE != 0 {
+            1.0 - 4.0 * (phase - 0.5).abs()
+        } else {
+            0.0
+        }
+    }
+
+    fn level(&self) -> f32 {
+        if self.control & GATE == 0 {
+            return 0.0;
+        }
+        self.wave() * self.volume as f32 / 15.0
+    }
+}
+
+/// three voices of tone or noise
+#[derive(Debug)]
+pub struct Sid<B> {
+    audio: SharedAudio<B>,
+    channels: [usize; 3],
+    voices: [Voice; 3],
+    master: u8,
+    /// cycles between updates of the levels, about a sample
+    period: u64,
+    /// the cycle the oscillators have been run up to
+    rendered: u64,
+    /// the cycle the cpu was at when the chip was last accessed
+    now: u64,
+}
+
+impl<B: AudioBackend> Sid<B> {
+    pub fn new(audio: SharedAudio<B>) -> Self {
+        let (channels, period) = {
+            let mut bridge = audio.borrow_mut();
+            let channels = [bridge.channel(), bridge.channel(), bridge.channel()];
+            (channels, (bridge.cycles_per_sample() as u64).max(1))
+        };
+        Self {
+            audio,
+            channels,
+            voices: Default::default(),
+            master: 15,
+            period,
+            rendered: 0,
+            now: 0,
+        }
+    }
+
+    /// run the oscillators up to `cycle` and hand the bridge their levels
+    fn render(&mut self, cycle: u64) {
+        let elapsed = cycle.saturating_sub(self.rendered);
+        self.rendered = self.rendered.max(cycle);
+        let mut audio = self.audio.borrow_mut();
+        for (voice, channel) in self.voices.iter_mut().zip(self.channels) {
+            voice.advance(elapsed);
+            // a third each so three voices at full volume don't clip
+            let level = voice.level() * self.master as f32 / 15.0 / 3.0;
+            audio.set_level(channel, self.rendered, level);
+        }
+    }
+}
+
+impl<B: AudioBackend> Device for Sid<B> {
+    fn read(&mut self, offset: u16) -> u8 {
+        if offset >= MASTER {
+            return self.master;
+        }
+        let voice = &self.voices[(offset / VOICE_LEN) as usize];
+        match offset % VOICE_LEN {
+            0 => voice.frequency as u8,
+            1 => (voice.frequency >> 8) as u8,
+            2 => voice.control,
+            _ => voice.volume,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.render(self.now);
+        if offset >= MASTER {
+            self.master = value & 0x0F;
+        } else {
+            let voice = &mut self.voices[(offset / VOICE_LEN) as usize];
+            match offset % VOICE_LEN {
+                0 => voice.frequency = voice.frequency & 0xFF00 | value as u16,
+                1 => voice.frequency = voice.frequency & 0x00FF | (value as u16) << 8,
+                2 => voice.control = value,
+                _ => voice.volume = value & 0x0F,
+            }
+        }
+        self.render(self.now);
+    }
+
+    fn scheduled(&self) -> bool {
+        true
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        Some(self.rendered + self.period)
+    }
+
+    fn event(&mut self, cycle: u64) {
+        self.render(cycle);
+    }
+
+    fn sync(&mut self, cycle: u64) {
+        self.now = cycle;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::audio::{AudioBridge, BufferBackend};
+
+    /// a second of a voice playing at 1MHz, sampled at 8kHz
+    fn play(control: u8, volume: u8) -> Vec<f32> {
+        let audio = Rc::new(RefCell::new(AudioBridge::new(
+            1_000_000,
+            BufferBackend::new(8000),
+        )));
+        let mut sid = Sid::new(audio.clone());
+        // 16777 is 1kHz at 1MHz
+        sid.write(0, 0x89);
+        sid.write(1, 0x41);
+        sid.write(3, volume);
+        sid.write(2, control);
+        while let Some(cycle) = sid.next_event().filter(|cycle| *cycle <= 1_000_000) {
+            sid.event(cycle);
+        }
+        let mut bridge = audio.borrow_mut();
+        bridge.flush();
+        bridge.backend().samples.clone()
+    }
+
+    #[test]
+    fn voices_play_at_their_frequency() {
+        let samples = play(PULSE | GATE, 15);
+        assert_eq!(samples.len(), 8000);
+        let flips = samples
+            .windows(2)
+            .filter(|pair| (pair[0] > 0.0) != (pair[1] > 0.0))
+            .count();
+        // two flips a cycle, give or take the edges
+        assert!((1990..=2010).contains(&flips), "{flips}");
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 1.0 / 3.0).abs() < 0.01, "{peak}");
+    }
+
+    #[test]
+    fn closed_gates_and_silent_voices_are_quiet() {
+        assert!(play(SAWTOOTH, 15).iter().all(|sample| *sample == 0.0));
+        assert!(play(TRIANGLE | GATE, 0).iter().all(|sample| *sample == 0.0));
+        assert!(play(NOISE | GATE, 15).iter().any(|sample| *sample != 0.0));
+    }
+
+    #[test]
+    fn registers_read_back() {
+        let audio = Rc::new(RefCell::new(AudioBridge::new(
+            1_000_000,
+            BufferBackend::new(8000),
+        )));
+        let mut sid = Sid::new(audio);
+        sid.write(5, 0x12);
+        sid.write(6, TRIANGLE | GATE);
+        sid.write(7, 0xFF);
+        sid.write(MASTER, 0x38);
+        assert_eq!(
+            (0..LEN as u16)
+                .map(|offset| sid.read(offset))
+                .collect::<Vec<_>>(),
+            [0, 0, 0, 0, 0, 0x12, 0x11, 0x0F, 0, 0, 0, 0, 0x08]
+        );
+    }
+}
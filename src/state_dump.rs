@@ -0,0 +1,99 @@
+use core::fmt;
+use std::ops::RangeInclusive;
+
+use crate::{cpu::Cpu, trace};
+
+/// bytes shown per row of a memory range
+const ROW_LEN: usize = 16;
+
+/// a canonical text dump of a cpu after a run, stable enough to snapshot in
+/// tests so any change to registers, flags, timing or memory shows up as a diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDump {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u16,
+    flags: String,
+    cycles: u64,
+    instructions: u64,
+    /// the selected memory ranges and their contents
+    memory: Vec<(u16, Vec<u8>)>,
+}
+
+impl StateDump {
+    /// capture the registers and the given memory ranges
+    pub fn new(cpu: &Cpu, ranges: &[RangeInclusive<u16>]) -> Self {
+        Self {
+            pc: cpu.pc(),
+            a: cpu.a(),
+            x: cpu.x(),
+            y: cpu.y(),
+            sp: cpu.sp(),
+            flags: trace::flags(cpu.status()),
+            cycles: cpu.cycles(),
+            instructions: cpu.instructions(),
+            memory: ranges
+                .iter()
+                .map(|range| {
+                    let bytes = range
+                        .clone()
+                        .map(|address| cpu.memory.read_byte(address as usize))
+                        .collect();
+                    (*range.start(), bytes)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for StateDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "PC:${:04X} A:${:02X} X:${:02X} Y:${:02X} SP:${:04X}",
+            self.pc, self.a, self.x, self.y, self.sp
+        )?;
+        writeln!(f, "flags: {}", self.flags)?;
+        writeln!(
+            f,
+            "cycles: {} instructions: {}",
+            self.cycles, self.instructions
+        )?;
+        for (start, bytes) in &self.memory {
+            for (row, chunk) in bytes.chunks(ROW_LEN).enumerate() {
+                let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02X}")).collect();
+                let address = start.wrapping_add((row * ROW_LEN) as u16);
+                writeln!(f, "${address:04X}: {}", hex.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn dump_after_run() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x80, PHA, TAX, SEC, NOP])
+            .memory(0x0010, vec![0xDE, 0xAD])
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+
+        let dump = StateDump::new(&cpu, &[0x0010..=0x0011, 0x00F8..=0x0100]);
+        insta::assert_snapshot!(dump, @r"
+        PC:$0606 A:$80 X:$80 Y:$00 SP:$01FF
+        flags: N.-....C
+        cycles: 11 instructions: 4
+        $0010: DE AD
+        $00F8: 00 00 00 00 00 00 00 00 80
+        ");
+    }
+}
@@ -0,0 +1,353 @@
+use core::fmt;
+use std::{
+    ops::{Range, RangeInclusive},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cdl::CodeDataLog,
+    cpu::{Cpu, CpuError},
+    registers::Registers,
+    runner::json_string,
+};
+
+/// throughput achieved over a run
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Speed {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub elapsed: Duration,
+}
+
+impl Speed {
+    /// throughput of a cpu that has been running for `elapsed` since reset
+    pub fn of(cpu: &Cpu, elapsed: Duration) -> Self {
+        Self {
+            instructions: cpu.instructions(),
+            cycles: cpu.cycles(),
+            elapsed,
+        }
+    }
+
+    pub fn instructions_per_sec(&self) -> f64 {
+        per_sec(self.instructions, self.elapsed)
+    }
+
+    /// clock speed of a real 6502 running the same number of cycles in the same time
+    pub fn mhz(&self) -> f64 {
+        per_sec(self.cycles, self.elapsed) / 1_000_000.0
+    }
+
+    /// whether cycles were counted, fast mode runs instructions without them
+    /// so there's no clock speed to report
+    pub fn counts_cycles(&self) -> bool {
+        self.cycles > 0 || self.instructions == 0
+    }
+
+    /// combine the throughput of two runs
+    pub fn add(&mut self, other: Speed) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+        self.elapsed += other.elapsed;
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.counts_cycles() {
+            return write!(
+                f,
+                "{} instructions in {:.3}s: {:.0} instructions/s, no MHz as fast mode doesn't count cycles",
+                self.instructions,
+                self.elapsed.as_secs_f64(),
+                self.instructions_per_sec()
+            );
+        }
+        write!(
+            f,
+            "{} instructions, {} cycles in {:.3}s: {:.0} instructions/s, {:.2} MHz",
+            self.instructions,
+            self.cycles,
+            self.elapsed.as_secs_f64(),
+            self.instructions_per_sec(),
+            self.mhz()
+        )
+    }
+}
+
+/// how a run went, for printing once it stops
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub speed: Speed,
+    /// why the run stopped
+    pub stop: String,
+    pub registers: Registers,
+    /// bytes of the program run as code, out of its length
+    pub coverage: Option<(usize, usize)>,
+    /// memory captured once the run stopped, from each start address
+    pub memory: Vec<(u16, Vec<u8>)>,
+    /// the NOP the cpu halted on, which never retires so isn't logged
+    halted_at: Option<u16>,
+}
+
+impl Summary {
+    /// a run of `cpu` that took `elapsed` since reset and ended with `result`
+    pub fn new(cpu: &Cpu, elapsed: Duration, result: &Result<(), CpuError>) -> Self {
+        // the pc has moved past the NOP that halted it
+        let halted_at = result.is_ok().then(|| cpu.pc().wrapping_sub(1));
+        Self {
+            speed: Speed::of(cpu, elapsed),
+            stop: match halted_at {
+                Some(address) => format!("halted at ${address:04X}"),
+                None => result
+                    .as_ref()
+                    .err()
+                    .map_or_else(String::new, |err| err.to_string()),
+            },
+            registers: cpu.registers(),
+            coverage: None,
+            memory: Vec::new(),
+            halted_at,
+        }
+    }
+
+    /// count the bytes of the program at `program` a code/data log saw run
+    pub fn coverage(mut self, log: &CodeDataLog, program: Range<usize>) -> Self {
+        let run = program
+            .clone()
+            .filter(|address| {
+                let address = *address as u16;
+                log.is_code(address) || self.halted_at == Some(address)
+            })
+            .count();
+        self.coverage = Some((run, program.len()));
+        self
+    }
+
+    /// capture the memory in `range` as the run left it
+    pub fn memory(mut self, cpu: &Cpu, range: RangeInclusive<u16>) -> Self {
+        let start = *range.start();
+        let bytes = range
+            .map(|address| cpu.memory.read_byte(address as usize))
+            .collect();
+        self.memory.push((start, bytes));
+        self
+    }
+
+    /// the summary as json for graders and scripts, fields are only ever added
+    /// to this, never renamed or removed, and `version` goes up when they are
+    pub fn to_json(&self) -> String {
+        let registers = &self.registers;
+        let coverage = match self.coverage {
+            Some((run, len)) => format!("{{\"run\":{run},\"bytes\":{len}}}"),
+            None => "null".to_string(),
+        };
+        let memory: Vec<String> = self
+            .memory
+            .iter()
+            .map(|(start, bytes)| {
+                let bytes: Vec<String> = bytes.iter().map(u8::to_string).collect();
+                format!("{{\"start\":{start},\"bytes\":[{}]}}", bytes.join(","))
+            })
+            .collect();
+        format!(
+            "{{\"version\":1,\"halted\":{},\"stop\":{},\"instructions\":{},\"cycles\":{},\
+             \"registers\":{{\"pc\":{},\"sp\":{},\"a\":{},\"x\":{},\"y\":{},\"status\":{}}},\
+             \"coverage\":{coverage},\"memory\":[{}]}}",
+            self.halted_at.is_some(),
+            json_string(&self.stop),
+            self.speed.instructions,
+            self.speed.cycles,
+            registers.pc,
+            registers.sp,
+            registers.a,
+            registers.x,
+            registers.y,
+            registers.status.bits(),
+            memory.join(",")
+        )
+    }
+
+    /// the share of the program run as code, as a percentage
+    pub fn coverage_percent(&self) -> Option<f64> {
+        self.coverage
+            .filter(|(_, len)| *len > 0)
+            .map(|(run, len)| run as f64 * 100.0 / len as f64)
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registers = &self.registers;
+        writeln!(f, "stopped       {}", self.stop)?;
+        writeln!(f, "instructions  {}", self.speed.instructions)?;
+        writeln!(f, "cycles        {}", self.speed.cycles)?;
+        writeln!(f, "wall time     {:.3}s", self.speed.elapsed.as_secs_f64())?;
+        if self.speed.counts_cycles() {
+            writeln!(f, "speed         {:.2} MHz", self.speed.mhz())?;
+        } else {
+            writeln!(
+                f,
+                "speed         {:.0} instructions/s, cycles aren't counted in fast mode",
+                self.speed.instructions_per_sec()
+            )?;
+        }
+        write!(
+            f,
+            "registers     PC=${:04X} SP=${:04X} A=${:02X} X=${:02X} Y=${:02X} P={}",
+            registers.pc, registers.sp, registers.a, registers.x, registers.y, registers.status
+        )?;
+        if let (Some((run, len)), Some(percent)) = (self.coverage, self.coverage_percent()) {
+            write!(
+                f,
+                "\ncoverage      {percent:.1}%, {run} of {len} program bytes run"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn per_sec(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        count as f64 / elapsed.as_secs_f64()
+    }
+}
+
+/// repeatedly run a halting workload from its initial state for at least `duration`
+/// each run starts from a clone of `cpu` and goes through `execute`,
+/// so fast mode and the block cache are measured when enabled
+pub fn bench(cpu: &Cpu, duration: Duration) -> Result<Speed, CpuError> {
+    let mut total = Speed::default();
+    while total.elapsed < duration {
+        let mut run = cpu.clone();
+        let start = Instant::now();
+        run.execute()?;
+        total.add(Speed::of(&run, start.elapsed()));
+    }
+    Ok(total)
+}
+
+/// like `bench`, but each run starts by restoring a snapshot of `cpu` rather
+/// than cloning it, the way search workloads rerun short programs
+pub fn bench_restore(cpu: &Cpu, duration: Duration) -> Result<Speed, CpuError> {
+    let mut run = cpu.clone();
+    let snapshot = run.snapshot();
+    let mut total = Speed::default();
+    while total.elapsed < duration {
+        let start = Instant::now();
+        run.restore(&snapshot);
+        run.execute()?;
+        total.add(Speed::of(&run, start.elapsed()));
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::op_codes::*;
+
+    #[test]
+    fn speed_rates() {
+        let speed = Speed {
+            instructions: 500_000,
+            cycles: 2_000_000,
+            elapsed: Duration::from_millis(500),
+        };
+        assert_eq!(speed.instructions_per_sec(), 1_000_000.0);
+        assert_eq!(speed.mhz(), 4.0);
+        assert_eq!(Speed::default().mhz(), 0.0);
+    }
+
+    #[test]
+    fn fast_benches_report_no_clock_speed() {
+        let cpu = Cpu::builder()
+            .fast(true)
+            .memory(0x0000, vec![LDA_IM, 0x42, NOP])
+            .build()
+            .unwrap();
+        let speed = bench(&cpu, Duration::from_millis(1)).unwrap();
+        assert!(speed.instructions > 0);
+        assert!(!speed.counts_cycles());
+        assert!(!speed.to_string().contains(" 0.00 MHz"));
+        assert!(speed
+            .to_string()
+            .ends_with("no MHz as fast mode doesn't count cycles"));
+    }
+
+    #[test]
+    fn summaries_describe_the_run() {
+        let log = Rc::new(RefCell::new(CodeDataLog::default()));
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, BNE, 0x01, TAX, NOP])
+            .observer(log.clone())
+            .build()
+            .unwrap();
+        let result = cpu.execute();
+
+        let summary = Summary::new(&cpu, Duration::from_millis(1), &result)
+            .coverage(&log.borrow(), 0x0600..0x0606);
+        assert_eq!(summary.stop, "halted at $0605");
+        assert_eq!(summary.coverage, Some((5, 6)));
+        let report = summary.to_string();
+        assert!(report.contains("\ninstructions  2\n"));
+        assert!(report.contains("\nregisters     PC=$0606 SP=$0100 A=$42 X=$00 Y=$00 P="));
+        assert!(report.ends_with("\ncoverage      83.3%, 5 of 6 program bytes run"));
+    }
+
+    #[test]
+    fn json_reports_have_a_stable_schema() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, TAX, NOP])
+            .memory(0x0200, vec![1, 2, 3])
+            .build()
+            .unwrap();
+        let result = cpu.execute();
+        let report = Summary::new(&cpu, Duration::ZERO, &result)
+            .memory(&cpu, 0x0200..=0x0202)
+            .to_json();
+        assert_eq!(
+            report,
+            "{\"version\":1,\"halted\":true,\"stop\":\"halted at $0603\",\"instructions\":2,\
+             \"cycles\":6,\"registers\":{\"pc\":1540,\"sp\":256,\"a\":66,\"x\":66,\"y\":0,\
+             \"status\":32},\"coverage\":null,\"memory\":[{\"start\":512,\"bytes\":[1,2,3]}]}"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(json["registers"]["a"], 0x42);
+    }
+
+    #[test]
+    fn bench_reruns_workload() {
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x42, TAX, NOP])
+            .build()
+            .unwrap();
+
+        let speed = bench(&cpu, Duration::from_millis(5)).unwrap();
+        assert!(speed.elapsed >= Duration::from_millis(5));
+        assert_eq!(speed.instructions % 2, 0);
+        assert_eq!(speed.cycles, speed.instructions / 2 * 6);
+    }
+
+    #[test]
+    fn bench_restore_reruns_from_the_snapshot() {
+        let cpu = Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![LDA_IM, 0x42, PHA, NOP])
+            .build()
+            .unwrap();
+
+        let speed = bench_restore(&cpu, Duration::from_millis(5)).unwrap();
+        assert!(speed.elapsed >= Duration::from_millis(5));
+        assert_eq!(speed.cycles, speed.instructions / 2 * 7);
+    }
+}
@@ -0,0 +1,263 @@
+//! stream instruction traces and screen memory changes to websocket clients,
+//! for browser front-ends
+//!
+//! every message is a json object with the instructions traced since the
+//! last message, how many trace lines were dropped for a slow client, and the
+//! screen bytes that changed as `[address, value]` pairs, clients start from
+//! a screen of zeros
+//!
+//! ```text
+//! {"trace":["0600  A9  LDA  A:00 ..."],"dropped":0,"screen":[[512,1]],"error":null}
+//! ```
+//!
+//! each client has its own bounded queue, when it's full the emulator carries
+//! on rather than waiting: trace lines are dropped and counted, and screen
+//! changes are held back and sent with the next message that fits
+
+use std::{
+    collections::VecDeque,
+    io,
+    net::{TcpListener, TcpStream},
+    ops::Range,
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+};
+
+use thiserror::Error;
+use tungstenite::Message;
+
+use crate::{
+    cpu::{Cpu, CpuError},
+    runner::json_string,
+    trace::TraceFormat,
+};
+
+/// address `cpu_emu stream` listens on unless told otherwise
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6503";
+
+/// screen memory of the 32x32 display easy6502 style programs draw to
+pub const DEFAULT_SCREEN: Range<u16> = 0x0200..0x0600;
+
+/// instructions executed between messages
+const FLUSH_STEPS: u64 = 1000;
+
+/// messages queued for a client before it counts as slow
+const QUEUE_LEN: usize = 16;
+
+/// trace lines kept per message, older ones are dropped
+const TRACE_LEN: usize = 256;
+
+/// errors serving a stream
+#[derive(Debug, Error)]
+pub enum StreamError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// the program failed, clients were sent the error too
+    #[error(transparent)]
+    Cpu(#[from] CpuError),
+}
+
+/// what one client has been sent and what it's still owed
+#[derive(Debug, Clone)]
+pub struct Streamer {
+    screen: Range<u16>,
+    /// screen memory as the client last saw it
+    shown: Vec<u8>,
+    trace: VecDeque<String>,
+    dropped: u64,
+}
+
+impl Streamer {
+    pub fn new(screen: Range<u16>) -> Self {
+        Self {
+            shown: vec![0; screen.len()],
+            screen,
+            trace: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// add a trace line to the next message
+    pub fn record(&mut self, line: String) {
+        if self.trace.len() == TRACE_LEN {
+            self.trace.pop_front();
+            self.dropped += 1;
+        }
+        self.trace.push_back(line);
+    }
+
+    /// the message bringing the client up to date with the cpu
+    pub fn message(&self, cpu: &Cpu, error: Option<&str>) -> String {
+        let changes: Vec<String> = self
+            .screen
+            .clone()
+            .zip(&self.shown)
+            .filter_map(|(address, shown)| {
+                let value = cpu.memory.read_byte(address as usize);
+                (value != *shown).then(|| format!("[{address},{value}]"))
+            })
+            .collect();
+        let trace: Vec<String> = self.trace.iter().map(|line| json_string(line)).collect();
+        format!(
+            "{{\"trace\":[{}],\"dropped\":{},\"screen\":[{}],\"error\":{}}}",
+            trace.join(","),
+            self.dropped,
+            changes.join(","),
+            error.map_or("null".to_string(), json_string)
+        )
+    }
+
+    /// the client got everything up to the cpu's current state
+    fn sent(&mut self, cpu: &Cpu) {
+        for (address, shown) in self.screen.clone().zip(&mut self.shown) {
+            *shown = cpu.memory.read_byte(address as usize);
+        }
+        self.trace.clear();
+        self.dropped = 0;
+    }
+
+    /// queue a message without waiting, returns false once the client is gone
+    pub fn flush(&mut self, cpu: &Cpu, queue: &SyncSender<String>) -> bool {
+        match queue.try_send(self.message(cpu, None)) {
+            Ok(()) => {
+                self.sent(cpu);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.dropped += self.trace.len() as u64;
+                self.trace.clear();
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// a connected client and the thread writing to its socket
+struct Client {
+    streamer: Streamer,
+    queue: SyncSender<String>,
+    writer: JoinHandle<()>,
+}
+
+impl Client {
+    fn new(stream: TcpStream, screen: Range<u16>) -> io::Result<Self> {
+        stream.set_nonblocking(false)?;
+        let (queue, messages) = mpsc::sync_channel::<String>(QUEUE_LEN);
+        let writer = thread::spawn(move || {
+            let Ok(mut socket) = tungstenite::accept(stream) else {
+                return;
+            };
+            for message in messages {
+                if socket.send(Message::Text(message)).is_err() {
+                    return;
+                }
+            }
+            let _ = socket.close(None);
+            let _ = socket.flush();
+        });
+        Ok(Self {
+            streamer: Streamer::new(screen),
+            queue,
+            writer,
+        })
+    }
+
+    /// send the final state, waiting for room this time, and hang up
+    fn finish(self, cpu: &Cpu, error: Option<&str>) {
+        let _ = self.queue.send(self.streamer.message(cpu, error));
+        drop(self.queue);
+        let _ = self.writer.join();
+    }
+}
+
+/// wait for a client on `listener`, then run the cpu until it halts, streaming
+/// to every client connected by then
+pub fn serve(listener: TcpListener, cpu: &mut Cpu, screen: Range<u16>) -> Result<(), StreamError> {
+    let (first, _) = listener.accept()?;
+    let mut clients = vec![Client::new(first, screen.clone())?];
+    listener.set_nonblocking(true)?;
+
+    let mut steps = 0u64;
+    let result = loop {
+        let line = TraceFormat::Default.format(cpu);
+        for client in &mut clients {
+            client.streamer.record(line.clone());
+        }
+
+        let running = cpu.step();
+        steps += 1;
+        if running != Ok(true) {
+            break running.map(|_| ());
+        }
+
+        if steps.is_multiple_of(FLUSH_STEPS) {
+            while let Ok((stream, _)) = listener.accept() {
+                clients.push(Client::new(stream, screen.clone())?);
+            }
+            clients.retain_mut(|client| client.streamer.flush(cpu, &client.queue));
+        }
+    };
+
+    let error = result.as_ref().err().map(ToString::to_string);
+    for client in clients {
+        client.finish(cpu, error.as_deref());
+    }
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op_codes::*;
+
+    fn cpu() -> Cpu {
+        Cpu::builder()
+            .pc(0x0600)
+            .memory(0x0600, vec![LDA_IM, 0x05, PHA, NOP])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn slow_clients_get_changes_once_they_catch_up() {
+        let (queue, messages) = mpsc::sync_channel(1);
+        let mut cpu = cpu();
+        let mut streamer = Streamer::new(0x0100..0x0102);
+
+        streamer.record("one".to_string());
+        cpu.step().unwrap();
+        assert!(streamer.flush(&cpu, &queue));
+
+        // the queue is full, the trace is dropped and the push stays pending
+        streamer.record("two".to_string());
+        cpu.step().unwrap();
+        assert!(streamer.flush(&cpu, &queue));
+
+        assert_eq!(
+            messages.recv().unwrap(),
+            "{\"trace\":[\"one\"],\"dropped\":0,\"screen\":[],\"error\":null}"
+        );
+        assert!(streamer.flush(&cpu, &queue));
+        assert_eq!(
+            messages.recv().unwrap(),
+            "{\"trace\":[],\"dropped\":1,\"screen\":[[256,5]],\"error\":null}"
+        );
+
+        drop(messages);
+        assert!(!streamer.flush(&cpu, &queue));
+    }
+
+    #[test]
+    fn streams_to_websocket_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || serve(listener, &mut cpu(), 0x0100..0x0101).is_ok());
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{address}")).unwrap();
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert!(message.starts_with("{\"trace\":[\"0600  A9  LDA"));
+        assert!(message.ends_with("\"dropped\":0,\"screen\":[[256,5]],\"error\":null}"));
+        assert!(server.join().unwrap());
+    }
+}
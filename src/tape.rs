@@ -0,0 +1,345 @@
+//! a cassette interface for Kansas City Standard tapes, the 300 baud audio
+//! format early microcomputers saved programs in
+//!
+//! | offset | read                                  | write           |
+//! |--------|---------------------------------------|-----------------|
+//! | 0      | next byte off the tape, 0 at the end  | byte to record  |
+//! | 1      | status                                | ignored         |
+//!
+//! a tape is decoded from a WAV file up front and plays back as fast as the
+//! program reads it, bytes written are recorded and encoded to a WAV file when
+//! the tape is saved or dropped
+//!
+//! each byte is framed as a 0 start bit, 8 data bits low bit first and two 1
+//! stop bits, a 0 is four cycles of 1200Hz and a 1 eight cycles of 2400Hz
+
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+use tracing::warn;
+
+use crate::device::Device;
+
+/// address the tape is mapped at unless told otherwise
+pub const DEFAULT_BASE: u16 = 0xD300;
+
+/// addresses the tape's registers take up
+pub const LEN: usize = 2;
+
+/// status bit set while a byte is waiting to be read
+pub const BYTE_READY: u8 = 0x01;
+/// status bit set once every byte on the tape has been read
+pub const TAPE_ENDED: u8 = 0x02;
+
+/// bits a second
+const BAUD: u32 = 300;
+/// the tone of a 1 bit, and of the leader before the data
+const MARK_HZ: u32 = 2400;
+/// the tone of a 0 bit
+const SPACE_HZ: u32 = 1200;
+
+/// sample rate tapes are written at, a whole number of samples a half cycle
+/// of either tone
+const WRITE_RATE: u32 = 48_000;
+/// peak level of written samples
+const AMPLITUDE: i16 = 0x3FFF;
+/// bits of mark tone written before and after the data, half a second
+const LEADER_BITS: usize = 150;
+
+/// errors reading or writing a tape image
+#[derive(Debug, Error)]
+pub enum TapeError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// the file isn't a WAV file this can read
+    #[error("not a PCM WAV file: {0}")]
+    Format(&'static str),
+}
+
+/// uncompressed audio, one channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Audio {
+    pub rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl Audio {
+    /// parse a PCM WAV file with 8 or 16 bit samples, keeping the first
+    /// channel
+    pub fn from_wav(bytes: &[u8]) -> Result<Self, TapeError> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(TapeError::Format("missing RIFF WAVE header"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut chunks = &bytes[12..];
+        while chunks.len() >= 8 {
+            let len = u32::from_le_bytes(chunks[4..8].try_into().unwrap()) as usize;
+            let body = &chunks[8..chunks.len().min(8 + len)];
+            match &chunks[..4] {
+                b"fmt " if body.len() >= 16 => format = Some(body),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+            // chunks are padded to an even length
+            chunks = &chunks[(8 + len + len % 2).min(chunks.len())..];
+        }
+        let format = format.ok_or(TapeError::Format("no fmt chunk"))?;
+        let data = data.ok_or(TapeError::Format("no data chunk"))?;
+
+        let word = |offset: usize| u16::from_le_bytes([format[offset], format[offset + 1]]);
+        if word(0) != 1 {
+            return Err(TapeError::Format("compressed audio"));
+        }
+        let channels = word(2).max(1) as usize;
+        let rate = u32::from_le_bytes(format[4..8].try_into().unwrap());
+        let samples = match word(14) {
+            8 => data
+                .chunks_exact(channels)
+                .map(|frame| (frame[0] as i16 - 0x80) << 8)
+                .collect(),
+            16 => data
+                .chunks_exact(2 * channels)
+                .map(|frame| i16::from_le_bytes([frame[0], frame[1]]))
+                .collect(),
+            _ => return Err(TapeError::Format("only 8 and 16 bit samples are supported")),
+        };
+        Ok(Self { rate, samples })
+    }
+
+    /// a 16 bit mono WAV file
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = self.samples.len() as u32 * 2;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, one channel
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&self.rate.to_le_bytes());
+        wav.extend_from_slice(&(self.rate * 2).to_le_bytes());
+        // bytes a frame, bits a sample
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+}
+
+/// the bytes recorded in Kansas City Standard audio
+/// noise that doesn't frame as a byte is skipped
+pub fn decode(audio: &Audio) -> Vec<u8> {
+    // the lengths of the half cycles between zero crossings tell the tones
+    // apart, anything shorter than the midpoint between them is mark
+    let threshold = audio.rate as f64 / (MARK_HZ + SPACE_HZ) as f64;
+    let mut tones: Vec<(bool, usize)> = Vec::new();
+    let mut last_crossing = 0;
+    let mut positive = audio.samples.first().is_some_and(|sample| *sample >= 0);
+    for (i, sample) in audio.samples.iter().enumerate() {
+        if (*sample >= 0) == positive {
+            continue;
+        }
+        positive = !positive;
+        let mark = ((i - last_crossing) as f64) < threshold;
+        last_crossing = i;
+        match tones.last_mut() {
+            Some((tone, run)) if *tone == mark => *run += 1,
+            _ => tones.push((mark, 1)),
+        }
+    }
+
+    // a bit is 16 half cycles of mark or 8 of space
+    let mut bits = Vec::new();
+    for (mark, run) in tones {
+        let per_bit = 2 * if mark { MARK_HZ } else { SPACE_HZ } / BAUD;
+        let count = (run + per_bit as usize / 2) / per_bit as usize;
+        bits.extend(std::iter::repeat_n(mark, count));
+    }
+
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i + 10 <= bits.len() {
+        // a start bit, data and at least the first stop bit
+        if bits[i] || !bits[i + 9] {
+            i += 1;
+            continue;
+        }
+        let byte = (0..8).fold(0u8, |byte, bit| byte | (bits[i + 1 + bit] as u8) << bit);
+        bytes.push(byte);
+        i += 10;
+    }
+    bytes
+}
+
+/// bytes as Kansas City Standard audio, with a leader of mark tone before and
+/// after them
+pub fn encode(bytes: &[u8]) -> Audio {
+    let mut bits = vec![true; LEADER_BITS];
+    for byte in bytes {
+        bits.push(false);
+        bits.extend((0..8).map(|bit| byte >> bit & 1 == 1));
+        bits.extend([true, true]);
+    }
+    bits.extend(std::iter::repeat_n(true, LEADER_BITS));
+
+    let mut samples = Vec::new();
+    for mark in bits {
+        let hz = if mark { MARK_HZ } else { SPACE_HZ };
+        let half_cycle = (WRITE_RATE / hz / 2) as usize;
+        for half in 0..2 * hz / BAUD {
+            let level = if half % 2 == 0 { AMPLITUDE } else { -AMPLITUDE };
+            samples.extend(std::iter::repeat_n(level, half_cycle));
+        }
+    }
+    Audio {
+        rate: WRITE_RATE,
+        samples,
+    }
+}
+
+/// a cassette deck holding a tape to play and recording what's written
+#[derive(Debug, Default)]
+pub struct Tape {
+    playing: VecDeque<u8>,
+    recorded: Vec<u8>,
+    /// where the recording is saved, none to keep it in memory
+    path: Option<PathBuf>,
+    /// whether anything was recorded since the last save
+    unsaved: bool,
+}
+
+impl Tape {
+    /// a tape playing back `bytes`
+    pub fn new(bytes: impl Into<VecDeque<u8>>) -> Self {
+        Self {
+            playing: bytes.into(),
+            recorded: Vec::new(),
+            path: None,
+            unsaved: false,
+        }
+    }
+
+    /// a tape decoded from a WAV file
+    pub fn open(path: &Path) -> Result<Self, TapeError> {
+        let audio = Audio::from_wav(&fs::read(path)?)?;
+        Ok(Self::new(decode(&audio)))
+    }
+
+    /// save what's recorded to a WAV file, when saved or dropped
+    pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// bytes still to be played
+    pub fn remaining(&self) -> usize {
+        self.playing.len()
+    }
+
+    pub fn recorded(&self) -> &[u8] {
+        &self.recorded
+    }
+
+    /// encode what's been recorded to the file, if it changed since last time
+    pub fn save(&mut self) -> io::Result<()> {
+        if let (Some(path), true) = (&self.path, self.unsaved) {
+            fs::write(path, encode(&self.recorded).to_wav())?;
+        }
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+impl Device for Tape {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => self.playing.pop_front().unwrap_or(0),
+            _ if self.playing.is_empty() => TAPE_ENDED,
+            _ => BYTE_READY,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        if offset == 0 {
+            self.recorded.push(value);
+            self.unsaved = true;
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("TAPE")
+    }
+
+    fn register_name(&self, offset: u16) -> Option<&'static str> {
+        Some(if offset == 0 { "DATA" } else { "STATUS" })
+    }
+}
+
+impl Drop for Tape {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            warn!("failed to save the tape: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_bytes_decode_back() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let audio = encode(&bytes);
+        assert_eq!(decode(&audio), bytes);
+
+        let wav = Audio::from_wav(&audio.to_wav()).unwrap();
+        assert_eq!(wav, audio);
+        assert!(matches!(
+            Audio::from_wav(b"RIFF\0\0\0\0AVI "),
+            Err(TapeError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn decoding_copes_with_other_rates_and_8_bit_samples() {
+        // resample to 8 bit 22050Hz, the edges land between samples
+        let audio = encode(b"HELLO");
+        let len = audio.samples.len() * 22050 / WRITE_RATE as usize;
+        let samples: Vec<i16> = (0..len)
+            .map(|i| audio.samples[i * WRITE_RATE as usize / 22050] >> 8 << 8)
+            .collect();
+        let audio = Audio {
+            rate: 22050,
+            samples,
+        };
+        assert_eq!(decode(&audio), b"HELLO");
+    }
+
+    #[test]
+    fn tapes_play_and_record_through_the_registers() {
+        let path = std::env::temp_dir().join("cpu_emu_tape.wav");
+        let _ = fs::remove_file(&path);
+
+        let mut tape = Tape::new(vec![0x42]).record_to(&path);
+        assert_eq!((tape.read(1), tape.read(0)), (BYTE_READY, 0x42));
+        assert_eq!((tape.read(1), tape.read(0)), (TAPE_ENDED, 0));
+        tape.write(0, 0x06);
+        tape.write(0, 0x02);
+        drop(tape);
+
+        let tape = Tape::open(&path).unwrap();
+        assert_eq!(tape.playing, [0x06, 0x02]);
+    }
+}
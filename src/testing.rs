@@ -0,0 +1,141 @@
+//! support for writing instruction tests, see [`cpu_test!`](crate::cpu_test)
+
+/// address test programs are assembled at unless the test gives an origin
+pub const DEFAULT_ORIGIN: u16 = 0x0600;
+
+/// declare cpu tests by their program and the state they should leave behind
+///
+/// the program is given as `asm` source or as `bytes`, a NOP is appended so
+/// it halts, `given` configures the [`CpuBuilder`](crate::CpuBuilder) with
+/// `method: value` pairs and `mem[address] = byte`, and `expect` checks
+/// `register == value`, `mem[address] == byte` and `flags: Z set, C clear`
+///
+/// ```
+/// use cpu_emu::cpu_test;
+///
+/// cpu_test! {
+///     fn tax_copies_a() {
+///         given: { a: 0x80 },
+///         asm: "TAX",
+///         expect: { x == 0x80, cycles == 4, flags: N set, Z clear },
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! cpu_test {
+    ($(
+        $(#[$meta:meta])*
+        fn $name:ident() {
+            $(origin: $origin:expr,)?
+            $(given: { $($given:tt)* },)?
+            $(asm: $asm:expr,)?
+            $(bytes: [$($byte:expr),* $(,)?],)?
+            expect: { $($expect:tt)* } $(,)?
+        }
+    )*) => {$(
+        $(#[$meta])*
+        #[test]
+        fn $name() {
+            #[allow(unused_mut, unused_assignments)]
+            let mut origin: u16 = $crate::testing::DEFAULT_ORIGIN;
+            $(origin = $origin;)?
+            #[allow(unused_mut, unused_assignments)]
+            let mut program: Vec<u8> = Vec::new();
+            $(program = $crate::assembler::assemble($asm, origin).expect("test program should assemble");)?
+            $(program = vec![$($byte),*];)?
+            program.push($crate::op_codes::NOP);
+
+            let builder = $crate::Cpu::builder()
+                .pc(origin)
+                .memory(origin as usize, program);
+            $(let builder = $crate::__cpu_test_given!(builder; $($given)*);)?
+            let mut cpu = builder.build().expect("test program should load");
+            cpu.execute().expect("test program should run");
+            $crate::__cpu_test_expect!(cpu; $($expect)*);
+        }
+    )*};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cpu_test_given {
+    ($builder:ident;) => {
+        $builder
+    };
+    ($builder:ident; mem[$address:expr] = $value:expr $(, $($rest:tt)*)?) => {{
+        let $builder = $builder.memory($address as usize, vec![$value]);
+        $crate::__cpu_test_given!($builder; $($($rest)*)?)
+    }};
+    ($builder:ident; $method:ident: $value:expr $(, $($rest:tt)*)?) => {{
+        let $builder = $builder.$method($value);
+        $crate::__cpu_test_given!($builder; $($($rest)*)?)
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cpu_test_expect {
+    ($cpu:ident;) => {};
+    (@flags $cpu:ident; $flag:ident set $(, $($rest:tt)*)?) => {
+        assert!(
+            $cpu.status().contains($crate::ProcessorStatus::$flag),
+            "{} should be set, status is {}",
+            stringify!($flag),
+            $cpu.status()
+        );
+        $crate::__cpu_test_expect!(@flags $cpu; $($($rest)*)?);
+    };
+    (@flags $cpu:ident; $flag:ident clear $(, $($rest:tt)*)?) => {
+        assert!(
+            !$cpu.status().contains($crate::ProcessorStatus::$flag),
+            "{} should be clear, status is {}",
+            stringify!($flag),
+            $cpu.status()
+        );
+        $crate::__cpu_test_expect!(@flags $cpu; $($($rest)*)?);
+    };
+    (@flags $cpu:ident; $($rest:tt)*) => {
+        $crate::__cpu_test_expect!($cpu; $($rest)*);
+    };
+    ($cpu:ident; flags: $($rest:tt)*) => {
+        $crate::__cpu_test_expect!(@flags $cpu; $($rest)*);
+    };
+    ($cpu:ident; mem[$address:expr] == $value:expr $(, $($rest:tt)*)?) => {
+        assert_eq!(
+            $cpu.memory.read_byte($address as usize),
+            $value,
+            "mem[{}]",
+            stringify!($address)
+        );
+        $crate::__cpu_test_expect!($cpu; $($($rest)*)?);
+    };
+    ($cpu:ident; $register:ident == $value:expr $(, $($rest:tt)*)?) => {
+        assert_eq!($cpu.$register(), $value, "{}", stringify!($register));
+        $crate::__cpu_test_expect!($cpu; $($($rest)*)?);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{op_codes::*, ProcessorStatus};
+
+    cpu_test! {
+        fn lda_immediate_from_source() {
+            asm: "LDA #$57",
+            expect: { a == 0x57, pc == 0x0603, flags: Z clear, N clear },
+        }
+
+        fn pha_from_bytes() {
+            origin: 0x0200,
+            given: { a: 0x42, sp: 0x01FF },
+            bytes: [PHA],
+            expect: { mem[0x01FF] == 0x42, sp == 0x01FE, cycles == 5 },
+        }
+
+        fn lda_zero_page_reads_given_memory() {
+            given: { mem[0x0010] = 0x80, status: ProcessorStatus::C },
+            asm: "LDA $10",
+            expect: { a == 0x80, flags: N set, C set, Z clear, instructions == 1 },
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! a text screen drawn through a character ROM, so PETSCII and custom fonts
+//! show up as the machine would draw them rather than as the host's glyphs
+//!
+//! | offset        | read and write                                   |
+//! |---------------|--------------------------------------------------|
+//! | 0 to cells-1  | screen RAM, a character code a cell, row by row  |
+//! | cells         | foreground color, 0 to 15                        |
+//! | cells + 1     | background color, 0 to 15                        |
+//! | cells + 2     | character set, which 256 glyphs of the ROM       |
+//!
+//! a character ROM is 8 bytes a glyph, a byte a row with the leftmost pixel in
+//! bit 7, like the PET's and the C64's, a 4k C64 ROM holds two sets
+//!
+//! [`TextVideo::render`] draws the screen into a framebuffer of palette
+//! indices, 8x8 pixels a cell
+
+use std::{fs, io, path::Path};
+
+use thiserror::Error;
+
+use crate::device::Device;
+
+/// address screen RAM is mapped at unless told otherwise, where the PET has it
+pub const DEFAULT_BASE: u16 = 0x8000;
+
+/// cells across the screen unless told otherwise
+pub const DEFAULT_COLUMNS: usize = 40;
+
+/// rows of cells down the screen unless told otherwise
+pub const DEFAULT_ROWS: usize = 25;
+
+/// bytes a glyph, and pixels a side of a cell
+const GLYPH: usize = 8;
+
+/// glyphs in a character set
+const SET: usize = 256;
+
+/// errors loading a character ROM
+#[derive(Debug, Error)]
+pub enum CharRomError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("a character ROM is a whole number of 8 byte glyphs, not {0} bytes")]
+    Size(usize),
+}
+
+/// 8x8 glyphs, in sets of 256
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharRom {
+    bytes: Vec<u8>,
+}
+
+impl CharRom {
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, CharRomError> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(GLYPH) {
+            return Err(CharRomError::Size(bytes.len()));
+        }
+        Ok(Self { bytes })
+    }
+
+    pub fn open(path: &Path) -> Result<Self, CharRomError> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    /// character sets in the ROM, a short last one counts
+    pub fn sets(&self) -> usize {
+        (self.bytes.len() / GLYPH).div_ceil(SET)
+    }
+
+    /// the rows of a glyph, blank past the end of the ROM
+    pub fn glyph(&self, set: usize, code: u8) -> [u8; GLYPH] {
+        let start = (set * SET + code as usize) * GLYPH;
+        self.bytes
+            .get(start..start + GLYPH)
+            .map_or([0; GLYPH], |rows| rows.try_into().unwrap())
+    }
+}
+
+/// screen RAM and the registers drawing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextVideo {
+    rom: CharRom,
+    columns: usize,
+    rows: usize,
+    screen: Vec<u8>,
+    foreground: u8,
+    background: u8,
+    set: u8,
+}
+
+impl TextVideo {
+    /// a 40x25 screen, white on black, filled with spaces
+    pub fn new(rom: CharRom) -> Self {
+        Self {
+            rom,
+            columns: DEFAULT_COLUMNS,
+            rows: DEFAULT_ROWS,
+            screen: vec![b' '; DEFAULT_COLUMNS * DEFAULT_ROWS],
+            foreground: 1,
+            background: 0,
+            set: 0,
+        }
+    }
+
+    /// a screen of `columns` by `rows` cells
+    pub fn size(mut self, columns: usize, rows: usize) -> Self {
+        self.columns = columns;
+        self.rows = rows;
+        self.screen = vec![b' '; columns * rows];
+        self
+    }
+
+    /// addresses screen RAM and the registers take up
+    pub fn mapped_len(&self) -> usize {
+        self.screen.len() + 3
+    }
+
+    /// width of the framebuffer in pixels
+    pub fn width(&self) -> usize {
+        self.columns * GLYPH
+    }
+
+    /// height of the framebuffer in pixels
+    pub fn height(&self) -> usize {
+        self.rows * GLYPH
+    }
+
+    /// the screen as palette indices, a byte a pixel, row by row
+    pub fn render(&self) -> Vec<u8> {
+        let width = self.width();
+        let mut pixels = vec![self.background; width * self.height()];
+        for (cell, code) in self.screen.iter().enumerate() {
+            let (column, row) = (cell % self.columns, cell / self.columns);
+            let glyph = self.rom.glyph(self.set as usize, *code);
+            for (y, bits) in glyph.iter().enumerate() {
+                let start = (row * GLYPH + y) * width + column * GLYPH;
+                for (x, pixel) in pixels[start..start + GLYPH].iter_mut().enumerate() {
+                    if bits << x & 0x80 != 0 {
+                        *pixel = self.foreground;
+                    }
+                }
+            }
+        }
+        pixels
+    }
+}
+
+impl Device for TextVideo {
+    fn read(&mut self, offset: u16) -> u8 {
+        let offset = offset as usize;
+        match offset.checked_sub(self.screen.len()) {
+            None => self.screen[offset],
+            Some(0) => self.foreground,
+            Some(1) => self.background,
+            Some(_) => self.set,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        let offset = offset as usize;
+        match offset.checked_sub(self.screen.len()) {
+            None => self.screen[offset] = value,
+            Some(0) => self.foreground = value & 0x0F,
+            Some(1) => self.background = value & 0x0F,
+            // sets past the end of the ROM would draw nothing
+            Some(_) => self.set = value % self.rom.sets() as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a ROM whose glyph for 'A' is a box and every other glyph blank, with
+    /// a second set where 'A' is filled in
+    fn rom() -> CharRom {
+        let mut bytes = vec![0; 2 * SET * GLYPH];
+        let a = b'A' as usize * GLYPH;
+        bytes[a..a + GLYPH].copy_from_slice(&[0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF]);
+        let filled = (SET + b'A' as usize) * GLYPH;
+        bytes[filled..filled + GLYPH].fill(0xFF);
+        CharRom::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn cells_draw_their_glyphs() {
+        let mut video = TextVideo::new(rom()).size(2, 1);
+        video.write(1, b'A');
+        video.write(2, 5);
+        video.write(3, 6);
+
+        let pixels = video.render();
+        assert_eq!((video.width(), video.height()), (16, 8));
+        let row = |y: usize| &pixels[y * 16..(y + 1) * 16];
+        assert_eq!(row(0), [6, 6, 6, 6, 6, 6, 6, 6, 5, 5, 5, 5, 5, 5, 5, 5]);
+        assert_eq!(row(3), [6, 6, 6, 6, 6, 6, 6, 6, 5, 6, 6, 6, 6, 6, 6, 5]);
+    }
+
+    #[test]
+    fn the_set_register_switches_glyphs() {
+        let mut video = TextVideo::new(rom()).size(1, 1);
+        video.write(0, b'A');
+        video.write(3, 3);
+        assert_eq!(video.read(3), 1);
+        assert!(video.render().iter().all(|pixel| *pixel == 1));
+    }
+
+    #[test]
+    fn roms_are_whole_glyphs() {
+        assert!(matches!(
+            CharRom::from_bytes(vec![0; 12]),
+            Err(CharRomError::Size(12))
+        ));
+        assert_eq!(rom().glyph(5, b'A'), [0; 8]);
+        assert_eq!(rom().sets(), 2);
+    }
+}
@@ -0,0 +1,222 @@
+//! a periodic interrupt, like the 50 or 60 Hz tick jiffy clocks and music
+//! players are driven from, without setting up a VIA's timers
+//!
+//! | offset | read                           | write                    |
+//! |--------|--------------------------------|--------------------------|
+//! | 0      | bit 7 set while a tick pends   | acknowledge the tick     |
+//! | 1      | bit 0 set while ticking        | start or stop ticking    |
+//!
+//! a tick holds the interrupt line until the program acknowledges it, as an
+//! NMI only one is taken per tick and ticks arriving before the last one is
+//! acknowledged are lost, ticks are counted from the cycle counter so they
+//! keep time at any emulation speed
+
+use crate::device::Device;
+
+/// addresses the ticker's registers take up
+pub const LEN: usize = 2;
+
+/// address the ticker is mapped at unless told otherwise
+pub const DEFAULT_BASE: u16 = 0xD100;
+
+/// clock speed ticks are timed against unless told otherwise, in Hz
+pub const DEFAULT_CLOCK: u64 = 1_000_000;
+
+/// ticks a second unless told otherwise
+pub const DEFAULT_RATE: u64 = 60;
+
+/// the interrupt line a ticker pulls
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    #[default]
+    Irq,
+    Nmi,
+}
+
+impl Line {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "irq" => Some(Line::Irq),
+            "nmi" => Some(Line::Nmi),
+            _ => None,
+        }
+    }
+}
+
+/// interrupts at a fixed rate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticker {
+    /// cycles between ticks
+    period: u64,
+    line: Line,
+    /// the cycle the next tick arrives on, none while stopped
+    next: Option<u64>,
+    /// the cycle the cpu was at when the ticker was last accessed
+    now: u64,
+    pending: bool,
+    ticks: u64,
+}
+
+impl Ticker {
+    /// `rate` ticks a second on a cpu clocked at `clock` Hz, on the IRQ line
+    /// and ticking from cycle 0
+    pub fn new(clock: u64, rate: u64) -> Self {
+        let period = (clock / rate.max(1)).max(1);
+        Self {
+            period,
+            line: Line::Irq,
+            next: Some(period),
+            now: 0,
+            pending: false,
+            ticks: 0,
+        }
+    }
+
+    /// pull `line` instead of IRQ
+    pub fn line(mut self, line: Line) -> Self {
+        self.line = line;
+        self
+    }
+
+    /// cycles between ticks
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// ticks since the ticker was made, acknowledged or not
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// whether a tick is waiting to be acknowledged
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+}
+
+impl Default for Ticker {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLOCK, DEFAULT_RATE)
+    }
+}
+
+impl Device for Ticker {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => (self.pending as u8) << 7,
+            _ => self.next.is_some() as u8,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset {
+            0 => self.pending = false,
+            _ if value & 1 == 0 => self.next = None,
+            // restarting leaves a running ticker in step
+            _ => {
+                self.next.get_or_insert(self.now + self.period);
+            }
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("TICKER")
+    }
+
+    fn register_name(&self, offset: u16) -> Option<&'static str> {
+        Some(if offset == 0 { "TICK" } else { "CONTROL" })
+    }
+
+    fn scheduled(&self) -> bool {
+        true
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        self.next
+    }
+
+    fn event(&mut self, cycle: u64) {
+        self.pending = true;
+        self.ticks += 1;
+        self.next = Some(cycle + self.period);
+    }
+
+    fn sync(&mut self, cycle: u64) {
+        self.now = cycle;
+    }
+
+    fn irq(&self) -> bool {
+        self.pending && self.line == Line::Irq
+    }
+
+    fn nmi(&self) -> bool {
+        self.pending && self.line == Line::Nmi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    /// a program spinning while a handler at $0700 counts ticks down from
+    /// $80 in $10 and acknowledges them
+    fn cpu(ticker: &Rc<RefCell<Ticker>>, vector: u16) -> Cpu {
+        Cpu::builder()
+            .pc(0x0600)
+            .sp(0x01FF)
+            .memory(0x0600, vec![JMP_ABS, 0x00, 0x06])
+            .memory(0x0700, vec![LSR_ZP, 0x10, LSR_ABS, 0x00, 0xD1, RTI])
+            .memory(0x0010, vec![0x80])
+            .memory(vector as usize, vec![0x00, 0x07])
+            .device(DEFAULT_BASE as usize, LEN, ticker.clone())
+            .build()
+            .unwrap()
+    }
+
+    fn run_for(cpu: &mut Cpu, cycles: u64) {
+        while cpu.cycles() < cycles {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn ticks_interrupt_at_the_rate() {
+        let ticker = Rc::new(RefCell::new(Ticker::new(6000, 60)));
+        let mut cpu = cpu(&ticker, 0xFFFE);
+
+        run_for(&mut cpu, 350);
+        assert_eq!(ticker.borrow().ticks(), 3);
+        assert_eq!(cpu.memory.read_byte(0x10), 0x10);
+        assert!(!ticker.borrow().pending());
+    }
+
+    #[test]
+    fn unacknowledged_nmi_ticks_are_lost() {
+        let ticker = Rc::new(RefCell::new(Ticker::new(6000, 60).line(Line::Nmi)));
+        let mut cpu = cpu(&ticker, 0xFFFA);
+        // the handler never acknowledges, so only the first tick gets through
+        cpu.memory.write_bytes(0x0702, &[TAX, TAX, TAX]).unwrap();
+
+        run_for(&mut cpu, 350);
+        assert_eq!(ticker.borrow().ticks(), 3);
+        assert_eq!(cpu.memory.read_byte(0x10), 0x40);
+    }
+
+    #[test]
+    fn stopped_tickers_stay_quiet() {
+        let mut ticker = Ticker::new(100, 10);
+        ticker.write(1, 0);
+        assert_eq!((ticker.read(1), ticker.next_event()), (0, None));
+        ticker.sync(25);
+        ticker.write(1, 1);
+        assert_eq!(ticker.next_event(), Some(35));
+        ticker.event(35);
+        assert_eq!(
+            (ticker.read(0), ticker.irq(), ticker.nmi()),
+            (0x80, true, false)
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::Mutex,
+};
+
+use crate::{
+    cpu::Cpu,
+    disassembler,
+    op_codes::{self, AddressingMode},
+    processor_status::ProcessorStatus,
+};
+
+/// columns written by [`TraceFormat::Csv`]
+pub const CSV_HEADER: &str = "pc,opcode,instruction,a,x,y,sp,p,cycles";
+
+/// how each traced instruction is written, so traces can be diffed against
+/// the log of whichever reference emulator is at hand
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TraceFormat {
+    /// pc, opcode, mnemonic and registers, then the device register the
+    /// instruction accesses if the device names it
+    #[default]
+    Default,
+    /// the layout of nestest.log, without the PPU columns
+    Nestest,
+    /// the layout of VICE's monitor trace
+    Vice,
+    /// comma separated values with the columns in [`CSV_HEADER`]
+    Csv,
+    /// a caller-provided formatter
+    Custom(fn(&Cpu) -> String),
+}
+
+impl TraceFormat {
+    /// look a built-in format up by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(TraceFormat::Default),
+            "nestest" => Some(TraceFormat::Nestest),
+            "vice" => Some(TraceFormat::Vice),
+            "csv" => Some(TraceFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// line to write before the trace, if the format has one
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            TraceFormat::Csv => Some(CSV_HEADER),
+            _ => None,
+        }
+    }
+
+    /// the trace line for the instruction at the cpu's pc, before it executes
+    pub fn format(&self, cpu: &Cpu) -> String {
+        let pc = cpu.pc();
+        let opcode = cpu.memory.read_byte(pc as usize);
+        let (a, x, y, sp, p, cycles) = (
+            cpu.a(),
+            cpu.x(),
+            cpu.y(),
+            cpu.sp(),
+            cpu.status().bits(),
+            cpu.cycles(),
+        );
+
+        match self {
+            TraceFormat::Default => {
+                let info = op_codes::instruction(opcode);
+                let mnemonic = info.map_or("???", |info| info.mnemonic);
+                let mut line = format!(
+                    "{pc:04X}  {opcode:02X}  {mnemonic}  A:{a:02X} X:{x:02X} Y:{y:02X} SP:{sp:04X} P:{}",
+                    cpu.status()
+                );
+                let register = info.and_then(|info| {
+                    let address = operand_address(cpu, info.mode)?;
+                    cpu.memory.register_name(address as usize)
+                });
+                if let Some(register) = register {
+                    line.push_str("  ");
+                    line.push_str(&register);
+                }
+                line
+            }
+            TraceFormat::Nestest => {
+                let line = disassembler::disassemble(&cpu.memory, pc);
+                format!(
+                    "{pc:04X}  {:<8}  {:<32}A:{a:02X} X:{x:02X} Y:{y:02X} P:{p:02X} SP:{:02X} CYC:{cycles}",
+                    hex(&line.bytes),
+                    line.text,
+                    sp as u8
+                )
+            }
+            TraceFormat::Vice => {
+                let line = disassembler::disassemble(&cpu.memory, pc);
+                format!(
+                    ".C:{pc:04X}  {:<9}  {:<15} - A:{a:02X} X:{x:02X} Y:{y:02X} SP:{:02X} {} {cycles}",
+                    hex(&line.bytes),
+                    line.text,
+                    sp as u8,
+                    flags(cpu.status())
+                )
+            }
+            TraceFormat::Csv => {
+                let line = disassembler::disassemble(&cpu.memory, pc);
+                format!(
+                    "{pc:04X},{opcode:02X},\"{}\",{a:02X},{x:02X},{y:02X},{sp:04X},{p:02X},{cycles}",
+                    line.text
+                )
+            }
+            TraceFormat::Custom(format) => format(cpu),
+        }
+    }
+}
+
+/// the address the instruction at the pc accesses, for the modes that don't
+/// go through a pointer
+fn operand_address(cpu: &Cpu, mode: AddressingMode) -> Option<u16> {
+    let operand = cpu.pc().wrapping_add(1) as usize;
+    let zero_page = || cpu.memory.read_byte(operand);
+    let absolute = || cpu.memory.read_word(operand);
+    Some(match mode {
+        AddressingMode::ZeroPage => zero_page() as u16,
+        AddressingMode::ZeroPageX => zero_page().wrapping_add(cpu.x()) as u16,
+        AddressingMode::ZeroPageY => zero_page().wrapping_add(cpu.y()) as u16,
+        AddressingMode::Absolute => absolute(),
+        AddressingMode::AbsoluteX => absolute().wrapping_add(cpu.x() as u16),
+        AddressingMode::AbsoluteY => absolute().wrapping_add(cpu.y() as u16),
+        _ => return None,
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    hex.join(" ")
+}
+
+/// status flags as letters, `.` for clear flags, the way VICE shows them
+pub(crate) fn flags(status: ProcessorStatus) -> String {
+    [
+        (ProcessorStatus::N, 'N'),
+        (ProcessorStatus::V, 'V'),
+        (ProcessorStatus::U, '-'),
+        (ProcessorStatus::B, 'B'),
+        (ProcessorStatus::D, 'D'),
+        (ProcessorStatus::I, 'I'),
+        (ProcessorStatus::Z, 'Z'),
+        (ProcessorStatus::C, 'C'),
+    ]
+    .iter()
+    .map(|(flag, letter)| if status.contains(*flag) { *letter } else { '.' })
+    .collect()
+}
+
+/// the file traces go to, stdout without one
+static TRACE_FILE: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+
+/// send traces written through [`TraceWriter`] to a file instead of stdout
+pub fn trace_to(file: File) {
+    *TRACE_FILE.lock().unwrap() = Some(BufWriter::new(file));
+}
+
+/// write out traces buffered for the file given to [`trace_to`]
+pub fn flush() {
+    if let Err(err) = TraceWriter.flush() {
+        eprintln!("failed to write traces: {err}");
+    }
+}
+
+/// writes trace lines to the file given to [`trace_to`], or stdout without
+/// one, for a subscriber's [`TRACE_TARGET`](crate::cpu::TRACE_TARGET) layer
+pub struct TraceWriter;
+
+impl Write for TraceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match TRACE_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.write(buf),
+            None => io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match TRACE_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.flush(),
+            None => io::stdout().flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        op_codes::*,
+        ticker::{self, Ticker},
+    };
+
+    fn cpu() -> Cpu {
+        Cpu::builder()
+            .pc(0xC000)
+            .memory(0xC000, vec![JMP_ABS, 0xF5, 0xC5])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn built_in_formats() {
+        let cpu = cpu();
+        assert_eq!(
+            TraceFormat::Nestest.format(&cpu),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:20 SP:00 CYC:0"
+        );
+        assert_eq!(
+            TraceFormat::Vice.format(&cpu),
+            ".C:C000  4C F5 C5   JMP $C5F5       - A:00 X:00 Y:00 SP:00 ..-..... 0"
+        );
+        assert_eq!(
+            TraceFormat::Csv.format(&cpu),
+            "C000,4C,\"JMP $C5F5\",00,00,00,0100,20,0"
+        );
+        assert_eq!(TraceFormat::Csv.header(), Some(CSV_HEADER));
+    }
+
+    #[test]
+    fn default_format_names_device_registers() {
+        let mut cpu = Cpu::builder()
+            .pc(0xC000)
+            .memory(0xC000, vec![LDA_ABS, 0x01, 0xD1, LDX_ZP, 0x01])
+            .device(
+                0xD100,
+                ticker::LEN,
+                Rc::new(RefCell::new(Ticker::default())),
+            )
+            .build()
+            .unwrap();
+        let line = TraceFormat::Default.format(&cpu);
+        assert!(line.starts_with("C000  AD  LDA  A:00"));
+        assert!(line.ends_with("P:nv-bdizc  TICKER.CONTROL"));
+
+        cpu.set_pc(0xC003);
+        assert!(TraceFormat::Default.format(&cpu).ends_with("P:nv-bdizc"));
+    }
+
+    #[test]
+    fn custom_format() {
+        let format = TraceFormat::Custom(|cpu| format!("at {:04X}", cpu.pc()));
+        assert_eq!(format.format(&cpu()), "at C000");
+        assert!(TraceFormat::from_name("vice").is_some());
+        assert!(TraceFormat::from_name("bogus").is_none());
+    }
+}
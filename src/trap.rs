@@ -0,0 +1,36 @@
+use std::{cell::RefCell, collections::BTreeMap, fmt, rc::Rc};
+
+use crate::cpu::Cpu;
+
+/// host code the cpu calls when it reaches an address, before executing the
+/// instruction there, e.g. the RTS of a subroutine implemented by the host
+pub type SharedTrap = Rc<RefCell<dyn FnMut(&mut Cpu)>>;
+
+/// the traps set on a cpu, by address
+#[derive(Default, Clone)]
+pub(crate) struct Traps(BTreeMap<u16, SharedTrap>);
+
+impl Traps {
+    /// call `trap` whenever the cpu reaches `address`, replacing any trap there
+    pub fn insert(&mut self, address: u16, trap: SharedTrap) {
+        self.0.insert(address, trap);
+    }
+
+    pub fn get(&self, address: u16) -> Option<SharedTrap> {
+        self.0.get(&address).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Traps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Traps(")?;
+        f.debug_list()
+            .entries(self.0.keys().map(|address| format!("${address:04X}")))
+            .finish()?;
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fmt::Write as _, fs, io, path::Path};
+
+/// the state of the bus pins during one cycle with a memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusCycle {
+    pub cycle: u64,
+    pub address: u16,
+    pub data: u8,
+    pub write: bool,
+    /// the cycle fetches an opcode
+    pub sync: bool,
+    /// the IRQ line is asserted
+    pub irq: bool,
+    /// an NMI edge is waiting to be taken
+    pub nmi: bool,
+}
+
+/// identifiers and widths of the exported signals, IRQB, NMIB and RWB are
+/// active low like the pins on the chip
+const SIGNALS: [(&str, &str, u32); 6] = [
+    ("!", "address", 16),
+    ("\"", "data", 8),
+    ("#", "rwb", 1),
+    ("$", "sync", 1),
+    ("%", "irqb", 1),
+    ("&", "nmib", 1),
+];
+
+/// render recorded bus cycles as a value change dump, one time unit per cycle
+/// at 1MHz, cycles without a bus access hold the previous values with SYNC low
+pub fn to_vcd(cycles: &[BusCycle]) -> String {
+    let mut out = String::new();
+    out.push_str("$version cpu_emu $end\n$timescale 1us $end\n$scope module cpu $end\n");
+    for (id, name, width) in SIGNALS {
+        writeln!(out, "$var wire {width} {id} {name} $end").unwrap();
+    }
+    out.push_str("$upscope $end\n$enddefinitions $end\n");
+
+    let mut previous: Option<[u32; 6]> = None;
+    for (index, bus) in cycles.iter().enumerate() {
+        let values = [
+            bus.address as u32,
+            bus.data as u32,
+            !bus.write as u32,
+            bus.sync as u32,
+            !bus.irq as u32,
+            !bus.nmi as u32,
+        ];
+        write_changes(&mut out, bus.cycle, previous.as_ref(), &values);
+        previous = Some(values);
+
+        // an internal cycle follows the opcode fetch, drop SYNC for it
+        let next = cycles.get(index + 1).map_or(u64::MAX, |next| next.cycle);
+        if bus.sync && next > bus.cycle + 1 {
+            let mut idle = values;
+            idle[3] = 0;
+            write_changes(&mut out, bus.cycle + 1, previous.as_ref(), &idle);
+            previous = Some(idle);
+        }
+    }
+    out
+}
+
+/// write a timestamp and every signal that differs from the previous values
+fn write_changes(out: &mut String, time: u64, previous: Option<&[u32; 6]>, values: &[u32; 6]) {
+    writeln!(out, "#{time}").unwrap();
+    for (index, (id, _, width)) in SIGNALS.iter().enumerate() {
+        if previous.is_some_and(|previous| previous[index] == values[index]) {
+            continue;
+        }
+        if *width == 1 {
+            writeln!(out, "{}{id}", values[index]).unwrap();
+        } else {
+            writeln!(out, "b{:b} {id}", values[index]).unwrap();
+        }
+    }
+}
+
+/// write recorded bus cycles to a VCD file for GTKWave and similar viewers
+pub fn save(path: &Path, cycles: &[BusCycle]) -> io::Result<()> {
+    fs::write(path, to_vcd(cycles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    #[test]
+    fn accurate_cpu_records_every_bus_access() {
+        let mut cpu = Cpu::builder()
+            .pc(0x0600)
+            .accurate(true)
+            .bus_trace(true)
+            .memory(0x0600, vec![LDA_ZP, 0x10, PHA, NOP])
+            .memory(0x0010, vec![0x42])
+            .build()
+            .unwrap();
+        cpu.execute().unwrap();
+
+        let accesses: Vec<(u64, u16, u8, bool, bool)> = cpu
+            .bus_trace()
+            .iter()
+            .map(|bus| (bus.cycle, bus.address, bus.data, bus.write, bus.sync))
+            .collect();
+        assert_eq!(
+            accesses,
+            [
+                (0, 0x0600, LDA_ZP, false, true),
+                (1, 0x0601, 0x10, false, false),
+                (2, 0x0010, 0x42, false, false),
+                (3, 0x0602, PHA, false, true),
+                (4, 0x0100, 0x42, true, false),
+                (6, 0x0603, NOP, false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn vcd_only_writes_changes() {
+        let fetch = BusCycle {
+            cycle: 0,
+            address: 0x0600,
+            data: 0xEA,
+            write: false,
+            sync: true,
+            irq: false,
+            nmi: false,
+        };
+        let read = BusCycle {
+            cycle: 3,
+            data: 0x01,
+            sync: false,
+            ..fetch
+        };
+        let vcd = to_vcd(&[fetch, read]);
+
+        assert!(vcd.contains("$var wire 16 ! address $end"));
+        let body = vcd.split("$enddefinitions $end\n").nth(1).unwrap();
+        assert_eq!(
+            body,
+            "#0\nb11000000000 !\nb11101010 \"\n1#\n1$\n1%\n1&\n#1\n0$\n#3\nb1 \"\n"
+        );
+    }
+}
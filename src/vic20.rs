@@ -0,0 +1,343 @@
+//! the VIC-20's memory map, enough for cartridge dumps and programs written
+//! for it to load where they expect and to find the RAM they were built for
+//!
+//! | range         | what's there                                          |
+//! |---------------|-------------------------------------------------------|
+//! | $0000-$03FF   | RAM                                                   |
+//! | $0400-$0FFF   | RAM1-3, the 3K expansion                              |
+//! | $1000-$1FFF   | RAM, the screen at $1E00, or $1000 once BLK1 is in    |
+//! | $2000-$7FFF   | BLK1, BLK2 and BLK3, 8K expansions each               |
+//! | $8000-$8FFF   | character ROM                                         |
+//! | $9000-$9FFF   | VIC, VIAs and color RAM, left as plain RAM here       |
+//! | $A000-$BFFF   | BLK5, cartridges or an 8K expansion                   |
+//! | $C000-$FFFF   | BASIC and KERNAL ROMs                                 |
+//!
+//! expansion blocks that aren't fitted read as the high byte of their
+//! address, like the floating bus of a real machine, and ignore writes
+//!
+//! programs are `.prg` files, a little endian load address then the bytes, a
+//! program loaded at BASIC's start that begins with a `SYS` line runs from the
+//! address it calls, and one loaded at $A000 with the `A0CBM` signature is a
+//! cartridge run from its cold start vector
+
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use thiserror::Error;
+
+use crate::{cpu::CpuBuilder, device::Device, memory::RomImage};
+
+/// the BASIC token for `SYS`
+const SYS: u8 = 0x9E;
+
+/// the autostart signature cartridges carry after their vectors, `A0` and
+/// `CBM` in PETSCII
+const AUTOSTART: [u8; 5] = [0x41, 0x30, 0xC3, 0xC2, 0xCD];
+
+/// where cartridges are mapped
+pub const CARTRIDGE: u16 = 0xA000;
+pub const CHAR_ROM: u16 = 0x8000;
+pub const BASIC_ROM: u16 = 0xC000;
+pub const KERNAL_ROM: u16 = 0xE000;
+
+/// errors loading a program into the machine
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Vic20Error {
+    #[error("a .prg file starts with its two byte load address")]
+    MissingLoadAddress,
+    #[error("${0:04X} needs RAM expansion that isn't fitted")]
+    Unfitted(u16),
+    #[error("the program runs past the end of memory")]
+    TooLong,
+}
+
+/// a block of RAM that can be fitted to the expansion port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Block {
+    /// the 3K at $0400
+    Ram1to3,
+    Blk1,
+    Blk2,
+    Blk3,
+    /// the cartridge space at $A000, as RAM
+    Blk5,
+}
+
+impl Block {
+    pub const ALL: [Block; 5] = [
+        Block::Ram1to3,
+        Block::Blk1,
+        Block::Blk2,
+        Block::Blk3,
+        Block::Blk5,
+    ];
+
+    pub fn range(self) -> Range<u16> {
+        match self {
+            Block::Ram1to3 => 0x0400..0x1000,
+            Block::Blk1 => 0x2000..0x4000,
+            Block::Blk2 => 0x4000..0x6000,
+            Block::Blk3 => 0x6000..0x8000,
+            Block::Blk5 => 0xA000..0xC000,
+        }
+    }
+}
+
+/// an expansion block that isn't fitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unfitted {
+    base: u16,
+}
+
+impl Device for Unfitted {
+    fn read(&mut self, offset: u16) -> u8 {
+        (self.base.wrapping_add(offset) >> 8) as u8
+    }
+
+    fn write(&mut self, _offset: u16, _value: u8) {}
+}
+
+/// a VIC-20 and the expansions fitted to it
+#[derive(Debug, Clone, Default)]
+pub struct Vic20 {
+    fitted: Vec<Block>,
+    char_rom: Option<RomImage>,
+    basic: Option<RomImage>,
+    kernal: Option<RomImage>,
+}
+
+impl Vic20 {
+    /// an unexpanded machine, 5K of RAM
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a machine expanded the way it's usually described, `unexpanded`,
+    /// `3k`, `8k`, `16k`, `24k` or `35k`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let blocks: &[Block] = match name {
+            "unexpanded" => &[],
+            "3k" => &[Block::Ram1to3],
+            "8k" => &[Block::Blk1],
+            "16k" => &[Block::Blk1, Block::Blk2],
+            "24k" => &[Block::Blk1, Block::Blk2, Block::Blk3],
+            "35k" => &Block::ALL,
+            _ => return None,
+        };
+        Some(
+            blocks
+                .iter()
+                .fold(Self::new(), |vic, block| vic.expansion(*block)),
+        )
+    }
+
+    /// fit a block of RAM
+    pub fn expansion(mut self, block: Block) -> Self {
+        if !self.fitted.contains(&block) {
+            self.fitted.push(block);
+        }
+        self
+    }
+
+    pub fn char_rom(mut self, image: RomImage) -> Self {
+        self.char_rom = Some(image);
+        self
+    }
+
+    pub fn basic(mut self, image: RomImage) -> Self {
+        self.basic = Some(image);
+        self
+    }
+
+    /// the KERNAL ROM, programs without a start of their own run from its
+    /// reset vector
+    pub fn kernal(mut self, image: RomImage) -> Self {
+        self.kernal = Some(image);
+        self
+    }
+
+    pub fn fitted(&self, block: Block) -> bool {
+        self.fitted.contains(&block)
+    }
+
+    /// where BASIC programs start, which moves with the RAM fitted
+    pub fn basic_start(&self) -> u16 {
+        if self.fitted(Block::Blk1) {
+            0x1201
+        } else if self.fitted(Block::Ram1to3) {
+            0x0401
+        } else {
+            0x1001
+        }
+    }
+
+    /// where the screen is
+    pub fn screen(&self) -> u16 {
+        match self.fitted(Block::Blk1) {
+            true => 0x1000,
+            false => 0x1E00,
+        }
+    }
+
+    /// whether an address is RAM or ROM on this machine rather than an
+    /// empty expansion block
+    pub fn populated(&self, address: u16) -> bool {
+        Block::ALL
+            .iter()
+            .all(|block| !block.range().contains(&address) || self.fitted(*block))
+    }
+
+    /// the machine's memory map on a cpu being built
+    pub fn attach(&self, mut builder: CpuBuilder) -> CpuBuilder {
+        for block in Block::ALL.iter().filter(|block| !self.fitted(**block)) {
+            let range = block.range();
+            builder = builder.device(
+                range.start as usize,
+                range.len(),
+                Rc::new(RefCell::new(Unfitted { base: range.start })),
+            );
+        }
+        let roms = [
+            (CHAR_ROM, &self.char_rom),
+            (BASIC_ROM, &self.basic),
+            (KERNAL_ROM, &self.kernal),
+        ];
+        for (address, image) in roms {
+            if let Some(image) = image {
+                builder = builder.rom(address as usize, image.clone());
+            }
+        }
+        builder
+    }
+
+    /// the memory map with a `.prg` loaded where it asks, starting where it
+    /// should run from
+    /// cartridges at $A000 are mapped as ROM even without BLK5 fitted
+    pub fn load(&self, builder: CpuBuilder, prg: &[u8]) -> Result<CpuBuilder, Vic20Error> {
+        let [low, high, ref bytes @ ..] = prg[..] else {
+            return Err(Vic20Error::MissingLoadAddress);
+        };
+        let address = u16::from_le_bytes([low, high]);
+        let end = address as usize + bytes.len();
+        if end > 0x10000 {
+            return Err(Vic20Error::TooLong);
+        }
+
+        let cartridge = address == CARTRIDGE && !self.fitted(Block::Blk5);
+        let mut vic = self.clone();
+        if cartridge {
+            // mapped as ROM over the empty block rather than written into it
+            vic = vic.expansion(Block::Blk5);
+        }
+        if let Some(unfitted) = (address as usize..end).find(|at| !vic.populated(*at as u16)) {
+            return Err(Vic20Error::Unfitted(unfitted as u16));
+        }
+
+        let mut builder = vic.attach(builder);
+        builder = match cartridge {
+            true => builder.rom(address as usize, RomImage::Shared(bytes.into())),
+            false => builder.memory(address as usize, bytes.to_vec()),
+        };
+        let pc = if address == CARTRIDGE && bytes.get(4..9) == Some(&AUTOSTART[..]) {
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+        } else if address == self.basic_start() {
+            sys_address(bytes).or(Some(address))
+        } else if self.kernal.is_some() {
+            // the reset vector
+            None
+        } else {
+            Some(address)
+        };
+        Ok(match pc {
+            Some(pc) => builder.pc(pc),
+            None => builder,
+        })
+    }
+}
+
+/// the address a BASIC program's first line calls with `SYS`, the usual stub
+/// in front of machine code
+pub fn sys_address(basic: &[u8]) -> Option<u16> {
+    // the link to the next line and the line number come first
+    let line = basic.get(4..)?;
+    let digits = line
+        .strip_prefix(&[SYS])?
+        .iter()
+        .skip_while(|byte| **byte == b' ' || **byte == b'(')
+        .take_while(|byte| byte.is_ascii_digit());
+    let digits: String = digits.map(|byte| *byte as char).collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, op_codes::*};
+
+    /// a `10 SYS 4109` stub loaded at $1001 followed by `program` at $100D
+    fn stub(program: &[u8]) -> Vec<u8> {
+        let mut prg = vec![0x01, 0x10, 0x0B, 0x10, 0x0A, 0x00, SYS];
+        prg.extend_from_slice(b"4109");
+        prg.extend_from_slice(&[0, 0, 0]);
+        prg.extend_from_slice(program);
+        prg
+    }
+
+    #[test]
+    fn sys_stubs_run_their_machine_code() {
+        let vic = Vic20::new();
+        let prg = stub(&[LDA_IM, 0x42, NOP]);
+        let mut cpu = vic.load(Cpu::builder(), &prg).unwrap().build().unwrap();
+        assert_eq!(cpu.pc(), 4109);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.a(), 0x42);
+        assert_eq!(sys_address(&prg[2..]), Some(4109));
+    }
+
+    #[test]
+    fn empty_blocks_float_and_refuse_programs() {
+        let vic = Vic20::new();
+        let mut cpu = vic
+            .load(Cpu::builder(), &[0x00, 0x10, NOP])
+            .unwrap()
+            .build()
+            .unwrap();
+        cpu.memory.write_byte(0x2345, 0x99);
+        assert_eq!(cpu.memory.read_byte(0x2345), 0x23);
+        assert_eq!(
+            vic.load(Cpu::builder(), &[0x00, 0x20, NOP]).unwrap_err(),
+            Vic20Error::Unfitted(0x2000)
+        );
+        assert_eq!(
+            vic.load(Cpu::builder(), &[0x00]).unwrap_err(),
+            Vic20Error::MissingLoadAddress
+        );
+
+        let expanded = Vic20::from_name("8k").unwrap();
+        assert_eq!(
+            (expanded.basic_start(), expanded.screen()),
+            (0x1201, 0x1000)
+        );
+        let mut cpu = expanded
+            .load(Cpu::builder(), &[0x00, 0x20, NOP])
+            .unwrap()
+            .build()
+            .unwrap();
+        cpu.memory.write_byte(0x2345, 0x99);
+        assert_eq!(cpu.memory.read_byte(0x2345), 0x99);
+    }
+
+    #[test]
+    fn cartridges_autostart() {
+        let mut cartridge = vec![0x00, 0xA0, 0x09, 0xA0, 0x09, 0xA0];
+        cartridge.extend_from_slice(&AUTOSTART);
+        cartridge.extend_from_slice(&[LDX_IM, 0x07, NOP]);
+        let mut cpu = Vic20::new()
+            .load(Cpu::builder(), &cartridge)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(cpu.pc(), 0xA009);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.x(), 0x07);
+    }
+}
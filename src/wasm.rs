@@ -0,0 +1,133 @@
+//! javascript bindings for running the emulator in a browser, used by the
+//! playground in `web/`
+//!
+//! a [`Playground`] assembles source at $0600 and steps it, the page reads
+//! registers and the 32x32 screen at $0200 back after every step or frame
+
+use wasm_bindgen::prelude::*;
+
+use crate::{assembler::assemble, cpu::Cpu, disassembler};
+
+/// where sources are assembled and run from
+const ORIGIN: u16 = 0x0600;
+
+/// where the screen's pixels start, a byte each, row by row
+const SCREEN: usize = 0x0200;
+
+/// bytes in the 32x32 screen
+const SCREEN_LEN: usize = 32 * 32;
+
+/// a cpu with the last assembled program loaded
+#[wasm_bindgen]
+pub struct Playground {
+    cpu: Cpu,
+    program: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            cpu: Cpu::new().reset(Some(ORIGIN)),
+            program: Vec::new(),
+        }
+    }
+
+    /// assemble `source` and load it, failing with the assembler's message
+    pub fn assemble(&mut self, source: &str) -> Result<usize, String> {
+        self.program = assemble(source, ORIGIN).map_err(|err| err.to_string())?;
+        self.reset()?;
+        Ok(self.program.len())
+    }
+
+    /// clear memory and the registers and load the program again
+    pub fn reset(&mut self) -> Result<(), String> {
+        let mut cpu = Cpu::new();
+        cpu.load_program(ORIGIN as usize, self.program.clone())
+            .map_err(|err| err.to_string())?;
+        self.cpu = cpu.reset(Some(ORIGIN));
+        Ok(())
+    }
+
+    /// run one instruction, false once the program has halted
+    pub fn step(&mut self) -> Result<bool, String> {
+        self.cpu.step().map_err(|err| err.to_string())
+    }
+
+    /// run up to `steps` instructions, a frame's worth, false once the program
+    /// has halted
+    pub fn run(&mut self, steps: u32) -> Result<bool, String> {
+        for _ in 0..steps {
+            if !self.step()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.cpu.sp()
+    }
+
+    pub fn a(&self) -> u8 {
+        self.cpu.a()
+    }
+
+    pub fn x(&self) -> u8 {
+        self.cpu.x()
+    }
+
+    pub fn y(&self) -> u8 {
+        self.cpu.y()
+    }
+
+    /// status flags as NV-BDIZC letters, upper case when set
+    pub fn flags(&self) -> String {
+        self.cpu.status().to_string()
+    }
+
+    /// cycles run since the last reset, as a javascript number
+    pub fn cycles(&self) -> f64 {
+        self.cpu.cycles() as f64
+    }
+
+    /// the instruction at the pc, e.g. `$0600  A9 01     LDA #$01`
+    pub fn instruction(&self) -> String {
+        disassembler::disassemble(&self.cpu.memory, self.cpu.pc()).to_string()
+    }
+
+    /// the screen's bytes, the low nibble of each picks its color
+    pub fn screen(&self) -> Vec<u8> {
+        (SCREEN..SCREEN + SCREEN_LEN)
+            .map(|address| self.cpu.memory.read_byte(address))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_step_and_reset() {
+        let mut playground = Playground::new();
+        let source = "LDA #$01\nLDX #$02\nLSR $0200\nNOP";
+        assert_eq!(playground.assemble(source), Ok(8));
+        assert_eq!(playground.instruction(), "$0600  A9 01     LDA #$01");
+        assert_eq!(playground.step(), Ok(true));
+        assert_eq!(playground.a(), 0x01);
+        assert_eq!(playground.run(100), Ok(false));
+        assert_eq!(playground.x(), 0x02);
+        assert_eq!(playground.screen().len(), SCREEN_LEN);
+
+        playground.reset().unwrap();
+        assert_eq!((playground.pc(), playground.a()), (ORIGIN, 0));
+        assert!(playground.assemble("FOO").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+// wires the page's controls to a Playground from the wasm bindings
+
+import init, { Playground } from "./pkg/cpu_emu.js";
+
+// instructions run per animation frame while running
+const STEPS_PER_FRAME = 2000;
+
+// the screen's colors, picked by the low nibble of each byte as easy6502 does
+const PALETTE = [
+  "#000000", "#ffffff", "#880000", "#aaffee", "#cc44cc", "#00cc55", "#0000aa", "#eeee77",
+  "#dd8855", "#664400", "#ff7777", "#333333", "#777777", "#aaff66", "#0088ff", "#bbbbbb",
+];
+
+const $ = (id) => document.getElementById(id);
+const hex = (value, digits) => value.toString(16).toUpperCase().padStart(digits, "0");
+
+await init();
+const playground = new Playground();
+const screen = $("screen").getContext("2d");
+let running = false;
+
+function show() {
+  $("registers").textContent =
+    `pc $${hex(playground.pc(), 4)}  sp $${hex(playground.sp(), 4)}\n` +
+    `a $${hex(playground.a(), 2)}  x $${hex(playground.x(), 2)}  y $${hex(playground.y(), 2)}\n` +
+    `flags ${playground.flags()}  cycles ${playground.cycles()}`;
+  $("instruction").textContent = playground.instruction();
+  playground.screen().forEach((value, i) => {
+    screen.fillStyle = PALETTE[value & 0x0f];
+    screen.fillRect(i % 32, Math.floor(i / 32), 1, 1);
+  });
+  $("run").textContent = running ? "pause" : "run";
+}
+
+// run a step or a frame's worth, stopping when the program halts or fails
+function attempt(action) {
+  try {
+    if (!action()) {
+      running = false;
+      $("error").textContent = "halted";
+    }
+  } catch (error) {
+    running = false;
+    $("error").textContent = error;
+  }
+  show();
+}
+
+function frame() {
+  if (!running) return;
+  attempt(() => playground.run(STEPS_PER_FRAME));
+  requestAnimationFrame(frame);
+}
+
+$("assemble").addEventListener("click", () => {
+  running = false;
+  try {
+    const len = playground.assemble($("source").value);
+    $("error").textContent = `assembled ${len} bytes`;
+    for (const id of ["run", "step", "reset"]) $(id).disabled = false;
+  } catch (error) {
+    $("error").textContent = error;
+  }
+  show();
+});
+
+$("run").addEventListener("click", () => {
+  running = !running;
+  $("error").textContent = "";
+  show();
+  requestAnimationFrame(frame);
+});
+
+$("step").addEventListener("click", () => {
+  running = false;
+  attempt(() => playground.step());
+});
+
+$("reset").addEventListener("click", () => {
+  running = false;
+  playground.reset();
+  $("error").textContent = "";
+  show();
+});
+
+show();